@@ -0,0 +1,81 @@
+use common::addressing::make_agent_address;
+use common::proto::agent;
+use consensource_sds::event_handler::EventHandler;
+use consensource_sds::event_types::EventTypes;
+use consensource_sds::limits::EventLimits;
+use consensource_sds::strict::Strictness;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use protobuf::{Message, RepeatedField};
+use sawtooth_sdk::messages::events::{Event, EventList, Event_Attribute};
+use sawtooth_sdk::messages::transaction_receipt::{StateChange, StateChangeList, StateChange_Type};
+
+/// The number of agents set in the benchmarked block
+const AGENT_COUNT: usize = 1000;
+
+/// Builds the events of a block setting AGENT_COUNT agents, each at its own address
+fn make_large_event_list(event_types: &EventTypes) -> Vec<u8> {
+    let attribute = |key: &str, value: &str| {
+        let mut attribute = Event_Attribute::new();
+        attribute.set_key(key.to_string());
+        attribute.set_value(value.to_string());
+        attribute
+    };
+    let mut block_commit = Event::new();
+    block_commit.set_event_type(event_types.block_commit.clone());
+    block_commit.set_attributes(RepeatedField::from_vec(vec![
+        attribute("block_num", "1"),
+        attribute("block_id", "bench_block"),
+    ]));
+
+    let changes = (0..AGENT_COUNT)
+        .map(|index| {
+            let public_key = format!("bench_public_key_{}", index);
+            let mut new_agent = agent::Agent::new();
+            new_agent.set_public_key(public_key.clone());
+            new_agent.set_name("bench".to_string());
+            new_agent.set_timestamp(1);
+            let mut container = agent::AgentContainer::new();
+            container.set_entries(RepeatedField::from_vec(vec![new_agent]));
+
+            let mut change = StateChange::new();
+            change.set_address(make_agent_address(&public_key));
+            change.set_value(container.write_to_bytes().unwrap());
+            change.set_field_type(StateChange_Type::SET);
+            change
+        })
+        .collect();
+    let mut change_list = StateChangeList::new();
+    change_list.set_state_changes(RepeatedField::from_vec(changes));
+    let mut state_delta = Event::new();
+    state_delta.set_event_type(event_types.state_delta.clone());
+    state_delta.set_data(change_list.write_to_bytes().unwrap());
+
+    let mut event_list = EventList::new();
+    event_list.set_events(RepeatedField::from_vec(vec![block_commit, state_delta]));
+    event_list.write_to_bytes().unwrap()
+}
+
+fn bench_parse_events(c: &mut Criterion) {
+    let event_types = EventTypes::default();
+    let namespace_regex = EventHandler::namespace_regex(None);
+    let limits = EventLimits::default();
+    let data = make_large_event_list(&event_types);
+    let mut group = c.benchmark_group("parse_events");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("1000 agents", |b| {
+        b.iter(|| {
+            EventHandler::parse_events(
+                &data,
+                &namespace_regex,
+                &event_types,
+                &Strictness::default(),
+                &limits,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_events);
+criterion_main!(benches);
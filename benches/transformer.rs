@@ -0,0 +1,107 @@
+use common::proto::{certificate, organization};
+use consensource_sds::transformer::Container;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use database::models::{
+    NewAccreditation, NewAddress, NewAuthorization, NewCertificate, NewContact, NewOrganization,
+};
+use protobuf::RepeatedField;
+
+/// The number of entries in each benchmarked container
+const ENTRY_COUNT: usize = 1000;
+
+/// The models of one organization, as written by the event handler
+type OrganizationModels = (
+    NewOrganization,
+    Option<Vec<NewAccreditation>>,
+    Option<NewAddress>,
+    Vec<NewAuthorization>,
+    Vec<NewContact>,
+);
+
+/// Builds a container of ENTRY_COUNT certifying bodies, each with an
+/// authorization, a contact and two accreditations
+fn make_organization_container() -> organization::OrganizationContainer {
+    let entries = (0..ENTRY_COUNT)
+        .map(|index| {
+            let mut org = organization::Organization::new();
+            org.set_id(format!("bench_org_{}", index));
+            org.set_name("bench".to_string());
+            org.set_organization_type(organization::Organization_Type::CERTIFYING_BODY);
+
+            let mut contact = organization::Organization_Contact::new();
+            contact.set_name("bench".to_string());
+            contact.set_phone_number("bench".to_string());
+            contact.set_language_code("en".to_string());
+            org.set_contacts(RepeatedField::from_vec(vec![contact]));
+
+            let mut auth = organization::Organization_Authorization::new();
+            auth.set_public_key(format!("bench_public_key_{}", index));
+            auth.set_role(organization::Organization_Authorization_Role::ADMIN);
+            org.set_authorizations(RepeatedField::from_vec(vec![auth]));
+
+            let accreditations = (0..2)
+                .map(|standard| {
+                    let mut accreditation = organization::CertifyingBody_Accreditation::new();
+                    accreditation.set_standard_id(format!("bench_standard_{}", standard));
+                    accreditation.set_standard_version("1.0".to_string());
+                    accreditation.set_accreditor_id("bench_standards_body".to_string());
+                    accreditation.set_valid_from(1);
+                    accreditation.set_valid_to(2);
+                    accreditation
+                })
+                .collect();
+            let mut details = organization::CertifyingBody::new();
+            details.set_accreditations(RepeatedField::from_vec(accreditations));
+            org.set_certifying_body_details(details);
+            org
+        })
+        .collect();
+    let mut container = organization::OrganizationContainer::new();
+    container.set_entries(RepeatedField::from_vec(entries));
+    container
+}
+
+/// Builds a container of ENTRY_COUNT certificates
+fn make_certificate_container() -> certificate::CertificateContainer {
+    let entries = (0..ENTRY_COUNT)
+        .map(|index| {
+            let mut cert = certificate::Certificate::new();
+            cert.set_id(format!("bench_cert_{}", index));
+            cert.set_certifying_body_id("bench_org".to_string());
+            cert.set_factory_id(format!("bench_factory_{}", index));
+            cert.set_standard_id("bench_standard".to_string());
+            cert.set_standard_version("1.0".to_string());
+            cert.set_valid_from(1);
+            cert.set_valid_to(2);
+            cert
+        })
+        .collect();
+    let mut container = certificate::CertificateContainer::new();
+    container.set_entries(RepeatedField::from_vec(entries));
+    container
+}
+
+fn bench_to_models(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_models");
+    group.throughput(Throughput::Elements(ENTRY_COUNT as u64));
+
+    let organizations = make_organization_container();
+    group.bench_function("1000 organizations", |b| {
+        b.iter(|| {
+            let models: Vec<OrganizationModels> = organizations.to_models(1);
+            models
+        })
+    });
+
+    let certificates = make_certificate_container();
+    group.bench_function("1000 certificates", |b| {
+        b.iter(|| {
+            let models: Vec<NewCertificate> = certificates.to_models(1);
+            models
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_to_models);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+#![no_main]
+use consensource_sds::event_handler::EventHandler;
+use consensource_sds::event_types::EventTypes;
+use consensource_sds::limits::EventLimits;
+use consensource_sds::strict::Strictness;
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+use regex::Regex;
+
+lazy_static! {
+    static ref NAMESPACE_REGEX: Regex = EventHandler::namespace_regex(None);
+    static ref EVENT_TYPES: EventTypes = EventTypes::default();
+    static ref LIMITS: EventLimits = EventLimits::default();
+    static ref STRICT: Strictness = Strictness::all();
+}
+
+// Malformed event data must be rejected with an error, never a panic
+fuzz_target!(|data: &[u8]| {
+    let _ = EventHandler::parse_events(data, &NAMESPACE_REGEX, &EVENT_TYPES, &STRICT, &LIMITS);
+});
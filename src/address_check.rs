@@ -0,0 +1,125 @@
+use crate::entity_ref::EntityRef;
+use crate::event_handler::{address_type_name, EventHandler};
+use crate::limits::EventLimits;
+use crate::strict::Strictness;
+
+use common::addressing::{get_address_type, AddressSpace};
+use database::models::Block;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How the subscriber would handle each address in the validator's state, for
+/// `--validate-addresses`
+#[derive(Debug, Default)]
+pub struct AddressReport {
+    /// The number of addresses handled, by the name of their type
+    pub handled: BTreeMap<&'static str, usize>,
+    /// The addresses in the namespace that route to no address type
+    pub unrouted: Vec<String>,
+    /// The addresses routed to a type whose transformer fails on their value,
+    /// with the error
+    pub unparsed: Vec<(String, String)>,
+}
+
+impl AddressReport {
+    /// Routes each state entry to its address type and parses its value with the
+    /// type's transformer, as a state change at the given block would be
+    pub fn check(entries: &[(String, Vec<u8>)], block: &Block, limits: &EventLimits) -> Self {
+        let mut report = AddressReport::default();
+        for (address, value) in entries {
+            let address_type = get_address_type(address);
+            if let AddressSpace::AnotherFamily = address_type {
+                report.unrouted.push(address.clone());
+                continue;
+            }
+            match EventHandler::parse_state(address, value, block, &Strictness::default(), limits) {
+                Ok(_) => {
+                    *report
+                        .handled
+                        .entry(address_type_name(&address_type))
+                        .or_insert(0) += 1
+                }
+                Err(err) => report.unparsed.push((address.clone(), err.to_string())),
+            }
+        }
+        report
+    }
+
+    /// Returns true if every address is routed to a type that handles its value
+    pub fn is_clean(&self) -> bool {
+        self.unrouted.is_empty() && self.unparsed.is_empty()
+    }
+}
+
+impl fmt::Display for AddressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, count) in &self.handled {
+            writeln!(f, "{:<14} {}", name, count)?;
+        }
+        writeln!(f, "{:<14} {}", "unrouted", self.unrouted.len())?;
+        writeln!(f, "{:<14} {}", "unparsed", self.unparsed.len())?;
+        for address in &self.unrouted {
+            writeln!(f, "unrouted {}", address)?;
+        }
+        for (address, err) in &self.unparsed {
+            writeln!(f, "unparsed {}: {}", EntityRef::from_address(address), err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::addressing::{get_family_namespace_prefix, make_agent_address};
+    use common::proto::agent;
+    use protobuf::Message;
+
+    #[test]
+    /// Test that handled addresses are counted by type, and addresses routing to no
+    /// type or failing to parse are listed
+    fn test_check_addresses() {
+        let mut new_agent = agent::Agent::new();
+        new_agent.set_public_key("test_public_key".to_string());
+        new_agent.set_name("test".to_string());
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![new_agent]));
+        let namespace = get_family_namespace_prefix();
+        let unrouted = (0..=u8::max_value())
+            .map(|infix| {
+                format!(
+                    "{}{:02x}{}",
+                    namespace,
+                    infix,
+                    "0".repeat(70 - namespace.len() - 2)
+                )
+            })
+            .find(|address| match get_address_type(address) {
+                AddressSpace::AnotherFamily => true,
+                _ => false,
+            })
+            .unwrap();
+        let entries = vec![
+            (
+                make_agent_address("test_public_key"),
+                agents.write_to_bytes().unwrap(),
+            ),
+            (unrouted.clone(), vec![]),
+            (make_agent_address("other_public_key"), vec![0xff, 0xff]),
+        ];
+        let block = Block {
+            block_num: 1,
+            block_id: "block_1".to_string(),
+        };
+
+        let report = AddressReport::check(&entries, &block, &EventLimits::default());
+        assert_eq!(report.handled.get("agent"), Some(&1));
+        assert_eq!(report.unrouted, vec![unrouted.clone()]);
+        assert_eq!(report.unparsed.len(), 1);
+        assert_eq!(report.unparsed[0].0, make_agent_address("other_public_key"));
+        assert!(!report.is_clean());
+        let output = report.to_string();
+        assert!(output.starts_with("agent          1\nunrouted       1\nunparsed       1\n"));
+        assert!(output.contains(&format!("unrouted {}\n", unrouted)));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::event_handler::address_type_name;
+use crate::shard::Shard;
+
+use common::addressing::{get_address_type, get_family_namespace_prefix, AddressSpace};
+
+/// The names of the address types that can be subscribed to
+pub const ADDRESS_TYPES: [&str; 6] = [
+    "agent",
+    "organization",
+    "certificate",
+    "request",
+    "standard",
+    "assertion",
+];
+/// The length of a Sawtooth state address, in hex characters
+const ADDRESS_LEN: usize = 70;
+/// The length of the infix following the namespace that sets an address's type
+const TYPE_INFIX_LEN: usize = 2;
+
+/// The address types, i.e. the sub-namespaces of the Certificate Registry
+/// namespace, whose state changes are subscribed to. The default is the whole
+/// namespace.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddressTypes {
+    prefixes: Vec<String>,
+}
+
+impl AddressTypes {
+    /// Parses a comma separated list of address type names, e.g. `agent,certificate`
+    /// ```
+    /// # Errors
+    /// Returns an error if a name is not one of ADDRESS_TYPES, or the list is empty
+    /// ```
+    pub fn parse(value: &str) -> Result<AddressTypes, String> {
+        let mut prefixes = Vec::new();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let prefix = type_prefix(name).ok_or_else(|| {
+                format!(
+                    "Unknown address type {:?}, expected one of {}",
+                    name,
+                    ADDRESS_TYPES.join(", ")
+                )
+            })?;
+            if !prefixes.contains(&prefix) {
+                prefixes.push(prefix);
+            }
+        }
+        if prefixes.is_empty() {
+            return Err("At least one address type is required".to_string());
+        }
+        Ok(AddressTypes { prefixes })
+    }
+
+    /// Returns the address prefix of each address type, or the namespace if no types
+    /// were given
+    pub fn prefixes(&self) -> Vec<String> {
+        if self.prefixes.is_empty() {
+            vec![get_family_namespace_prefix()]
+        } else {
+            self.prefixes.clone()
+        }
+    }
+
+    /// Returns one regex per address type matching its addresses in the given
+    /// shard, or a single regex for the whole namespace if no types were given
+    pub fn address_regexes(&self, shard: Option<Shard>) -> Vec<String> {
+        self.prefixes()
+            .iter()
+            .map(|prefix| match shard {
+                Some(shard) => shard.address_regex(prefix),
+                None => format!(r"^{}", prefix),
+            })
+            .collect()
+    }
+}
+
+/// Returns the address prefix of the named address type: the namespace followed
+/// by the type's infix. The addressing layer only classifies whole addresses, so
+/// the prefix is found by classifying an address built from each possible infix.
+pub fn type_prefix(name: &str) -> Option<String> {
+    let namespace = get_family_namespace_prefix();
+    let padding = "0".repeat(ADDRESS_LEN - namespace.len() - TYPE_INFIX_LEN);
+    (0..=u8::max_value())
+        .map(|infix| format!("{}{:02x}", namespace, infix))
+        .find(|prefix| {
+            let address_type = get_address_type(&format!("{}{}", prefix, padding));
+            match address_type {
+                AddressSpace::AnotherFamily => false,
+                _ => address_type_name(&address_type) == name,
+            }
+        })
+}
+
+/// Returns a short description of the entities stored under the named address type
+pub fn type_description(name: &str) -> Option<&'static str> {
+    match name {
+        "agent" => Some("agents, addressed by their public key"),
+        "organization" => Some("standards bodies, certifying bodies and factories"),
+        "certificate" => Some("certificates issued to factories"),
+        "request" => Some("certification requests opened by factories"),
+        "standard" => Some("standards and their versions"),
+        "assertion" => Some("assertions made about other entities"),
+        _ => None,
+    }
+}
+
+/// Returns the namespace and every address type the subscriber handles as lines of
+/// name, prefix and description, namespace first, for `--list-address-spaces`
+pub fn address_space_lines() -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<14} {:<8} {}",
+        "namespace",
+        get_family_namespace_prefix(),
+        "every Certificate Registry address"
+    )];
+    lines.extend(ADDRESS_TYPES.iter().map(|name| {
+        format!(
+            "{:<14} {:<8} {}",
+            name,
+            type_prefix(name).unwrap_or_default(),
+            type_description(name).unwrap_or_default()
+        )
+    }));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    /// Test that every address type has a distinct prefix within the namespace
+    fn test_type_prefixes() {
+        let namespace = get_family_namespace_prefix();
+        let mut prefixes: Vec<String> = ADDRESS_TYPES
+            .iter()
+            .map(|name| type_prefix(name).unwrap())
+            .collect();
+        assert!(prefixes.iter().all(|prefix| prefix.starts_with(&namespace)));
+        prefixes.sort();
+        prefixes.dedup();
+        assert_eq!(prefixes.len(), ADDRESS_TYPES.len());
+        assert_eq!(type_prefix("unknown"), None);
+    }
+
+    #[test]
+    /// Test that the regexes of the given types only match addresses of those types
+    fn test_address_regexes() {
+        let types = AddressTypes::parse("agent, assertion").unwrap();
+        let regexes: Vec<Regex> = types
+            .address_regexes(None)
+            .iter()
+            .map(|regex| Regex::new(regex).unwrap())
+            .collect();
+        assert_eq!(regexes.len(), 2);
+        let padding =
+            "0".repeat(ADDRESS_LEN - get_family_namespace_prefix().len() - TYPE_INFIX_LEN);
+        for name in &ADDRESS_TYPES {
+            let address = format!("{}{}", type_prefix(name).unwrap(), padding);
+            let matched = regexes.iter().any(|regex| regex.is_match(&address));
+            assert_eq!(matched, *name == "agent" || *name == "assertion");
+        }
+
+        assert_eq!(
+            AddressTypes::default().address_regexes(None),
+            vec![format!("^{}", get_family_namespace_prefix())]
+        );
+        assert!(AddressTypes::parse("agent,unknown").is_err());
+        assert!(AddressTypes::parse(" , ").is_err());
+    }
+
+    #[test]
+    /// Test that the namespace and every address type are listed with their prefix
+    fn test_address_space_lines() {
+        let lines = address_space_lines();
+        assert_eq!(lines.len(), ADDRESS_TYPES.len() + 1);
+        assert!(lines[0].starts_with("namespace"));
+        assert!(lines[0].contains(&get_family_namespace_prefix()));
+        for (name, line) in ADDRESS_TYPES.iter().zip(&lines[1..]) {
+            assert!(line.starts_with(name));
+            assert!(line.contains(&type_prefix(name).unwrap()));
+            assert!(line.ends_with(type_description(name).unwrap()));
+        }
+        assert_eq!(type_description("unknown"), None);
+    }
+}
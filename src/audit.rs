@@ -0,0 +1,414 @@
+use crate::errors::SubscriberError;
+
+use database::{data_manager::OperationType, models::Block};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// The names of the operations in audit records
+pub const OPERATIONS: [&str; 7] = [
+    "create_organization",
+    "create_agent",
+    "create_certificate",
+    "create_request",
+    "create_standard",
+    "create_assertion",
+    "delete_assertion",
+];
+
+/// Builds one audit record for each entity written by the operations of a block.
+///
+/// Each record is a JSON object with the block it was applied in, the
+/// operation, the id of the entity and the key fields that were written:
+/// ```text
+/// {"block_num":1,"block_id":"...","operation":"create_agent","id":"...","fields":{...}}
+/// ```
+pub fn operation_records(block: &Block, operations: &[OperationType]) -> Vec<Value> {
+    let record = |operation: &str, id: &str, fields: Value| {
+        json!({
+            "block_num": block.block_num,
+            "block_id": block.block_id,
+            "operation": operation,
+            "id": id,
+            "fields": fields,
+        })
+    };
+    let mut records = Vec::new();
+    for operation in operations {
+        match operation {
+            OperationType::CreateOrganization(orgs) => {
+                records.extend(orgs.iter().map(|(org, accreditations, address, auths, contacts)| {
+                    record(
+                        "create_organization",
+                        &org.organization_id,
+                        json!({
+                            "name": org.name,
+                            "organization_type": format!("{:?}", org.organization_type),
+                            "authorizations": auths
+                                .iter()
+                                .map(|auth| json!({
+                                    "public_key": auth.public_key,
+                                    "role": format!("{:?}", auth.role),
+                                }))
+                                .collect::<Vec<Value>>(),
+                            "contacts": contacts.iter().map(|contact| &contact.name).collect::<Vec<&String>>(),
+                            "accreditations": accreditations
+                                .iter()
+                                .flatten()
+                                .map(|accreditation| json!({
+                                    "standard_id": accreditation.standard_id,
+                                    "standard_version": accreditation.standard_version,
+                                    "accreditor_id": accreditation.accreditor_id,
+                                }))
+                                .collect::<Vec<Value>>(),
+                            "has_address": address.is_some(),
+                        }),
+                    )
+                }))
+            }
+            OperationType::CreateAgent(agents) => records.extend(agents.iter().map(|agent| {
+                record(
+                    "create_agent",
+                    &agent.public_key,
+                    json!({
+                        "organization_id": agent.organization_id,
+                        "name": agent.name,
+                    }),
+                )
+            })),
+            OperationType::CreateCertificate(certificates) => {
+                records.extend(certificates.iter().map(|certificate| {
+                    record(
+                        "create_certificate",
+                        &certificate.certificate_id,
+                        json!({
+                            "certifying_body_id": certificate.certifying_body_id,
+                            "factory_id": certificate.factory_id,
+                            "standard_id": certificate.standard_id,
+                            "standard_version": certificate.standard_version,
+                            "valid_from": certificate.valid_from,
+                            "valid_to": certificate.valid_to,
+                        }),
+                    )
+                }))
+            }
+            OperationType::CreateRequest(requests) => {
+                records.extend(requests.iter().map(|request| {
+                    record(
+                        "create_request",
+                        &request.request_id,
+                        json!({
+                            "factory_id": request.factory_id,
+                            "standard_id": request.standard_id,
+                            "status": format!("{:?}", request.status),
+                        }),
+                    )
+                }))
+            }
+            OperationType::CreateStandard(standards) => {
+                records.extend(standards.iter().map(|(standard, versions)| {
+                    record(
+                        "create_standard",
+                        &standard.standard_id,
+                        json!({
+                            "organization_id": standard.organization_id,
+                            "name": standard.name,
+                            "versions": versions
+                                .iter()
+                                .map(|version| &version.version)
+                                .collect::<Vec<&String>>(),
+                        }),
+                    )
+                }))
+            }
+            OperationType::CreateAssertion(assertions) => {
+                records.extend(assertions.iter().map(|assertion| {
+                    record(
+                        "create_assertion",
+                        &assertion.assertion_id,
+                        json!({
+                            "address": assertion.address,
+                            "assertor_pub_key": assertion.assertor_pub_key,
+                            "assertion_type": format!("{:?}", assertion.assertion_type),
+                            "object_id": assertion.object_id,
+                        }),
+                    )
+                }))
+            }
+            OperationType::DeleteAssertion(address, _) => {
+                records.push(record("delete_assertion", address, json!({})))
+            }
+        }
+    }
+    records
+}
+
+/// Returns a copy of a record with string values longer than max_len characters
+/// cut short, noting how many characters were removed, so records can be logged
+/// without flooding the log
+pub fn truncate_strings(record: &Value, max_len: usize) -> Value {
+    match record {
+        Value::String(text) if text.chars().count() > max_len => Value::String(format!(
+            "{}...({} more)",
+            text.chars().take(max_len).collect::<String>(),
+            text.chars().count() - max_len
+        )),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| truncate_strings(value, max_len))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), truncate_strings(value, max_len)))
+                .collect(),
+        ),
+        _ => record.clone(),
+    }
+}
+
+/// Shown in place of the value of a redacted field
+pub const REDACTED_VALUE: &str = "<redacted>";
+
+/// The names of fields whose values are masked in the records written to logs,
+/// stdout, the audit log and the webhook. The reporting database still gets them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redaction {
+    fields: BTreeSet<String>,
+}
+
+impl Redaction {
+    /// Parses a comma separated list of field names, e.g. name,phone_number
+    pub fn parse(value: &str) -> Redaction {
+        Redaction {
+            fields: value
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    /// Returns true if no field is redacted
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns a copy of a record with the value of every field named in the list
+    /// masked, at any depth. A list or object value is masked as a whole. A null
+    /// value is kept, so the record still shows the field was unset.
+    pub fn apply(&self, record: &Value) -> Value {
+        match record {
+            Value::Array(values) => {
+                Value::Array(values.iter().map(|value| self.apply(value)).collect())
+            }
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| {
+                        if self.fields.contains(key) && !value.is_null() {
+                            (key.clone(), json!(REDACTED_VALUE))
+                        } else {
+                            (key.clone(), self.apply(value))
+                        }
+                    })
+                    .collect(),
+            ),
+            _ => record.clone(),
+        }
+    }
+}
+
+/// Builds the records of a block's operations, as operation_records does, with the
+/// redacted fields masked. This is how records are built for every output other
+/// than the reporting database.
+pub fn redacted_records(
+    block: &Block,
+    operations: &[OperationType],
+    redaction: &Redaction,
+) -> Vec<Value> {
+    let records = operation_records(block, operations);
+    if redaction.is_empty() {
+        return records;
+    }
+    records
+        .iter()
+        .map(|record| redaction.apply(record))
+        .collect()
+}
+
+/// An append-only file recording every operation applied to the reporting database
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the given path, creating it if it does not exist.
+    /// Existing records are never truncated or rewritten.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AuditLog, SubscriberError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    /// Appends the records of a committed block, one JSON object per line,
+    /// and flushes them to disk before returning
+    pub fn append(&mut self, records: &[Value]) -> Result<(), SubscriberError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        write_records(&mut self.file, records)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Writes records as newline-delimited JSON, one object per line, in a
+/// single write, and flushes the writer
+pub fn write_records<W: Write>(writer: &mut W, records: &[Value]) -> Result<(), SubscriberError> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&record.to_string());
+        lines.push('\n');
+    }
+    writer.write_all(lines.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::{data_manager::MAX_BLOCK_NUM, models::NewAgent};
+    use std::fs;
+
+    #[test]
+    /// Test that each entity in an operation becomes a record with its block and id
+    fn test_operation_records() {
+        let block = Block {
+            block_num: 3,
+            block_id: "test_block".to_string(),
+        };
+        let agent = NewAgent {
+            public_key: "test_public_key".to_string(),
+            organization_id: None,
+            name: "test".to_string(),
+            timestamp: 1,
+            start_block_num: 3,
+            end_block_num: MAX_BLOCK_NUM,
+        };
+        let operations = vec![
+            OperationType::CreateAgent(vec![agent]),
+            OperationType::DeleteAssertion("some_state_address".to_string(), 3),
+        ];
+        assert_eq!(
+            operation_records(&block, &operations),
+            vec![
+                json!({
+                    "block_num": 3,
+                    "block_id": "test_block",
+                    "operation": "create_agent",
+                    "id": "test_public_key",
+                    "fields": {"organization_id": null, "name": "test"},
+                }),
+                json!({
+                    "block_num": 3,
+                    "block_id": "test_block",
+                    "operation": "delete_assertion",
+                    "id": "some_state_address",
+                    "fields": {},
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that long strings are truncated wherever they are nested in a record
+    fn test_truncate_strings() {
+        let record = json!({
+            "id": "abcdefgh",
+            "fields": {"name": "abc", "contacts": ["abcdefghij"], "valid_from": 1},
+        });
+        assert_eq!(
+            truncate_strings(&record, 5),
+            json!({
+                "id": "abcde...(3 more)",
+                "fields": {"name": "abc", "contacts": ["abcde...(5 more)"], "valid_from": 1},
+            })
+        );
+    }
+
+    #[test]
+    /// Test that redacted fields don't appear in the serialized records, at any
+    /// depth, while other fields and unset ones are kept
+    fn test_redacted_records() {
+        let block = Block {
+            block_num: 3,
+            block_id: "test_block".to_string(),
+        };
+        let agent = NewAgent {
+            public_key: "test_public_key".to_string(),
+            organization_id: None,
+            name: "Jane Doe".to_string(),
+            timestamp: 1,
+            start_block_num: 3,
+            end_block_num: MAX_BLOCK_NUM,
+        };
+        let operations = vec![OperationType::CreateAgent(vec![agent])];
+        let redaction = Redaction::parse("name, organization_id,");
+        let records = redacted_records(&block, &operations, &redaction);
+        let mut output = Vec::new();
+        write_records(&mut output, &records).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("Jane Doe"));
+        assert_eq!(records[0]["fields"]["name"], REDACTED_VALUE);
+        assert!(records[0]["fields"]["organization_id"].is_null());
+        assert_eq!(records[0]["id"], "test_public_key");
+
+        let nested = json!({"contacts": [{"name": "Jane Doe", "phone_number": "555-0100"}]});
+        assert_eq!(
+            Redaction::parse("phone_number").apply(&nested),
+            json!({"contacts": [{"name": "Jane Doe", "phone_number": REDACTED_VALUE}]})
+        );
+        assert_eq!(
+            redacted_records(&block, &operations, &Redaction::default()),
+            operation_records(&block, &operations)
+        );
+    }
+
+    #[test]
+    /// Test that records are appended to the audit log without truncating it
+    fn test_audit_log_appends() {
+        let path = std::env::temp_dir().join(format!("sds-audit-{}.log", uuid::Uuid::new_v4()));
+        AuditLog::open(&path)
+            .unwrap()
+            .append(&[json!({"id": "first"})])
+            .unwrap();
+        AuditLog::open(&path)
+            .unwrap()
+            .append(&[json!({"id": "second"})])
+            .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "{\"id\":\"first\"}\n{\"id\":\"second\"}\n");
+    }
+
+    #[test]
+    /// Test that records are written as one JSON object per line
+    fn test_write_records() {
+        let mut output = Vec::new();
+        write_records(
+            &mut output,
+            &[json!({"id": "first"}), json!({"id": "second"})],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"id\":\"first\"}\n{\"id\":\"second\"}\n"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use database::{data_manager::OperationType, models::Block};
+use serde_json::Value;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How many blocks are coalesced into one database transaction. With neither
+/// bound set, every block is committed in its own transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CommitInterval {
+    /// Commit once this many blocks are pending
+    pub blocks: Option<usize>,
+    /// Commit once the oldest pending block has waited this long
+    pub duration: Option<Duration>,
+}
+
+impl CommitInterval {
+    /// Returns true if the given pending blocks should be committed now
+    pub fn is_due(&self, pending: usize, oldest: Instant, now: Instant) -> bool {
+        if pending == 0 {
+            return false;
+        }
+        if self.blocks.is_none() && self.duration.is_none() {
+            return true;
+        }
+        self.blocks.map_or(false, |blocks| pending >= blocks)
+            || self
+                .duration
+                .map_or(false, |duration| now.duration_since(oldest) >= duration)
+    }
+}
+
+/// Blocks handled but not yet committed to the reporting database, with the
+/// records of their operations to write once they are, and when each block's
+/// events were received
+#[derive(Default)]
+pub struct PendingBlocks {
+    blocks: Vec<(Vec<OperationType>, Block)>,
+    records: Vec<Value>,
+    received: Vec<Instant>,
+    since: Option<Instant>,
+}
+
+impl PendingBlocks {
+    /// Adds a block to commit with the next batch, with when its events were received
+    pub fn push(
+        &mut self,
+        operations: Vec<OperationType>,
+        block: &Block,
+        records: Vec<Value>,
+        received: Instant,
+    ) {
+        self.since.get_or_insert_with(Instant::now);
+        self.received.push(received);
+        self.blocks.push((
+            operations,
+            Block {
+                block_num: block.block_num,
+                block_id: block.block_id.clone(),
+            },
+        ));
+        self.records.extend(records);
+    }
+
+    /// Returns the number of pending blocks
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns true if no block is pending
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns true if the pending blocks should be committed now
+    pub fn is_due(&self, interval: &CommitInterval, now: Instant) -> bool {
+        match self.since {
+            Some(since) => interval.is_due(self.blocks.len(), since, now),
+            None => false,
+        }
+    }
+
+    /// Takes the pending blocks, their records and when each block was received,
+    /// leaving none pending
+    pub fn take(&mut self) -> (Vec<(Vec<OperationType>, Block)>, Vec<Value>, Vec<Instant>) {
+        self.since = None;
+        (
+            mem::replace(&mut self.blocks, vec![]),
+            mem::replace(&mut self.records, vec![]),
+            mem::replace(&mut self.received, vec![]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that every block is due without an interval, and batches are due once
+    /// either bound is reached
+    fn test_commit_interval() {
+        let start = Instant::now();
+        let later = start + Duration::from_millis(500);
+
+        let per_block = CommitInterval::default();
+        assert!(!per_block.is_due(0, start, start));
+        assert!(per_block.is_due(1, start, start));
+
+        let by_blocks = CommitInterval {
+            blocks: Some(3),
+            duration: None,
+        };
+        assert!(!by_blocks.is_due(2, start, later));
+        assert!(by_blocks.is_due(3, start, start));
+
+        let by_time = CommitInterval {
+            blocks: Some(3),
+            duration: Some(Duration::from_millis(500)),
+        };
+        assert!(!by_time.is_due(1, start, start));
+        assert!(by_time.is_due(1, start, later));
+    }
+
+    #[test]
+    /// Test that taking the pending blocks empties them
+    fn test_pending_blocks() {
+        let mut pending = PendingBlocks::default();
+        let interval = CommitInterval::default();
+        assert!(!pending.is_due(&interval, Instant::now()));
+
+        let block = Block {
+            block_num: 1,
+            block_id: "block_1".to_string(),
+        };
+        let received = Instant::now();
+        pending.push(vec![], &block, vec![Value::Null], received);
+        assert_eq!(pending.len(), 1);
+        assert!(pending.is_due(&interval, Instant::now()));
+
+        let (blocks, records, received_at) = pending.take();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(records, vec![Value::Null]);
+        assert_eq!(received_at, vec![received]);
+        assert!(pending.is_empty());
+        assert!(!pending.is_due(&interval, Instant::now()));
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The schema used when none is given, matching the Postgres default search_path
+pub const DEFAULT_SCHEMA: &str = "public";
+
+/// Session settings applied to each connection to the reporting database
+pub struct ConnectionOptions<'a> {
+    /// The schema holding the reporting tables
+    pub schema: &'a str,
+    /// The longest a single statement may run before the database aborts it
+    pub statement_timeout: Option<Duration>,
+    /// The isolation level of each transaction, if not the database's default
+    pub isolation: Option<IsolationLevel>,
+}
+
+impl<'a> Default for ConnectionOptions<'a> {
+    fn default() -> Self {
+        ConnectionOptions {
+            schema: DEFAULT_SCHEMA,
+            statement_timeout: None,
+            isolation: None,
+        }
+    }
+}
+
+/// The isolation level of the transactions writing blocks to the reporting database
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Returns the Postgres name of the isolation level
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            IsolationLevel::ReadCommitted => "read committed",
+            IsolationLevel::RepeatableRead => "repeatable read",
+            IsolationLevel::Serializable => "serializable",
+        }
+    }
+}
+
+impl fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_sql())
+    }
+}
+
+impl FromStr for IsolationLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "read-committed" => Ok(IsolationLevel::ReadCommitted),
+            "repeatable-read" => Ok(IsolationLevel::RepeatableRead),
+            "serializable" => Ok(IsolationLevel::Serializable),
+            _ => Err(format!(
+                "Invalid isolation level {:?}, expected read-committed, repeatable-read \
+                 or serializable",
+                value
+            )),
+        }
+    }
+}
+
+/// Builds the connection string for the reporting database.
+///
+/// If a schema other than the default is given, the connection sets its
+/// `search_path` to that schema, so the reporting tables are read from and
+/// written to it. If a statement timeout is given, the database aborts any
+/// statement that runs longer, failing the block rather than hanging on it. If an
+/// isolation level is given, it becomes the default of every transaction on the
+/// connection, including the one each block is written in.
+/// ```
+/// # Errors
+/// Returns an error if the schema is not a valid unquoted Postgres identifier
+/// ```
+pub fn build_dsn(
+    user: &str,
+    password: &str,
+    host: &str,
+    port: &str,
+    name: &str,
+    options: &ConnectionOptions,
+) -> Result<String, String> {
+    let dsn = format!("{}:{}@{}:{}/{}", user, password, host, port, name);
+    let mut settings = Vec::new();
+    if options.schema != DEFAULT_SCHEMA {
+        validate_schema(options.schema)?;
+        settings.push(format!("-c%20search_path%3D{}", options.schema));
+    }
+    if let Some(timeout) = options.statement_timeout {
+        settings.push(format!(
+            "-c%20statement_timeout%3D{}",
+            timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis())
+        ));
+    }
+    if let Some(isolation) = options.isolation {
+        // Spaces within an option value are escaped with a backslash
+        settings.push(format!(
+            "-c%20default_transaction_isolation%3D{}",
+            isolation.as_sql().replace(' ', "%5C%20")
+        ));
+    }
+    if settings.is_empty() {
+        Ok(dsn)
+    } else {
+        Ok(format!("{}?options={}", dsn, settings.join("%20")))
+    }
+}
+
+/// Checks that a schema name is a valid unquoted Postgres identifier, so it
+/// can be placed in the connection options without escaping
+fn validate_schema(schema: &str) -> Result<(), String> {
+    let mut chars = schema.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            (first.is_ascii_lowercase() || first == '_')
+                && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        }
+        None => false,
+    };
+    if valid && schema.len() <= 63 {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid database schema {:?}, expected a lowercase identifier",
+            schema
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the default options leave the connection string unchanged
+    fn test_build_dsn_default_options() {
+        assert_eq!(
+            build_dsn(
+                "user",
+                "pass",
+                "localhost",
+                "5432",
+                "db",
+                &ConnectionOptions::default()
+            ),
+            Ok("user:pass@localhost:5432/db".to_string())
+        );
+    }
+
+    #[test]
+    /// Test that a custom schema sets the search_path of the connection
+    fn test_build_dsn_custom_schema() {
+        let options = ConnectionOptions {
+            schema: "tenant_1",
+            ..ConnectionOptions::default()
+        };
+        assert_eq!(
+            build_dsn("user", "pass", "localhost", "5432", "db", &options),
+            Ok("user:pass@localhost:5432/db?options=-c%20search_path%3Dtenant_1".to_string())
+        );
+    }
+
+    #[test]
+    /// Test that a statement timeout is set in milliseconds alongside the schema
+    fn test_build_dsn_statement_timeout() {
+        let options = ConnectionOptions {
+            schema: "tenant_1",
+            statement_timeout: Some(Duration::from_secs(30)),
+            isolation: None,
+        };
+        assert_eq!(
+            build_dsn("user", "pass", "localhost", "5432", "db", &options),
+            Ok(
+                "user:pass@localhost:5432/db?options=-c%20search_path%3Dtenant_1\
+                %20-c%20statement_timeout%3D30000"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    /// Test that an isolation level sets the default of every transaction, with the
+    /// space in its name escaped
+    fn test_build_dsn_isolation() {
+        let options = ConnectionOptions {
+            isolation: Some("repeatable-read".parse().unwrap()),
+            ..ConnectionOptions::default()
+        };
+        assert_eq!(
+            build_dsn("user", "pass", "localhost", "5432", "db", &options),
+            Ok("user:pass@localhost:5432/db?options=\
+                 -c%20default_transaction_isolation%3Drepeatable%5C%20read"
+                .to_string())
+        );
+        assert_eq!(
+            "serializable".parse::<IsolationLevel>(),
+            Ok(IsolationLevel::Serializable)
+        );
+        assert!("snapshot".parse::<IsolationLevel>().is_err());
+    }
+
+    #[test]
+    /// Test that schemas that are not plain identifiers are rejected
+    fn test_build_dsn_invalid_schema() {
+        for schema in ["", "1tenant", "Tenant", "tenant;drop", "tenant,public"].iter() {
+            let options = ConnectionOptions {
+                schema,
+                ..ConnectionOptions::default()
+            };
+            assert!(build_dsn("user", "pass", "localhost", "5432", "db", &options).is_err());
+        }
+    }
+}
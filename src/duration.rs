@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// Parses a duration given as a whole number followed by an optional unit:
+/// `s` for seconds, `m` for minutes, `h` for hours or `d` for days.
+/// A number without a unit is a number of seconds.
+/// ```
+/// # Errors
+/// Returns an error if the value is not a whole number with a known unit
+/// ```
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid duration {:?}, expected e.g. 90s, 15m or 2h", value))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit {:?} in {:?}, expected s, m, h or d",
+                unit, value
+            ))
+        }
+    };
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Duration {:?} is too large", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that durations with and without units are parsed
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    /// Test that malformed durations are rejected
+    fn test_parse_invalid_duration() {
+        for value in ["", "m", "-5s", "1.5h", "10w", "5 m"].iter() {
+            assert!(
+                parse_duration(value).is_err(),
+                "{:?} should be rejected",
+                value
+            );
+        }
+    }
+}
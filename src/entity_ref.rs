@@ -0,0 +1,115 @@
+use crate::event_handler::address_type_name;
+
+use common::addressing::get_address_type;
+use std::fmt;
+
+/// The number of leading characters of an address shown: the namespace and type infix
+const SHORT_PREFIX_LEN: usize = 8;
+/// The number of trailing characters of an address shown
+const SHORT_SUFFIX_LEN: usize = 8;
+
+/// A readable reference to the entity stored at a state address, for logs and errors.
+///
+/// Addresses are built from a hash of the entity's id, so the id can't be derived
+/// from the address itself. The entity type is read from the address, and the id
+/// is shown when it is known from the state value. Otherwise the address is shown
+/// shortened, e.g. `certificate at 5f8c2a01…9ab3cd12`. The alternate format, `{:#}`,
+/// shows the full address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityRef<'a> {
+    address: &'a str,
+    ids: Vec<String>,
+}
+
+impl<'a> EntityRef<'a> {
+    /// Refers to the entity at the given address, without its id
+    pub fn from_address(address: &'a str) -> EntityRef<'a> {
+        EntityRef {
+            address,
+            ids: vec![],
+        }
+    }
+
+    /// Adds the ids of the entities at the address, as read from its state value.
+    /// Empty ids are left out.
+    pub fn with_ids<I, S>(mut self, ids: I) -> EntityRef<'a>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.ids.extend(
+            ids.into_iter()
+                .map(|id| id.as_ref().to_string())
+                .filter(|id| !id.is_empty()),
+        );
+        self
+    }
+
+    /// Returns the name of the entity type stored at the address
+    pub fn kind(&self) -> &'static str {
+        address_type_name(&get_address_type(self.address))
+    }
+}
+
+impl<'a> fmt::Display for EntityRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.ids.is_empty() {
+            write!(f, "{} {}", self.kind(), self.ids.join(", "))?;
+            if f.alternate() {
+                write!(f, " at {}", self.address)?;
+            }
+            return Ok(());
+        }
+        if f.alternate() {
+            write!(f, "{} at {}", self.kind(), self.address)
+        } else {
+            write!(f, "{} at {}", self.kind(), short_address(self.address))
+        }
+    }
+}
+
+/// Shortens an address to its namespace and type infix and its last characters
+pub fn short_address(address: &str) -> String {
+    if address.len() <= SHORT_PREFIX_LEN + SHORT_SUFFIX_LEN + 1 || !address.is_ascii() {
+        return address.to_string();
+    }
+    format!(
+        "{}…{}",
+        &address[..SHORT_PREFIX_LEN],
+        &address[address.len() - SHORT_SUFFIX_LEN..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::addressing::make_agent_address;
+
+    #[test]
+    /// Test that an address is shortened to its prefix and suffix
+    fn test_short_address() {
+        let address = format!("{}{}", "a".repeat(8), "0".repeat(62));
+        assert_eq!(short_address(&address), "aaaaaaaa…00000000");
+        assert_eq!(short_address("abc"), "abc");
+    }
+
+    #[test]
+    /// Test that the entity is described by its type and id when known, and its
+    /// shortened address otherwise
+    fn test_entity_ref() {
+        let address = make_agent_address("test_public_key");
+        let entity = EntityRef::from_address(&address);
+        assert_eq!(
+            entity.to_string(),
+            format!("agent at {}", short_address(&address))
+        );
+        assert_eq!(format!("{:#}", entity), format!("agent at {}", address));
+
+        let entity = entity.with_ids(vec!["test_public_key", ""]);
+        assert_eq!(entity.to_string(), "agent test_public_key");
+        assert_eq!(
+            format!("{:#}", entity),
+            format!("agent test_public_key at {}", address)
+        );
+    }
+}
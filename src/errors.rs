@@ -1,35 +1,88 @@
 use database::errors::DatabaseError;
+use sawtooth_sdk::messages::client_event::ClientEventsSubscribeResponse_Status;
 use std;
+use std::error::Error;
+
+/// Messages of database errors that are safe to retry: Postgres serialization
+/// failures (SQLSTATE 40001) and deadlocks (40P01), and a locked SQLite database.
+/// The database crate wraps the driver's error, so it is recognized by its message.
+const RETRYABLE_MESSAGES: [&str; 4] = [
+    "could not serialize access",
+    "SerializationFailure",
+    "deadlock detected",
+    "database is locked",
+];
+
+/// Messages of database errors meaning the connection was lost or refused, as
+/// when the database restarts, compared ignoring case. The transaction in flight
+/// was rolled back, so the same transaction can be retried once reconnected.
+const CONNECTION_LOST_MESSAGES: [&str; 9] = [
+    "connection refused",
+    "connection reset",
+    "broken pipe",
+    "server closed the connection",
+    "terminating connection",
+    "the database system is starting up",
+    "the database system is shutting down",
+    "timed out waiting for connection",
+    "error communicating with the server",
+];
 
 #[derive(Debug)]
 pub enum SubscriberError {
+    /// The validator could not be connected to. Errors of the reporting database
+    /// are DBErrors.
     ConnError(String),
+    /// A request could not be sent to the validator
+    SendError(String),
+    /// The validator did not respond in time, the response failed, or its status
+    /// was not OK
+    ResponseError(String),
+    /// The validator refused the event subscription with this status
+    SubscribeRejected(ClientEventsSubscribeResponse_Status, String),
+    /// A response from the validator could not be deserialized
+    DecodeError(String),
     EventParseError(String),
+    /// A block skipped more block numbers after the previous one than allowed, so
+    /// it was not applied and the subscription should resume after the previous one
+    BlockGap(String),
     DBError(DatabaseError),
+    IoError(std::io::Error),
 }
 
 impl std::fmt::Display for SubscriberError {
     #[cfg(not(tarpaulin_include))]
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
-            SubscriberError::ConnError(ref err) => {
+            SubscriberError::ConnError(ref err)
+            | SubscriberError::SendError(ref err)
+            | SubscriberError::ResponseError(ref err)
+            | SubscriberError::SubscribeRejected(_, ref err)
+            | SubscriberError::DecodeError(ref err) => {
                 write!(f, "Error connecting to validator {}", err)
             }
             SubscriberError::EventParseError(ref err) => write!(f, "Error parsing event {}", err),
+            SubscriberError::BlockGap(ref err) => write!(f, "Missed blocks {}", err),
             SubscriberError::DBError(ref err) => {
                 write!(f, "The database returned an error {}", err)
             }
+            SubscriberError::IoError(ref err) => write!(f, "Error writing output {}", err),
         }
     }
 }
 
 impl std::error::Error for SubscriberError {
     #[cfg(not(tarpaulin_include))]
-    fn cause(&self) -> Option<&dyn std::error::Error> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
-            SubscriberError::ConnError(_) => None,
-            SubscriberError::EventParseError(_) => None,
+            SubscriberError::ConnError(_)
+            | SubscriberError::SendError(_)
+            | SubscriberError::ResponseError(_)
+            | SubscriberError::SubscribeRejected(..)
+            | SubscriberError::DecodeError(_) => None,
+            SubscriberError::EventParseError(_) | SubscriberError::BlockGap(_) => None,
             SubscriberError::DBError(ref err) => Some(err),
+            SubscriberError::IoError(ref err) => Some(err),
         }
     }
 }
@@ -38,11 +91,81 @@ impl From<SubscriberError> for String {
     #[cfg(not(tarpaulin_include))]
     fn from(err: SubscriberError) -> String {
         match err {
-            SubscriberError::ConnError(ref err) => format!("Error connecting to validator {}", err),
+            SubscriberError::ConnError(ref err)
+            | SubscriberError::SendError(ref err)
+            | SubscriberError::ResponseError(ref err)
+            | SubscriberError::SubscribeRejected(_, ref err)
+            | SubscriberError::DecodeError(ref err) => {
+                format!("Error connecting to validator {}", err)
+            }
             SubscriberError::EventParseError(ref err) => format!("Error parsing event {}", err),
+            SubscriberError::BlockGap(ref err) => format!("Missed blocks {}", err),
             SubscriberError::DBError(ref err) => format!("Error parsing event {}", err),
+            SubscriberError::IoError(ref err) => format!("Error writing output {}", err),
+        }
+    }
+}
+
+impl SubscriberError {
+    /// Returns true if the error is a transient database error, after which the
+    /// same transaction can be retried. Every other error is fatal for the block.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            SubscriberError::DBError(ref err) => is_retryable_database_error(err),
+            _ => false,
+        }
+    }
+
+    /// Returns true if a request to the validator failed in sending or awaiting
+    /// its response, so the same request can be tried again. A rejected
+    /// subscription or an undecodable response would fail again, so is fatal.
+    pub fn is_retryable_request(&self) -> bool {
+        match *self {
+            SubscriberError::SendError(_) | SubscriberError::ResponseError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the error is in the block's events, or the database refused
+    /// the block after any retries, so applying the same block again would fail
+    /// again. A lost database connection, missed blocks and output errors are not
+    /// caused by the block.
+    pub fn is_poison(&self) -> bool {
+        match *self {
+            SubscriberError::DecodeError(_) | SubscriberError::EventParseError(_) => true,
+            SubscriberError::DBError(ref err) => !is_connection_lost_database_error(err),
+            _ => false,
+        }
+    }
+}
+
+/// Returns true if the database error, or any error it was caused by, is a
+/// serialization failure or deadlock that is safe to retry
+pub fn is_retryable_database_error(err: &DatabaseError) -> bool {
+    error_chain_contains(err, &RETRYABLE_MESSAGES)
+}
+
+/// Returns true if the database error, or any error it was caused by, means the
+/// connection to the database was lost or refused
+pub fn is_connection_lost_database_error(err: &DatabaseError) -> bool {
+    error_chain_contains(err, &CONNECTION_LOST_MESSAGES)
+}
+
+/// Returns true if the message of the error or of any error it was caused by
+/// contains one of the messages, ignoring case
+fn error_chain_contains(err: &DatabaseError, messages: &[&str]) -> bool {
+    let mut current: Option<&(dyn Error + 'static)> = Some(err as &(dyn Error + 'static));
+    while let Some(cause) = current {
+        let message = cause.to_string().to_lowercase();
+        if messages
+            .iter()
+            .any(|expected| message.contains(&expected.to_lowercase()))
+        {
+            return true;
         }
+        current = cause.source();
     }
+    false
 }
 
 impl From<DatabaseError> for SubscriberError {
@@ -51,3 +174,119 @@ impl From<DatabaseError> for SubscriberError {
         SubscriberError::DBError(err)
     }
 }
+
+impl From<std::io::Error> for SubscriberError {
+    #[cfg(not(tarpaulin_include))]
+    fn from(err: std::io::Error) -> SubscriberError {
+        SubscriberError::IoError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the source chain of a DBError leads to the underlying DatabaseError
+    fn test_db_error_source_chain() {
+        let err = SubscriberError::from(DatabaseError::ConnectionError(Box::new(
+            SubscriberError::ConnError("test".to_string()),
+        )));
+        let source = err.source().expect("DBError should have a source");
+        assert!(source.is::<DatabaseError>());
+
+        let mut chain = vec![err.to_string()];
+        let mut current: Option<&(dyn Error + 'static)> = err.source();
+        while let Some(cause) = current {
+            chain.push(cause.to_string());
+            current = cause.source();
+        }
+        assert!(chain.len() >= 2);
+        assert_eq!(chain[1], source.to_string());
+    }
+
+    #[test]
+    /// Test that errors not caused by another error have no source
+    fn test_leaf_errors_have_no_source() {
+        assert!(SubscriberError::ConnError("test".to_string())
+            .source()
+            .is_none());
+        assert!(SubscriberError::EventParseError("test".to_string())
+            .source()
+            .is_none());
+    }
+
+    #[test]
+    /// Test that send and response errors are retryable requests, and a rejected
+    /// subscription or undecodable response is not, keeping the Display message
+    fn test_request_errors() {
+        assert!(SubscriberError::SendError("test".to_string()).is_retryable_request());
+        assert!(SubscriberError::ResponseError("test".to_string()).is_retryable_request());
+        let rejected = SubscriberError::SubscribeRejected(
+            ClientEventsSubscribeResponse_Status::INVALID_FILTER,
+            "refused".to_string(),
+        );
+        assert!(!rejected.is_retryable_request());
+        assert_eq!(
+            rejected.to_string(),
+            "Error connecting to validator refused"
+        );
+        assert!(!SubscriberError::DecodeError("test".to_string()).is_retryable_request());
+    }
+
+    #[test]
+    /// Test that serialization failures and deadlocks are retryable, and other
+    /// errors are not
+    fn test_is_retryable() {
+        let database_error = |message: &str| {
+            DatabaseError::ConnectionError(Box::new(SubscriberError::ConnError(
+                message.to_string(),
+            )))
+        };
+        assert!(is_retryable_database_error(&database_error(
+            "could not serialize access due to concurrent update"
+        )));
+        assert!(SubscriberError::from(database_error("deadlock detected")).is_retryable());
+        assert!(!SubscriberError::from(database_error("relation does not exist")).is_retryable());
+        assert!(!SubscriberError::EventParseError("deadlock detected".to_string()).is_retryable());
+    }
+
+    #[test]
+    /// Test that refused and dropped connections are recognized as lost, and
+    /// serialization failures are not
+    fn test_is_connection_lost() {
+        let database_error = |message: &str| {
+            DatabaseError::ConnectionError(Box::new(SubscriberError::ConnError(
+                message.to_string(),
+            )))
+        };
+        assert!(is_connection_lost_database_error(&database_error(
+            "could not connect to server: Connection refused"
+        )));
+        assert!(is_connection_lost_database_error(&database_error(
+            "FATAL: terminating connection due to administrator command"
+        )));
+        assert!(!is_connection_lost_database_error(&database_error(
+            "could not serialize access due to concurrent update"
+        )));
+    }
+
+    #[test]
+    /// Test that parse errors and refused blocks are poison, and lost connections,
+    /// missed blocks and output errors are not
+    fn test_is_poison() {
+        let database_error = |message: &str| {
+            SubscriberError::from(DatabaseError::ConnectionError(Box::new(
+                SubscriberError::ConnError(message.to_string()),
+            )))
+        };
+        assert!(SubscriberError::EventParseError("test".to_string()).is_poison());
+        assert!(database_error("value too long for type character varying(256)").is_poison());
+        assert!(!database_error("could not connect to server: Connection refused").is_poison());
+        assert!(!SubscriberError::BlockGap("test".to_string()).is_poison());
+        assert!(
+            !SubscriberError::IoError(std::io::Error::new(std::io::ErrorKind::Other, "test"))
+                .is_poison()
+        );
+    }
+}
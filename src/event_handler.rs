@@ -1,45 +1,660 @@
+use crate::address_types::AddressTypes;
+use crate::audit::{self, AuditLog, Redaction};
+use crate::commit::{CommitInterval, PendingBlocks};
 use crate::containerize;
-use crate::errors::SubscriberError;
+use crate::entity_ref::EntityRef;
+use crate::errors::{
+    is_connection_lost_database_error, is_retryable_database_error, SubscriberError,
+};
+use crate::event_types::EventTypes;
+use crate::histogram::LatencyHistogram;
+use crate::integrity::{
+    CheckMode, OrganizationIds, ReferenceUpdate, StandardVersions, VersionUpdate,
+};
+use crate::limits::EventLimits;
+use crate::resume_file::ResumeFile;
+use crate::retry::RetryPolicy;
+use crate::sequence::{BlockSequence, BlockSequenceCheck};
+use crate::shard::Shard;
+use crate::store::BlockStore;
+use crate::strict::Strictness;
 use crate::transformer::{Container, FromStateAtBlock};
+use crate::transitions::RequestTransitions;
+use crate::verify::{Verifier, VerifyStats};
+use crate::webhook::Webhook;
 
-use common::addressing::{get_address_type, get_family_namespace_prefix, AddressSpace};
+use common::addressing::{get_address_type, AddressSpace};
 use common::proto::{agent, assertion, certificate, organization, request, standard};
 use database::{
     custom_types::*,
-    data_manager::{DataManager, OperationType, MAX_BLOCK_NUM},
+    data_manager::{OperationType, MAX_BLOCK_NUM},
     models::*,
 };
+use flate2::read::{GzDecoder, ZlibDecoder};
 use protobuf::Message;
 use regex::Regex;
-use sawtooth_sdk::messages::events::{Event, EventList, Event_Attribute};
+use sawtooth_sdk::messages::events::{Event, EventList};
 use sawtooth_sdk::messages::transaction_receipt::{StateChange, StateChangeList, StateChange_Type};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZLIB_DEFLATE_METHOD: u8 = 0x08;
+/// The longest string value logged in full by --verbose-sql
+const VERBOSE_SQL_MAX_LEN: usize = 64;
+
+/// A function called with each block and its operations once they are committed.
+/// An error is logged, as the block is already committed.
+pub type BlockAppliedHook = Box<dyn FnMut(&Block, &[OperationType]) -> Result<(), String>>;
+
+/// The outcome of handling one message of events from the validator
+pub struct BlockReport {
+    /// The committed block, or None if the events were an empty heartbeat. When
+    /// blocks are coalesced, the block may still be pending commit.
+    pub block: Option<Block>,
+    /// The number of operations applied to the reporting database for the block
+    pub operations_applied: usize,
+    /// How the block's number follows the previously committed block
+    pub sequence: BlockSequence,
+    /// True if the block failed to apply and was written to the poison block
+    /// dead-letter log instead
+    pub skipped: bool,
+}
+
+impl BlockReport {
+    /// Returns true if the events were a heartbeat rather than a committed block
+    pub fn is_heartbeat(&self) -> bool {
+        self.block.is_none() && !self.skipped
+    }
+
+    /// Returns true if a block was committed without any Certificate Registry state changes
+    pub fn is_empty_block(&self) -> bool {
+        self.block.is_some() && self.operations_applied == 0
+    }
+}
+
+/// Options controlling how the event handler parses and applies events
+#[derive(Default)]
+pub struct EventHandlerOptions {
+    /// Only apply state changes at addresses in this shard of the namespace
+    pub shard: Option<Shard>,
+    /// Only apply state changes at addresses of these types
+    pub address_types: AddressTypes,
+    /// The types of the events to parse
+    pub event_types: EventTypes,
+    /// Append every applied operation to this audit log
+    pub audit_log: Option<AuditLog>,
+    /// Fail the block if a request status moves backwards, instead of logging a warning
+    pub strict_transitions: bool,
+    /// Write every applied operation to stdout as newline-delimited JSON
+    pub stdout_ndjson: bool,
+    /// The fields masked in the records of operations written anywhere other than
+    /// the reporting database
+    pub redaction: Redaction,
+    /// Which state changes fail the block while parsing, instead of being skipped
+    /// or partly dropped with a warning
+    pub strict: Strictness,
+    /// Log every operation handed to the database layer at debug level
+    pub verbose_sql: bool,
+    /// Send the records of selected operations to this webhook
+    pub webhook: Option<Webhook>,
+    /// How to handle certificates referencing a standard version missing from reporting
+    pub standard_version_check: CheckMode,
+    /// The standard versions already in the reporting database
+    pub standard_versions: StandardVersions,
+    /// How to handle standards and accreditations referencing an organization
+    /// missing from reporting
+    pub organization_reference_check: CheckMode,
+    /// The organizations already in the reporting database
+    pub organization_ids: OrganizationIds,
+    /// Bounds on the size of a single event list
+    pub limits: EventLimits,
+    /// How many blocks to coalesce into one database transaction
+    pub commit_interval: CommitInterval,
+    /// How to retry commits failing with a serialization failure or deadlock
+    pub retry_policy: RetryPolicy,
+    /// Record the last committed block in this file
+    pub resume_file: Option<ResumeFile>,
+    /// Compare each block with the reporting database with this verifier instead
+    /// of writing it
+    pub verifier: Option<Verifier>,
+    /// Refuse a block that skips more than this many block numbers after the
+    /// previous one, so the subscription can resume after the previous block
+    pub max_block_gap: Option<i64>,
+    /// Skip blocks that fail to apply with a poison error, writing each one's raw
+    /// events and error to this dead-letter log, instead of failing. Blocks must be
+    /// committed one at a time, as a failed batch can't be told apart by block.
+    pub poison_dead_letter: Option<AuditLog>,
+    /// Log a warning for each block taking longer than this from receiving its
+    /// events to committing it
+    pub latency_warning: Option<Duration>,
+}
 
 /// Given a connection to the reporting database, it parses the event data received from the
 /// subscriber and adds that data to reporting DB.
 pub struct EventHandler {
-    data_manager: DataManager,
+    data_manager: Box<dyn BlockStore>,
+    namespace_regex: Regex,
+    event_types: EventTypes,
+    transitions: RequestTransitions,
+    sequence: BlockSequenceCheck,
+    max_block_gap: Option<i64>,
+    audit_log: Option<AuditLog>,
+    strict_transitions: bool,
+    stdout_ndjson: bool,
+    redaction: Redaction,
+    strict: Strictness,
+    verbose_sql: bool,
+    webhook: Option<Webhook>,
+    standard_version_check: CheckMode,
+    standard_versions: StandardVersions,
+    organization_reference_check: CheckMode,
+    organization_ids: OrganizationIds,
+    limits: EventLimits,
+    commit_interval: CommitInterval,
+    pending: PendingBlocks,
+    retry_policy: RetryPolicy,
+    db_retries: u64,
+    resume_file: Option<ResumeFile>,
+    verifier: Option<Verifier>,
+    poison_dead_letter: Option<AuditLog>,
+    latency_warning: Option<Duration>,
+    block_latency: LatencyHistogram,
+    block_applied_hooks: Vec<BlockAppliedHook>,
 }
 
 impl EventHandler {
-    pub fn new(data_manager: DataManager) -> EventHandler {
-        EventHandler { data_manager }
+    /// Creates an event handler that applies parsed events to the reporting database
+    pub fn new(data_manager: Box<dyn BlockStore>, options: EventHandlerOptions) -> EventHandler {
+        EventHandler {
+            data_manager,
+            namespace_regex: Self::address_regex(options.shard, &options.address_types),
+            event_types: options.event_types,
+            transitions: RequestTransitions::default(),
+            sequence: BlockSequenceCheck::default(),
+            max_block_gap: options.max_block_gap,
+            audit_log: options.audit_log,
+            strict_transitions: options.strict_transitions,
+            stdout_ndjson: options.stdout_ndjson,
+            redaction: options.redaction,
+            strict: options.strict,
+            verbose_sql: options.verbose_sql,
+            webhook: options.webhook,
+            standard_version_check: options.standard_version_check,
+            standard_versions: options.standard_versions,
+            organization_reference_check: options.organization_reference_check,
+            organization_ids: options.organization_ids,
+            limits: options.limits,
+            commit_interval: options.commit_interval,
+            pending: PendingBlocks::default(),
+            retry_policy: options.retry_policy,
+            resume_file: options.resume_file,
+            verifier: options.verifier,
+            poison_dead_letter: options.poison_dead_letter,
+            latency_warning: options.latency_warning,
+            block_latency: LatencyHistogram::default(),
+            db_retries: 0,
+            block_applied_hooks: vec![],
+        }
+    }
+
+    /// Registers a function to call after each block is committed, in the order
+    /// registered, with the block and its operations. It runs after the block's
+    /// transaction, so it can't roll the block back, and its errors are logged.
+    /// Blocks that are only verified are not applied, so it isn't called for them.
+    pub fn on_block_applied(&mut self, hook: BlockAppliedHook) {
+        self.block_applied_hooks.push(hook);
     }
 
-    pub fn handle_events(&self, data: &[u8]) -> Result<(), SubscriberError> {
-        let (block, operations) = self.parse_events(data)?;
+    /// Parses the event data and submits it to the reporting database, or adds it
+    /// to the pending blocks if blocks are coalesced and the batch isn't due.
+    /// Returns a report of the block that was committed and the number of
+    /// operations applied for it, or of the empty heartbeat that was skipped.
+    /// If poison blocks are skipped, a block failing with a poison error is written
+    /// to the dead-letter log and reported as skipped.
+    pub fn handle_events(&mut self, data: &[u8]) -> Result<BlockReport, SubscriberError> {
+        self.handle_received_events(data, Instant::now())
+    }
+
+    /// Handles the event data as handle_events does, given when it was received,
+    /// from which the block's latency is measured once it is committed
+    pub fn handle_received_events(
+        &mut self,
+        data: &[u8],
+        received: Instant,
+    ) -> Result<BlockReport, SubscriberError> {
+        match self.apply_events(data, received) {
+            Err(ref err) if self.poison_dead_letter.is_some() && err.is_poison() => {
+                self.skip_poison_block(data, err)
+            }
+            result => result,
+        }
+    }
+
+    /// Writes a block that failed to apply to the dead-letter log, with its raw
+    /// events hex encoded so they can be replayed, and records its number so the
+    /// next block doesn't count as a gap. The block is read from the events if it
+    /// can be, and is null in the record otherwise.
+    /// ```
+    /// # Errors
+    /// Returns an error if the record cannot be written
+    /// ```
+    fn skip_poison_block(
+        &mut self,
+        data: &[u8],
+        err: &SubscriberError,
+    ) -> Result<BlockReport, SubscriberError> {
+        let block = Self::unpack_data::<EventList>(data, &self.limits)
+            .and_then(|list| Self::parse_block(list.get_events(), &self.event_types.block_commit))
+            .ok();
+        let record = poison_record(block.as_ref(), data, err);
+        if let Some(ref mut dead_letter) = self.poison_dead_letter {
+            dead_letter.append(&[record])?;
+        }
+        match block {
+            Some(ref block) => {
+                error!(
+                    "Skipped block {} {}, written to the dead-letter log: {}",
+                    block.block_num, block.block_id, err
+                );
+                self.sequence.record(block.block_num);
+            }
+            None => error!(
+                "Skipped an event list of an unknown block, written to the dead-letter log: {}",
+                err
+            ),
+        }
+        Ok(BlockReport {
+            block: None,
+            operations_applied: 0,
+            sequence: BlockSequence::default(),
+            skipped: true,
+        })
+    }
+
+    /// Parses the event data and submits it to the reporting database, as
+    /// handle_events does without skipping poison blocks
+    fn apply_events(
+        &mut self,
+        data: &[u8],
+        received: Instant,
+    ) -> Result<BlockReport, SubscriberError> {
+        let (block, operations) = Self::parse_events(
+            data,
+            &self.namespace_regex,
+            &self.event_types,
+            &self.strict,
+            &self.limits,
+        )?;
         // Handle empty event from sawtooth-settings-tp heartbeat pings
         if block.block_id == "" && operations.is_empty() {
-            return Ok::<(), SubscriberError>(());
+            return Ok::<BlockReport, SubscriberError>(BlockReport {
+                block: None,
+                operations_applied: 0,
+                sequence: BlockSequence::default(),
+                skipped: false,
+            });
+        }
+        let operations_applied = operations.len();
+        let sequence = self.sequence.check(block.block_num);
+        if let (BlockSequence::Gap { previous, missing }, Some(max)) =
+            (sequence, self.max_block_gap)
+        {
+            if missing > max {
+                return Err(SubscriberError::BlockGap(format!(
+                    "{} blocks between block {} and block {} {}, more than the {} allowed",
+                    missing, previous, block.block_num, block.block_id, max
+                )));
+            }
+        }
+        let transition_update = self.transitions.check(&operations);
+        for transition in &transition_update.illegal {
+            let message = format!(
+                "Request {} moved from {} to {} at block {}",
+                transition.request_id, transition.from, transition.to, block.block_num
+            );
+            if self.strict_transitions {
+                return Err(SubscriberError::EventParseError(message));
+            }
+            warn!("{}", message);
+        }
+        let version_update = match self.standard_version_check {
+            CheckMode::Off => VersionUpdate::default(),
+            _ => self.standard_versions.check(&operations),
+        };
+        for missing in &version_update.missing {
+            let message = missing.describe(block.block_num);
+            if self.standard_version_check == CheckMode::Error {
+                return Err(SubscriberError::EventParseError(message));
+            }
+            warn!("{}", message);
+        }
+        let reference_update = match self.organization_reference_check {
+            CheckMode::Off => ReferenceUpdate::default(),
+            _ => self.organization_ids.check(&operations),
+        };
+        for dangling in &reference_update.dangling {
+            let message = dangling.describe(block.block_num);
+            if self.organization_reference_check == CheckMode::Error {
+                return Err(SubscriberError::EventParseError(message));
+            }
+            warn!("{}", message);
+        }
+        if let Some(ref mut verifier) = self.verifier {
+            let block = Block {
+                block_num: block.block_num,
+                block_id: block.block_id.clone(),
+            };
+            verifier.verify(block, operations)?;
+        } else {
+            let audit_records = self.audit_records(&block, &operations);
+            self.pending
+                .push(operations, &block, audit_records, received);
+            self.flush_if_due()?;
+        }
+        self.transitions.record(transition_update);
+        self.standard_versions.record(version_update);
+        self.organization_ids.record(reference_update);
+        match sequence {
+            BlockSequence::Gap { .. } | BlockSequence::Reorg { .. } => {
+                warn!("Block {} is {}", block.block_num, sequence)
+            }
+            _ => (),
+        }
+        self.sequence.record(block.block_num);
+        Ok(BlockReport {
+            block: Some(block),
+            operations_applied,
+            sequence,
+            skipped: false,
+        })
+    }
+
+    /// Applies the state of every entity, as read from the validator at the given
+    /// block, as that block in one transaction, so the subscription resumes after it.
+    /// Addresses outside the shard and the address types are skipped. Later blocks
+    /// are checked against the entities applied. Returns the number of operations
+    /// applied.
+    /// ```
+    /// # Errors
+    /// Returns an error if a value cannot be parsed, or the block cannot be committed
+    /// or its records written
+    /// ```
+    pub fn apply_snapshot(
+        &mut self,
+        block: Block,
+        entries: Vec<(String, Vec<u8>)>,
+    ) -> Result<usize, SubscriberError> {
+        let mut operations = Vec::new();
+        for (address, value) in &entries {
+            if self.namespace_regex.is_match(address) {
+                operations.push(Self::parse_state(
+                    address,
+                    value,
+                    &block,
+                    &self.strict,
+                    &self.limits,
+                )?);
+            }
+        }
+        let operations_applied = operations.len();
+        let transition_update = self.transitions.check(&operations);
+        let version_update = self.standard_versions.check(&operations);
+        let reference_update = self.organization_ids.check(&operations);
+        let audit_records = self.audit_records(&block, &operations);
+        self.pending
+            .push(operations, &block, audit_records, Instant::now());
+        self.flush()?;
+        self.transitions.record(transition_update);
+        self.standard_versions.record(version_update);
+        self.organization_ids.record(reference_update);
+        self.sequence.record(block.block_num);
+        Ok(operations_applied)
+    }
+
+    /// Returns the records of a block's operations, with the redacted fields masked,
+    /// if any output needs them, logging each one if SQL is logged
+    fn audit_records(&self, block: &Block, operations: &[OperationType]) -> Vec<serde_json::Value> {
+        let audit_records = if self.audit_log.is_some()
+            || self.stdout_ndjson
+            || self.verbose_sql
+            || self.webhook.is_some()
+        {
+            audit::redacted_records(block, operations, &self.redaction)
+        } else {
+            vec![]
+        };
+        if self.verbose_sql {
+            for record in &audit_records {
+                debug!(
+                    "Executing {}",
+                    audit::truncate_strings(record, VERBOSE_SQL_MAX_LEN)
+                );
+            }
+        }
+        audit_records
+    }
+
+    /// Commits the pending blocks if the commit interval is due, returning the number
+    /// of blocks committed. When verifying, it first verifies the blocks the primary
+    /// subscriber has committed since.
+    /// ```
+    /// # Errors
+    /// Returns an error if the blocks cannot be committed or their records written,
+    /// or the reporting database cannot be read to verify blocks
+    /// ```
+    pub fn flush_if_due(&mut self) -> Result<usize, SubscriberError> {
+        if let Some(ref mut verifier) = self.verifier {
+            verifier.verify_waiting()?;
         }
-        self.data_manager
-            .execute_operations_in_block(operations, &block)?;
-        info!("Successfully submitted event data to reporting database");
-        Ok(())
+        if self.pending.is_due(&self.commit_interval, Instant::now()) {
+            self.flush()
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Commits the pending blocks in one transaction, then writes the records of
+    /// their operations to the audit log, stdout and webhook. Returns the number of
+    /// blocks committed.
+    /// ```
+    /// # Errors
+    /// Returns an error if the blocks cannot be committed or their records written
+    /// ```
+    pub fn flush(&mut self) -> Result<usize, SubscriberError> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let (blocks, audit_records, received) = self.pending.take();
+        let committed = blocks.len();
+        let last_block = blocks.last().map(|(_, block)| Block {
+            block_num: block.block_num,
+            block_id: block.block_id.clone(),
+        });
+        self.execute_with_retry(&blocks)?;
+        let committed_at = Instant::now();
+        for ((_, block), received) in blocks.iter().zip(received) {
+            let latency = committed_at.saturating_duration_since(received);
+            self.block_latency.record(latency);
+            if let Some(threshold) = self.latency_warning {
+                if latency > threshold {
+                    warn!(
+                        "Block {} took {:?} from receiving its events to committing it, \
+                         more than {:?}",
+                        block.block_num, latency, threshold
+                    );
+                }
+            }
+        }
+        if committed == 1 {
+            info!("Successfully submitted event data to reporting database");
+        } else {
+            info!(
+                "Successfully submitted event data of {} blocks to reporting database",
+                committed
+            );
+        }
+        if let Some(ref mut audit_log) = self.audit_log {
+            audit_log.append(&audit_records)?;
+        }
+        if self.stdout_ndjson && !audit_records.is_empty() {
+            audit::write_records(&mut io::stdout().lock(), &audit_records)?;
+        }
+        if let Some(ref webhook) = self.webhook {
+            webhook.send(&audit_records);
+        }
+        for (operations, block) in &blocks {
+            for hook in &mut self.block_applied_hooks {
+                if let Err(err) = hook(block, operations) {
+                    warn!(
+                        "Error in a block applied hook for block {}: {}",
+                        block.block_num, err
+                    );
+                }
+            }
+        }
+        if let (Some(resume_file), Some(block)) = (&self.resume_file, last_block) {
+            // The blocks are committed, so failing here would only reapply them
+            if let Err(err) = resume_file.write(&block) {
+                warn!(
+                    "Error writing block {} to the resume file: {}",
+                    block.block_num, err
+                );
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Returns the blocks the reporting database knows now, newest first, including
+    /// any committed by another process since the subscription started. A handler
+    /// verifying blocks returns None, as it must resume after the last block it
+    /// verified rather than the last one the primary subscriber committed.
+    /// ```
+    /// # Errors
+    /// Returns an error if the known blocks cannot be read
+    /// ```
+    pub fn known_blocks(&self) -> Result<Option<Vec<Block>>, SubscriberError> {
+        if self.verifier.is_some() {
+            return Ok(None);
+        }
+        let mut blocks = self.data_manager.fetch_known_blocks()?;
+        blocks.sort_by(|a, b| b.block_num.cmp(&a.block_num));
+        Ok(Some(blocks))
+    }
+
+    /// Returns the number of commits retried after a serialization failure or deadlock
+    pub fn db_retries(&self) -> u64 {
+        self.db_retries
+    }
+
+    /// Returns the histogram of the time from receiving each committed block's
+    /// events to committing it
+    pub fn block_latency(&self) -> &LatencyHistogram {
+        &self.block_latency
     }
 
-    fn parse_events(&self, data: &[u8]) -> Result<(Block, Vec<OperationType>), SubscriberError> {
-        let event_list: EventList = Self::unpack_data(data);
-        let events = event_list.get_events().to_vec();
+    /// Returns the counts of the blocks verified, if the handler verifies blocks
+    /// instead of writing them
+    pub fn verify_stats(&self) -> Option<VerifyStats> {
+        self.verifier
+            .as_ref()
+            .map(|verifier| verifier.stats().clone())
+    }
+
+    /// Commits the given blocks, retrying the commit with a backoff while it fails
+    /// with a retryable error and retries are left. If the database connection is
+    /// lost, it reconnects and retries with a backoff until the reconnect timeout.
+    /// Other errors fail at once.
+    fn execute_with_retry(
+        &mut self,
+        blocks: &[(Vec<OperationType>, Block)],
+    ) -> Result<(), SubscriberError> {
+        let mut retry = 0;
+        let mut reconnects = 0;
+        let mut lost_since: Option<Instant> = None;
+        loop {
+            let attempt = blocks
+                .iter()
+                .map(|(operations, block)| {
+                    (
+                        operations.clone(),
+                        Block {
+                            block_num: block.block_num,
+                            block_id: block.block_id.clone(),
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            let result = if attempt.len() == 1 {
+                let (operations, block) = attempt.into_iter().next().unwrap();
+                self.data_manager
+                    .execute_operations_in_block(operations, &block)
+            } else {
+                self.data_manager.execute_operations_in_blocks(attempt)
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err)
+                    if retry < self.retry_policy.max_retries
+                        && is_retryable_database_error(&err) =>
+                {
+                    retry += 1;
+                    self.db_retries += 1;
+                    let backoff = self.retry_policy.backoff(retry);
+                    warn!(
+                        "Retrying commit in {:?} ({} of {}) after a retryable error: {}",
+                        backoff, retry, self.retry_policy.max_retries, err
+                    );
+                    thread::sleep(backoff);
+                }
+                Err(err) if is_connection_lost_database_error(&err) => {
+                    let timeout = match self.retry_policy.reconnect_timeout {
+                        Some(timeout) => timeout,
+                        None => return Err(err.into()),
+                    };
+                    let lost_since = *lost_since.get_or_insert_with(Instant::now);
+                    if lost_since.elapsed() >= timeout {
+                        error!("Could not reconnect to the database within {:?}", timeout);
+                        return Err(err.into());
+                    }
+                    reconnects += 1;
+                    self.db_retries += 1;
+                    let backoff = self.retry_policy.reconnect_backoff(reconnects);
+                    warn!(
+                        "Lost the database connection, reconnecting in {:?} (attempt {}): {}",
+                        backoff, reconnects, err
+                    );
+                    thread::sleep(backoff);
+                    if let Err(err) = self.data_manager.reconnect() {
+                        warn!("Error reconnecting to the database: {}", err);
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Parses the event data received from the validator into the block it belongs to
+    /// and the operations to apply for the state changes matching the namespace regex.
+    /// Only events of the given event types are parsed. The strictness sets which
+    /// state changes are an error instead of a warning.
+    /// ```
+    /// # Errors
+    /// Returns an error if the event data is malformed or exceeds the limits
+    /// ```
+    pub fn parse_events(
+        data: &[u8],
+        namespace_regex: &Regex,
+        event_types: &EventTypes,
+        strict: &Strictness,
+        limits: &EventLimits,
+    ) -> Result<(Block, Vec<OperationType>), SubscriberError> {
+        let event_list: EventList = Self::unpack_data(data, limits)?;
+        let events = event_list.get_events();
+        EventLimits::check("events", events.len(), limits.max_events)?;
         // Handle empty event from sawtooth-settings-tp heartbeat pings
         if events.is_empty() {
             return Ok::<(Block, Vec<OperationType>), SubscriberError>((
@@ -50,78 +665,275 @@ impl EventHandler {
                 Vec::<OperationType>::new(),
             ));
         }
-        let block = self.parse_block(&events)?;
-        let state_changes = self.parse_state_delta_events(&events)?;
+        let block = Self::parse_block(events, &event_types.block_commit)?;
+        let state_changes = Self::parse_state_delta_events(
+            events,
+            &event_types.state_delta,
+            namespace_regex,
+            limits,
+        )?;
         let mut operations = Vec::<OperationType>::new();
         for change in state_changes {
-            operations.push(self.parse_operation(&change, &block)?);
+            if change.get_field_type() == StateChange_Type::DELETE {
+                if let Some(operation) = Self::parse_delete(&change, &block, strict.deletes)? {
+                    operations.push(operation);
+                }
+            } else {
+                operations.push(Self::parse_operation(&change, &block, strict, limits)?);
+            }
         }
         Ok((block, operations))
     }
 
-    fn parse_block(&self, events: &[Event]) -> Result<Block, SubscriberError> {
-        events
-            .iter()
-            .filter(|e| e.get_event_type() == "sawtooth/block-commit")
-            .map(|block_commit_event| {
-                let block_num: Vec<Event_Attribute> = block_commit_event
-                    .get_attributes()
-                    .to_vec()
-                    .into_iter()
-                    .filter(|a| a.get_key() == "block_num")
-                    .collect();
-                let block_id: Vec<Event_Attribute> = block_commit_event
-                    .get_attributes()
-                    .to_vec()
-                    .into_iter()
-                    .filter(|a| a.get_key() == "block_id")
-                    .collect();
+    /// Parses an entity's state, as read from the validator at the given block, into
+    /// the operation that applying it at that block would write
+    /// ```
+    /// # Errors
+    /// Returns an error if the address is not part of the Certificate Registry
+    /// namespace or the value cannot be deserialized
+    /// ```
+    pub fn parse_state(
+        address: &str,
+        value: &[u8],
+        block: &Block,
+        strict: &Strictness,
+        limits: &EventLimits,
+    ) -> Result<OperationType, SubscriberError> {
+        let mut change = StateChange::new();
+        change.set_address(address.to_string());
+        change.set_value(value.to_vec());
+        change.set_field_type(StateChange_Type::SET);
+        Self::parse_operation(&change, block, strict, limits)
+    }
 
-                Ok(Block {
-                    block_num: block_num[0]
-                        .get_value()
-                        .parse::<i64>()
-                        .map_err(|err| SubscriberError::EventParseError(err.to_string()))?,
-                    block_id: block_id[0].get_value().to_string(),
-                })
-            })
-            .last()
-            .unwrap_or_else(|| {
-                Err(SubscriberError::EventParseError(
-                    "Could not parse block event".to_string(),
-                ))
-            })
+    /// Reads the block number and id of the block commit event. A list may repeat
+    /// the event for the same block, but state delta events don't name their block,
+    /// so a list committing several blocks is refused rather than having every
+    /// state change attributed to the last block and the earlier blocks dropped.
+    /// ```
+    /// # Errors
+    /// Returns an error if there is no block commit event, if the events are for
+    /// more than one block, or if one is missing either attribute or has an
+    /// invalid block number
+    /// ```
+    fn parse_block(events: &[Event], event_type: &str) -> Result<Block, SubscriberError> {
+        let mut blocks = Vec::<Block>::new();
+        for event in events.iter().filter(|e| e.get_event_type() == event_type) {
+            let block = Self::parse_block_commit(event)?;
+            let seen = blocks
+                .iter()
+                .any(|seen| seen.block_num == block.block_num && seen.block_id == block.block_id);
+            if !seen {
+                blocks.push(block);
+            }
+        }
+        if blocks.len() > 1 {
+            let names: Vec<String> = blocks
+                .iter()
+                .map(|block| format!("{} ({})", block.block_num, block.block_id))
+                .collect();
+            return Err(SubscriberError::EventParseError(format!(
+                "Event list holds block commit events for {} blocks, {}, whose state changes \
+                 can't be told apart",
+                blocks.len(),
+                names.join(", ")
+            )));
+        }
+        blocks.pop().ok_or_else(|| {
+            SubscriberError::EventParseError("Could not parse block event".to_string())
+        })
+    }
+
+    /// Reads the block number and id of one block commit event, scanning its
+    /// attributes once. The first value of each attribute is used.
+    fn parse_block_commit(block_commit_event: &Event) -> Result<Block, SubscriberError> {
+        let mut block_num = None;
+        let mut block_id = None;
+        for attribute in block_commit_event.get_attributes() {
+            match attribute.get_key() {
+                "block_num" if block_num.is_none() => block_num = Some(attribute.get_value()),
+                "block_id" if block_id.is_none() => block_id = Some(attribute.get_value()),
+                _ => (),
+            }
+        }
+        let missing = |key: &str| {
+            SubscriberError::EventParseError(format!(
+                "Block commit event is missing the {} attribute",
+                key
+            ))
+        };
+
+        Ok(Block {
+            block_num: block_num
+                .ok_or_else(|| missing("block_num"))?
+                .parse::<i64>()
+                .map_err(|err| SubscriberError::EventParseError(err.to_string()))
+                .and_then(validate_block_num)?,
+            block_id: block_id
+                .ok_or_else(|| missing("block_id"))
+                .and_then(validate_block_id)?
+                .to_string(),
+        })
     }
 
     fn parse_state_delta_events(
-        &self,
         events: &[Event],
+        event_type: &str,
+        namespace_regex: &Regex,
+        limits: &EventLimits,
     ) -> Result<Vec<StateChange>, SubscriberError> {
-        let namespace_regex = self.get_namespace_regex();
-        Ok(events
-            .iter()
-            .filter(|e| e.get_event_type() == "sawtooth/state-delta")
-            .flat_map(|event| {
-                let mut change_list = Self::unpack_data::<StateChangeList>(event.get_data());
+        let mut state_changes = Vec::new();
+        for event in events.iter().filter(|e| e.get_event_type() == event_type) {
+            let mut change_list: StateChangeList = Self::unpack_data(event.get_data(), limits)?;
+            state_changes.extend(
                 change_list
                     .take_state_changes()
                     .into_iter()
-                    .filter(|state_change| (namespace_regex.is_match(state_change.get_address())))
-            })
-            .collect())
+                    .filter(|state_change| namespace_regex.is_match(state_change.get_address())),
+            );
+            EventLimits::check(
+                "state changes",
+                state_changes.len(),
+                limits.max_state_changes,
+            )?;
+        }
+        Ok(Self::net_state_changes(state_changes))
+    }
+
+    /// Collapses the state changes of a block to the last change of each address,
+    /// in the order of those last changes. The validator sends one change per
+    /// address, but a list repeating state delta events may hold several, e.g. a
+    /// set then a delete. Applying both would write rows the block didn't leave,
+    /// so an address set then deleted is only deleted, and one deleted then set
+    /// is only set, opening its rows at the block.
+    fn net_state_changes(state_changes: Vec<StateChange>) -> Vec<StateChange> {
+        let last_changes: HashMap<String, usize> = state_changes
+            .iter()
+            .enumerate()
+            .map(|(index, change)| (change.get_address().to_string(), index))
+            .collect();
+        let collapsed = state_changes.len() - last_changes.len();
+        if collapsed > 0 {
+            debug!(
+                "Collapsed {} state changes to an address changed again in the block",
+                collapsed
+            );
+        }
+        state_changes
+            .into_iter()
+            .enumerate()
+            .filter(|(index, change)| last_changes[change.get_address()] == *index)
+            .map(|(_, change)| change)
+            .collect()
     }
 
-    fn get_namespace_regex(&self) -> Regex {
-        let namespace = get_family_namespace_prefix();
-        Regex::new(&format!(r"^{}", namespace)).unwrap()
+    /// Returns a regex matching the addresses in the Certificate Registry Namespace,
+    /// narrowed to the given shard if there is one
+    pub fn namespace_regex(shard: Option<Shard>) -> Regex {
+        Self::address_regex(shard, &AddressTypes::default())
     }
 
-    /// Deserializes binary data to a protobuf message
-    fn unpack_data<T>(data: &[u8]) -> T
+    /// Returns a regex matching the addresses of the given types in the Certificate
+    /// Registry Namespace, narrowed to the given shard if there is one
+    pub fn address_regex(shard: Option<Shard>, address_types: &AddressTypes) -> Regex {
+        Regex::new(&address_types.address_regexes(shard).join("|")).unwrap()
+    }
+
+    /// Deserializes binary data to a protobuf message, decompressing it first
+    /// if it is gzip or zlib compressed. The data is validator-supplied, so its
+    /// size and the nesting of the messages in it are bounded by the limits.
+    /// ```
+    /// # Errors
+    /// Returns an error if the data is not a valid protobuf message of type T, is
+    /// larger or inflates to more than the largest message, or nests messages
+    /// deeper than the decode depth
+    /// ```
+    fn unpack_data<T>(data: &[u8], limits: &EventLimits) -> Result<T, SubscriberError>
     where
         T: protobuf::Message,
     {
-        Message::parse_from_bytes(&data).expect("Error parsing protobuf data.")
+        EventLimits::check("bytes", data.len(), limits.max_message_bytes)?;
+        let data = Self::decompress(data, limits.max_message_bytes)?;
+        let parse_error = |err: protobuf::ProtobufError| {
+            SubscriberError::EventParseError(format!("Error parsing protobuf data: {}", err))
+        };
+        let mut input = protobuf::CodedInputStream::from_bytes(data.as_ref());
+        // Each nested message counts towards the limit, the outermost one excepted
+        input.set_recursion_limit(limits.max_decode_depth);
+        let mut message = T::new();
+        message.merge_from(&mut input).map_err(parse_error)?;
+        input.check_eof().map_err(parse_error)?;
+        message.check_initialized().map_err(parse_error)?;
+        Ok(message)
+    }
+
+    /// Deserializes the value of a state change, naming the entity at its address
+    /// if it can't be
+    /// ```
+    /// # Errors
+    /// Returns an error if the value is not a valid protobuf message of type T
+    /// ```
+    fn unpack_state<T>(state: &StateChange, limits: &EventLimits) -> Result<T, SubscriberError>
+    where
+        T: protobuf::Message,
+    {
+        Self::unpack_data(state.get_value(), limits).map_err(|err| match err {
+            SubscriberError::EventParseError(message) => SubscriberError::EventParseError(format!(
+                "{} for {}",
+                message,
+                EntityRef::from_address(state.get_address())
+            )),
+            err => err,
+        })
+    }
+
+    /// Sniffs the header of the given bytes and inflates them if they are
+    /// gzip or zlib compressed. Data that is not compressed, or that fails
+    /// to inflate, is returned unchanged. Inflating stops once the output
+    /// passes max_len bytes, which is an error.
+    fn decompress(data: &[u8], max_len: usize) -> Result<Cow<[u8]>, SubscriberError> {
+        let mut inflated = Vec::new();
+        // One byte past the limit is enough to tell the payload is too large
+        let bound = max_len as u64 + 1;
+        let result = if data.starts_with(&GZIP_MAGIC) {
+            GzDecoder::new(data).take(bound).read_to_end(&mut inflated)
+        } else if Self::is_zlib_header(data) {
+            ZlibDecoder::new(data)
+                .take(bound)
+                .read_to_end(&mut inflated)
+        } else {
+            return Ok(Cow::Borrowed(data));
+        };
+        match result {
+            Ok(_) => {
+                if inflated.len() > max_len {
+                    return Err(SubscriberError::EventParseError(format!(
+                        "Refusing compressed payload of {} bytes that inflates to more than {} \
+                         bytes, raise the limit to accept it",
+                        data.len(),
+                        max_len
+                    )));
+                }
+                debug!(
+                    "Decompressed event payload from {} to {} bytes",
+                    data.len(),
+                    inflated.len()
+                );
+                Ok(Cow::Owned(inflated))
+            }
+            Err(err) => {
+                debug!("Payload is not compressed, parsing as is: {}", err);
+                Ok(Cow::Borrowed(data))
+            }
+        }
+    }
+
+    /// A zlib stream starts with a CMF byte using the deflate method, followed
+    /// by a FLG byte such that CMF * 256 + FLG is a multiple of 31
+    fn is_zlib_header(data: &[u8]) -> bool {
+        data.len() >= 2
+            && data[0] & 0x0f == ZLIB_DEFLATE_METHOD
+            && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
     }
 
     /// Given a state change it deserializes the data to a protobuf message,
@@ -129,69 +941,112 @@ impl EventHandler {
     /// database via the data_manager.
     /// ```
     /// # Errors
-    /// Returns an error if State Change address is not part of the Certificate Registry Namespace
+    /// Returns an error if
+    /// - State Change address is not part of the Certificate Registry Namespace
+    /// - State Change value cannot be deserialized
     /// ```
     fn parse_operation(
-        &self,
         state: &StateChange,
         block: &Block,
+        strict: &Strictness,
+        limits: &EventLimits,
     ) -> Result<OperationType, SubscriberError> {
         let address_type = get_address_type(state.get_address());
         match address_type {
             AddressSpace::Organization => {
                 let org_container: organization::OrganizationContainer =
-                    Self::unpack_data(state.get_value());
+                    Self::unpack_state(state, limits)?;
+                check_full_snapshot(state.get_address(), org_container.values(), |org| {
+                    org.get_id()
+                })?;
+                for org in org_container.get_entries() {
+                    check_required_fields(org, org.get_id(), strict.required_fields)?;
+                    check_enum_field(org, "organization_type", org.get_id(), strict.enums)?;
+                    check_organization_type(org, strict.unset_organization_types)?;
+                    for auth in org.get_authorizations() {
+                        check_enum_field(auth, "role", auth.get_public_key(), strict.enums)?;
+                    }
+                }
 
                 let transaction =
                     OperationType::CreateOrganization(org_container.to_models(block.block_num));
                 Ok(transaction)
             }
             AddressSpace::Agent => {
-                let agent_container: agent::AgentContainer = Self::unpack_data(state.get_value());
+                let agent_container: agent::AgentContainer = Self::unpack_state(state, limits)?;
+                check_full_snapshot(state.get_address(), agent_container.values(), |agent| {
+                    agent.get_public_key()
+                })?;
+                for agent in agent_container.get_entries() {
+                    check_required_fields(agent, agent.get_public_key(), strict.required_fields)?;
+                }
                 let transaction =
                     OperationType::CreateAgent(agent_container.to_models(block.block_num));
                 Ok(transaction)
             }
             AddressSpace::Certificate => {
                 let cert_container: certificate::CertificateContainer =
-                    Self::unpack_data(state.get_value());
+                    Self::unpack_state(state, limits)?;
+                check_full_snapshot(state.get_address(), cert_container.values(), |cert| {
+                    cert.get_id()
+                })?;
                 let transaction =
                     OperationType::CreateCertificate(cert_container.to_models(block.block_num));
                 Ok(transaction)
             }
             AddressSpace::Request => {
                 let request_container: request::RequestContainer =
-                    Self::unpack_data(state.get_value());
+                    Self::unpack_state(state, limits)?;
+                check_full_snapshot(state.get_address(), request_container.values(), |request| {
+                    request.get_id()
+                })?;
+                for request in request_container.get_entries() {
+                    check_enum_field(request, "status", request.get_id(), strict.enums)?;
+                }
                 let transaction =
                     OperationType::CreateRequest(request_container.to_models(block.block_num));
                 Ok(transaction)
             }
             AddressSpace::Standard => {
                 let standard_container: standard::StandardContainer =
-                    Self::unpack_data(state.get_value());
+                    Self::unpack_state(state, limits)?;
+                check_full_snapshot(
+                    state.get_address(),
+                    standard_container.values(),
+                    |standard| standard.get_id(),
+                )?;
+                for standard in standard_container.get_entries() {
+                    check_required_fields(standard, standard.get_id(), strict.required_fields)?;
+                }
                 let transaction =
                     OperationType::CreateStandard(standard_container.to_models(block.block_num));
                 Ok(transaction)
             }
             AddressSpace::Assertion => match state.get_field_type() {
-                StateChange_Type::SET => {
+                StateChange_Type::TYPE_UNSET => Err(SubscriberError::EventParseError(
+                    "StateChange for Assertion had TYPE_UNSET".to_string(),
+                )),
+                _ => {
                     let assertion_container: assertion::AssertionContainer =
-                        Self::unpack_data(state.get_value());
+                        Self::unpack_state(state, limits)?;
+                    check_full_snapshot(
+                        state.get_address(),
+                        assertion_container.values(),
+                        |assertion| assertion.get_id(),
+                    )?;
+                    for assertion in assertion_container.get_entries() {
+                        check_enum_field(
+                            assertion,
+                            "assertion_type",
+                            assertion.get_id(),
+                            strict.enums,
+                        )?;
+                    }
                     let transaction = OperationType::CreateAssertion(
                         assertion_container.to_models(block.block_num),
                     );
                     Ok(transaction)
                 }
-                StateChange_Type::DELETE => {
-                    let transaction = OperationType::DeleteAssertion(
-                        state.get_address().to_string(),
-                        block.block_num,
-                    );
-                    Ok(transaction)
-                }
-                StateChange_Type::TYPE_UNSET => Err(SubscriberError::EventParseError(
-                    "StateChange for Assertion had TYPE_UNSET".to_string(),
-                )),
             },
             AddressSpace::AnotherFamily => Err(SubscriberError::EventParseError(
                 "Address didnt match any existent state data
@@ -200,6 +1055,283 @@ impl EventHandler {
             )),
         }
     }
+
+    /// Given a state change that deletes an address, it derives the type of the
+    /// removed entity from the address alone, since a deleted address has no value
+    /// to decode. The address is used as the key of the rows to close.
+    /// Returns None if the reporting database has no delete operation for the entity type.
+    /// ```
+    /// # Errors
+    /// Returns an error if
+    /// - State Change address is not part of the Certificate Registry Namespace
+    /// - Strict is set and the reporting database has no delete operation for the entity type
+    /// ```
+    fn parse_delete(
+        state: &StateChange,
+        block: &Block,
+        strict: bool,
+    ) -> Result<Option<OperationType>, SubscriberError> {
+        let address = state.get_address();
+        let address_type = get_address_type(address);
+        match address_type {
+            AddressSpace::Assertion => Ok(Some(OperationType::DeleteAssertion(
+                address.to_string(),
+                block.block_num,
+            ))),
+            AddressSpace::AnotherFamily => Err(SubscriberError::EventParseError(format!(
+                "Deleted address {} is not part of the Certificate Registry Namespace",
+                address
+            ))),
+            _ => {
+                let description = format!(
+                    "the reporting database cannot close the deleted {}",
+                    EntityRef::from_address(address)
+                );
+                if strict {
+                    return Err(SubscriberError::EventParseError(format!(
+                        "Refusing block {}: {}",
+                        block.block_num, description
+                    )));
+                }
+                warn!("Skipping deletion, {}", description);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Checks that a block number is a real block, distinct from MAX_BLOCK_NUM.
+///
+/// Every row written by this crate is opened with `end_block_num: MAX_BLOCK_NUM`,
+/// the sentinel the database crate uses to find the rows that are still current.
+/// The constant is imported from the database crate rather than redefined, so the
+/// two can't drift apart. A block at or beyond the sentinel would close rows with
+/// an end block that reads as still open, so it is rejected instead of applied.
+/// ```
+/// # Errors
+/// Returns an error if the block number is negative or not less than MAX_BLOCK_NUM
+/// ```
+pub fn validate_block_num(block_num: i64) -> Result<i64, SubscriberError> {
+    if block_num < 0 || block_num >= MAX_BLOCK_NUM {
+        return Err(SubscriberError::EventParseError(format!(
+            "Block number {} is outside the range of real blocks [0, {})",
+            block_num, MAX_BLOCK_NUM
+        )));
+    }
+    Ok(block_num)
+}
+
+/// Checks that the block id of a block commit event is not empty. Heartbeats are
+/// the only blocks without an id, so a real block with an empty one would be
+/// mistaken for a heartbeat when resuming.
+/// ```
+/// # Errors
+/// Returns an error if the block id is empty or blank
+/// ```
+fn validate_block_id(block_id: &str) -> Result<&str, SubscriberError> {
+    if block_id.trim().is_empty() {
+        return Err(SubscriberError::EventParseError(
+            "Block commit event has an empty block_id attribute".to_string(),
+        ));
+    }
+    Ok(block_id)
+}
+
+/// Builds the dead-letter record of a block that failed to apply: its number and
+/// id, or null if the events didn't name one, the error, and the raw event list as
+/// received, hex encoded so it can be replayed:
+/// ```text
+/// {"block_num":12,"block_id":"...","error":"...","events":"0a2c..."}
+/// ```
+pub fn poison_record(
+    block: Option<&Block>,
+    data: &[u8],
+    err: &SubscriberError,
+) -> serde_json::Value {
+    serde_json::json!({
+        "block_num": block.map(|block| block.block_num),
+        "block_id": block.map(|block| &block.block_id),
+        "error": err.to_string(),
+        "events": data.iter().map(|byte| format!("{:02x}", byte)).collect::<String>(),
+    })
+}
+
+/// Checks that a container decoded from a state change looks like a full snapshot
+/// of the state at its address, rather than a partial or delta update.
+///
+/// The transformers close every row for an address and open rows for the entries
+/// in the container, so they rely on each state change holding every entry at the
+/// address. Sawtooth state deltas always carry the full value, and an address with
+/// no entries is deleted rather than set, so an empty container or an entry
+/// without its id can only come from a protocol change this build doesn't
+/// understand. Such a change is rejected instead of mis-ingested.
+/// ```
+/// # Errors
+/// Returns an error if the container has no entries, or an entry has an empty id
+/// ```
+fn check_full_snapshot<S, F>(address: &str, entries: &[S], id: F) -> Result<(), SubscriberError>
+where
+    F: Fn(&S) -> &str,
+{
+    if entries.is_empty() {
+        return Err(SubscriberError::EventParseError(format!(
+            "The state of {} has no entries, expected a full snapshot",
+            EntityRef::from_address(address)
+        )));
+    }
+    if let Some(index) = entries.iter().position(|entry| id(entry).is_empty()) {
+        return Err(SubscriberError::EventParseError(format!(
+            "Entry {} of the state of {} has no id, expected a full snapshot",
+            index,
+            EntityRef::from_address(address).with_ids(entries.iter().map(&id))
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the raw values of an enum field that this build's protobuf definitions
+/// don't know, such as a variant added by a newer transaction processor.
+/// rust-protobuf leaves such a field at its default, the UNSET variant, and keeps
+/// the raw value with the message's unknown fields.
+pub fn unknown_enum_values<M: Message>(message: &M, field_name: &str) -> Vec<u64> {
+    let field_number = message
+        .descriptor()
+        .field_by_name(field_name)
+        .proto()
+        .get_number() as u32;
+    message
+        .get_unknown_fields()
+        .get(field_number)
+        .map(|values| values.varint.clone())
+        .unwrap_or_default()
+}
+
+/// Checks an enum field of a message for values unknown to this build. The field is
+/// read as unset, so an unknown value is logged as a warning, or is an error if strict.
+/// ```
+/// # Errors
+/// Returns an error if strict is set and the field holds an unknown value
+/// ```
+fn check_enum_field<M: Message>(
+    message: &M,
+    field_name: &str,
+    id: &str,
+    strict: bool,
+) -> Result<(), SubscriberError> {
+    for value in unknown_enum_values(message, field_name) {
+        let description = format!(
+            "The {} {} of {} {} is not a known value",
+            field_name,
+            value,
+            message.descriptor().name(),
+            id
+        );
+        if strict {
+            return Err(SubscriberError::EventParseError(description));
+        }
+        warn!("{}, reading it as unset", description);
+    }
+    Ok(())
+}
+
+/// Whether a string field of a state message must be set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Presence {
+    /// An entity with the field empty is probably invalid
+    Required,
+    /// The field may be empty. If its column is nullable, empty is stored as null.
+    Optional,
+}
+
+/// The presence of the string fields of the state messages, by message and field
+/// name, that check_required_fields checks. Ids are checked for every entity by
+/// check_full_snapshot. Fields not listed are copied as they are.
+pub const STRING_FIELD_PRESENCE: [(&str, &str, Presence); 4] = [
+    ("Agent", "name", Presence::Required),
+    ("Agent", "organization_id", Presence::Optional),
+    ("Organization", "name", Presence::Required),
+    ("Standard", "name", Presence::Required),
+];
+
+/// Checks that the required string fields of an entity, per STRING_FIELD_PRESENCE,
+/// are not empty. The entity is applied with the field empty, so an empty field is
+/// logged as a warning naming the entity, or is an error if strict.
+/// ```
+/// # Errors
+/// Returns an error if strict is set and a required field is empty
+/// ```
+fn check_required_fields<M: Message>(
+    message: &M,
+    id: &str,
+    strict: bool,
+) -> Result<(), SubscriberError> {
+    let descriptor = message.descriptor();
+    for (_, field_name, _) in STRING_FIELD_PRESENCE
+        .iter()
+        .filter(|(name, _, presence)| *name == descriptor.name() && *presence == Presence::Required)
+    {
+        if !descriptor
+            .field_by_name(field_name)
+            .get_str(message)
+            .is_empty()
+        {
+            continue;
+        }
+        let description = format!(
+            "The {} of {} {} is empty, but required",
+            field_name,
+            descriptor.name(),
+            id
+        );
+        if strict {
+            return Err(SubscriberError::EventParseError(description));
+        }
+        warn!("{}, applying it as it is", description);
+    }
+    Ok(())
+}
+
+/// Checks that an organization has a type. An organization stored with UNSET_TYPE
+/// likely comes from a bug in the transaction processor, as it gets no accreditations
+/// or address in the reporting database. It is logged as a warning, or is an error
+/// if strict. Unknown type values, also read as UNSET_TYPE, are left to
+/// check_enum_field.
+/// ```
+/// # Errors
+/// Returns an error if strict is set and the organization type is UNSET_TYPE
+/// ```
+fn check_organization_type(
+    org: &organization::Organization,
+    strict: bool,
+) -> Result<(), SubscriberError> {
+    if org.get_organization_type() != organization::Organization_Type::UNSET_TYPE
+        || !unknown_enum_values(org, "organization_type").is_empty()
+    {
+        return Ok(());
+    }
+    let description = format!(
+        "Organization {} has the UNSET_TYPE organization type, which likely indicates \
+         a bug in the transaction processor",
+        org.get_id()
+    );
+    if strict {
+        return Err(SubscriberError::EventParseError(description));
+    }
+    warn!("{}", description);
+    Ok(())
+}
+
+/// Returns a human-readable name for the type of entity stored in an address space
+pub fn address_type_name(address_type: &AddressSpace) -> &'static str {
+    match address_type {
+        AddressSpace::Organization => "organization",
+        AddressSpace::Agent => "agent",
+        AddressSpace::Certificate => "certificate",
+        AddressSpace::Request => "request",
+        AddressSpace::Standard => "standard",
+        AddressSpace::Assertion => "assertion",
+        AddressSpace::AnotherFamily => "unknown",
+    }
 }
 
 containerize!(
@@ -237,8 +1369,7 @@ impl FromStateAtBlock<organization::Organization>
             organization::Organization_Type::CERTIFYING_BODY => {
                 let accreditations: Vec<NewAccreditation> = org
                     .get_certifying_body_details()
-                    .clone()
-                    .accreditations
+                    .get_accreditations()
                     .iter()
                     .map(|accreditation| NewAccreditation {
                         organization_id: org.id.clone(),
@@ -290,29 +1421,13 @@ impl FromStateAtBlock<organization::Organization>
             organization::Organization_Type::FACTORY => {
                 let address = org
                     .get_factory_details()
-                    .clone()
                     .address
-                    .map(|address| NewAddress {
-                        organization_id: org.id.clone(),
-                        street_line_1: address.get_street_line_1().to_string(),
-                        street_line_2: match address.get_street_line_2() {
-                            "" => None,
-                            _ => Some(address.get_street_line_2().to_string()),
-                        },
-                        city: address.get_city().to_string(),
-                        state_province: match address.get_state_province() {
-                            "" => None,
-                            _ => Some(address.get_state_province().to_string()),
-                        },
-                        country: address.get_country().to_string(),
-                        postal_code: match address.get_postal_code() {
-                            "" => None,
-                            _ => Some(address.get_postal_code().to_string()),
-                        },
-                        start_block_num: block_num,
-                        end_block_num: MAX_BLOCK_NUM,
-                    });
-                Some(address.unwrap())
+                    .as_ref()
+                    .map(|address| factory_address(&org.id, address, block_num));
+                if address.is_none() {
+                    warn!("Factory {} has no address", org.id);
+                }
+                address
             }
             _ => None,
         };
@@ -327,6 +1442,36 @@ impl FromStateAtBlock<organization::Organization>
     }
 }
 
+/// Maps a factory's address to the row stored in the reporting database.
+/// New address fields, such as geo coordinates once the proto has them, only
+/// need to be mapped here.
+fn factory_address(
+    organization_id: &str,
+    address: &organization::Factory_Address,
+    block_num: i64,
+) -> NewAddress {
+    NewAddress {
+        organization_id: organization_id.to_string(),
+        street_line_1: address.get_street_line_1().to_string(),
+        street_line_2: match address.get_street_line_2() {
+            "" => None,
+            _ => Some(address.get_street_line_2().to_string()),
+        },
+        city: address.get_city().to_string(),
+        state_province: match address.get_state_province() {
+            "" => None,
+            _ => Some(address.get_state_province().to_string()),
+        },
+        country: address.get_country().to_string(),
+        postal_code: match address.get_postal_code() {
+            "" => None,
+            _ => Some(address.get_postal_code().to_string()),
+        },
+        start_block_num: block_num,
+        end_block_num: MAX_BLOCK_NUM,
+    }
+}
+
 containerize!(agent::Agent, agent::AgentContainer);
 impl FromStateAtBlock<agent::Agent> for NewAgent {
     fn at_block(block_num: i64, agent: &agent::Agent) -> Self {
@@ -394,9 +1539,15 @@ impl FromStateAtBlock<standard::Standard> for (NewStandard, Vec<NewStandardVersi
             end_block_num: MAX_BLOCK_NUM,
         };
 
-        let db_versions = standard
-            .versions
-            .iter()
+        // Versions are ordered oldest to newest by approval date, so the last
+        // entry is the current version of the standard. The sort is stable,
+        // so versions approved on the same date keep their order in state.
+        let mut versions: Vec<&standard::Standard_StandardVersion> =
+            standard.versions.iter().collect();
+        versions.sort_by_key(|version| version.approval_date);
+
+        let db_versions = versions
+            .into_iter()
             .map(|version| NewStandardVersion {
                 standard_id: standard.id.clone(),
                 version: version.version.clone(),
@@ -436,9 +1587,55 @@ impl FromStateAtBlock<assertion::Assertion> for NewAssertion {
     }
 }
 
+/// Event lists shared by the tests of the event handler and the subscriber
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use sawtooth_sdk::messages::events::Event_Attribute;
+
+    /// Returns an event list committing the block, with the given state changes
+    pub fn make_events(block_num: i64, block_id: &str, changes: Vec<StateChange>) -> Vec<u8> {
+        let event_types = EventTypes::default();
+        let attribute = |key: &str, value: &str| {
+            let mut attribute = Event_Attribute::new();
+            attribute.set_key(key.to_string());
+            attribute.set_value(value.to_string());
+            attribute
+        };
+        let mut block_commit = Event::new();
+        block_commit.set_event_type(event_types.block_commit.clone());
+        block_commit.set_attributes(protobuf::RepeatedField::from_vec(vec![
+            attribute("block_num", &block_num.to_string()),
+            attribute("block_id", block_id),
+        ]));
+
+        let mut change_list = StateChangeList::new();
+        change_list.set_state_changes(protobuf::RepeatedField::from_vec(changes));
+        let mut state_delta = Event::new();
+        state_delta.set_event_type(event_types.state_delta.clone());
+        state_delta.set_data(change_list.write_to_bytes().unwrap());
+
+        let mut event_list = EventList::new();
+        event_list.set_events(protobuf::RepeatedField::from_vec(vec![
+            block_commit,
+            state_delta,
+        ]));
+        event_list.write_to_bytes().unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::test_support::make_events;
     use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::ReconnectingStore;
+    use common::addressing::{
+        make_agent_address, make_assertion_address, make_organization_address,
+        make_standard_address,
+    };
+    use database::errors::DatabaseError;
+    use sawtooth_sdk::messages::events::Event_Attribute;
 
     const PUBLIC_KEY: &str = "test_public_key";
     const ORG_ID: &str = "test_org";
@@ -451,6 +1648,1123 @@ mod tests {
     const ASSERTION_ID: &str = "test_assertion";
     const ASSERTION_ADDRESS: &str = "some_state_address";
 
+    #[test]
+    /// Test that gzip and zlib compressed payloads are unpacked to the same message as
+    /// uncompressed payloads
+    fn test_unpack_compressed_data() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let standard = make_standard();
+        let bytes = standard.write_to_bytes().unwrap();
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&bytes).unwrap();
+        let gzip_bytes = gzip.finish().unwrap();
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&bytes).unwrap();
+        let zlib_bytes = zlib.finish().unwrap();
+
+        let from_raw: standard::Standard =
+            EventHandler::unpack_data(&bytes, &EventLimits::default()).unwrap();
+        let from_gzip: standard::Standard =
+            EventHandler::unpack_data(&gzip_bytes, &EventLimits::default()).unwrap();
+        let from_zlib: standard::Standard =
+            EventHandler::unpack_data(&zlib_bytes, &EventLimits::default()).unwrap();
+        assert_eq!(from_raw, standard);
+        assert_eq!(from_gzip, standard);
+        assert_eq!(from_zlib, standard);
+    }
+
+    #[test]
+    /// Test that a compressed payload inflating past the limit is refused, instead of
+    /// being inflated in full
+    fn test_unpack_oversized_data() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        // A compressible payload, so the compressed size is under the limit
+        let mut standard = make_standard();
+        standard.set_name("test".repeat(256));
+        let bytes = standard.write_to_bytes().unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&bytes).unwrap();
+        let gzip_bytes = gzip.finish().unwrap();
+
+        let limits = |max_message_bytes: usize| EventLimits {
+            max_message_bytes,
+            ..EventLimits::default()
+        };
+        assert!(
+            EventHandler::unpack_data::<standard::Standard>(&gzip_bytes, &limits(bytes.len()))
+                .is_ok()
+        );
+        let err =
+            EventHandler::unpack_data::<standard::Standard>(&gzip_bytes, &limits(bytes.len() - 1))
+                .unwrap_err();
+        assert!(err.to_string().contains("inflates to more than"));
+    }
+
+    #[test]
+    /// Test that a payload nesting messages past the decode depth, or larger than
+    /// the largest message before any inflating, is refused
+    fn test_unpack_nested_data() {
+        let mut orgs = organization::OrganizationContainer::new();
+        orgs.set_entries(protobuf::RepeatedField::from_vec(vec![
+            make_certifying_body(),
+        ]));
+        let bytes = orgs.write_to_bytes().unwrap();
+
+        // The container nests the organization, its certifying body details and
+        // their accreditations three levels deep
+        let limits = |max_decode_depth: u32, max_message_bytes: usize| EventLimits {
+            max_decode_depth,
+            max_message_bytes,
+            ..EventLimits::default()
+        };
+        let from_raw: organization::OrganizationContainer =
+            EventHandler::unpack_data(&bytes, &limits(3, bytes.len())).unwrap();
+        assert_eq!(from_raw, orgs);
+
+        let err = EventHandler::unpack_data::<organization::OrganizationContainer>(
+            &bytes,
+            &limits(2, bytes.len()),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Error parsing protobuf data"));
+
+        let err = EventHandler::unpack_data::<organization::OrganizationContainer>(
+            &bytes,
+            &limits(3, bytes.len() - 1),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("more than"));
+    }
+
+    #[test]
+    /// Test that an event list over the byte or event count limits is refused
+    fn test_parse_events_over_limits() {
+        let namespace_regex = EventHandler::namespace_regex(None);
+        let event_types = EventTypes::default();
+        let data = make_events(1, "block_1", vec![]);
+        let parse = |limits: EventLimits| {
+            EventHandler::parse_events(
+                &data,
+                &namespace_regex,
+                &event_types,
+                &Strictness::default(),
+                &limits,
+            )
+        };
+
+        assert!(parse(EventLimits::default()).is_ok());
+        assert!(parse(EventLimits {
+            max_message_bytes: data.len() - 1,
+            ..EventLimits::default()
+        })
+        .is_err());
+        assert!(parse(EventLimits {
+            max_events: 1,
+            ..EventLimits::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    /// Test that malformed event data is rejected with an error instead of panicking
+    fn test_parse_malformed_events() {
+        let namespace_regex = EventHandler::namespace_regex(None);
+        let event_types = EventTypes::default();
+        assert!(EventHandler::parse_events(
+            &[0xff, 0xff, 0xff],
+            &namespace_regex,
+            &event_types,
+            &Strictness::default(),
+            &EventLimits::default()
+        )
+        .is_err());
+
+        let mut block_commit = Event::new();
+        block_commit.set_event_type(event_types.block_commit.clone());
+        let mut block_num = Event_Attribute::new();
+        block_num.set_key("block_num".to_string());
+        block_num.set_value("1".to_string());
+        block_commit.set_attributes(protobuf::RepeatedField::from_vec(vec![block_num]));
+        let mut event_list = EventList::new();
+        event_list.set_events(protobuf::RepeatedField::from_vec(vec![block_commit]));
+        let data = event_list.write_to_bytes().unwrap();
+        assert!(EventHandler::parse_events(
+            &data,
+            &namespace_regex,
+            &event_types,
+            &Strictness::default(),
+            &EventLimits::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    /// Test that an organization type from a newer transaction processor is read as unset,
+    /// and is only an error in strict mode
+    fn test_unknown_organization_type() {
+        let mut org = make_factory();
+        let field_number = org
+            .descriptor()
+            .field_by_name("organization_type")
+            .proto()
+            .get_number() as u32;
+        org.clear_organization_type();
+        org.mut_unknown_fields().add_varint(field_number, 99);
+        let org: organization::Organization =
+            EventHandler::unpack_data(&org.write_to_bytes().unwrap(), &EventLimits::default())
+                .unwrap();
+
+        assert_eq!(
+            org.get_organization_type(),
+            organization::Organization_Type::UNSET_TYPE
+        );
+        assert_eq!(unknown_enum_values(&org, "organization_type"), vec![99]);
+        assert!(check_enum_field(&org, "organization_type", FACTORY_ID, false).is_ok());
+        assert!(check_enum_field(&org, "organization_type", FACTORY_ID, true).is_err());
+        assert!(check_enum_field(&make_factory(), "organization_type", FACTORY_ID, true).is_ok());
+
+        let (new_org, _, _, _, _): (
+            NewOrganization,
+            Option<Vec<NewAccreditation>>,
+            Option<NewAddress>,
+            Vec<NewAuthorization>,
+            Vec<NewContact>,
+        ) = FromStateAtBlock::at_block(1, &org);
+        assert_eq!(new_org.organization_type, OrganizationTypeEnum::UnsetType);
+    }
+
+    #[test]
+    /// Test that an organization with UNSET_TYPE is applied with a warning, without
+    /// accreditations or an address, and is only an error in strict mode
+    fn test_unset_organization_type() {
+        let mut org = make_factory();
+        org.set_organization_type(organization::Organization_Type::UNSET_TYPE);
+        assert!(check_organization_type(&org, false).is_ok());
+        assert!(check_organization_type(&org, true).is_err());
+        assert!(check_organization_type(&make_factory(), true).is_ok());
+
+        let mut orgs = organization::OrganizationContainer::new();
+        orgs.set_entries(protobuf::RepeatedField::from_vec(vec![org]));
+        let mut change = StateChange::new();
+        change.set_address(make_organization_address(FACTORY_ID));
+        change.set_value(orgs.write_to_bytes().unwrap());
+        change.set_field_type(StateChange_Type::SET);
+        let data = make_events(1, "block_1", vec![change]);
+        let parse = |strict: &Strictness| {
+            EventHandler::parse_events(
+                &data,
+                &EventHandler::namespace_regex(None),
+                &EventTypes::default(),
+                strict,
+                &EventLimits::default(),
+            )
+        };
+
+        let (_, operations) = parse(&Strictness::default()).unwrap();
+        match &operations[..] {
+            [OperationType::CreateOrganization(orgs)] => {
+                let (org, accreditations, address, _, _) = &orgs[0];
+                assert_eq!(org.organization_type, OrganizationTypeEnum::UnsetType);
+                assert!(accreditations.is_none());
+                assert!(address.is_none());
+            }
+            _ => panic!("Expected a CreateOrganization operation"),
+        }
+        assert!(parse(&Strictness::all()).is_err());
+    }
+
+    #[test]
+    /// Test that an entity's state is parsed at the given block
+    fn test_parse_state() {
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![make_agent()]));
+        let block = Block {
+            block_num: 5,
+            block_id: "block_5".to_string(),
+        };
+        let operation = EventHandler::parse_state(
+            &make_agent_address(PUBLIC_KEY),
+            &agents.write_to_bytes().unwrap(),
+            &block,
+            &Strictness::default(),
+            &EventLimits::default(),
+        )
+        .unwrap();
+        match operation {
+            OperationType::CreateAgent(agents) => {
+                assert_eq!(agents[0].public_key, PUBLIC_KEY);
+                assert_eq!(agents[0].start_block_num, 5);
+            }
+            _ => panic!("Expected a CreateAgent operation"),
+        }
+    }
+
+    #[test]
+    /// Test that an agent with an empty name is applied with a warning, and is only
+    /// an error in strict mode, while an agent without an organization is valid
+    fn test_empty_agent_name() {
+        let mut agent = make_agent();
+        agent.clear_organization_id();
+        assert!(check_required_fields(&agent, PUBLIC_KEY, true).is_ok());
+
+        agent.clear_name();
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![agent]));
+        let parse = |strict: Strictness| {
+            EventHandler::parse_state(
+                &make_agent_address(PUBLIC_KEY),
+                &agents.write_to_bytes().unwrap(),
+                &Block {
+                    block_num: 5,
+                    block_id: "block_5".to_string(),
+                },
+                &strict,
+                &EventLimits::default(),
+            )
+        };
+        match parse(Strictness::default()).unwrap() {
+            OperationType::CreateAgent(agents) => assert_eq!(agents[0].name, ""),
+            _ => panic!("Expected a CreateAgent operation"),
+        }
+        let err = parse(Strictness {
+            required_fields: true,
+            ..Strictness::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Error parsing event The name of Agent {} is empty, but required",
+                PUBLIC_KEY
+            )
+        );
+    }
+
+    #[test]
+    /// Test that an organization with an empty name is applied with a warning, and
+    /// is only an error in strict mode
+    fn test_empty_organization_name() {
+        let mut org = make_factory();
+        assert!(check_required_fields(&org, FACTORY_ID, true).is_ok());
+        org.clear_name();
+        let mut orgs = organization::OrganizationContainer::new();
+        orgs.set_entries(protobuf::RepeatedField::from_vec(vec![org]));
+        let parse = |strict: Strictness| {
+            EventHandler::parse_state(
+                &make_organization_address(FACTORY_ID),
+                &orgs.write_to_bytes().unwrap(),
+                &Block {
+                    block_num: 5,
+                    block_id: "block_5".to_string(),
+                },
+                &strict,
+                &EventLimits::default(),
+            )
+        };
+        assert!(parse(Strictness::default()).is_ok());
+        let err = parse(Strictness::all()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("The name of Organization {} is empty", FACTORY_ID)));
+    }
+
+    #[test]
+    /// Test that handling a block applies its operations to the store with the block
+    fn test_handle_events() {
+        let store = MemoryStore::default();
+        let mut handler =
+            EventHandler::new(Box::new(store.clone()), EventHandlerOptions::default());
+
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![make_agent()]));
+        let mut change = StateChange::new();
+        change.set_address(make_agent_address(PUBLIC_KEY));
+        change.set_value(agents.write_to_bytes().unwrap());
+        change.set_field_type(StateChange_Type::SET);
+
+        let report = handler
+            .handle_events(&make_events(3, "test_block", vec![change]))
+            .unwrap();
+        assert_eq!(report.operations_applied, 1);
+        assert_eq!(store.block_ids(), vec![(3, "test_block".to_string())]);
+        let expected: NewAgent = FromStateAtBlock::at_block(3, &make_agent());
+        store.with_operations(|blocks| match &blocks[0].1[..] {
+            [OperationType::CreateAgent(agents)] => assert_eq!(agents, &vec![expected]),
+            _ => panic!("Expected a CreateAgent operation"),
+        });
+    }
+
+    #[test]
+    /// Test that a state snapshot is committed as one block, skipping addresses
+    /// outside the subscribed address types, and later blocks follow it
+    fn test_apply_snapshot() {
+        let store = MemoryStore::default();
+        let mut handler = EventHandler::new(
+            Box::new(store.clone()),
+            EventHandlerOptions {
+                address_types: AddressTypes::parse("agent").unwrap(),
+                ..EventHandlerOptions::default()
+            },
+        );
+
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![make_agent()]));
+        let mut standards = standard::StandardContainer::new();
+        standards.set_entries(protobuf::RepeatedField::from_vec(vec![make_standard()]));
+        let block = Block {
+            block_num: 5,
+            block_id: "block_5".to_string(),
+        };
+        let applied = handler
+            .apply_snapshot(
+                block,
+                vec![
+                    (
+                        make_agent_address(PUBLIC_KEY),
+                        agents.write_to_bytes().unwrap(),
+                    ),
+                    (
+                        make_standard_address(STANDARD_ID),
+                        standards.write_to_bytes().unwrap(),
+                    ),
+                ],
+            )
+            .unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(store.block_ids(), vec![(5, "block_5".to_string())]);
+        let expected: NewAgent = FromStateAtBlock::at_block(5, &make_agent());
+        store.with_operations(|blocks| match &blocks[0].1[..] {
+            [OperationType::CreateAgent(agents)] => assert_eq!(agents, &vec![expected]),
+            _ => panic!("Expected a CreateAgent operation"),
+        });
+
+        let report = handler
+            .handle_events(&make_events(6, "block_6", vec![]))
+            .unwrap();
+        assert_eq!(report.sequence, BlockSequence::Next);
+    }
+
+    #[test]
+    /// Test that block applied hooks are called after each commit, and a failing hook
+    /// neither fails the block nor stops later hooks
+    fn test_on_block_applied() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let store = MemoryStore::default();
+        let mut handler =
+            EventHandler::new(Box::new(store.clone()), EventHandlerOptions::default());
+        let applied = Rc::new(RefCell::new(vec![]));
+        handler.on_block_applied(Box::new(|_, _| Err("test".to_string())));
+        let hook_applied = applied.clone();
+        handler.on_block_applied(Box::new(move |block, operations| {
+            hook_applied
+                .borrow_mut()
+                .push((block.block_num, operations.len()));
+            Ok(())
+        }));
+
+        let mut agents = agent::AgentContainer::new();
+        agents.set_entries(protobuf::RepeatedField::from_vec(vec![make_agent()]));
+        let mut change = StateChange::new();
+        change.set_address(make_agent_address(PUBLIC_KEY));
+        change.set_value(agents.write_to_bytes().unwrap());
+        change.set_field_type(StateChange_Type::SET);
+        handler
+            .handle_events(&make_events(3, "block_3", vec![change]))
+            .unwrap();
+        handler
+            .handle_events(&make_events(4, "block_4", vec![]))
+            .unwrap();
+
+        assert_eq!(store.block_ids().len(), 2);
+        assert_eq!(*applied.borrow(), vec![(3, 1), (4, 0)]);
+    }
+
+    #[test]
+    /// Test that an empty event list, as sent for settings heartbeats, parses to the
+    /// zeroed block with no operations
+    fn test_parse_heartbeat() {
+        let data = EventList::new().write_to_bytes().unwrap();
+        let (block, operations) = EventHandler::parse_events(
+            &data,
+            &EventHandler::namespace_regex(None),
+            &EventTypes::default(),
+            &Strictness::default(),
+            &EventLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(block.block_num, 0);
+        assert_eq!(block.block_id, "");
+        assert!(operations.is_empty());
+    }
+
+    #[test]
+    /// Test that a heartbeat is a no-op: nothing is written to the database, and the
+    /// block position doesn't move, so no genesis block is recorded
+    fn test_handle_heartbeat() {
+        let store = MemoryStore::default();
+        let mut handler =
+            EventHandler::new(Box::new(store.clone()), EventHandlerOptions::default());
+        let heartbeat = EventList::new().write_to_bytes().unwrap();
+
+        let report = handler.handle_events(&heartbeat).unwrap();
+        assert!(report.is_heartbeat());
+        assert!(!report.is_empty_block());
+        assert_eq!(report.operations_applied, 0);
+        assert!(store.block_ids().is_empty());
+
+        let report = handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .unwrap();
+        assert_eq!(report.sequence, BlockSequence::First);
+        handler.handle_events(&heartbeat).unwrap();
+        let report = handler
+            .handle_events(&make_events(2, "block_2", vec![]))
+            .unwrap();
+        assert_eq!(report.sequence, BlockSequence::Next);
+        assert_eq!(
+            store.block_ids(),
+            vec![(1, "block_1".to_string()), (2, "block_2".to_string())]
+        );
+    }
+
+    #[test]
+    /// Test that a standard referencing a missing standards body fails the block
+    /// when the organization reference check is an error, and is applied once the
+    /// standards body is known
+    fn test_handle_events_missing_standards_body() {
+        let store = MemoryStore::default();
+        let mut handler = EventHandler::new(
+            Box::new(store.clone()),
+            EventHandlerOptions {
+                organization_reference_check: CheckMode::Error,
+                ..EventHandlerOptions::default()
+            },
+        );
+        let mut standards = standard::StandardContainer::new();
+        standards.set_entries(protobuf::RepeatedField::from_vec(vec![make_standard()]));
+        let mut change = StateChange::new();
+        change.set_address(make_standard_address(STANDARD_ID));
+        change.set_value(standards.write_to_bytes().unwrap());
+        change.set_field_type(StateChange_Type::SET);
+
+        let err = handler
+            .handle_events(&make_events(1, "block_1", vec![change.clone()]))
+            .unwrap_err();
+        assert!(err.to_string().contains(STANDARDS_BODY_ID));
+        assert!(store.block_ids().is_empty());
+
+        let mut handler = EventHandler::new(
+            Box::new(store.clone()),
+            EventHandlerOptions {
+                organization_reference_check: CheckMode::Error,
+                organization_ids: OrganizationIds::from_rows(vec![STANDARDS_BODY_ID.to_string()]),
+                ..EventHandlerOptions::default()
+            },
+        );
+        handler
+            .handle_events(&make_events(1, "block_1", vec![change]))
+            .unwrap();
+        assert_eq!(store.block_ids().len(), 1);
+    }
+
+    #[test]
+    /// Test that coalesced blocks are committed together once the interval is due,
+    /// and that flushing commits a partial batch
+    fn test_handle_events_commit_interval() {
+        let store = MemoryStore::default();
+        let mut handler = EventHandler::new(
+            Box::new(store.clone()),
+            EventHandlerOptions {
+                commit_interval: CommitInterval {
+                    blocks: Some(2),
+                    duration: None,
+                },
+                ..EventHandlerOptions::default()
+            },
+        );
+
+        handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .unwrap();
+        assert!(store.block_ids().is_empty());
+        handler
+            .handle_events(&make_events(2, "block_2", vec![]))
+            .unwrap();
+        assert_eq!(store.block_ids().len(), 2);
+        handler
+            .handle_events(&make_events(3, "block_3", vec![]))
+            .unwrap();
+        assert_eq!(handler.flush_if_due().unwrap(), 0);
+        assert_eq!(handler.flush().unwrap(), 1);
+        assert_eq!(
+            store.block_ids(),
+            vec![
+                (1, "block_1".to_string()),
+                (2, "block_2".to_string()),
+                (3, "block_3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that each committed block's latency is measured from when its events
+    /// were received, including the time it waited for its batch
+    fn test_block_latency() {
+        let mut handler = EventHandler::new(
+            Box::new(MemoryStore::default()),
+            EventHandlerOptions {
+                commit_interval: CommitInterval {
+                    blocks: Some(2),
+                    duration: None,
+                },
+                latency_warning: Some(Duration::from_millis(10)),
+                ..EventHandlerOptions::default()
+            },
+        );
+        let received = Instant::now()
+            .checked_sub(Duration::from_millis(200))
+            .unwrap();
+        handler
+            .handle_received_events(&make_events(1, "block_1", vec![]), received)
+            .unwrap();
+        let heartbeat = EventList::new().write_to_bytes().unwrap();
+        handler
+            .handle_received_events(&heartbeat, Instant::now())
+            .unwrap();
+        assert_eq!(handler.block_latency().count(), 0);
+        handler
+            .handle_events(&make_events(2, "block_2", vec![]))
+            .unwrap();
+
+        let latency = handler.block_latency();
+        assert_eq!(latency.count(), 2);
+        assert!(latency.max() >= Duration::from_millis(200));
+        assert!(latency.quantile(0.5).unwrap() < Duration::from_millis(200));
+    }
+
+    /// A store that fails the first commits with a serialization failure, or with
+    /// another error if not retryable, then commits to a memory store
+    struct FlakyStore {
+        store: MemoryStore,
+        failures: std::cell::Cell<u32>,
+        retryable: bool,
+    }
+
+    impl BlockStore for FlakyStore {
+        fn execute_operations_in_block(
+            &self,
+            operations: Vec<OperationType>,
+            block: &Block,
+        ) -> Result<(), DatabaseError> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                let message = if self.retryable {
+                    "could not serialize access due to concurrent update"
+                } else {
+                    "relation does not exist"
+                };
+                return Err(DatabaseError::ConnectionError(Box::new(io::Error::new(
+                    io::ErrorKind::Other,
+                    message,
+                ))));
+            }
+            self.store.execute_operations_in_block(operations, block)
+        }
+
+        fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+            self.store.fetch_known_blocks()
+        }
+    }
+
+    #[test]
+    /// Test that a commit failing with a serialization failure is retried until it
+    /// succeeds, and that other errors and exhausted retries fail the block
+    fn test_handle_events_retry() {
+        let make_handler = |store: &MemoryStore, failures, retryable| {
+            EventHandler::new(
+                Box::new(FlakyStore {
+                    store: store.clone(),
+                    failures: std::cell::Cell::new(failures),
+                    retryable,
+                }),
+                EventHandlerOptions {
+                    retry_policy: RetryPolicy {
+                        max_retries: 2,
+                        initial_backoff: std::time::Duration::from_millis(0),
+                        reconnect_timeout: None,
+                    },
+                    ..EventHandlerOptions::default()
+                },
+            )
+        };
+
+        let store = MemoryStore::default();
+        let mut handler = make_handler(&store, 2, true);
+        handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .unwrap();
+        assert_eq!(handler.db_retries(), 2);
+        assert_eq!(store.block_ids(), vec![(1, "block_1".to_string())]);
+
+        let store = MemoryStore::default();
+        let mut handler = make_handler(&store, 3, true);
+        assert!(handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .is_err());
+        assert_eq!(handler.db_retries(), 2);
+        assert!(store.block_ids().is_empty());
+
+        let store = MemoryStore::default();
+        let mut handler = make_handler(&store, 1, false);
+        assert!(handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .is_err());
+        assert_eq!(handler.db_retries(), 0);
+    }
+
+    #[test]
+    /// Test that a block the database refuses is written to the dead-letter log and
+    /// skipped when poison blocks are skipped, so the next block follows it in order
+    fn test_skip_poison_block() {
+        let path = std::env::temp_dir().join(format!("sds-poison-{}.log", uuid::Uuid::new_v4()));
+        let store = MemoryStore::default();
+        let mut handler = EventHandler::new(
+            Box::new(FlakyStore {
+                store: store.clone(),
+                failures: std::cell::Cell::new(1),
+                retryable: false,
+            }),
+            EventHandlerOptions {
+                poison_dead_letter: Some(AuditLog::open(&path).unwrap()),
+                ..EventHandlerOptions::default()
+            },
+        );
+        let poison = make_events(1, "block_1", vec![]);
+        let report = handler.handle_events(&poison).unwrap();
+        assert!(report.skipped);
+        assert!(!report.is_heartbeat());
+        let report = handler
+            .handle_events(&make_events(2, "block_2", vec![]))
+            .unwrap();
+        assert_eq!(report.sequence, BlockSequence::Next);
+        assert!(!report.skipped);
+        assert!(handler.handle_events(&[0xff, 0xff]).unwrap().skipped);
+        assert_eq!(store.block_ids(), vec![(2, "block_2".to_string())]);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records = contents
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["block_num"], 1);
+        assert_eq!(records[0]["block_id"], "block_1");
+        assert!(records[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("relation does not exist"));
+        let events = records[0]["events"].as_str().unwrap();
+        let decoded = (0..events.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&events[index..index + 2], 16).unwrap())
+            .collect::<Vec<u8>>();
+        assert_eq!(decoded, poison);
+        assert!(records[1]["block_num"].is_null());
+        assert_eq!(records[1]["events"], "ffff");
+
+        // Without a dead-letter log, the block fails
+        let mut handler = EventHandler::new(
+            Box::new(MemoryStore::default()),
+            EventHandlerOptions::default(),
+        );
+        assert!(handler.handle_events(&[0xff, 0xff]).is_err());
+    }
+
+    /// A database server that restarts, dropping its connections, then refuses
+    /// a number of connections before accepting them again
+    #[derive(Default)]
+    struct RestartingServer {
+        store: MemoryStore,
+        restarts: std::cell::Cell<u32>,
+        refusals: std::cell::Cell<u32>,
+    }
+
+    /// A connection to a restarting server, which is lost once the server restarts
+    struct ServerConnection {
+        server: std::rc::Rc<RestartingServer>,
+        restarts: u32,
+    }
+
+    impl ServerConnection {
+        fn connect(
+            server: &std::rc::Rc<RestartingServer>,
+        ) -> Result<Box<dyn BlockStore>, DatabaseError> {
+            if server.refusals.get() > 0 {
+                server.refusals.set(server.refusals.get() - 1);
+                return Err(DatabaseError::ConnectionError(Box::new(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "could not connect to server: Connection refused",
+                ))));
+            }
+            Ok(Box::new(ServerConnection {
+                server: server.clone(),
+                restarts: server.restarts.get(),
+            }))
+        }
+    }
+
+    impl BlockStore for ServerConnection {
+        fn execute_operations_in_block(
+            &self,
+            operations: Vec<OperationType>,
+            block: &Block,
+        ) -> Result<(), DatabaseError> {
+            if self.restarts != self.server.restarts.get() {
+                return Err(DatabaseError::ConnectionError(Box::new(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "server closed the connection unexpectedly",
+                ))));
+            }
+            self.server
+                .store
+                .execute_operations_in_block(operations, block)
+        }
+
+        fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+            self.server.store.fetch_known_blocks()
+        }
+    }
+
+    #[test]
+    /// Test that a block failing because the database restarted is applied once
+    /// the handler reconnects, and fails if it can't reconnect within the timeout
+    fn test_handle_events_reconnect() {
+        let make_handler = |server: &std::rc::Rc<RestartingServer>, timeout| {
+            let server = server.clone();
+            let store =
+                ReconnectingStore::connect(Box::new(move || ServerConnection::connect(&server)))
+                    .unwrap();
+            EventHandler::new(
+                Box::new(store),
+                EventHandlerOptions {
+                    retry_policy: RetryPolicy {
+                        max_retries: 2,
+                        initial_backoff: std::time::Duration::from_millis(0),
+                        reconnect_timeout: Some(timeout),
+                    },
+                    ..EventHandlerOptions::default()
+                },
+            )
+        };
+
+        let server = std::rc::Rc::new(RestartingServer::default());
+        let mut handler = make_handler(&server, std::time::Duration::from_secs(60));
+        handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .unwrap();
+        server.restarts.set(1);
+        server.refusals.set(2);
+        handler
+            .handle_events(&make_events(2, "block_2", vec![]))
+            .unwrap();
+        assert_eq!(handler.db_retries(), 3);
+        assert_eq!(
+            server.store.block_ids(),
+            vec![(1, "block_1".to_string()), (2, "block_2".to_string())]
+        );
+
+        let server = std::rc::Rc::new(RestartingServer::default());
+        let mut handler = make_handler(&server, std::time::Duration::from_secs(0));
+        server.restarts.set(1);
+        assert!(handler
+            .handle_events(&make_events(1, "block_1", vec![]))
+            .is_err());
+        assert!(server.store.block_ids().is_empty());
+    }
+
+    #[test]
+    /// Test that gapped and out-of-order block sequences are reported
+    fn test_handle_events_sequence() {
+        let store = MemoryStore::default();
+        let mut handler =
+            EventHandler::new(Box::new(store.clone()), EventHandlerOptions::default());
+        let mut handle = |block_num: i64| {
+            let block_id = format!("block_{}", block_num);
+            handler
+                .handle_events(&make_events(block_num, &block_id, vec![]))
+                .unwrap()
+                .sequence
+        };
+
+        assert_eq!(handle(1), BlockSequence::First);
+        assert_eq!(handle(2), BlockSequence::Next);
+        assert_eq!(
+            handle(5),
+            BlockSequence::Gap {
+                previous: 2,
+                missing: 2
+            }
+        );
+        assert_eq!(handle(4), BlockSequence::Reorg { previous: 5 });
+        assert_eq!(handle(5), BlockSequence::Next);
+        assert_eq!(store.block_ids().len(), 5);
+    }
+
+    #[test]
+    /// Test that a block skipping more block numbers than the maximum gap is refused
+    /// without being applied, so the subscriber resyncs, and smaller gaps are applied
+    fn test_max_block_gap() {
+        let store = MemoryStore::default();
+        let mut handler = EventHandler::new(
+            Box::new(store.clone()),
+            EventHandlerOptions {
+                max_block_gap: Some(10),
+                ..EventHandlerOptions::default()
+            },
+        );
+        let mut handle = |block_num: i64| {
+            let block_id = format!("block_{}", block_num);
+            handler.handle_events(&make_events(block_num, &block_id, vec![]))
+        };
+
+        handle(1).unwrap();
+        handle(2).unwrap();
+        match handle(100) {
+            Err(SubscriberError::BlockGap(message)) => assert_eq!(
+                message,
+                "97 blocks between block 2 and block 100 block_100, more than the 10 allowed"
+            ),
+            other => panic!(
+                "Expected a block gap, got {:?}",
+                other.map(|report| report.sequence)
+            ),
+        }
+        assert_eq!(store.block_ids().len(), 2);
+
+        // The resync resumes after block 2, so the blocks arrive in order again
+        assert_eq!(handle(3).unwrap().sequence, BlockSequence::Next);
+        assert_eq!(
+            handle(13).unwrap().sequence,
+            BlockSequence::Gap {
+                previous: 3,
+                missing: 9
+            }
+        );
+        assert_eq!(store.block_ids().len(), 4);
+    }
+
+    #[test]
+    /// Test that containers that don't look like full snapshots are rejected
+    fn test_check_full_snapshot() {
+        let address = make_agent_address(PUBLIC_KEY);
+        let get_id = |agent: &agent::Agent| agent.get_public_key();
+        assert!(check_full_snapshot(&address, &[make_agent()], get_id).is_ok());
+        assert!(check_full_snapshot::<agent::Agent, _>(&address, &[], get_id).is_err());
+
+        let mut partial = make_agent();
+        partial.clear_public_key();
+        assert!(check_full_snapshot(&address, &[make_agent(), partial], get_id).is_err());
+    }
+
+    #[test]
+    /// Test that the block is read from the first block_num and block_id attributes,
+    /// whatever the order of the attributes
+    fn test_parse_block() {
+        let event_types = EventTypes::default();
+        let attribute = |key: &str, value: &str| {
+            let mut attribute = Event_Attribute::new();
+            attribute.set_key(key.to_string());
+            attribute.set_value(value.to_string());
+            attribute
+        };
+        let mut block_commit = Event::new();
+        block_commit.set_event_type(event_types.block_commit.clone());
+        block_commit.set_attributes(protobuf::RepeatedField::from_vec(vec![
+            attribute("block_id", "test_block"),
+            attribute("previous_block_id", "test_previous_block"),
+            attribute("block_num", "7"),
+            attribute("block_num", "8"),
+        ]));
+        let block = EventHandler::parse_block(&[block_commit], &event_types.block_commit).unwrap();
+        assert_eq!(block.block_num, 7);
+        assert_eq!(block.block_id, "test_block");
+    }
+
+    #[test]
+    /// Test that a repeated block commit event is read as one block, and block commit
+    /// events for two blocks in one list are refused rather than one being dropped
+    fn test_parse_block_duplicate_events() {
+        let event_types = EventTypes::default();
+        let block_commit = |block_num: &str, block_id: &str| {
+            let mut block_num_attribute = Event_Attribute::new();
+            block_num_attribute.set_key("block_num".to_string());
+            block_num_attribute.set_value(block_num.to_string());
+            let mut block_id_attribute = Event_Attribute::new();
+            block_id_attribute.set_key("block_id".to_string());
+            block_id_attribute.set_value(block_id.to_string());
+            let mut event = Event::new();
+            event.set_event_type(event_types.block_commit.clone());
+            event.set_attributes(protobuf::RepeatedField::from_vec(vec![
+                block_num_attribute,
+                block_id_attribute,
+            ]));
+            event
+        };
+
+        let block = EventHandler::parse_block(
+            &[block_commit("7", "block_7"), block_commit("7", "block_7")],
+            &event_types.block_commit,
+        )
+        .unwrap();
+        assert_eq!(block.block_num, 7);
+        assert_eq!(block.block_id, "block_7");
+
+        match EventHandler::parse_block(
+            &[block_commit("7", "block_7"), block_commit("8", "block_8")],
+            &event_types.block_commit,
+        ) {
+            Err(SubscriberError::EventParseError(message)) => {
+                assert!(message.contains("for 2 blocks"));
+                assert!(message.contains("7 (block_7)"));
+                assert!(message.contains("8 (block_8)"));
+            }
+            _ => panic!("Expected an EventParseError"),
+        }
+    }
+
+    #[test]
+    /// Test that a block commit event with an empty block id is rejected rather than
+    /// read as a heartbeat
+    fn test_parse_block_empty_id() {
+        let event_types = EventTypes::default();
+        for block_id in &["", " "] {
+            let mut block_id_attribute = Event_Attribute::new();
+            block_id_attribute.set_key("block_id".to_string());
+            block_id_attribute.set_value(block_id.to_string());
+            let mut block_num_attribute = Event_Attribute::new();
+            block_num_attribute.set_key("block_num".to_string());
+            block_num_attribute.set_value("7".to_string());
+            let mut block_commit = Event::new();
+            block_commit.set_event_type(event_types.block_commit.clone());
+            block_commit.set_attributes(protobuf::RepeatedField::from_vec(vec![
+                block_id_attribute,
+                block_num_attribute,
+            ]));
+            match EventHandler::parse_block(&[block_commit], &event_types.block_commit) {
+                Err(SubscriberError::EventParseError(message)) => {
+                    assert!(message.contains("empty block_id"))
+                }
+                _ => panic!("Expected an EventParseError"),
+            }
+        }
+        assert!(EventHandler::parse_events(
+            &make_events(7, "", vec![]),
+            &EventHandler::namespace_regex(None),
+            &event_types,
+            &Strictness::default(),
+            &EventLimits::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    /// Test that block numbers that could collide with the open row sentinel are rejected
+    fn test_validate_block_num() {
+        assert_eq!(validate_block_num(0).unwrap(), 0);
+        assert_eq!(
+            validate_block_num(MAX_BLOCK_NUM - 1).unwrap(),
+            MAX_BLOCK_NUM - 1
+        );
+        assert!(validate_block_num(-1).is_err());
+        assert!(validate_block_num(MAX_BLOCK_NUM).is_err());
+    }
+
+    #[test]
+    /// Test that an assertion deletion is closed using only its address
+    fn test_parse_assertion_delete() {
+        let address = make_assertion_address(ASSERTION_ID);
+        let block = Block {
+            block_num: 2,
+            block_id: "test_block".to_string(),
+        };
+        match EventHandler::parse_delete(&make_delete(&address), &block, true) {
+            Ok(Some(OperationType::DeleteAssertion(deleted, block_num))) => {
+                assert_eq!(deleted, address);
+                assert_eq!(block_num, 2);
+            }
+            _ => panic!("Expected a DeleteAssertion operation"),
+        }
+    }
+
+    #[test]
+    /// Test that an address set then deleted within a block is only deleted, and
+    /// one deleted then set is only created at the block
+    fn test_create_and_delete_in_block() {
+        let address = make_assertion_address(ASSERTION_ID);
+        let mut assertions = assertion::AssertionContainer::new();
+        assertions.set_entries(protobuf::RepeatedField::from_vec(vec![make_assertion()]));
+        let mut set = StateChange::new();
+        set.set_address(address.clone());
+        set.set_value(assertions.write_to_bytes().unwrap());
+        set.set_field_type(StateChange_Type::SET);
+        let parse = |changes: Vec<StateChange>| {
+            EventHandler::parse_events(
+                &make_events(2, "block_2", changes),
+                &EventHandler::namespace_regex(None),
+                &EventTypes::default(),
+                &Strictness::default(),
+                &EventLimits::default(),
+            )
+            .unwrap()
+            .1
+        };
+
+        match &parse(vec![set.clone(), make_delete(&address)])[..] {
+            [OperationType::DeleteAssertion(deleted, 2)] => assert_eq!(deleted, &address),
+            _ => panic!("Expected only a DeleteAssertion operation"),
+        }
+        match &parse(vec![make_delete(&address), set.clone()])[..] {
+            [OperationType::CreateAssertion(created)] => {
+                assert_eq!(created[0].start_block_num, 2);
+                assert_eq!(created[0].end_block_num, MAX_BLOCK_NUM);
+            }
+            _ => panic!("Expected only a CreateAssertion operation"),
+        }
+        let agent_delete = make_delete(&make_agent_address(PUBLIC_KEY));
+        assert_eq!(
+            parse(vec![set, agent_delete, make_delete(&address)]).len(),
+            1
+        );
+    }
+
+    #[test]
+    /// Test that deleted entities with no delete operation are skipped, or rejected if
+    /// strict, and that deletions outside of the namespace are rejected
+    fn test_parse_unsupported_delete() {
+        let block = Block {
+            block_num: 2,
+            block_id: "test_block".to_string(),
+        };
+        let agent_delete = make_delete(&make_agent_address(PUBLIC_KEY));
+        assert!(EventHandler::parse_delete(&agent_delete, &block, false)
+            .unwrap()
+            .is_none());
+        assert!(EventHandler::parse_delete(&agent_delete, &block, true).is_err());
+        let foreign_delete = make_delete(&"f".repeat(70));
+        assert!(EventHandler::parse_delete(&foreign_delete, &block, false).is_err());
+    }
+
     #[test]
     /// Test that FromStateAtBlock::at_block returns a valid cert body, accreditation, auth, and contact
     fn test_cert_body_at_block() {
@@ -611,6 +2925,27 @@ mod tests {
         assert_eq!(from_state, (new_standard, vec![new_standard_version]));
     }
 
+    #[test]
+    /// Test that FromStateAtBlock::at_block orders standard versions by approval date,
+    /// leaving the current version last
+    fn test_standard_versions_ordered_at_block() {
+        let mut new_standard = make_standard();
+        new_standard.set_versions(protobuf::RepeatedField::from_vec(vec![
+            make_standard_version("2.0", 20),
+            make_standard_version("3.0", 30),
+            make_standard_version("1.0", 10),
+        ]));
+
+        let (_, versions): (NewStandard, Vec<NewStandardVersion>) =
+            FromStateAtBlock::at_block(1, &new_standard);
+        let ordered: Vec<(&str, i64)> = versions
+            .iter()
+            .map(|version| (version.version.as_str(), version.approval_date))
+            .collect();
+        assert_eq!(ordered, vec![("1.0", 10), ("2.0", 20), ("3.0", 30)]);
+        assert_eq!(versions.last().unwrap().version, "3.0");
+    }
+
     #[test]
     /// Test that FromStateAtBlock::at_block returns a valid assertion
     fn test_assertion_at_block() {
@@ -628,6 +2963,14 @@ mod tests {
         assert_eq!(from_state, new_assertion);
     }
 
+    fn make_delete(address: &str) -> StateChange {
+        let mut state_change = StateChange::new();
+        state_change.set_address(address.to_string());
+        state_change.set_field_type(StateChange_Type::DELETE);
+
+        state_change
+    }
+
     fn make_agent() -> agent::Agent {
         let mut new_agent = agent::Agent::new();
         new_agent.set_public_key(PUBLIC_KEY.to_string());
@@ -735,6 +3078,19 @@ mod tests {
         new_standard
     }
 
+    fn make_standard_version(
+        version: &str,
+        approval_date: u64,
+    ) -> standard::Standard_StandardVersion {
+        let mut new_standard_version = standard::Standard_StandardVersion::new();
+        new_standard_version.set_version(version.to_string());
+        new_standard_version.set_description("test".to_string());
+        new_standard_version.set_link("test".to_string());
+        new_standard_version.set_approval_date(approval_date);
+
+        new_standard_version
+    }
+
     fn make_assertion() -> assertion::Assertion {
         let mut assertion = assertion::Assertion::new();
         assertion.set_id(ASSERTION_ID.to_string());
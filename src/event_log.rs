@@ -0,0 +1,342 @@
+use crate::audit;
+use crate::errors::SubscriberError;
+use crate::store::BlockStore;
+
+use database::{data_manager::OperationType, errors::DatabaseError, models::Block};
+use rusqlite::{params, NO_PARAMS};
+use std::cell::RefCell;
+use std::path::Path;
+
+/// The name of the append-only table, which may start with a prefix with SQLite
+pub const EVENT_TABLE: &str = "operation_events";
+
+/// An entity written by an operation of a block, as appended to the event log
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRow {
+    /// The position of the entity among those written by the block
+    pub position: i64,
+    pub operation: String,
+    pub entity_id: String,
+    /// The key fields written, as JSON
+    pub fields: String,
+}
+
+/// Returns a row for each entity written by the operations of a block, in order,
+/// with the same operations, ids and fields as the audit records
+pub fn event_rows(block: &Block, operations: &[OperationType]) -> Vec<EventRow> {
+    audit::operation_records(block, operations)
+        .iter()
+        .enumerate()
+        .map(|(position, record)| EventRow {
+            position: position as i64,
+            operation: record["operation"].as_str().unwrap_or_default().to_string(),
+            entity_id: record["id"].as_str().unwrap_or_default().to_string(),
+            fields: record["fields"].to_string(),
+        })
+        .collect()
+}
+
+/// A table the event log is appended to
+pub trait EventTable {
+    /// Appends the rows of a block in one transaction. The rows of a block already
+    /// appended, as when the block is applied again after a crash, are skipped.
+    fn append(&self, block: &Block, rows: &[EventRow]) -> Result<(), DatabaseError>;
+
+    /// Replaces the connection to the database after it was lost
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+/// The event log in a Postgres schema, with a sequence numbering every row
+pub struct PostgresEventTable {
+    url: String,
+    connection: RefCell<postgres::Connection>,
+    table: String,
+}
+
+impl PostgresEventTable {
+    /// Connects to the database and creates the table in the schema if it doesn't
+    /// exist
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be connected to or the table created
+    /// ```
+    pub fn connect(url: &str, schema: &str) -> Result<PostgresEventTable, SubscriberError> {
+        let connection = postgres::Connection::connect(url, postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        let table = format!("\"{}\".\"{}\"", schema, EVENT_TABLE);
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (\
+                     sequence BIGSERIAL PRIMARY KEY, \
+                     block_num BIGINT NOT NULL, \
+                     block_id TEXT NOT NULL, \
+                     position INTEGER NOT NULL, \
+                     operation TEXT NOT NULL, \
+                     entity_id TEXT NOT NULL, \
+                     fields JSONB NOT NULL, \
+                     UNIQUE (block_id, position))",
+                    table
+                ),
+                &[],
+            )
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(PostgresEventTable {
+            url: url.to_string(),
+            connection: RefCell::new(connection),
+            table,
+        })
+    }
+}
+
+impl EventTable for PostgresEventTable {
+    fn append(&self, block: &Block, rows: &[EventRow]) -> Result<(), DatabaseError> {
+        let to_database_error =
+            |err: postgres::Error| DatabaseError::ConnectionError(Box::new(err));
+        let connection = self.connection.borrow();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        {
+            let statement = transaction
+                .prepare(&format!(
+                    "INSERT INTO {} (block_num, block_id, position, operation, entity_id, fields) \
+                     VALUES ($1, $2, $3, $4, $5, $6::text::jsonb) \
+                     ON CONFLICT (block_id, position) DO NOTHING",
+                    self.table
+                ))
+                .map_err(to_database_error)?;
+            for row in rows {
+                statement
+                    .execute(&[
+                        &block.block_num,
+                        &block.block_id,
+                        &(row.position as i32),
+                        &row.operation,
+                        &row.entity_id,
+                        &row.fields,
+                    ])
+                    .map_err(to_database_error)?;
+            }
+        }
+        transaction.commit().map_err(to_database_error)
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        let connection = postgres::Connection::connect(self.url.as_str(), postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        *self.connection.borrow_mut() = connection;
+        Ok(())
+    }
+}
+
+/// The event log in a SQLite file, with a sequence numbering every row
+pub struct SqliteEventTable {
+    connection: RefCell<rusqlite::Connection>,
+    table: String,
+}
+
+impl SqliteEventTable {
+    /// Opens the SQLite database at the given path, creating it and the table,
+    /// its name starting with the prefix, if they don't exist
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be opened or the table created
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, prefix: &str) -> Result<SqliteEventTable, DatabaseError> {
+        let connection = rusqlite::Connection::open(path).map_err(to_database_error)?;
+        let table = format!("{}{}", prefix, EVENT_TABLE);
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (\
+                     sequence INTEGER PRIMARY KEY AUTOINCREMENT, \
+                     block_num INTEGER NOT NULL, \
+                     block_id TEXT NOT NULL, \
+                     position INTEGER NOT NULL, \
+                     operation TEXT NOT NULL, \
+                     entity_id TEXT NOT NULL, \
+                     fields TEXT NOT NULL, \
+                     UNIQUE (block_id, position))",
+                    table
+                ),
+                NO_PARAMS,
+            )
+            .map_err(to_database_error)?;
+        Ok(SqliteEventTable {
+            connection: RefCell::new(connection),
+            table,
+        })
+    }
+}
+
+impl EventTable for SqliteEventTable {
+    fn append(&self, block: &Block, rows: &[EventRow]) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        {
+            let mut statement = transaction
+                .prepare(&format!(
+                    "INSERT OR IGNORE INTO {} \
+                     (block_num, block_id, position, operation, entity_id, fields) \
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    self.table
+                ))
+                .map_err(to_database_error)?;
+            for row in rows {
+                statement
+                    .execute(params![
+                        block.block_num,
+                        block.block_id,
+                        row.position,
+                        row.operation,
+                        row.entity_id,
+                        row.fields,
+                    ])
+                    .map_err(to_database_error)?;
+            }
+        }
+        transaction.commit().map_err(to_database_error)
+    }
+}
+
+fn to_database_error(err: rusqlite::Error) -> DatabaseError {
+    DatabaseError::ConnectionError(Box::new(err))
+}
+
+/// Appends every entity written by a block to an append-only event log, then
+/// applies the block to the reporting database.
+///
+/// The log is written first, in its own transaction, so a block is never in the
+/// reporting database without being in the log. If the block then fails, it is
+/// applied again after the subscriber resumes, and its rows already in the log
+/// are skipped. The log is never updated, so the rows of a block later replaced
+/// by a fork stay in it.
+pub struct EventLogStore {
+    store: Box<dyn BlockStore>,
+    table: Box<dyn EventTable>,
+}
+
+impl EventLogStore {
+    pub fn new(store: Box<dyn BlockStore>, table: Box<dyn EventTable>) -> EventLogStore {
+        EventLogStore { store, table }
+    }
+}
+
+impl BlockStore for EventLogStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        self.table.append(block, &event_rows(block, &operations))?;
+        self.store.execute_operations_in_block(operations, block)
+    }
+
+    /// Appends each block to the log, then applies them all to the reporting
+    /// database as the wrapped store does, so a batch stays in one transaction
+    fn execute_operations_in_blocks(
+        &self,
+        blocks: Vec<(Vec<OperationType>, Block)>,
+    ) -> Result<(), DatabaseError> {
+        for (operations, block) in &blocks {
+            self.table.append(block, &event_rows(block, operations))?;
+        }
+        self.store.execute_operations_in_blocks(blocks)
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        self.store.fetch_known_blocks()
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        self.table.reconnect()?;
+        self.store.reconnect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::test_support::{make_agent_operation_with_key, make_block};
+
+    #[test]
+    /// Test that each entity written is appended once with an increasing sequence,
+    /// applying a block again appends nothing, and the wrapped store gets every block
+    fn test_event_log_store() {
+        let path = std::env::temp_dir().join(format!("sds-events-{}.db", uuid::Uuid::new_v4()));
+        let store = MemoryStore::default();
+        let event_log = EventLogStore::new(
+            Box::new(store.clone()),
+            Box::new(SqliteEventTable::open(&path, "").unwrap()),
+        );
+        event_log
+            .execute_operations_in_block(
+                vec![
+                    make_agent_operation_with_key("key_1", "test", 1),
+                    make_agent_operation_with_key("key_2", "test", 1),
+                ],
+                &make_block(1),
+            )
+            .unwrap();
+        event_log
+            .execute_operations_in_block(
+                vec![make_agent_operation_with_key("key_1", "test", 1)],
+                &make_block(1),
+            )
+            .unwrap();
+        event_log
+            .execute_operations_in_blocks(vec![
+                (
+                    vec![make_agent_operation_with_key("key_1", "test", 2)],
+                    make_block(2),
+                ),
+                (vec![], make_block(3)),
+            ])
+            .unwrap();
+
+        assert_eq!(store.block_ids().len(), 4);
+        let agent = |sequence, block_num, position, key: &str| {
+            (
+                sequence,
+                block_num,
+                position,
+                "create_agent".to_string(),
+                key.to_string(),
+            )
+        };
+        assert_eq!(
+            logged_rows(&path),
+            vec![
+                agent(1, 1, 0, "key_1"),
+                agent(2, 1, 1, "key_2"),
+                agent(3, 2, 0, "key_1"),
+            ]
+        );
+        drop(event_log);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn logged_rows(path: &Path) -> Vec<(i64, i64, i64, String, String)> {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT sequence, block_num, position, operation, entity_id \
+                 FROM operation_events ORDER BY sequence",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+}
@@ -0,0 +1,23 @@
+/// The event type of the validator's block-commit events
+pub const BLOCK_COMMIT_EVENT: &str = "sawtooth/block-commit";
+/// The event type of the validator's state-delta events
+pub const STATE_DELTA_EVENT: &str = "sawtooth/state-delta";
+
+/// The types of the events the subscriber subscribes to and parses.
+///
+/// The same instance is given to the subscription request and to the event
+/// parser, so the events requested are always the events parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventTypes {
+    pub block_commit: String,
+    pub state_delta: String,
+}
+
+impl Default for EventTypes {
+    fn default() -> Self {
+        EventTypes {
+            block_commit: BLOCK_COMMIT_EVENT.to_string(),
+            state_delta: STATE_DELTA_EVENT.to_string(),
+        }
+    }
+}
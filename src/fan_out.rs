@@ -0,0 +1,330 @@
+use crate::store::BlockStore;
+
+use database::{data_manager::OperationType, errors::DatabaseError, models::Block};
+use std::cell::Cell;
+use std::str::FromStr;
+
+/// How a block that fails on some of the reporting databases is handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FanOutPolicy {
+    /// The block fails if it fails on any database, which stops the subscriber
+    All,
+    /// The block only fails if it fails on the first database. Failures on the
+    /// others are logged as errors and the block is skipped for them.
+    BestEffort,
+}
+
+impl Default for FanOutPolicy {
+    fn default() -> Self {
+        FanOutPolicy::All
+    }
+}
+
+impl FromStr for FanOutPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(FanOutPolicy::All),
+            "best-effort" => Ok(FanOutPolicy::BestEffort),
+            _ => Err(format!(
+                "Invalid fan-out policy {:?}, expected all or best-effort",
+                value
+            )),
+        }
+    }
+}
+
+/// A database written to by a fan-out store, with the number of blocks that
+/// failed on it
+struct Target {
+    name: String,
+    store: Box<dyn BlockStore>,
+    failures: Cell<u64>,
+}
+
+/// Applies every block to several independent reporting databases in turn. Each
+/// database commits the block in its own transaction, so a block can be committed
+/// on some databases and not on others.
+pub struct FanOutStore {
+    targets: Vec<Target>,
+    policy: FanOutPolicy,
+}
+
+impl FanOutStore {
+    /// Creates a store writing to the given named databases, in order. The first is
+    /// the primary.
+    pub fn new(targets: Vec<(String, Box<dyn BlockStore>)>, policy: FanOutPolicy) -> FanOutStore {
+        FanOutStore {
+            targets: targets
+                .into_iter()
+                .map(|(name, store)| Target {
+                    name,
+                    store,
+                    failures: Cell::new(0),
+                })
+                .collect(),
+            policy,
+        }
+    }
+
+    /// Handles a failure to apply a block to the target at the given index,
+    /// returning the error if the block should fail
+    fn failed(&self, index: usize, block: &Block, err: DatabaseError) -> Result<(), DatabaseError> {
+        let target = &self.targets[index];
+        target.failures.set(target.failures.get() + 1);
+        if index == 0 || self.policy == FanOutPolicy::All {
+            return Err(err);
+        }
+        error!(
+            "Skipping block {} ({}) on reporting database {} after an error, {} blocks \
+             skipped on it so far: {}",
+            block.block_num,
+            block.block_id,
+            target.name,
+            target.failures.get(),
+            err
+        );
+        Ok(())
+    }
+}
+
+impl BlockStore for FanOutStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        for (index, target) in self.targets.iter().enumerate() {
+            if let Err(err) = target
+                .store
+                .execute_operations_in_block(operations.clone(), block)
+            {
+                self.failed(index, block, err)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the known blocks of the database that is furthest behind, so the
+    /// subscription resumes early enough for every database. Blocks already
+    /// committed on the others are applied to them again.
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        let mut furthest_behind: Option<Vec<Block>> = None;
+        for target in &self.targets {
+            let blocks = target.store.fetch_known_blocks()?;
+            let last = |blocks: &[Block]| blocks.iter().map(|block| block.block_num).max();
+            let behind = match furthest_behind {
+                Some(ref known) => last(&blocks) < last(known),
+                None => true,
+            };
+            if behind {
+                furthest_behind = Some(blocks);
+            }
+        }
+        Ok(furthest_behind.unwrap_or_default())
+    }
+
+    /// Reconnects every database, so one that is still down doesn't stop the
+    /// others from reconnecting
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        let mut result = Ok(());
+        for target in &self.targets {
+            if let Err(err) = target.store.reconnect() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_store::SqliteStore;
+    use crate::store::memory::MemoryStore;
+    use crate::store::test_support::{make_agent_operation, make_block};
+    use rusqlite::NO_PARAMS;
+    use std::io;
+
+    /// A store that fails every block
+    struct FailingStore;
+
+    impl BlockStore for FailingStore {
+        fn execute_operations_in_block(
+            &self,
+            _operations: Vec<OperationType>,
+            _block: &Block,
+        ) -> Result<(), DatabaseError> {
+            Err(DatabaseError::ConnectionError(Box::new(io::Error::new(
+                io::ErrorKind::Other,
+                "test failure",
+            ))))
+        }
+
+        fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+            Ok(vec![])
+        }
+    }
+
+    /// A store that fails its first block, then applies blocks to the wrapped store
+    struct FailingOnceStore {
+        store: MemoryStore,
+        failed: Cell<bool>,
+    }
+
+    impl BlockStore for FailingOnceStore {
+        fn execute_operations_in_block(
+            &self,
+            operations: Vec<OperationType>,
+            block: &Block,
+        ) -> Result<(), DatabaseError> {
+            if !self.failed.replace(true) {
+                return FailingStore.execute_operations_in_block(operations, block);
+            }
+            self.store.execute_operations_in_block(operations, block)
+        }
+
+        fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+            self.store.fetch_known_blocks()
+        }
+    }
+
+    fn target<S: BlockStore + 'static>(name: &str, store: S) -> (String, Box<dyn BlockStore>) {
+        (name.to_string(), Box::new(store))
+    }
+
+    #[test]
+    /// Test that each block is applied to every database
+    fn test_fan_out() {
+        let first = MemoryStore::default();
+        let second = MemoryStore::default();
+        let store = FanOutStore::new(
+            vec![
+                target("first", first.clone()),
+                target("second", second.clone()),
+            ],
+            FanOutPolicy::All,
+        );
+        store
+            .execute_operations_in_block(vec![], &make_block(1))
+            .unwrap();
+        assert_eq!(first.block_ids(), vec![(1, "test_block_1".to_string())]);
+        assert_eq!(second.block_ids(), first.block_ids());
+    }
+
+    #[test]
+    /// Test that a failure on a secondary database only fails the block under the
+    /// all policy, and a failure on the primary always does
+    fn test_fan_out_policies() {
+        let primary = MemoryStore::default();
+        let all = FanOutStore::new(
+            vec![
+                target("primary", primary.clone()),
+                target("secondary", FailingStore),
+            ],
+            FanOutPolicy::All,
+        );
+        assert!(all
+            .execute_operations_in_block(vec![], &make_block(1))
+            .is_err());
+
+        let best_effort = FanOutStore::new(
+            vec![
+                target("primary", primary.clone()),
+                target("secondary", FailingStore),
+            ],
+            FanOutPolicy::BestEffort,
+        );
+        assert!(best_effort
+            .execute_operations_in_block(vec![], &make_block(2))
+            .is_ok());
+        assert_eq!(best_effort.targets[1].failures.get(), 1);
+        assert_eq!(primary.block_ids().len(), 2);
+
+        let failing_primary = FanOutStore::new(
+            vec![
+                target("primary", FailingStore),
+                target("secondary", primary),
+            ],
+            FanOutPolicy::BestEffort,
+        );
+        assert!(failing_primary
+            .execute_operations_in_block(vec![], &make_block(3))
+            .is_err());
+    }
+
+    #[test]
+    /// Test that a block committed on the first database and failing on the second
+    /// under the all policy can be retried, leaving no duplicate rows on the first
+    fn test_retry_after_partial_failure() {
+        let path = std::env::temp_dir().join(format!("sds-fan-out-{}.db", uuid::Uuid::new_v4()));
+        let second = MemoryStore::default();
+        let store = FanOutStore::new(
+            vec![
+                target("first", SqliteStore::open(&path).unwrap()),
+                target(
+                    "second",
+                    FailingOnceStore {
+                        store: second.clone(),
+                        failed: Cell::new(false),
+                    },
+                ),
+            ],
+            FanOutPolicy::All,
+        );
+        assert!(store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .is_err());
+        assert!(second.block_ids().is_empty());
+
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        assert_eq!(second.block_ids(), vec![(1, "test_block_1".to_string())]);
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let count = |table: &str| -> i64 {
+            connection
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {}", table),
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(count("agents"), 1);
+        assert_eq!(count("blocks"), 1);
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that the subscription resumes from the database furthest behind
+    fn test_fetch_known_blocks() {
+        let ahead = MemoryStore::default();
+        let behind = MemoryStore::default();
+        for block_num in 1..4 {
+            ahead
+                .execute_operations_in_block(vec![], &make_block(block_num))
+                .unwrap();
+        }
+        behind
+            .execute_operations_in_block(vec![], &make_block(1))
+            .unwrap();
+        let store = FanOutStore::new(
+            vec![target("ahead", ahead), target("behind", behind)],
+            FanOutPolicy::All,
+        );
+        let known: Vec<i64> = store
+            .fetch_known_blocks()
+            .unwrap()
+            .iter()
+            .map(|block| block.block_num)
+            .collect();
+        assert_eq!(known, vec![1]);
+        assert_eq!("best-effort".parse(), Ok(FanOutPolicy::BestEffort));
+        assert!("some".parse::<FanOutPolicy>().is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use crate::errors::SubscriberError;
+
+use database::{data_manager::MAX_BLOCK_NUM, errors::DatabaseError};
+use postgres::{Connection, TlsMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A kind of entity drawn in the relationship graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeKind {
+    Organization,
+    Agent,
+    Standard,
+    Certificate,
+    Request,
+}
+
+impl NodeKind {
+    fn name(self) -> &'static str {
+        match self {
+            NodeKind::Organization => "organization",
+            NodeKind::Agent => "agent",
+            NodeKind::Standard => "standard",
+            NodeKind::Certificate => "certificate",
+            NodeKind::Request => "request",
+        }
+    }
+
+    fn shape(self) -> &'static str {
+        match self {
+            NodeKind::Organization => "box",
+            NodeKind::Agent => "ellipse",
+            NodeKind::Standard => "note",
+            NodeKind::Certificate => "component",
+            NodeKind::Request => "diamond",
+        }
+    }
+}
+
+/// A column referencing another entity by its id
+#[derive(Debug)]
+pub struct Reference {
+    pub column: &'static str,
+    pub kind: NodeKind,
+    /// The label of the edge drawn for the reference
+    pub label: &'static str,
+}
+
+/// A reporting table whose current rows are drawn as nodes of one kind, with an
+/// edge for each reference
+#[derive(Debug)]
+pub struct GraphTable {
+    pub table: &'static str,
+    pub kind: NodeKind,
+    pub id_column: &'static str,
+    /// The column naming the entity, if it has a name
+    pub name_column: Option<&'static str>,
+    pub references: &'static [Reference],
+}
+
+impl GraphTable {
+    /// Returns the columns read from the table: the id, the name if there is one,
+    /// then each reference
+    pub fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec![self.id_column];
+        columns.extend(self.name_column);
+        columns.extend(self.references.iter().map(|reference| reference.column));
+        columns
+    }
+
+    /// Returns the statement selecting the columns of the current rows from the
+    /// table, given its name as it appears in the query
+    pub fn select_statement(&self, table_name: &str) -> String {
+        format!(
+            "SELECT {} FROM {} WHERE end_block_num = {}",
+            self.columns().join(", "),
+            table_name,
+            MAX_BLOCK_NUM
+        )
+    }
+}
+
+/// The tables drawn in the graph and the references between them
+pub const GRAPH_TABLES: [GraphTable; 5] = [
+    GraphTable {
+        table: "organizations",
+        kind: NodeKind::Organization,
+        id_column: "organization_id",
+        name_column: Some("name"),
+        references: &[],
+    },
+    GraphTable {
+        table: "agents",
+        kind: NodeKind::Agent,
+        id_column: "public_key",
+        name_column: Some("name"),
+        references: &[Reference {
+            column: "organization_id",
+            kind: NodeKind::Organization,
+            label: "member of",
+        }],
+    },
+    GraphTable {
+        table: "standards",
+        kind: NodeKind::Standard,
+        id_column: "standard_id",
+        name_column: Some("name"),
+        references: &[Reference {
+            column: "organization_id",
+            kind: NodeKind::Organization,
+            label: "set by",
+        }],
+    },
+    GraphTable {
+        table: "certificates",
+        kind: NodeKind::Certificate,
+        id_column: "certificate_id",
+        name_column: None,
+        references: &[
+            Reference {
+                column: "certifying_body_id",
+                kind: NodeKind::Organization,
+                label: "certified by",
+            },
+            Reference {
+                column: "factory_id",
+                kind: NodeKind::Organization,
+                label: "issued to",
+            },
+            Reference {
+                column: "standard_id",
+                kind: NodeKind::Standard,
+                label: "for",
+            },
+        ],
+    },
+    GraphTable {
+        table: "requests",
+        kind: NodeKind::Request,
+        id_column: "request_id",
+        name_column: None,
+        references: &[
+            Reference {
+                column: "factory_id",
+                kind: NodeKind::Organization,
+                label: "requested by",
+            },
+            Reference {
+                column: "standard_id",
+                kind: NodeKind::Standard,
+                label: "for",
+            },
+        ],
+    },
+];
+
+/// The entities in the reporting database and the id references between them,
+/// written as a Graphviz DOT graph by its Display implementation.
+///
+/// An entity is referenced by id, which the reporting database does not enforce,
+/// so a referenced entity may be missing. It is still drawn, with a dashed outline.
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// The name of each entity, by its kind and id
+    nodes: BTreeMap<(NodeKind, String), Option<String>>,
+    edges: BTreeSet<(NodeKind, String, NodeKind, String, &'static str)>,
+}
+
+impl Graph {
+    /// Adds an entity from a row of the table, holding its values in the order of
+    /// the table's columns. A row without an id is skipped.
+    pub fn add_row(&mut self, table: &GraphTable, values: Vec<Option<String>>) {
+        let mut values = values.into_iter();
+        let id = match values.next() {
+            Some(Some(id)) => id,
+            _ => return,
+        };
+        let name = if table.name_column.is_some() {
+            values.next().flatten()
+        } else {
+            None
+        };
+        for (reference, target) in table.references.iter().zip(values) {
+            if let Some(target) = target.filter(|target| !target.is_empty()) {
+                self.edges.insert((
+                    table.kind,
+                    id.clone(),
+                    reference.kind,
+                    target,
+                    reference.label,
+                ));
+            }
+        }
+        self.nodes.insert((table.kind, id), name);
+    }
+
+    /// Returns the number of entities and references in the graph
+    pub fn size(&self) -> (usize, usize) {
+        (self.nodes.len(), self.edges.len())
+    }
+}
+
+impl fmt::Display for Graph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "digraph reporting {{")?;
+        writeln!(f, "    rankdir=LR;")?;
+        for ((kind, id), name) in &self.nodes {
+            let label = match name {
+                Some(name) => format!("{}\\n{} {}", quote(name), kind.name(), quote(id)),
+                None => format!("{} {}", kind.name(), quote(id)),
+            };
+            writeln!(
+                f,
+                "    \"{}\" [label=\"{}\", shape={}];",
+                node_id(*kind, id),
+                label,
+                kind.shape()
+            )?;
+        }
+        let missing: BTreeSet<(NodeKind, &String)> = self
+            .edges
+            .iter()
+            .map(|(_, _, kind, id, _)| (*kind, id))
+            .filter(|(kind, id)| !self.nodes.contains_key(&(*kind, id.to_string())))
+            .collect();
+        for (kind, id) in missing {
+            writeln!(
+                f,
+                "    \"{}\" [label=\"{} {}\\n(not in reporting)\", shape={}, style=dashed];",
+                node_id(kind, id),
+                kind.name(),
+                quote(id),
+                kind.shape()
+            )?;
+        }
+        for (from_kind, from, to_kind, to, label) in &self.edges {
+            writeln!(
+                f,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                node_id(*from_kind, from),
+                node_id(*to_kind, to),
+                label
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Returns the DOT id of an entity, unique across kinds, without its quotes
+fn node_id(kind: NodeKind, id: &str) -> String {
+    format!("{}:{}", kind.name(), quote(id))
+}
+
+/// Escapes a value for a quoted DOT string
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Reads the current rows of the graph's tables in the given schema of the
+/// reporting database
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried
+/// ```
+pub fn fetch_graph(url: &str, schema: &str) -> Result<Graph, SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let mut graph = Graph::default();
+    for table in GRAPH_TABLES.iter() {
+        let rows = conn
+            .query(
+                &table.select_statement(&format!("\"{}\".\"{}\"", schema, table.table)),
+                &[],
+            )
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        for row in rows.iter() {
+            graph.add_row(table, (0..row.len()).map(|index| row.get(index)).collect());
+        }
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that entities are drawn with an edge per reference, a referenced
+    /// entity missing from reporting is drawn dashed, and values are escaped
+    fn test_graph_dot() {
+        let mut graph = Graph::default();
+        let value = |value: &str| Some(value.to_string());
+        graph.add_row(
+            &GRAPH_TABLES[0],
+            vec![value("org_1"), value("Acme \"East\"")],
+        );
+        graph.add_row(
+            &GRAPH_TABLES[1],
+            vec![value("key_1"), value("Alice"), value("org_1")],
+        );
+        graph.add_row(&GRAPH_TABLES[1], vec![value("key_2"), value("Bob"), None]);
+        graph.add_row(
+            &GRAPH_TABLES[3],
+            vec![
+                value("cert_1"),
+                value("org_1"),
+                value("factory_1"),
+                value("standard_1"),
+            ],
+        );
+        assert_eq!(graph.size(), (4, 4));
+
+        let dot = graph.to_string();
+        assert!(dot.starts_with("digraph reporting {\n"));
+        assert!(dot.contains(
+            "\"organization:org_1\" [label=\"Acme \\\"East\\\"\\norganization org_1\", shape=box];"
+        ));
+        assert!(dot.contains("\"agent:key_2\" [label=\"Bob\\nagent key_2\", shape=ellipse];"));
+        assert!(dot.contains("\"agent:key_1\" -> \"organization:org_1\" [label=\"member of\"];"));
+        assert!(dot.contains(
+            "\"certificate:cert_1\" -> \"organization:factory_1\" [label=\"issued to\"];"
+        ));
+        assert!(dot.contains(
+            "\"standard:standard_1\" [label=\"standard standard_1\\n(not in reporting)\", \
+             shape=note, style=dashed];"
+        ));
+        assert!(!dot.contains("organization org_1\\n(not in reporting)"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    /// Test that the statement selects the id, name and references of current rows
+    fn test_select_statement() {
+        assert_eq!(
+            GRAPH_TABLES[1].select_statement("agents"),
+            format!(
+                "SELECT public_key, name, organization_id FROM agents WHERE end_block_num = {}",
+                MAX_BLOCK_NUM
+            )
+        );
+    }
+}
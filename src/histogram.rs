@@ -0,0 +1,195 @@
+use std::fmt;
+use std::time::Duration;
+
+/// The number of power of two buckets, enough for any size up to 2^40 bytes
+const BUCKETS: usize = 41;
+
+/// A histogram of message sizes in bytes, in power of two buckets.
+///
+/// Bucket `n` counts sizes up to `2^n` bytes that are larger than `2^(n-1)`,
+/// so the histogram stays small whatever the sizes seen. Larger sizes are
+/// counted in the last bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total: u64,
+    max: usize,
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        SizeHistogram {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            total: 0,
+            max: 0,
+        }
+    }
+}
+
+impl SizeHistogram {
+    /// Records a size in bytes
+    pub fn record(&mut self, size: usize) {
+        let bucket = size.next_power_of_two().trailing_zeros() as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total += size as u64;
+        self.max = self.max.max(size);
+    }
+
+    /// Returns the number of sizes recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the sizes recorded
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the largest size recorded
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Returns the upper bound of each bucket holding any sizes, with the number
+    /// of sizes in it, smallest first
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (1u64 << bucket, *count))
+            .collect()
+    }
+
+    /// Returns the upper bound of the bucket holding the given quantile, from 0 to 1,
+    /// of the sizes recorded, or None if none were
+    pub fn quantile(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return Some(bound);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no messages");
+        }
+        write!(
+            f,
+            "{} messages, {} bytes, mean {} bytes, p50 <= {} bytes, p99 <= {} bytes, max {} bytes",
+            self.count,
+            self.total,
+            self.total / self.count,
+            self.quantile(0.5).unwrap_or_default(),
+            self.quantile(0.99).unwrap_or_default(),
+            self.max
+        )
+    }
+}
+
+/// A histogram of the time from receiving a block's event message to committing
+/// it, in power of two buckets of milliseconds, as for message sizes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyHistogram {
+    millis: SizeHistogram,
+}
+
+impl LatencyHistogram {
+    /// Records a block's latency
+    pub fn record(&mut self, latency: Duration) {
+        self.millis.record(latency.as_millis() as usize);
+    }
+
+    /// Returns the number of blocks recorded
+    pub fn count(&self) -> u64 {
+        self.millis.count()
+    }
+
+    /// Returns the longest latency recorded
+    pub fn max(&self) -> Duration {
+        Duration::from_millis(self.millis.max() as u64)
+    }
+
+    /// Returns the upper bound of the bucket holding the given quantile, from 0 to 1,
+    /// of the latencies recorded, or None if none were
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        self.millis.quantile(quantile).map(Duration::from_millis)
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.millis.count();
+        if count == 0 {
+            return write!(f, "no blocks");
+        }
+        write!(
+            f,
+            "{} blocks, mean {} ms, p50 <= {} ms, p99 <= {} ms, max {} ms",
+            count,
+            self.millis.total() / count,
+            self.millis.quantile(0.5).unwrap_or_default(),
+            self.millis.quantile(0.99).unwrap_or_default(),
+            self.millis.max()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that sizes are counted in power of two buckets and summarized
+    fn test_size_histogram() {
+        let mut histogram = SizeHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        assert_eq!(histogram.to_string(), "no messages");
+
+        for size in &[0, 1, 3, 4, 5, 1000] {
+            histogram.record(*size);
+        }
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.total(), 1013);
+        assert_eq!(histogram.max(), 1000);
+        assert_eq!(histogram.buckets(), vec![(1, 2), (4, 2), (8, 1), (1024, 1)]);
+        assert_eq!(histogram.quantile(0.5), Some(4));
+        assert_eq!(histogram.quantile(1.0), Some(1024));
+        assert_eq!(
+            histogram.to_string(),
+            "6 messages, 1013 bytes, mean 168 bytes, p50 <= 4 bytes, p99 <= 1024 bytes, \
+             max 1000 bytes"
+        );
+    }
+
+    #[test]
+    /// Test that latencies are counted in power of two buckets of milliseconds
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.to_string(), "no blocks");
+
+        for millis in &[3, 4, 20, 1500] {
+            histogram.record(Duration::from_millis(*millis));
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.max(), Duration::from_millis(1500));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(4)));
+        assert_eq!(
+            histogram.to_string(),
+            "4 blocks, mean 381 ms, p50 <= 4 ms, p99 <= 2048 ms, max 1500 ms"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::address_types::ADDRESS_TYPES;
+use crate::sqlite_store::entity_writes;
+
+use common::addressing::{
+    get_address_type, make_agent_address, make_assertion_address, make_certificate_address,
+    make_organization_address, make_request_address, make_standard_address, AddressSpace,
+};
+use database::data_manager::OperationType;
+use rusqlite::types::Value as SqlValue;
+use serde_json::{json, Map, Value};
+
+/// Resolves the entity to inspect to its state address. The entity is given either
+/// as its address, or as its type and id, e.g. `certificate:cert_1`. Ids are the
+/// public key for agents and the id of every other entity.
+/// ```
+/// # Errors
+/// Returns an error if the address is not in the Certificate Registry namespace,
+/// or the type is not one of ADDRESS_TYPES
+/// ```
+pub fn resolve_address(target: &str) -> Result<String, String> {
+    if let Some(index) = target.find(':') {
+        let (kind, id) = (&target[..index], &target[index + 1..]);
+        let address = match kind {
+            "agent" => make_agent_address(id),
+            "organization" => make_organization_address(id),
+            "certificate" => make_certificate_address(id),
+            "request" => make_request_address(id),
+            "standard" => make_standard_address(id),
+            "assertion" => make_assertion_address(id),
+            _ => {
+                return Err(format!(
+                    "Unknown entity type {:?}, expected one of {}",
+                    kind,
+                    ADDRESS_TYPES.join(", ")
+                ))
+            }
+        };
+        return Ok(address);
+    }
+    let is_address = target.len() == 70
+        && target
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_uppercase());
+    if !is_address {
+        return Err(format!(
+            "{:?} is not a state address or <type>:<id>",
+            target
+        ));
+    }
+    match get_address_type(target) {
+        AddressSpace::AnotherFamily => Err(format!(
+            "{:?} is not a Certificate Registry address or <type>:<id>",
+            target
+        )),
+        _ => Ok(target.to_string()),
+    }
+}
+
+/// Returns the rows an operation writes to the reporting database as JSON, one
+/// object per row with its table and every column, in the order they are written
+pub fn operation_rows(operation: &OperationType) -> Vec<Value> {
+    entity_writes(operation)
+        .into_iter()
+        .flat_map(|write| write.inserts)
+        .map(|(table, columns)| {
+            let row: Map<String, Value> = columns
+                .into_iter()
+                .map(|(column, value)| (column.to_string(), sql_to_json(value)))
+                .collect();
+            json!({ "table": table, "row": row })
+        })
+        .collect()
+}
+
+fn sql_to_json(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(value) => json!(value),
+        SqlValue::Real(value) => json!(value),
+        SqlValue::Text(value) => json!(value),
+        SqlValue::Blob(value) => json!(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::data_manager::MAX_BLOCK_NUM;
+    use database::models::NewAgent;
+
+    #[test]
+    /// Test that entities are resolved from their address or their type and id
+    fn test_resolve_address() {
+        let address = make_agent_address("test_public_key");
+        assert_eq!(resolve_address(&address), Ok(address.clone()));
+        assert_eq!(resolve_address("agent:test_public_key"), Ok(address));
+        assert!(resolve_address("widget:test").is_err());
+        assert!(resolve_address(&"f".repeat(70)).is_err());
+        assert!(resolve_address(&address[..10]).is_err());
+    }
+
+    #[test]
+    /// Test that an operation is shown as the rows it writes, with every column
+    fn test_operation_rows() {
+        let operation = OperationType::CreateAgent(vec![NewAgent {
+            public_key: "test_public_key".to_string(),
+            organization_id: None,
+            name: "test".to_string(),
+            timestamp: 1,
+            start_block_num: 2,
+            end_block_num: MAX_BLOCK_NUM,
+        }]);
+        let rows = operation_rows(&operation);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["table"], "agents");
+        assert_eq!(rows[0]["row"]["public_key"], "test_public_key");
+        assert_eq!(rows[0]["row"]["organization_id"], Value::Null);
+        assert_eq!(rows[0]["row"]["start_block_num"], 2);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::errors::SubscriberError;
+
+use database::{
+    data_manager::{OperationType, MAX_BLOCK_NUM},
+    errors::DatabaseError,
+};
+use postgres::{Connection, TlsMode};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+/// How a failed integrity check is handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckMode {
+    /// The check is not run
+    Off,
+    /// A failed check is logged as a warning
+    Warn,
+    /// A failed check fails the block
+    Error,
+}
+
+impl Default for CheckMode {
+    fn default() -> Self {
+        CheckMode::Off
+    }
+}
+
+impl FromStr for CheckMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(CheckMode::Off),
+            "warn" => Ok(CheckMode::Warn),
+            "error" => Ok(CheckMode::Error),
+            _ => Err(format!(
+                "Invalid check mode {:?}, expected off, warn or error",
+                value
+            )),
+        }
+    }
+}
+
+/// A certificate referencing a standard version that is not in the reporting database
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingVersion {
+    pub certificate_id: String,
+    pub standard_id: String,
+    pub standard_version: String,
+    /// The versions the standard has, in order, so a format drift such as 1.0
+    /// against 1.0.0 shows in the message
+    pub known_versions: Vec<String>,
+}
+
+impl MissingVersion {
+    /// Describes the reference at the given block, with the standard's versions
+    pub fn describe(&self, block_num: i64) -> String {
+        let known = if self.known_versions.is_empty() {
+            "the standard has no versions there".to_string()
+        } else {
+            format!("its versions are {}", self.known_versions.join(", "))
+        };
+        format!(
+            "Certificate {} at block {} references version {} of standard {}, which is \
+             not in the reporting database: {}",
+            self.certificate_id, block_num, self.standard_version, self.standard_id, known
+        )
+    }
+}
+
+/// The standard versions written by a block. They are only recorded once the
+/// block is committed.
+#[derive(Debug, Default)]
+pub struct VersionUpdate {
+    pub missing: Vec<MissingVersion>,
+    standards: HashMap<String, BTreeSet<String>>,
+}
+
+/// Tracks the current versions of each standard in the reporting database, so
+/// certificates can be checked against them
+#[derive(Debug, Default)]
+pub struct StandardVersions {
+    standards: HashMap<String, BTreeSet<String>>,
+}
+
+impl StandardVersions {
+    /// Creates a tracker from the standard id and version of the current
+    /// standard_versions rows in the reporting database
+    pub fn from_rows<I: IntoIterator<Item = (String, String)>>(rows: I) -> StandardVersions {
+        let mut standards: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (standard_id, version) in rows {
+            standards.entry(standard_id).or_default().insert(version);
+        }
+        StandardVersions { standards }
+    }
+
+    /// Checks the certificates in a block's operations against the standard
+    /// versions, including the versions written earlier or later in the same block
+    pub fn check(&self, operations: &[OperationType]) -> VersionUpdate {
+        let mut update = VersionUpdate::default();
+        for operation in operations {
+            if let OperationType::CreateStandard(standards) = operation {
+                for (standard, versions) in standards {
+                    // A standard update replaces all of its versions
+                    update.standards.insert(
+                        standard.standard_id.clone(),
+                        versions
+                            .iter()
+                            .map(|version| version.version.clone())
+                            .collect(),
+                    );
+                }
+            }
+        }
+        for operation in operations {
+            if let OperationType::CreateCertificate(certificates) = operation {
+                for certificate in certificates {
+                    let versions = update
+                        .standards
+                        .get(&certificate.standard_id)
+                        .or_else(|| self.standards.get(&certificate.standard_id));
+                    let found = versions
+                        .map(|versions| versions.contains(&certificate.standard_version))
+                        .unwrap_or(false);
+                    if !found {
+                        update.missing.push(MissingVersion {
+                            certificate_id: certificate.certificate_id.clone(),
+                            standard_id: certificate.standard_id.clone(),
+                            standard_version: certificate.standard_version.clone(),
+                            known_versions: versions
+                                .map(|versions| versions.iter().cloned().collect())
+                                .unwrap_or_default(),
+                        });
+                    }
+                }
+            }
+        }
+        update
+    }
+
+    /// Records the standard versions from a committed block
+    pub fn record(&mut self, update: VersionUpdate) {
+        self.standards.extend(update.standards);
+    }
+}
+
+/// Which organization a standard or accreditation references
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceKind {
+    /// The standards body that authored a standard
+    StandardsBody,
+    /// The organization that accredited a certifying body
+    Accreditor,
+}
+
+/// A standard or accreditation referencing an organization that is not in the
+/// reporting database
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanglingReference {
+    pub kind: ReferenceKind,
+    /// The standard, or the accredited organization
+    pub referrer_id: String,
+    pub standard_id: String,
+    pub organization_id: String,
+}
+
+impl DanglingReference {
+    /// Describes the reference at the given block
+    pub fn describe(&self, block_num: i64) -> String {
+        match self.kind {
+            ReferenceKind::StandardsBody => format!(
+                "Standard {} at block {} names standards body {}, which is not in the \
+                 reporting database",
+                self.standard_id, block_num, self.organization_id
+            ),
+            ReferenceKind::Accreditor => format!(
+                "Organization {} at block {} is accredited for standard {} by {}, which is \
+                 not in the reporting database",
+                self.referrer_id, block_num, self.standard_id, self.organization_id
+            ),
+        }
+    }
+}
+
+/// The organizations written by a block. They are only recorded once the block
+/// is committed.
+#[derive(Debug, Default)]
+pub struct ReferenceUpdate {
+    pub dangling: Vec<DanglingReference>,
+    organizations: HashSet<String>,
+}
+
+/// Tracks the ids of the organizations in the reporting database, so the
+/// standards bodies of standards and the accreditors of accreditations can be
+/// checked against them
+#[derive(Debug, Default)]
+pub struct OrganizationIds {
+    organizations: HashSet<String>,
+}
+
+impl OrganizationIds {
+    /// Creates a tracker from the ids of the current organizations rows in the
+    /// reporting database
+    pub fn from_rows<I: IntoIterator<Item = String>>(rows: I) -> OrganizationIds {
+        OrganizationIds {
+            organizations: rows.into_iter().collect(),
+        }
+    }
+
+    /// Checks the organizations referenced by a block's standards and
+    /// accreditations, including organizations written earlier or later in the
+    /// same block
+    pub fn check(&self, operations: &[OperationType]) -> ReferenceUpdate {
+        let mut update = ReferenceUpdate::default();
+        for operation in operations {
+            if let OperationType::CreateOrganization(orgs) = operation {
+                for (org, _, _, _, _) in orgs {
+                    update.organizations.insert(org.organization_id.clone());
+                }
+            }
+        }
+        let known = |organization_id: &str| {
+            update.organizations.contains(organization_id)
+                || self.organizations.contains(organization_id)
+        };
+        let mut dangling = vec![];
+        for operation in operations {
+            match operation {
+                OperationType::CreateStandard(standards) => {
+                    for (standard, _) in standards {
+                        if !known(&standard.organization_id) {
+                            dangling.push(DanglingReference {
+                                kind: ReferenceKind::StandardsBody,
+                                referrer_id: standard.standard_id.clone(),
+                                standard_id: standard.standard_id.clone(),
+                                organization_id: standard.organization_id.clone(),
+                            });
+                        }
+                    }
+                }
+                OperationType::CreateOrganization(orgs) => {
+                    for (org, accreditations, _, _, _) in orgs {
+                        for accreditation in accreditations.iter().flatten() {
+                            if !known(&accreditation.accreditor_id) {
+                                dangling.push(DanglingReference {
+                                    kind: ReferenceKind::Accreditor,
+                                    referrer_id: org.organization_id.clone(),
+                                    standard_id: accreditation.standard_id.clone(),
+                                    organization_id: accreditation.accreditor_id.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        update.dangling = dangling;
+        update
+    }
+
+    /// Records the organizations from a committed block
+    pub fn record(&mut self, update: ReferenceUpdate) {
+        self.organizations.extend(update.organizations);
+    }
+}
+
+/// Reads the id of every current organizations row in the given schema of the
+/// reporting database
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried
+/// ```
+pub fn fetch_organization_ids(url: &str, schema: &str) -> Result<Vec<String>, SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT organization_id FROM \"{}\".\"organizations\" \
+                 WHERE end_block_num = $1",
+                schema
+            ),
+            &[&MAX_BLOCK_NUM],
+        )
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Reads the standard id and version of every current standard_versions row
+/// in the given schema of the reporting database
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried
+/// ```
+pub fn fetch_standard_versions(
+    url: &str,
+    schema: &str,
+) -> Result<Vec<(String, String)>, SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT standard_id, version FROM \"{}\".\"standard_versions\" \
+                 WHERE end_block_num = $1",
+                schema
+            ),
+            &[&MAX_BLOCK_NUM],
+        )
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::custom_types::OrganizationTypeEnum;
+    use database::models::{
+        NewAccreditation, NewCertificate, NewOrganization, NewStandard, NewStandardVersion,
+    };
+
+    const STANDARD_ID: &str = "test_standard";
+
+    #[test]
+    /// Test that certificates are checked against known versions and versions in the same block
+    fn test_check_standard_versions() {
+        let mut versions =
+            StandardVersions::from_rows(vec![(STANDARD_ID.to_string(), "1.0".to_string())]);
+        let update = versions.check(&[make_certificate_operation("cert_1", "1.0")]);
+        assert!(update.missing.is_empty());
+
+        let update = versions.check(&[
+            make_certificate_operation("cert_2", "2.0"),
+            make_standard_operation(&["1.0", "2.0"]),
+        ]);
+        assert!(update.missing.is_empty());
+        versions.record(update);
+
+        let update = versions.check(&[make_certificate_operation("cert_3", "3.0")]);
+        assert_eq!(
+            update.missing,
+            vec![MissingVersion {
+                certificate_id: "cert_3".to_string(),
+                standard_id: STANDARD_ID.to_string(),
+                standard_version: "3.0".to_string(),
+                known_versions: vec!["1.0".to_string(), "2.0".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    /// Test that a version string drifting from the standard's, such as 1.0 against
+    /// 1.0.0, is reported with both the referenced and the known versions
+    fn test_check_mismatched_version() {
+        let versions =
+            StandardVersions::from_rows(vec![(STANDARD_ID.to_string(), "1.0.0".to_string())]);
+        let update = versions.check(&[make_certificate_operation("cert_1", "1.0")]);
+        assert_eq!(update.missing.len(), 1);
+        let message = update.missing[0].describe(7);
+        assert!(message.contains("references version 1.0 of standard test_standard"));
+        assert!(message.contains("its versions are 1.0.0"));
+
+        let update =
+            StandardVersions::default().check(&[make_certificate_operation("cert_1", "1.0")]);
+        assert!(update.missing[0]
+            .describe(7)
+            .contains("the standard has no versions there"));
+    }
+
+    #[test]
+    /// Test that a standard referencing a missing standards body and an accreditation
+    /// by a missing accreditor are flagged, unless the organization is known or
+    /// written in the same block
+    fn test_check_organization_references() {
+        let mut organizations = OrganizationIds::from_rows(vec!["test_accreditor".to_string()]);
+        let update = organizations.check(&[make_standard_operation(&["1.0"])]);
+        assert_eq!(
+            update.dangling,
+            vec![DanglingReference {
+                kind: ReferenceKind::StandardsBody,
+                referrer_id: STANDARD_ID.to_string(),
+                standard_id: STANDARD_ID.to_string(),
+                organization_id: "test_standards_body".to_string(),
+            }]
+        );
+        assert_eq!(
+            update.dangling[0].describe(1),
+            "Standard test_standard at block 1 names standards body test_standards_body, \
+             which is not in the reporting database"
+        );
+
+        let update = organizations.check(&[
+            make_standard_operation(&["1.0"]),
+            make_organization_operation("test_standards_body", "test_accreditor"),
+        ]);
+        assert!(update.dangling.is_empty());
+        organizations.record(update);
+        assert!(organizations
+            .check(&[make_standard_operation(&["1.0"])])
+            .dangling
+            .is_empty());
+
+        let update =
+            organizations.check(&[make_organization_operation("test_cert_org", "missing")]);
+        assert_eq!(update.dangling.len(), 1);
+        assert_eq!(update.dangling[0].kind, ReferenceKind::Accreditor);
+        assert_eq!(update.dangling[0].referrer_id, "test_cert_org");
+        assert_eq!(update.dangling[0].organization_id, "missing");
+    }
+
+    #[test]
+    /// Test that check modes are parsed from their names
+    fn test_parse_check_mode() {
+        assert_eq!("off".parse::<CheckMode>().unwrap(), CheckMode::Off);
+        assert_eq!("warn".parse::<CheckMode>().unwrap(), CheckMode::Warn);
+        assert_eq!("error".parse::<CheckMode>().unwrap(), CheckMode::Error);
+        assert!("strict".parse::<CheckMode>().is_err());
+    }
+
+    fn make_certificate_operation(certificate_id: &str, version: &str) -> OperationType {
+        OperationType::CreateCertificate(vec![NewCertificate {
+            certificate_id: certificate_id.to_string(),
+            certifying_body_id: "test_cert_org".to_string(),
+            factory_id: "test_factory".to_string(),
+            standard_id: STANDARD_ID.to_string(),
+            standard_version: version.to_string(),
+            valid_from: 1,
+            valid_to: 2,
+            start_block_num: 1,
+            end_block_num: MAX_BLOCK_NUM,
+        }])
+    }
+
+    fn make_organization_operation(organization_id: &str, accreditor_id: &str) -> OperationType {
+        OperationType::CreateOrganization(vec![(
+            NewOrganization {
+                organization_id: organization_id.to_string(),
+                name: "test".to_string(),
+                organization_type: OrganizationTypeEnum::CertifyingBody,
+                start_block_num: 1,
+                end_block_num: MAX_BLOCK_NUM,
+            },
+            Some(vec![NewAccreditation {
+                organization_id: organization_id.to_string(),
+                standard_id: STANDARD_ID.to_string(),
+                standard_version: "1.0".to_string(),
+                accreditor_id: accreditor_id.to_string(),
+                valid_from: 1,
+                valid_to: 2,
+                start_block_num: 1,
+                end_block_num: MAX_BLOCK_NUM,
+            }]),
+            None,
+            vec![],
+            vec![],
+        )])
+    }
+
+    fn make_standard_operation(versions: &[&str]) -> OperationType {
+        OperationType::CreateStandard(vec![(
+            NewStandard {
+                standard_id: STANDARD_ID.to_string(),
+                organization_id: "test_standards_body".to_string(),
+                name: "test".to_string(),
+                start_block_num: 1,
+                end_block_num: MAX_BLOCK_NUM,
+            },
+            versions
+                .iter()
+                .map(|version| NewStandardVersion {
+                    standard_id: STANDARD_ID.to_string(),
+                    version: version.to_string(),
+                    link: "test".to_string(),
+                    description: "test".to_string(),
+                    approval_date: 1,
+                    start_block_num: 1,
+                    end_block_num: MAX_BLOCK_NUM,
+                })
+                .collect(),
+        )])
+    }
+}
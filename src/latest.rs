@@ -0,0 +1,626 @@
+use crate::errors::SubscriberError;
+use crate::schema_check::EXPECTED_TABLES;
+use crate::store::BlockStore;
+
+use database::{
+    data_manager::{OperationType, MAX_BLOCK_NUM},
+    errors::DatabaseError,
+    models::Block,
+};
+use rusqlite::types::Value;
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the reporting database keeps the versions of an entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageMode {
+    /// A change closes the current row of an entity and opens a new one, so
+    /// every version is kept
+    Versioned,
+    /// Rows closed by a change are deleted once the block is committed, or once
+    /// they fall out of the blocks kept for forks, so only the current row of
+    /// each entity is kept
+    Latest,
+}
+
+impl Default for StorageMode {
+    fn default() -> Self {
+        StorageMode::Versioned
+    }
+}
+
+impl FromStr for StorageMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "versioned" => Ok(StorageMode::Versioned),
+            "latest" => Ok(StorageMode::Latest),
+            _ => Err(format!(
+                "Invalid storage mode {:?}, expected versioned or latest",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StorageMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageMode::Versioned => write!(f, "versioned"),
+            StorageMode::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+/// Returns one DELETE statement for each versioned table, named by `table_name`.
+/// Each deletes the rows closed before the block number bound to the first
+/// parameter. Open rows hold MAX_BLOCK_NUM, bound to the second, and are kept.
+pub fn delete_closed_statements<F>(table_name: F, placeholders: (&str, &str)) -> Vec<String>
+where
+    F: Fn(&str) -> String,
+{
+    EXPECTED_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "blocks")
+        .map(|(table, _)| {
+            format!(
+                "DELETE FROM {} WHERE end_block_num < {} AND end_block_num <> {}",
+                table_name(table),
+                placeholders.0,
+                placeholders.1
+            )
+        })
+        .collect()
+}
+
+/// Returns one SELECT statement for each versioned table, named by `table_name`,
+/// counting the rows closed before the block number bound to the parameter
+pub fn count_closed_statements<F>(table_name: F, placeholder: &str) -> Vec<String>
+where
+    F: Fn(&str) -> String,
+{
+    EXPECTED_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "blocks")
+        .map(|(table, _)| {
+            format!(
+                "SELECT COUNT(*) FROM {} WHERE end_block_num < {}",
+                table_name(table),
+                placeholder
+            )
+        })
+        .collect()
+}
+
+/// The closed rows of a reporting database
+pub trait ClosedRows {
+    /// Returns the number of rows closed before the given block
+    fn count_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError>;
+
+    /// Deletes the rows closed before the given block in one transaction,
+    /// returning the number of rows deleted
+    fn delete_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError>;
+
+    /// Replaces the connection to the database after it was lost
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+/// The closed rows of the reporting tables in a Postgres schema
+pub struct PostgresClosedRows {
+    url: String,
+    connection: RefCell<postgres::Connection>,
+    statements: Vec<String>,
+    count_statements: Vec<String>,
+}
+
+impl PostgresClosedRows {
+    /// Connects to the database holding the reporting tables in the schema
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be connected to
+    /// ```
+    pub fn connect(url: &str, schema: &str) -> Result<PostgresClosedRows, SubscriberError> {
+        let connection = postgres::Connection::connect(url, postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(PostgresClosedRows {
+            url: url.to_string(),
+            connection: RefCell::new(connection),
+            statements: delete_closed_statements(
+                |table| format!("\"{}\".\"{}\"", schema, table),
+                ("$1", "$2"),
+            ),
+            count_statements: count_closed_statements(
+                |table| format!("\"{}\".\"{}\"", schema, table),
+                "$1",
+            ),
+        })
+    }
+}
+
+impl ClosedRows for PostgresClosedRows {
+    fn count_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut closed = 0;
+        for statement in &self.count_statements {
+            let count: i64 = connection
+                .query(statement, &[&block_num])
+                .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?
+                .get(0)
+                .get(0);
+            closed += count as u64;
+        }
+        Ok(closed)
+    }
+
+    fn delete_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError> {
+        let to_database_error =
+            |err: postgres::Error| DatabaseError::ConnectionError(Box::new(err));
+        let connection = self.connection.borrow();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        let mut deleted = 0;
+        for statement in &self.statements {
+            deleted += transaction
+                .execute(statement, &[&block_num, &MAX_BLOCK_NUM])
+                .map_err(to_database_error)?;
+        }
+        transaction.commit().map_err(to_database_error)?;
+        Ok(deleted)
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        let connection = postgres::Connection::connect(self.url.as_str(), postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        *self.connection.borrow_mut() = connection;
+        Ok(())
+    }
+}
+
+/// The closed rows of the reporting tables in a SQLite file, each name starting
+/// with the prefix
+pub struct SqliteClosedRows {
+    connection: RefCell<rusqlite::Connection>,
+    statements: Vec<String>,
+    count_statements: Vec<String>,
+}
+
+impl SqliteClosedRows {
+    /// Opens the SQLite database at the given path, which must already hold the
+    /// reporting tables
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be opened
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, prefix: &str) -> Result<SqliteClosedRows, DatabaseError> {
+        let connection = rusqlite::Connection::open(path).map_err(to_database_error)?;
+        Ok(SqliteClosedRows {
+            connection: RefCell::new(connection),
+            statements: delete_closed_statements(
+                |table| format!("{}{}", prefix, table),
+                ("?", "?"),
+            ),
+            count_statements: count_closed_statements(|table| format!("{}{}", prefix, table), "?"),
+        })
+    }
+}
+
+impl ClosedRows for SqliteClosedRows {
+    fn count_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut closed = 0;
+        for statement in &self.count_statements {
+            let count: i64 = connection
+                .query_row(statement, &[Value::Integer(block_num)], |row| row.get(0))
+                .map_err(to_database_error)?;
+            closed += count as u64;
+        }
+        Ok(closed)
+    }
+
+    fn delete_closed_before(&self, block_num: i64) -> Result<u64, DatabaseError> {
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        let mut deleted = 0;
+        for statement in &self.statements {
+            deleted += transaction
+                .execute(
+                    statement,
+                    &[Value::Integer(block_num), Value::Integer(MAX_BLOCK_NUM)],
+                )
+                .map_err(to_database_error)? as u64;
+        }
+        transaction.commit().map_err(to_database_error)?;
+        Ok(deleted)
+    }
+}
+
+fn to_database_error(err: rusqlite::Error) -> DatabaseError {
+    DatabaseError::ConnectionError(Box::new(err))
+}
+
+/// Keeps only the current row of each entity: applies each block to the reporting
+/// database as the wrapped store does, then deletes the rows closed more than
+/// `keep_blocks` blocks before it. With no blocks kept, the rows the block closed
+/// are deleted at once.
+///
+/// The rows are deleted in a transaction of their own after the block commits. If
+/// the subscriber stops in between, the rows are left closed, so they are deleted
+/// when the store is created, before any block is applied. Deleting closed rows
+/// is safe to repeat, so the latest known block applied again after resuming is
+/// handled like any other.
+///
+/// A fork is rolled back by the wrapped store, reopening the rows the abandoned
+/// blocks closed, so it can be applied only if none of those rows was deleted. A
+/// fork within the kept blocks is applied as in versioned mode. A deeper one is
+/// refused, since entities changed only on the abandoned blocks would be lost.
+pub struct LatestStore {
+    store: Box<dyn BlockStore>,
+    closed_rows: Box<dyn ClosedRows>,
+    keep_blocks: i64,
+    latest_block: RefCell<Option<(i64, String)>>,
+    /// The last block whose closed rows may have been deleted, at or below which
+    /// a fork can't be rolled back
+    deleted_through: RefCell<Option<i64>>,
+}
+
+impl LatestStore {
+    /// Wraps the store, first deleting the rows closed more than `keep_blocks`
+    /// blocks before the latest known block. Rows closed before that are the
+    /// history of their entities, and are deleted only if `discard_history` is set.
+    /// ```
+    /// # Errors
+    /// Returns an error if the database holds history and `discard_history` is not
+    /// set, or the closed rows or known blocks cannot be read or deleted
+    /// ```
+    pub fn new(
+        store: Box<dyn BlockStore>,
+        closed_rows: Box<dyn ClosedRows>,
+        keep_blocks: i64,
+        discard_history: bool,
+    ) -> Result<LatestStore, DatabaseError> {
+        let latest_block = store
+            .fetch_known_blocks()?
+            .into_iter()
+            .max_by_key(|block| block.block_num)
+            .map(|block| (block.block_num, block.block_id));
+        let deleted_through = latest_block
+            .as_ref()
+            .map(|(block_num, _)| block_num - keep_blocks);
+        // Rows closed by the last block to delete were left by a stop before they
+        // were deleted, and are deleted without asking
+        let history = closed_rows.count_closed_before(deleted_through.unwrap_or(MAX_BLOCK_NUM))?;
+        if history > 0 && !discard_history {
+            return Err(DatabaseError::ConnectionError(
+                format!(
+                    "The reporting database holds {} closed rows, the history of its \
+                     entities, which --storage-mode latest would delete. Pass \
+                     --discard-history to delete them",
+                    history
+                )
+                .into(),
+            ));
+        }
+        let deleted = closed_rows.delete_closed_before(
+            deleted_through.map_or(MAX_BLOCK_NUM, |block_num| block_num + 1),
+        )?;
+        if deleted > 0 {
+            info!(
+                "Deleted {} closed rows, keeping the latest versions",
+                deleted
+            );
+        }
+        Ok(LatestStore {
+            store,
+            closed_rows,
+            keep_blocks,
+            latest_block: RefCell::new(latest_block),
+            deleted_through: RefCell::new(deleted_through),
+        })
+    }
+
+    /// Deletes the rows closed more than `keep_blocks` blocks before the block,
+    /// the latest applied
+    fn delete_closed_rows(&self, block_num: i64) -> Result<(), DatabaseError> {
+        let through = block_num - self.keep_blocks;
+        let deleted = self.closed_rows.delete_closed_before(through + 1)?;
+        if deleted > 0 {
+            debug!("Deleted {} rows closed through block {}", deleted, through);
+        }
+        let mut deleted_through = self.deleted_through.borrow_mut();
+        *deleted_through = Some(deleted_through.map_or(through, |last| last.max(through)));
+        Ok(())
+    }
+
+    /// Checks that the block can be applied: it is above the last block whose
+    /// closed rows may have been deleted, or is the latest block applied again
+    fn check_fork(
+        &self,
+        latest_block: &Option<(i64, String)>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        let deleted_through = match *self.deleted_through.borrow() {
+            Some(deleted_through) if block.block_num <= deleted_through => deleted_through,
+            _ => return Ok(()),
+        };
+        match latest_block {
+            Some((block_num, block_id))
+                if block.block_num == *block_num && block.block_id == *block_id =>
+            {
+                Ok(())
+            }
+            _ => Err(DatabaseError::ConnectionError(
+                format!(
+                    "Block {} {} forks the chain at or before block {}, whose closed rows \
+                     are already deleted with --storage-mode latest, so the fork can't be \
+                     applied. Rebuild the reporting database to follow the new chain, or \
+                     keep more blocks with --latest-keep-blocks",
+                    block.block_num, block.block_id, deleted_through
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+impl BlockStore for LatestStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        let block_num = block.block_num;
+        self.check_fork(&self.latest_block.borrow(), block)?;
+        self.store.execute_operations_in_block(operations, block)?;
+        *self.latest_block.borrow_mut() = Some((block_num, block.block_id.clone()));
+        self.delete_closed_rows(block_num)
+    }
+
+    /// Applies the blocks as the wrapped store does, so a batch stays in one
+    /// transaction, then deletes the rows closed more than `keep_blocks` blocks
+    /// before the last of them
+    fn execute_operations_in_blocks(
+        &self,
+        blocks: Vec<(Vec<OperationType>, Block)>,
+    ) -> Result<(), DatabaseError> {
+        let mut latest_block = self.latest_block.borrow().clone();
+        for (_, block) in &blocks {
+            self.check_fork(&latest_block, block)?;
+            latest_block = Some((block.block_num, block.block_id.clone()));
+        }
+        self.store.execute_operations_in_blocks(blocks)?;
+        *self.latest_block.borrow_mut() = latest_block.clone();
+        match latest_block {
+            Some((block_num, _)) => self.delete_closed_rows(block_num),
+            None => Ok(()),
+        }
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        self.store.fetch_known_blocks()
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        self.closed_rows.reconnect()?;
+        self.store.reconnect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_store::SqliteStore;
+    use crate::store::test_support::{make_agent_operation, make_block};
+    use rusqlite::NO_PARAMS;
+
+    #[test]
+    /// Test that the storage modes parse from their names, defaulting to versioned
+    fn test_storage_mode() {
+        assert_eq!("latest".parse(), Ok(StorageMode::Latest));
+        assert_eq!("versioned".parse(), Ok(StorageMode::Versioned));
+        assert!("history".parse::<StorageMode>().is_err());
+        assert_eq!(StorageMode::default().to_string(), "versioned");
+    }
+
+    #[test]
+    /// Test that only the current row of an entity is kept, rows already closed are
+    /// deleted when the store is created only if history may be discarded, and the
+    /// known blocks are kept for resuming
+    fn test_latest_store() {
+        let path = std::env::temp_dir().join(format!("sds-latest-{}.db", uuid::Uuid::new_v4()));
+        let versioned = SqliteStore::open(&path).unwrap();
+        for (block_num, name) in [(1, "first"), (2, "second"), (3, "third")].iter() {
+            versioned
+                .execute_operations_in_block(
+                    vec![make_agent_operation(name, *block_num)],
+                    &make_block(*block_num),
+                )
+                .unwrap();
+        }
+        assert_eq!(agent_rows(&path).len(), 3);
+
+        let refused = LatestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            0,
+            false,
+        );
+        assert!(refused.is_err());
+        assert_eq!(agent_rows(&path).len(), 3);
+
+        let latest = LatestStore::new(
+            Box::new(versioned),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(agent_rows(&path), vec![("third".to_string(), 3)]);
+
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("fourth", 4)], &make_block(4))
+            .unwrap();
+        latest
+            .execute_operations_in_blocks(vec![
+                (vec![make_agent_operation("fifth", 5)], make_block(5)),
+                (vec![make_agent_operation("sixth", 6)], make_block(6)),
+            ])
+            .unwrap();
+        assert_eq!(agent_rows(&path), vec![("sixth".to_string(), 6)]);
+
+        // Applying the last block again, as after resuming, leaves the same row
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("sixth", 6)], &make_block(6))
+            .unwrap();
+        assert_eq!(agent_rows(&path), vec![("sixth".to_string(), 6)]);
+        assert_eq!(latest.fetch_known_blocks().unwrap().len(), 6);
+        drop(latest);
+
+        // A stop between a block's commit and the deletion leaves the rows closed by
+        // the latest known block, which are deleted without --discard-history
+        let versioned = SqliteStore::open(&path).unwrap();
+        versioned
+            .execute_operations_in_block(vec![make_agent_operation("seventh", 7)], &make_block(7))
+            .unwrap();
+        let latest = LatestStore::new(
+            Box::new(versioned),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            0,
+            false,
+        )
+        .unwrap();
+        assert_eq!(agent_rows(&path), vec![("seventh".to_string(), 7)]);
+
+        drop(latest);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that without kept blocks, a block forking the chain below the latest
+    /// known block, or at it with another id, is refused without writing it
+    fn test_latest_store_refuses_fork() {
+        let path = std::env::temp_dir().join(format!("sds-latest-{}.db", uuid::Uuid::new_v4()));
+        let latest = LatestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            0,
+            false,
+        )
+        .unwrap();
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+
+        let fork = Block {
+            block_num: 2,
+            block_id: "test_fork_2".to_string(),
+        };
+        assert!(latest
+            .execute_operations_in_block(vec![make_agent_operation("fork", 2)], &fork)
+            .is_err());
+        assert!(latest
+            .execute_operations_in_blocks(vec![
+                (vec![make_agent_operation("third", 3)], make_block(3)),
+                (vec![make_agent_operation("fork", 1)], make_block(1)),
+            ])
+            .is_err());
+        assert_eq!(agent_rows(&path), vec![("second".to_string(), 2)]);
+
+        // The latest known block is read back when the store is created again
+        drop(latest);
+        let latest = LatestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            0,
+            false,
+        )
+        .unwrap();
+        assert!(latest
+            .execute_operations_in_block(vec![make_agent_operation("fork", 2)], &fork)
+            .is_err());
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("third", 3)], &make_block(3))
+            .unwrap();
+        assert_eq!(agent_rows(&path), vec![("third".to_string(), 3)]);
+
+        drop(latest);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that a fork within the kept blocks is rolled back and applied, the rows
+    /// closed more than the kept blocks ago are deleted, and a fork at or below
+    /// the last block whose closed rows were deleted is refused
+    fn test_latest_store_keeps_blocks_for_forks() {
+        let path = std::env::temp_dir().join(format!("sds-latest-{}.db", uuid::Uuid::new_v4()));
+        let latest = LatestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Box::new(SqliteClosedRows::open(&path, "").unwrap()),
+            2,
+            false,
+        )
+        .unwrap();
+        for (block_num, name) in [(1, "first"), (2, "second"), (3, "third"), (4, "fourth")].iter() {
+            latest
+                .execute_operations_in_block(
+                    vec![make_agent_operation(name, *block_num)],
+                    &make_block(*block_num),
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            agent_rows(&path),
+            vec![
+                ("second".to_string(), 2),
+                ("third".to_string(), 3),
+                ("fourth".to_string(), 4)
+            ]
+        );
+
+        let fork = Block {
+            block_num: 3,
+            block_id: "test_fork_3".to_string(),
+        };
+        latest
+            .execute_operations_in_block(vec![make_agent_operation("fork", 3)], &fork)
+            .unwrap();
+        assert_eq!(
+            agent_rows(&path),
+            vec![("second".to_string(), 2), ("fork".to_string(), 3)]
+        );
+
+        // The rows closed by block 2 were deleted after block 4, so a fork there
+        // can't be rolled back, even once the chain is back at block 3
+        let deep_fork = Block {
+            block_num: 2,
+            block_id: "test_fork_2".to_string(),
+        };
+        assert!(latest
+            .execute_operations_in_block(vec![make_agent_operation("fork", 2)], &deep_fork)
+            .is_err());
+        assert_eq!(
+            agent_rows(&path),
+            vec![("second".to_string(), 2), ("fork".to_string(), 3)]
+        );
+
+        drop(latest);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn agent_rows(path: &Path) -> Vec<(String, i64)> {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare("SELECT name, start_block_num FROM agents ORDER BY start_block_num")
+            .unwrap();
+        let rows = statement
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate log;
+
+pub mod address_check;
+pub mod address_types;
+pub mod audit;
+pub mod commit;
+pub mod config;
+pub mod dsn;
+pub mod duration;
+pub mod entity_ref;
+pub mod errors;
+pub mod event_handler;
+pub mod event_log;
+pub mod event_types;
+pub mod fan_out;
+pub mod graph;
+pub mod histogram;
+pub mod inspect;
+pub mod integrity;
+pub mod latest;
+pub mod limits;
+pub mod liveness;
+pub mod log_level;
+pub mod progress;
+pub mod proto_contract;
+pub mod prune;
+pub mod queue;
+pub mod rebuild;
+pub mod reingest;
+pub mod replay;
+pub mod resume;
+pub mod resume_file;
+pub mod retry;
+pub mod schema_check;
+pub mod sequence;
+pub mod shard;
+pub mod shard_claim;
+pub mod snapshot;
+pub mod sqlite_store;
+pub mod status;
+pub mod stderr_logger;
+pub mod store;
+pub mod strict;
+pub mod subscriber;
+pub mod subscription_state;
+pub mod throughput;
+pub mod transformer;
+pub mod transitions;
+pub mod verify;
+pub mod webhook;
@@ -0,0 +1,63 @@
+use crate::errors::SubscriberError;
+
+/// The default largest event list accepted, in bytes, before or after decompression
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+/// The default largest number of events accepted in one event list
+pub const DEFAULT_MAX_EVENTS: usize = 10_000;
+/// The default largest number of namespace state changes accepted for one block
+pub const DEFAULT_MAX_STATE_CHANGES: usize = 100_000;
+/// The default deepest nesting of messages accepted while decoding. The deepest
+/// message the subscriber decodes nests about four levels down.
+pub const DEFAULT_MAX_DECODE_DEPTH: u32 = 32;
+
+/// Bounds on the size of a single event list, so one block with enormous state
+/// fails with a clear error instead of exhausting memory while it is decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventLimits {
+    /// The largest payload accepted, in bytes. Compressed payloads are bounded both
+    /// as received and as inflated.
+    pub max_message_bytes: usize,
+    /// The largest number of events in one event list
+    pub max_events: usize,
+    /// The largest number of state changes in the namespace for one block
+    pub max_state_changes: usize,
+    /// The deepest nesting of messages accepted while decoding
+    pub max_decode_depth: u32,
+}
+
+impl Default for EventLimits {
+    fn default() -> Self {
+        EventLimits {
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            max_events: DEFAULT_MAX_EVENTS,
+            max_state_changes: DEFAULT_MAX_STATE_CHANGES,
+            max_decode_depth: DEFAULT_MAX_DECODE_DEPTH,
+        }
+    }
+}
+
+impl EventLimits {
+    /// Returns an error naming the limit if count is over max
+    pub fn check(what: &str, count: usize, max: usize) -> Result<(), SubscriberError> {
+        if count > max {
+            return Err(SubscriberError::EventParseError(format!(
+                "Refusing event list with more than {} {} ({}), raise the limit to accept it",
+                max, what, count
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that counts up to the limit are accepted and larger counts are refused
+    fn test_check() {
+        assert!(EventLimits::check("events", 10, 10).is_ok());
+        let err = EventLimits::check("events", 11, 10).unwrap_err();
+        assert!(err.to_string().contains("more than 10 events"));
+    }
+}
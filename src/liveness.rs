@@ -0,0 +1,104 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What the validator has sent within the liveness timeout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Liveness {
+    /// A block was committed, or the subscription was (re)started, within the timeout
+    Active,
+    /// No block was committed, but settings heartbeats arrived, so the connection is
+    /// alive and the chain is just quiet
+    Quiet,
+    /// Nothing arrived within the timeout, so the validator may be wedged
+    Silent,
+}
+
+impl fmt::Display for Liveness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Liveness::Active => write!(f, "active"),
+            Liveness::Quiet => write!(f, "quiet but alive"),
+            Liveness::Silent => write!(f, "silent"),
+        }
+    }
+}
+
+/// Tracks when the validator last sent a block and a heartbeat, to tell a quiet
+/// chain from a validator that has stopped sending anything
+#[derive(Debug, Clone)]
+pub struct LivenessTracker {
+    since: Instant,
+    last_block: Option<Instant>,
+    last_heartbeat: Option<Instant>,
+}
+
+impl LivenessTracker {
+    pub fn new(now: Instant) -> LivenessTracker {
+        LivenessTracker {
+            since: now,
+            last_block: None,
+            last_heartbeat: None,
+        }
+    }
+
+    /// Records that a block was received at the given time
+    pub fn record_block(&mut self, now: Instant) {
+        self.last_block = Some(now);
+    }
+
+    /// Records that a heartbeat was received at the given time
+    pub fn record_heartbeat(&mut self, now: Instant) {
+        self.last_heartbeat = Some(now);
+    }
+
+    /// Starts a new timeout at the given time, e.g. after the validator answered a
+    /// probe, as if a block had just been received
+    pub fn reset(&mut self, now: Instant) {
+        self.since = now;
+    }
+
+    /// Returns what the validator has sent within the timeout before the given time
+    pub fn check(&self, now: Instant, timeout: Duration) -> Liveness {
+        let within = |at: Instant| now.duration_since(at) < timeout;
+        if within(self.since) || self.last_block.map_or(false, within) {
+            Liveness::Active
+        } else if self.last_heartbeat.map_or(false, within) {
+            Liveness::Quiet
+        } else {
+            Liveness::Silent
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that heartbeat-only traffic keeps the validator quiet but alive, and it
+    /// only turns silent once the heartbeats stop too
+    fn test_heartbeats_keep_validator_alive() {
+        let timeout = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut tracker = LivenessTracker::new(start);
+        tracker.record_block(start);
+        assert_eq!(tracker.check(start, timeout), Liveness::Active);
+
+        for minute in 1..10 {
+            let now = start + Duration::from_secs(minute * 60);
+            tracker.record_heartbeat(now - Duration::from_secs(10));
+            assert_eq!(tracker.check(now, timeout), Liveness::Quiet);
+        }
+
+        let last_heartbeat = start + Duration::from_secs(9 * 60 - 10);
+        assert_eq!(
+            tracker.check(last_heartbeat + timeout, timeout),
+            Liveness::Silent
+        );
+        tracker.reset(last_heartbeat + timeout);
+        assert_eq!(
+            tracker.check(last_heartbeat + timeout, timeout),
+            Liveness::Active
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use log::LogLevel;
+use std::env;
+
+/// The environment variable setting the log level, overriding -v
+pub const LOG_LEVEL_ENV: &str = "CREG_LOG_LEVEL";
+/// Read when LOG_LEVEL_ENV is unset, if it holds a single level
+pub const RUST_LOG_ENV: &str = "RUST_LOG";
+
+/// Reads the log level set in the environment, as described by env_level
+/// ```
+/// # Errors
+/// Returns an error if CREG_LOG_LEVEL is set to an unknown level
+/// ```
+pub fn from_env() -> Result<Option<LogLevel>, String> {
+    let creg_log_level = env::var(LOG_LEVEL_ENV).ok();
+    let rust_log = env::var(RUST_LOG_ENV).ok();
+    env_level(
+        creg_log_level.as_ref().map(String::as_str),
+        rust_log.as_ref().map(String::as_str),
+    )
+}
+
+/// Returns the log level set by the given environment values, if any. CREG_LOG_LEVEL takes
+/// precedence over RUST_LOG. RUST_LOG is only used if it is a plain level such as
+/// `debug`, since per-module directives can't be honored by this logger.
+/// ```
+/// # Errors
+/// Returns an error if CREG_LOG_LEVEL is set to an unknown level
+/// ```
+pub fn env_level(
+    creg_log_level: Option<&str>,
+    rust_log: Option<&str>,
+) -> Result<Option<LogLevel>, String> {
+    match creg_log_level
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        Some(value) => value.parse::<LogLevel>().map(Some).map_err(|_| {
+            format!(
+                "Invalid {} {:?}, expected error, warn, info, debug or trace",
+                LOG_LEVEL_ENV, value
+            )
+        }),
+        None => Ok(rust_log.and_then(|value| value.trim().parse::<LogLevel>().ok())),
+    }
+}
+
+/// Selects the log level. A level set in the environment overrides the -v count;
+/// otherwise -v sets it, and --verbose-sql raises it to debug if -v wasn't given.
+pub fn select_level(env_level: Option<LogLevel>, verbosity: u64, verbose_sql: bool) -> LogLevel {
+    if let Some(level) = env_level {
+        return level;
+    }
+    match verbosity {
+        1 => LogLevel::Info,
+        2 => LogLevel::Debug,
+        // Database operations are logged at debug level
+        _ if verbose_sql => LogLevel::Debug,
+        _ => LogLevel::Warn,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that CREG_LOG_LEVEL takes precedence over RUST_LOG, and a RUST_LOG with
+    /// module directives is ignored
+    fn test_env_level() {
+        assert_eq!(env_level(None, None), Ok(None));
+        assert_eq!(env_level(Some("DEBUG"), None), Ok(Some(LogLevel::Debug)));
+        assert_eq!(
+            env_level(Some("error"), Some("trace")),
+            Ok(Some(LogLevel::Error))
+        );
+        assert_eq!(env_level(Some(""), Some("info")), Ok(Some(LogLevel::Info)));
+        assert_eq!(env_level(None, Some("consensource_sds=debug")), Ok(None));
+        assert!(env_level(Some("loud"), None).is_err());
+    }
+
+    #[test]
+    /// Test that a level from the environment overrides -v and --verbose-sql
+    fn test_select_level() {
+        assert_eq!(select_level(None, 0, false), LogLevel::Warn);
+        assert_eq!(select_level(None, 1, false), LogLevel::Info);
+        assert_eq!(select_level(None, 0, true), LogLevel::Debug);
+        assert_eq!(select_level(None, 1, true), LogLevel::Info);
+        assert_eq!(
+            select_level(Some(LogLevel::Error), 2, true),
+            LogLevel::Error
+        );
+    }
+}
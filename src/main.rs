@@ -1,17 +1,59 @@
 #[macro_use]
 extern crate log;
 
-mod errors;
-mod event_handler;
-mod subscriber;
-mod transformer;
-
-use clap::{clap_app, crate_version};
+use clap::{clap_app, crate_version, ArgMatches};
+use consensource_sds::address_check::AddressReport;
+use consensource_sds::address_types::{self, AddressTypes};
+use consensource_sds::audit::{AuditLog, Redaction};
+use consensource_sds::commit::CommitInterval;
+use consensource_sds::config::{
+    DatabaseConfig, EffectiveConfig, EventConfig, OutputConfig, Secret, ValidatorConfig,
+};
+use consensource_sds::dsn::{self, ConnectionOptions, IsolationLevel};
+use consensource_sds::duration::parse_duration;
+use consensource_sds::event_handler::{validate_block_num, EventHandler, EventHandlerOptions};
+use consensource_sds::event_log::{
+    self, EventLogStore, EventTable, PostgresEventTable, SqliteEventTable,
+};
+use consensource_sds::event_types::{EventTypes, BLOCK_COMMIT_EVENT, STATE_DELTA_EVENT};
+use consensource_sds::fan_out::{FanOutPolicy, FanOutStore};
+use consensource_sds::graph;
+use consensource_sds::inspect;
+use consensource_sds::integrity::{self, CheckMode, OrganizationIds, StandardVersions};
+use consensource_sds::latest::{
+    ClosedRows, LatestStore, PostgresClosedRows, SqliteClosedRows, StorageMode,
+};
+use consensource_sds::limits::{self, EventLimits};
+use consensource_sds::log_level;
+use consensource_sds::prune;
+use consensource_sds::rebuild;
+use consensource_sds::reingest::{PostgresReingestTarget, ReingestStore, ReingestTarget, Reset};
+use consensource_sds::replay;
+use consensource_sds::resume::{self, UnknownBlocksPolicy};
+use consensource_sds::resume_file::ResumeFile;
+use consensource_sds::retry::RetryPolicy;
+use consensource_sds::schema_check;
+use consensource_sds::shard::Shard;
+use consensource_sds::shard_claim;
+use consensource_sds::snapshot::{PostgresReplacedRows, ReplacedRowsStore};
+use consensource_sds::sqlite_store::{self, SqliteStore};
+use consensource_sds::stderr_logger;
+use consensource_sds::store::{BlockStore, ReconnectingStore};
+use consensource_sds::strict::Strictness;
+use consensource_sds::subscriber::{Subscriber, SubscriberOptions};
+use consensource_sds::verify::{PostgresRowReader, RowReader, Verifier};
+use consensource_sds::webhook::{self, Webhook, WebhookOptions};
 use database::data_manager::DataManager;
-use event_handler::EventHandler;
+use database::models::Block;
 use log::LogLevel;
-use std::sync::atomic::Ordering;
-use subscriber::Subscriber;
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Entry point for the subscriber
 /// Establish a connection with the reporting database and fetches
@@ -25,44 +67,655 @@ fn main() {
         (@arg connect: default_value("tcp://localhost:4004") -C --connect +takes_value
            "connection endpoint for validator")
         (@arg verbose: -v --verbose +multiple
-           "increase output verbosity")
+           "increase output verbosity, overridden by CREG_LOG_LEVEL")
+        (@arg db_backend: default_value("postgres") --("db-backend") +takes_value
+            possible_value[postgres sqlite] "the kind of reporting database")
+        (@arg dbpath: --dbpath +takes_value
+            "the file of the SQLite reporting database, for --db-backend sqlite")
+        (@arg table_prefix: --("table-prefix") +takes_value
+            "start every reporting table name with this prefix, for --db-backend sqlite")
+        (@arg event_log: --("event-log")
+            "also append every entity written to the append-only operation_events table")
+        (@arg storage_mode: default_value("versioned") --("storage-mode") +takes_value
+            possible_value[versioned latest]
+            "versioned keeps every version of an entity, latest only its current row")
+        (@arg discard_history: --("discard-history")
+            "with --storage-mode latest, delete the closed rows already in the database")
+        (@arg latest_keep_blocks: --("latest-keep-blocks") +takes_value
+            "with --storage-mode latest, keep rows closed in this many recent blocks for forks")
         (@arg dbname: default_value("consensourcedb") --dbname +takes_value
            "the name of the database")
-        (@arg dbhost: default_value("localhost") --dbhost +takes_value
-            "the host of the database")
+        (@arg dbhost: default_value("localhost") --dbhost +takes_value +multiple
+            number_of_values(1) "the host of the database, repeat to write to several")
+        (@arg fan_out_policy: default_value("all") --("fan-out-policy") +takes_value
+            "all or best-effort: whether a block failing on a database after the first fails")
         (@arg dbport: default_value("5432") --dbport +takes_value
             "the port of the database")
+        (@arg read_dbhost: --("read-dbhost") +takes_value
+            "the host of a replica to read from, defaults to --dbhost")
+        (@arg read_dbport: --("read-dbport") +takes_value
+            "the port of the replica to read from, defaults to --dbport")
         (@arg dbuser: default_value("consensourcedb") --dbuser +takes_value
             "the authorized user of the database")
         (@arg dbpass: default_value("consensourcedb") --dbpass +takes_value
-            "the authorized user's password for database access"))
+            "the authorized user's password for database access")
+        (@arg dbschema: default_value(dsn::DEFAULT_SCHEMA) --dbschema +takes_value
+            "the schema holding the reporting tables")
+        (@arg db_isolation: --("db-isolation") +takes_value
+            "read-committed, repeatable-read or serializable: the isolation of block transactions")
+        (@arg apply_timeout: default_value("0") --("apply-timeout") +takes_value
+            "seconds a block's database statements may run before the block fails, or 0 for no limit")
+        (@arg once: --once
+            "process a single block, then unsubscribe and exit")
+        (@arg shard: --shard +takes_value
+            "only handle the <n>/<total> shard of the namespace, where n is zero based")
+        (@arg address_types: --("address-types") +takes_value
+            "comma separated address types to subscribe to, e.g. agent,certificate, defaults to all")
+        (@arg progress_interval: default_value("30") --("progress-interval") +takes_value
+            "seconds between catch-up progress logs, or 0 to disable")
+        (@arg throughput_window: default_value("60s") --("throughput-window") +takes_value
+            "window of the blocks/sec and events/sec moving averages, e.g. 30s or 5m")
+        (@arg block_latency_warning: --("block-latency-warning") +takes_value
+            "warn when a block takes longer than this from receipt to commit, e.g. 5s")
+        (@arg audit_log: --("audit-log") +takes_value
+            "append a record of every applied operation to this file")
+        (@arg block_commit_event: default_value(BLOCK_COMMIT_EVENT) --("block-commit-event")
+            +takes_value "the event type of block-commit events")
+        (@arg state_delta_event: default_value(STATE_DELTA_EVENT) --("state-delta-event")
+            +takes_value "the event type of state-delta events")
+        (@arg max_runtime: --("max-runtime") +takes_value
+            "stop cleanly after this long, e.g. 90s, 15m or 2h")
+        (@arg liveness_timeout: default_value("5m") --("liveness-timeout") +takes_value
+            "probe the validator after this long without events, or 0 to disable")
+        (@arg verify_only: --("verify-only") conflicts_with[truncate replay_apply]
+            "compare each block with what the reporting database holds and report divergences, never writing")
+        (@arg check_schema: --("check-schema")
+            "check the reporting database has every column the subscriber writes, then exit")
+        (@arg export_graph: --("export-graph") +takes_value
+            "write a Graphviz DOT graph of the reporting entities and their references to this file, then exit")
+        (@arg strict_transitions: --("strict-transitions")
+            "fail instead of warning when a request status moves backwards")
+        (@arg stdout_ndjson: --("stdout-ndjson")
+            "also write every applied operation to stdout as newline-delimited JSON")
+        (@arg redact_fields: --("redact-fields") +takes_value
+            "comma separated fields, e.g. name,phone_number, masked in every output but the reporting database")
+        (@arg strict_enums: --("strict-enums")
+            "fail instead of warning when an enum field holds a value this build doesn't know")
+        (@arg strict_required_fields: --("strict-required-fields")
+            "fail instead of warning when a required string field, such as an agent's name, is empty")
+        (@arg strict: --strict
+            "fail the block instead of skipping or dropping any state change, see the README")
+        (@arg verbose_sql: --("verbose-sql")
+            "log every operation handed to the database at debug level")
+        (@arg prune_older_than: --("prune-older-than") +takes_value
+            "periodically delete rows closed more than this many blocks before the last block")
+        (@arg prune_interval: default_value("1h") --("prune-interval") +takes_value
+            "how often to prune closed rows, e.g. 15m or 1h")
+        (@arg from_genesis: --("from-genesis")
+            "subscribe from the genesis block, ignoring the blocks in the database")
+        (@arg replay_from_id: --("replay-from-id") +takes_value conflicts_with[from_genesis]
+            "resume the subscription after this block in the database, reapplying later blocks")
+        (@arg bootstrap_from_state: --("bootstrap-from-state")
+            conflicts_with[replay_from_id verify_only]
+            "on an empty reporting database, load the state at the chain head before subscribing after it")
+        (@arg resume_file: --("resume-file") +takes_value
+            "record the last committed block in this file and resume from it at startup")
+        (@arg reingest: --reingest +takes_value
+            conflicts_with[from_genesis replay_from_id bootstrap_from_state verify_only truncate
+                event_log resume_file address_types replay_file replay_dir]
+            "re-derive the rows of one address type, e.g. certificate, from the known blocks, then exit")
+        (@arg reingest_from: --("reingest-from") +takes_value requires[reingest]
+            "the first block to re-ingest, defaults to the genesis block")
+        (@arg truncate: --truncate requires[from_genesis confirm_truncate]
+            "empty every reporting table before subscribing from the genesis block")
+        (@arg confirm_truncate: --("confirm-truncate")
+            "confirm that --truncate should delete all reported data")
+        (@arg webhook_url: --("webhook-url") +takes_value
+            "POST the records of selected operations to this URL")
+        (@arg webhook_operations: default_value("create_certificate") --("webhook-operations")
+            +takes_value "comma separated operations sent to the webhook")
+        (@arg webhook_batch_interval: default_value("5s") --("webhook-batch-interval")
+            +takes_value "how long records are collected before they are sent as one batch")
+        (@arg webhook_retries: default_value("5") --("webhook-retries") +takes_value
+            "how many times a failed delivery is retried")
+        (@arg webhook_dead_letter: --("webhook-dead-letter") +takes_value
+            "append batches that could not be delivered to this file")
+        (@arg wait_for_validator: --("wait-for-validator") +takes_value
+            "before subscribing, wait up to this long for the validator to be ready")
+        (@arg connect_timeout: default_value("10s") --("connect-timeout") +takes_value
+            "how long to wait for the validator endpoint to accept a connection at startup, or 0 to not check")
+        (@arg response_timeout: default_value("30s") --("response-timeout") +takes_value
+            "how long to wait for the validator to respond to a request, or 0 for no limit")
+        (@arg on_unknown_blocks: default_value("genesis") --("on-unknown-blocks") +takes_value
+            possible_value[genesis error]
+            "genesis or error: what to do when the validator knows none of the known blocks")
+        (@arg receive_queue_size: default_value("64") --("receive-queue-size") +takes_value
+            "how many received messages may wait for the event handler")
+        (@arg replay_file: --("replay-file") +takes_value
+            "parse the event lists captured in this file and print them, then exit")
+        (@arg replay_dir: --("replay-dir") +takes_value conflicts_with[replay_file]
+            "parse the event lists captured in the files of this directory, in name order, and print them, then exit")
+        (@arg replay_apply: --("replay-apply")
+            "also apply the replayed events to the reporting database")
+        (@arg standard_version_check: default_value("warn") --("standard-version-check")
+            +takes_value possible_value[off warn error]
+            "how to handle certificates referencing a standard version missing from reporting")
+        (@arg organization_reference_check: default_value("warn")
+            --("organization-reference-check") +takes_value possible_value[off warn error]
+            "how to handle standards and accreditations referencing an organization missing from reporting")
+        (@arg max_event_bytes: --("max-event-bytes") +takes_value
+            "refuse event lists larger than this many bytes, compressed or inflated")
+        (@arg max_events: --("max-events") +takes_value
+            "refuse event lists holding more than this many events")
+        (@arg max_state_changes: --("max-state-changes") +takes_value
+            "refuse blocks with more than this many state changes in the namespace")
+        (@arg max_decode_depth: --("max-decode-depth") +takes_value
+            "refuse payloads nesting messages deeper than this while decoding")
+        (@arg max_block_gap: --("max-block-gap") +takes_value
+            "resubscribe after the last block instead of applying one skipping more than this many blocks")
+        (@arg skip_poison_blocks: --("skip-poison-blocks") requires[poison_dead_letter]
+            conflicts_with[commit_interval_blocks commit_interval_ms verify_only]
+            "write blocks that fail to apply to the dead-letter log and skip them, instead of stopping")
+        (@arg poison_dead_letter: --("poison-dead-letter") +takes_value requires[skip_poison_blocks]
+            "append the raw events and error of each skipped poison block to this file")
+        (@arg commit_interval_blocks: --("commit-interval-blocks") +takes_value
+            "for --db-backend sqlite, commit blocks in one transaction once this many are pending")
+        (@arg commit_interval_ms: --("commit-interval-ms") +takes_value
+            "for --db-backend sqlite, commit pending blocks once the oldest waited this many ms")
+        (@arg inspect: --inspect +takes_value
+            "print the rows an entity's current state would write, given as its address or <type>:<id>, then exit")
+        (@arg validate_addresses: --("validate-addresses")
+            "check every address in the validator's state is routed to a type that parses it, then exit")
+        (@arg list_address_spaces: --("list-address-spaces")
+            "print the namespace prefix and the prefix of each address type, then exit")
+        (@arg schema_info: --("schema-info")
+            "print the expected schema version and the columns each model writes, then exit")
+        (@arg print_config: --("print-config")
+            "print the effective configuration as JSON, with secrets redacted, then exit")
+        (@arg db_retries: default_value("3") --("db-retries") +takes_value
+            "how many times a commit failing with a serialization failure or deadlock is retried")
+        (@arg db_retry_backoff_ms: default_value("100") --("db-retry-backoff-ms") +takes_value
+            "how many milliseconds to wait before the first commit retry, doubled for each retry")
+        (@arg db_reconnect_timeout: default_value("5m") --("db-reconnect-timeout") +takes_value
+            "how long to keep reconnecting after the database connection is lost, or 0 to fail at once"))
     .get_matches();
 
-    let _logger = match matches.occurrences_of("verbose") {
-        1 => simple_logger::init_with_level(LogLevel::Info),
-        2 => simple_logger::init_with_level(LogLevel::Debug),
-        _ => simple_logger::init_with_level(LogLevel::Warn),
+    let env_level = log_level::from_env().expect("Invalid log level");
+    let log_level = log_level::select_level(
+        env_level,
+        matches.occurrences_of("verbose"),
+        matches.is_present("verbose_sql"),
+    );
+    // Operations written to stdout must not be interleaved with log lines
+    let _logger = if matches.is_present("stdout_ndjson") {
+        stderr_logger::init_with_level(log_level)
+    } else {
+        simple_logger::init_with_level(log_level)
     };
 
-    let dsn = format!(
-        "{}:{}@{}:{}/{}",
-        matches.value_of("dbuser").unwrap(),
-        matches.value_of("dbpass").unwrap(),
-        matches.value_of("dbhost").unwrap(),
-        matches.value_of("dbport").unwrap(),
-        matches.value_of("dbname").unwrap()
-    );
+    if matches.is_present("list_address_spaces") {
+        for line in address_types::address_space_lines() {
+            println!("{}", line);
+        }
+        process::exit(0);
+    }
+    if matches.is_present("schema_info") {
+        for line in schema_check::schema_info_lines() {
+            println!("{}", line);
+        }
+        process::exit(0);
+    }
 
-    let manager = DataManager::new(&dsn).expect("Failed to connect to database");
-    let last_blocks = manager
-        .fetch_known_blocks()
-        .expect("Error fetching known blocks");
-    let known_block_ids: Vec<String> = last_blocks
+    let config = effective_config(&matches, log_level);
+    if matches.is_present("print_config") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config.to_json()).expect("Error printing the config")
+        );
+        process::exit(0);
+    }
+    info!("Effective configuration: {}", config);
+
+    let shard = matches
+        .value_of("shard")
+        .map(|value| Shard::parse(value).expect("Invalid shard"));
+    if let Some(shard) = shard {
+        info!("Handling shard {} of the namespace", shard);
+    }
+
+    // Re-ingesting subscribes to the one address type it re-derives
+    let address_types = matches
+        .value_of("address_types")
+        .or_else(|| matches.value_of("reingest"))
+        .map_or_else(AddressTypes::default, |value| {
+            AddressTypes::parse(value).expect("Invalid address types")
+        });
+
+    let event_types = EventTypes {
+        block_commit: matches.value_of("block_commit_event").unwrap().to_string(),
+        state_delta: matches.value_of("state_delta_event").unwrap().to_string(),
+    };
+    let limits = event_limits(&matches);
+    let replay = replay_source(&matches);
+    if let Some(ref source) = replay {
+        if !matches.is_present("replay_apply") {
+            print_replay(
+                &matches,
+                source,
+                shard,
+                &address_types,
+                &event_types,
+                &limits,
+            );
+        }
+    }
+
+    let apply_timeout = matches
+        .value_of("apply_timeout")
+        .unwrap()
+        .parse::<u64>()
+        .expect("Apply timeout must be a whole number of seconds");
+    let isolation = matches.value_of("db_isolation").map(|value| {
+        value
+            .parse::<IsolationLevel>()
+            .expect("Invalid isolation level")
+    });
+    let host_dsn = |host: &str| {
+        dsn::build_dsn(
+            matches.value_of("dbuser").unwrap(),
+            matches.value_of("dbpass").unwrap(),
+            host,
+            matches.value_of("dbport").unwrap(),
+            matches.value_of("dbname").unwrap(),
+            &ConnectionOptions {
+                schema: matches.value_of("dbschema").unwrap(),
+                statement_timeout: match apply_timeout {
+                    0 => None,
+                    seconds => Some(Duration::from_secs(seconds)),
+                },
+                isolation,
+            },
+        )
+        .expect("Invalid database configuration")
+    };
+    // The first --dbhost is the primary, used for everything but fan-out writes
+    let dsn = host_dsn(matches.value_of("dbhost").unwrap());
+
+    let sqlite = matches.value_of("db_backend") == Some("sqlite");
+    if sqlite
+        && (matches.is_present("check_schema")
+            || matches.is_present("prune_older_than")
+            || matches.is_present("truncate"))
+    {
+        panic!("--check-schema, --prune-older-than and --truncate need the postgres backend");
+    }
+    if !sqlite
+        && (matches.is_present("commit_interval_blocks")
+            || matches.is_present("commit_interval_ms"))
+    {
+        // The database crate applies one block per transaction, so a batch could only
+        // defer the writes
+        panic!("--commit-interval-blocks and --commit-interval-ms need the sqlite backend");
+    }
+    let storage_mode = matches
+        .value_of("storage_mode")
+        .unwrap()
+        .parse::<StorageMode>()
+        .expect("Invalid storage mode");
+    if storage_mode == StorageMode::Latest
+        && (matches.is_present("prune_older_than") || matches.is_present("reingest"))
+    {
+        panic!("--prune-older-than and --reingest need --storage-mode versioned");
+    }
+    if storage_mode == StorageMode::Versioned
+        && (matches.is_present("discard_history") || matches.is_present("latest_keep_blocks"))
+    {
+        panic!("--discard-history and --latest-keep-blocks need --storage-mode latest");
+    }
+    let discard_history = matches.is_present("discard_history");
+    let keep_blocks =
+        matches
+            .value_of("latest_keep_blocks")
+            .map_or(0, |value| match value.parse::<i64>() {
+                Ok(keep_blocks) if keep_blocks >= 0 => keep_blocks,
+                _ => panic!("--latest-keep-blocks must be a number of blocks"),
+            });
+    if sqlite && isolation.is_some() {
+        warn!("Ignoring --db-isolation, SQLite transactions are always serializable");
+    }
+    let prefix = table_prefix(&matches);
+
+    if matches.is_present("check_schema") {
+        check_schema(&matches);
+    }
+    if let Some(path) = matches.value_of("export_graph") {
+        export_graph(&matches, sqlite, path);
+    }
+    if let Some(age) = matches.value_of("prune_older_than") {
+        start_pruning(&matches, age);
+    }
+
+    if !matches.is_present("verify_only") {
+        let dsns: Vec<String> = matches
+            .values_of("dbhost")
+            .unwrap()
+            .map(&host_dsn)
+            .collect();
+        claim_shard(&matches, sqlite, &prefix, &dsns, shard.unwrap_or_default());
+    }
+    if matches.is_present("truncate") {
+        warn!("Emptying every reporting table for a rebuild from the genesis block");
+        rebuild::truncate_reporting_tables(
+            &format!("postgres://{}", dsn),
+            matches.value_of("dbschema").unwrap(),
+        )
+        .expect("Error emptying the reporting tables");
+    }
+
+    let standard_version_check = if matches.is_present("strict") {
+        CheckMode::Error
+    } else {
+        matches
+            .value_of("standard_version_check")
+            .unwrap()
+            .parse::<CheckMode>()
+            .expect("Invalid standard version check")
+    };
+    let organization_reference_check = if matches.is_present("strict") {
+        CheckMode::Error
+    } else {
+        matches
+            .value_of("organization_reference_check")
+            .unwrap()
+            .parse::<CheckMode>()
+            .expect("Invalid organization reference check")
+    };
+    let mut standard_versions = vec![];
+    let mut organization_ids = vec![];
+    let manager: Box<dyn BlockStore> = if sqlite {
+        let path = matches
+            .value_of("dbpath")
+            .expect("--dbpath is required for the sqlite backend");
+        let store = SqliteStore::open_with_prefix(path, &prefix)
+            .expect("Failed to open the SQLite database");
+        if standard_version_check != CheckMode::Off {
+            standard_versions = store
+                .fetch_standard_versions()
+                .expect("Error fetching standard versions");
+        }
+        if organization_reference_check != CheckMode::Off {
+            organization_ids = store
+                .fetch_organization_ids()
+                .expect("Error fetching organization ids");
+        }
+        with_storage_mode(
+            Box::new(store),
+            storage_mode,
+            keep_blocks,
+            discard_history,
+            || {
+                Box::new(
+                    SqliteClosedRows::open(path, &prefix)
+                        .expect("Failed to open the SQLite database"),
+                )
+            },
+        )
+    } else {
+        if standard_version_check != CheckMode::Off {
+            standard_versions = integrity::fetch_standard_versions(
+                &format!("postgres://{}", dsn),
+                matches.value_of("dbschema").unwrap(),
+            )
+            .expect("Error fetching standard versions");
+        }
+        if organization_reference_check != CheckMode::Off {
+            organization_ids = integrity::fetch_organization_ids(
+                &format!("postgres://{}", dsn),
+                matches.value_of("dbschema").unwrap(),
+            )
+            .expect("Error fetching organization ids");
+        }
+        let hosts: Vec<&str> = matches.values_of("dbhost").unwrap().collect();
+        let schema = matches.value_of("dbschema").unwrap();
+        let data_manager = |dsn: String| {
+            let store = ReplacedRowsStore::new(
+                Box::new(reconnecting_data_manager(dsn.clone())),
+                Box::new(
+                    PostgresReplacedRows::connect(&format!("postgres://{}", dsn), schema)
+                        .expect("Failed to connect to database"),
+                ),
+            )
+            .expect("Error closing rows removed from organizations");
+            with_storage_mode(
+                Box::new(store),
+                storage_mode,
+                keep_blocks,
+                discard_history,
+                || {
+                    Box::new(
+                        PostgresClosedRows::connect(&format!("postgres://{}", dsn), schema)
+                            .expect("Failed to connect to database"),
+                    )
+                },
+            )
+        };
+        if hosts.len() == 1 {
+            data_manager(dsn.clone())
+        } else {
+            let policy = matches
+                .value_of("fan_out_policy")
+                .unwrap()
+                .parse::<FanOutPolicy>()
+                .expect("Invalid fan-out policy");
+            let targets = hosts
+                .into_iter()
+                .map(|host| (host.to_string(), data_manager(host_dsn(host))))
+                .collect();
+            Box::new(FanOutStore::new(targets, policy))
+        }
+    };
+    let manager: Box<dyn BlockStore> = if matches.is_present("event_log") {
+        Box::new(EventLogStore::new(
+            manager,
+            event_table(&matches, sqlite, &dsn, &prefix),
+        ))
+    } else {
+        manager
+    };
+    let last_blocks = if matches.is_present("from_genesis") {
+        info!("Subscribing from the genesis block, ignoring known blocks");
+        vec![]
+    } else {
+        // Known blocks are read from the primary, not the read replica: a lagging
+        // replica would resume the subscription before blocks the primary has already
+        // committed
+        let known_blocks = manager
+            .fetch_known_blocks()
+            .expect("Error fetching known blocks");
+        match matches.value_of("replay_from_id") {
+            Some(block_id) => {
+                let blocks = match resume::resume_from_id(known_blocks, block_id) {
+                    Ok(blocks) => blocks,
+                    Err(err) => {
+                        error!("{}", err);
+                        process::exit(1);
+                    }
+                };
+                info!(
+                    "Resuming the subscription after block {} ({})",
+                    blocks[0].block_num, block_id
+                );
+                blocks
+            }
+            None => {
+                let recorded = matches
+                    .value_of("resume_file")
+                    .and_then(|path| ResumeFile::new(path).read());
+                let recorded_block = recorded
+                    .as_ref()
+                    .map(|block| (block.block_num, block.block_id.clone()));
+                let blocks = resume::resume_position(known_blocks, recorded);
+                if let Some((block_num, block_id)) = recorded_block {
+                    match blocks.first() {
+                        Some(first) if first.block_id == block_id => info!(
+                            "Resuming the subscription after block {} ({}) from the resume file",
+                            block_num, block_id
+                        ),
+                        _ => info!(
+                            "Ignoring block {} in the resume file, the reporting database \
+                             has committed later blocks",
+                            block_num
+                        ),
+                    }
+                }
+                blocks
+            }
+        }
+    };
+    let reingest_active = Arc::new(AtomicBool::new(true));
+    let (manager, last_blocks) = match matches.value_of("reingest") {
+        Some(name) => reingest_store(
+            &matches,
+            name,
+            manager.as_ref(),
+            sqlite,
+            &dsn,
+            &prefix,
+            reingest_active.clone(),
+        ),
+        None => (manager, last_blocks),
+    };
+    // Known blocks at or beyond the open row sentinel mean the database was written
+    // with a different MAX_BLOCK_NUM than the one this subscriber opens rows with
+    for block in &last_blocks {
+        validate_block_num(block.block_num)
+            .expect("Known block conflicts with the MAX_BLOCK_NUM open row sentinel");
+    }
+    let mut known_block_ids: Vec<String> = last_blocks
         .into_iter()
         .map(|block| block.block_id)
         .collect();
-    let event_handler = EventHandler::new(manager);
-    let mut subscriber = Subscriber::new(matches.value_of("connect").unwrap(), event_handler);
+    let audit_log = matches
+        .value_of("audit_log")
+        .map(|path| AuditLog::open(path).expect("Error opening audit log"));
+    let webhook = matches.value_of("webhook_url").map(|url| {
+        Webhook::start(WebhookOptions {
+            url: url.to_string(),
+            operations: webhook::parse_operations(matches.value_of("webhook_operations").unwrap())
+                .expect("Invalid webhook operations"),
+            batch_interval: parse_duration(matches.value_of("webhook_batch_interval").unwrap())
+                .expect("Invalid webhook batch interval"),
+            max_retries: matches
+                .value_of("webhook_retries")
+                .unwrap()
+                .parse::<u32>()
+                .expect("Webhook retries must be a whole number"),
+            dead_letter: matches
+                .value_of("webhook_dead_letter")
+                .map(|path| AuditLog::open(path).expect("Error opening webhook dead-letter log")),
+        })
+    });
+    let event_handler = EventHandler::new(
+        manager,
+        EventHandlerOptions {
+            shard,
+            address_types: address_types.clone(),
+            event_types: event_types.clone(),
+            audit_log,
+            strict_transitions: matches.is_present("strict_transitions")
+                || matches.is_present("strict"),
+            stdout_ndjson: matches.is_present("stdout_ndjson"),
+            redaction: redaction(&matches),
+            strict: strictness(&matches),
+            verbose_sql: matches.is_present("verbose_sql"),
+            webhook,
+            standard_version_check,
+            standard_versions: StandardVersions::from_rows(standard_versions),
+            organization_reference_check,
+            organization_ids: OrganizationIds::from_rows(organization_ids),
+            limits,
+            commit_interval: commit_interval(&matches),
+            retry_policy: retry_policy(&matches),
+            resume_file: matches.value_of("resume_file").map(ResumeFile::new),
+            verifier: if matches.is_present("verify_only") {
+                Some(verifier(&matches, sqlite))
+            } else {
+                None
+            },
+            max_block_gap: matches.value_of("max_block_gap").map(|value| {
+                match value.parse::<i64>() {
+                    Ok(blocks) if blocks >= 0 => blocks,
+                    _ => panic!("--max-block-gap must be a whole number"),
+                }
+            }),
+            poison_dead_letter: matches.value_of("poison_dead_letter").map(|path| {
+                AuditLog::open(path).expect("Error opening poison block dead-letter log")
+            }),
+            latency_warning: matches
+                .value_of("block_latency_warning")
+                .map(|value| parse_duration(value).expect("Invalid block latency warning")),
+        },
+    );
+    if let Some(source) = replay {
+        apply_replay(&source, event_handler);
+    }
+    let progress_interval = matches
+        .value_of("progress_interval")
+        .unwrap()
+        .parse::<u64>()
+        .expect("Progress interval must be a whole number of seconds");
+    let options = SubscriberOptions {
+        once: matches.is_present("once"),
+        shard,
+        address_types,
+        progress_interval: match progress_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        },
+        event_types,
+        liveness_timeout: Some(
+            parse_duration(matches.value_of("liveness_timeout").unwrap())
+                .expect("Invalid liveness timeout"),
+        )
+        .filter(|timeout| *timeout > Duration::from_secs(0)),
+        max_runtime: matches
+            .value_of("max_runtime")
+            .map(|value| parse_duration(value).expect("Invalid maximum runtime")),
+        response_timeout: Some(
+            parse_duration(matches.value_of("response_timeout").unwrap())
+                .expect("Invalid response timeout"),
+        )
+        .filter(|timeout| *timeout > Duration::from_secs(0)),
+        receive_queue_size: matches
+            .value_of("receive_queue_size")
+            .unwrap()
+            .parse::<usize>()
+            .expect("Receive queue size must be a whole number"),
+        unknown_blocks: matches
+            .value_of("on_unknown_blocks")
+            .unwrap()
+            .parse::<UnknownBlocksPolicy>()
+            .expect("Invalid unknown blocks policy"),
+        // A validator still starting is waited for instead of failing the connect check
+        connect_timeout: Some(
+            parse_duration(matches.value_of("connect_timeout").unwrap())
+                .expect("Invalid connect timeout"),
+        )
+        .filter(|timeout| {
+            *timeout > Duration::from_secs(0) && !matches.is_present("wait_for_validator")
+        }),
+        throughput_window: Some(
+            parse_duration(matches.value_of("throughput_window").unwrap())
+                .expect("Invalid throughput window"),
+        )
+        .filter(|window| *window > Duration::from_secs(0)),
+    };
+    let mut subscriber =
+        Subscriber::new(matches.value_of("connect").unwrap(), event_handler, options)
+            .expect("Error creating subscriber");
+    if matches.is_present("reingest") {
+        subscriber.active = reingest_active;
+    }
 
     let active = subscriber.active.clone();
     ctrlc::set_handler(move || {
@@ -70,7 +723,696 @@ fn main() {
     })
     .expect("Error setting Ctrl-C handler");
 
-    subscriber
+    if let Some(timeout) = matches.value_of("wait_for_validator") {
+        let timeout = parse_duration(timeout).expect("Invalid validator wait timeout");
+        match subscriber.wait_for_validator(timeout) {
+            Ok(true) => (),
+            Ok(false) => process::exit(0),
+            Err(err) => {
+                error!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(target) = matches.value_of("inspect") {
+        inspect(&mut subscriber, target, &matches);
+    }
+    if matches.is_present("validate_addresses") {
+        validate_addresses(&mut subscriber, &matches);
+    }
+
+    if matches.is_present("bootstrap_from_state") {
+        if known_block_ids.is_empty() {
+            let block = subscriber
+                .bootstrap_from_state()
+                .expect("Error bootstrapping from the validator's state");
+            known_block_ids = vec![block.block_id];
+        } else {
+            info!("Not bootstrapping from state, the reporting database already has blocks");
+        }
+    }
+
+    let status = subscriber
         .start(&known_block_ids, 0)
         .expect("Error subscribing to validator");
+    info!("Subscriber {}", status);
+    info!("Received message sizes: {}", status.message_sizes);
+    info!("Received {} heartbeats", status.heartbeats);
+    info!("Throughput: {}", status.throughput.at(Instant::now()));
+    info!("Block latency: {}", status.block_latency);
+    if let Some(verification) = &status.verification {
+        info!("Verification: {}", verification);
+    }
+    if status.gap_resyncs > 0 {
+        warn!(
+            "Resubscribed {} times after blocks skipping more than --max-block-gap blocks",
+            status.gap_resyncs
+        );
+    }
+    if status.poison_blocks > 0 {
+        warn!(
+            "Skipped {} blocks that failed to apply, recorded in the poison block dead-letter log",
+            status.poison_blocks
+        );
+    }
+    if status.genesis_fallbacks > 0 {
+        warn!(
+            "Fell back to the genesis block {} times, as the validator knew none of the \
+             known blocks",
+            status.genesis_fallbacks
+        );
+    }
+}
+
+/// Reads an entity's current state from the validator and prints the rows it would
+/// write to the reporting database as JSON, without writing them, then exits
+#[cfg(not(tarpaulin_include))]
+fn inspect(subscriber: &mut Subscriber, target: &str, matches: &ArgMatches) {
+    let address = inspect::resolve_address(target).unwrap_or_else(|err| {
+        error!("{}", err);
+        process::exit(1);
+    });
+    let (block, value) = match subscriber.get_state(&address) {
+        Ok(Some(state)) => state,
+        Ok(None) => {
+            error!("There is no state at address {}", address);
+            process::exit(1);
+        }
+        Err(err) => {
+            error!("Error reading the state at address {}: {}", address, err);
+            process::exit(1);
+        }
+    };
+    let operation = EventHandler::parse_state(
+        &address,
+        &value,
+        &block,
+        &strictness(matches),
+        &event_limits(matches),
+    )
+    .unwrap_or_else(|err| {
+        error!("Error parsing the state at address {}: {}", address, err);
+        process::exit(1);
+    });
+    let output = json!({
+        "address": address,
+        "block_num": block.block_num,
+        "block_id": block.block_id,
+        "rows": redaction(matches).apply(&json!(inspect::operation_rows(&operation))),
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&output).expect("Error printing the entity")
+    );
+    process::exit(0);
+}
+
+/// Reads every address in the namespace from the validator's state at the chain
+/// head and prints how many each address type handles, and the addresses routed
+/// to no type or failing to parse, then exits non-zero if there are any
+#[cfg(not(tarpaulin_include))]
+fn validate_addresses(subscriber: &mut Subscriber, matches: &ArgMatches) -> ! {
+    let (block, entries) = subscriber
+        .read_state(&AddressTypes::default().prefixes())
+        .unwrap_or_else(|err| {
+            error!("Error reading the validator's state: {}", err);
+            process::exit(1);
+        });
+    info!(
+        "Validating {} addresses at block {} ({})",
+        entries.len(),
+        block.block_num,
+        block.block_id
+    );
+    let report = AddressReport::check(&entries, &block, &event_limits(matches));
+    print!("{}", report);
+    process::exit(if report.is_clean() { 0 } else { 1 });
+}
+
+/// Where captured event lists are replayed from
+enum ReplaySource {
+    /// A single capture file, from --replay-file
+    File(String),
+    /// The capture files of an archive directory, in the order they are replayed,
+    /// from --replay-dir
+    Archive(Vec<PathBuf>),
+}
+
+/// Reads the capture file or archive directory to replay, if any. --replay-apply
+/// needs one of them.
+#[cfg(not(tarpaulin_include))]
+fn replay_source(matches: &ArgMatches) -> Option<ReplaySource> {
+    let source = if let Some(path) = matches.value_of("replay_file") {
+        Some(ReplaySource::File(path.to_string()))
+    } else if let Some(dir) = matches.value_of("replay_dir") {
+        let files =
+            replay::archive_files(Path::new(dir)).expect("Error reading the replay directory");
+        info!("Replaying {} capture files from {}", files.len(), dir);
+        Some(ReplaySource::Archive(files))
+    } else {
+        None
+    };
+    if source.is_none() && matches.is_present("replay_apply") {
+        panic!("--replay-apply needs --replay-file or --replay-dir");
+    }
+    source
+}
+
+/// Parses the captured event lists and prints their blocks and operations, then
+/// exits non-zero if any failed to parse
+#[cfg(not(tarpaulin_include))]
+fn print_replay(
+    matches: &ArgMatches,
+    source: &ReplaySource,
+    shard: Option<Shard>,
+    address_types: &AddressTypes,
+    event_types: &EventTypes,
+    limits: &EventLimits,
+) -> ! {
+    let namespace_regex = EventHandler::address_regex(shard, address_types);
+    let failures = match source {
+        ReplaySource::File(path) => replay::print_events(
+            &fs::read(path).expect("Error reading the replay file"),
+            &namespace_regex,
+            event_types,
+            &strictness(matches),
+            limits,
+            &redaction(matches),
+            &mut io::stdout(),
+        ),
+        ReplaySource::Archive(files) => replay::print_archive(
+            files,
+            &namespace_regex,
+            event_types,
+            &strictness(matches),
+            limits,
+            &redaction(matches),
+            &mut io::stdout(),
+        ),
+    }
+    .expect("Error writing the replayed events");
+    process::exit(if failures == 0 { 0 } else { 1 });
+}
+
+/// Returns a store re-ingesting the named address type from --reingest-from up to
+/// the last known block, with the block to subscribe after, if any
+#[cfg(not(tarpaulin_include))]
+fn reingest_store(
+    matches: &ArgMatches,
+    name: &str,
+    manager: &dyn BlockStore,
+    sqlite: bool,
+    dsn: &str,
+    prefix: &str,
+    active: Arc<AtomicBool>,
+) -> (Box<dyn BlockStore>, Vec<Block>) {
+    let from_block =
+        matches
+            .value_of("reingest_from")
+            .map_or(0, |value| match value.parse::<i64>() {
+                Ok(block_num) if block_num >= 0 => block_num,
+                _ => panic!("--reingest-from must be a block number"),
+            });
+    let reset = Reset::new(name, from_block).expect("Invalid address type to re-ingest");
+    let last_block = match manager
+        .fetch_known_blocks()
+        .expect("Error fetching known blocks")
+        .into_iter()
+        .max_by_key(|block| block.block_num)
+    {
+        Some(block) if block.block_num >= from_block => block,
+        Some(block) => {
+            error!(
+                "Cannot re-ingest from block {}, the last known block is {}",
+                from_block, block.block_num
+            );
+            process::exit(1);
+        }
+        None => {
+            error!("The reporting database has no blocks to re-ingest");
+            process::exit(1);
+        }
+    };
+    let target: Box<dyn ReingestTarget> = if sqlite {
+        Box::new(
+            SqliteStore::open_with_prefix(matches.value_of("dbpath").unwrap(), prefix)
+                .expect("Failed to open the SQLite database"),
+        )
+    } else {
+        Box::new(
+            PostgresReingestTarget::connect(
+                &format!("postgres://{}", dsn),
+                matches.value_of("dbschema").unwrap(),
+            )
+            .expect("Error connecting to the reporting database"),
+        )
+    };
+    let start = if from_block == 0 {
+        vec![]
+    } else {
+        match target
+            .block_id(from_block - 1)
+            .expect("Error reading the block before the first re-ingested block")
+        {
+            Some(block_id) => vec![Block {
+                block_num: from_block - 1,
+                block_id,
+            }],
+            None => {
+                error!(
+                    "Block {} is not in the reporting database, so re-ingesting cannot \
+                     resume after it",
+                    from_block - 1
+                );
+                process::exit(1);
+            }
+        }
+    };
+    warn!(
+        "Re-ingesting the {} tables ({}) from block {} to block {} ({})",
+        name,
+        reset.tables.join(", "),
+        from_block,
+        last_block.block_num,
+        last_block.block_id
+    );
+    (
+        Box::new(ReingestStore::new(target, reset, last_block, active)),
+        start,
+    )
+}
+
+/// Resolves the configuration from the flags, their defaults and the environment
+#[cfg(not(tarpaulin_include))]
+fn effective_config(matches: &ArgMatches, log_level: LogLevel) -> EffectiveConfig {
+    let value = |name: &str| matches.value_of(name).unwrap_or_default().to_string();
+    let optional = |name: &str| matches.value_of(name).map(String::from);
+    let limits = event_limits(matches);
+    EffectiveConfig {
+        log_level: log_level.to_string(),
+        validator: ValidatorConfig {
+            endpoint: value("connect"),
+            connect_timeout: value("connect_timeout"),
+            response_timeout: value("response_timeout"),
+            liveness_timeout: value("liveness_timeout"),
+            wait_for_validator: optional("wait_for_validator"),
+            on_unknown_blocks: value("on_unknown_blocks"),
+        },
+        database: DatabaseConfig {
+            backend: value("db_backend"),
+            path: optional("dbpath"),
+            table_prefix: optional("table_prefix"),
+            event_log: matches.is_present("event_log"),
+            storage_mode: value("storage_mode"),
+            discard_history: matches.is_present("discard_history"),
+            latest_keep_blocks: optional("latest_keep_blocks"),
+            hosts: matches
+                .values_of("dbhost")
+                .map(|hosts| hosts.map(String::from).collect())
+                .unwrap_or_default(),
+            port: value("dbport"),
+            read_host: optional("read_dbhost"),
+            read_port: optional("read_dbport"),
+            name: value("dbname"),
+            user: value("dbuser"),
+            password: Secret::new(optional("dbpass")),
+            schema: value("dbschema"),
+            fan_out_policy: value("fan_out_policy"),
+            apply_timeout_secs: value("apply_timeout"),
+            isolation: optional("db_isolation"),
+            retries: value("db_retries"),
+            retry_backoff_ms: value("db_retry_backoff_ms"),
+            reconnect_timeout: value("db_reconnect_timeout"),
+        },
+        events: EventConfig {
+            block_commit_event: value("block_commit_event"),
+            state_delta_event: value("state_delta_event"),
+            shard: optional("shard"),
+            address_types: optional("address_types"),
+            max_event_bytes: limits.max_message_bytes,
+            max_events: limits.max_events,
+            max_state_changes: limits.max_state_changes,
+            max_decode_depth: limits.max_decode_depth,
+            max_block_gap: optional("max_block_gap"),
+            skip_poison_blocks: matches.is_present("skip_poison_blocks"),
+            commit_interval_blocks: optional("commit_interval_blocks"),
+            commit_interval_ms: optional("commit_interval_ms"),
+            receive_queue_size: value("receive_queue_size"),
+            throughput_window: value("throughput_window"),
+            block_latency_warning: optional("block_latency_warning"),
+            strict: matches.is_present("strict"),
+            verify_only: matches.is_present("verify_only"),
+            bootstrap_from_state: matches.is_present("bootstrap_from_state"),
+            reingest: optional("reingest"),
+            reingest_from: optional("reingest_from"),
+        },
+        outputs: OutputConfig {
+            audit_log: optional("audit_log"),
+            resume_file: optional("resume_file"),
+            poison_dead_letter: optional("poison_dead_letter"),
+            stdout_ndjson: matches.is_present("stdout_ndjson"),
+            redact_fields: optional("redact_fields"),
+            webhook_url: optional("webhook_url"),
+        },
+    }
+}
+
+/// Reads the limits on the size of a single event list, defaulting any not given
+#[cfg(not(tarpaulin_include))]
+fn event_limits(matches: &ArgMatches) -> EventLimits {
+    let limit = |name: &str, default: usize| {
+        matches.value_of(name).map_or(default, |value| {
+            value
+                .parse::<usize>()
+                .unwrap_or_else(|_| panic!("--{} must be a whole number", name.replace('_', "-")))
+        })
+    };
+    EventLimits {
+        max_message_bytes: limit("max_event_bytes", limits::DEFAULT_MAX_MESSAGE_BYTES),
+        max_events: limit("max_events", limits::DEFAULT_MAX_EVENTS),
+        max_state_changes: limit("max_state_changes", limits::DEFAULT_MAX_STATE_CHANGES),
+        max_decode_depth: matches.value_of("max_decode_depth").map_or(
+            limits::DEFAULT_MAX_DECODE_DEPTH,
+            |value| {
+                value
+                    .parse::<u32>()
+                    .unwrap_or_else(|_| panic!("--max-decode-depth must be a whole number"))
+            },
+        ),
+    }
+}
+
+/// Reads the fields masked in the records of operations written to any output
+/// other than the reporting database
+#[cfg(not(tarpaulin_include))]
+fn redaction(matches: &ArgMatches) -> Redaction {
+    matches
+        .value_of("redact_fields")
+        .map(Redaction::parse)
+        .unwrap_or_default()
+}
+
+/// Reads which state changes fail the block while parsing. --strict makes every
+/// one of them fail.
+#[cfg(not(tarpaulin_include))]
+fn strictness(matches: &ArgMatches) -> Strictness {
+    if matches.is_present("strict") {
+        return Strictness::all();
+    }
+    Strictness {
+        enums: matches.is_present("strict_enums"),
+        required_fields: matches.is_present("strict_required_fields"),
+        ..Strictness::default()
+    }
+}
+
+/// Reads how many blocks to coalesce into one transaction. Without either flag,
+/// every block is committed in its own transaction.
+#[cfg(not(tarpaulin_include))]
+fn commit_interval(matches: &ArgMatches) -> CommitInterval {
+    CommitInterval {
+        blocks: matches.value_of("commit_interval_blocks").map(|value| {
+            match value.parse::<usize>() {
+                Ok(blocks) if blocks > 0 => blocks,
+                _ => panic!("Commit interval blocks must be a positive number"),
+            }
+        }),
+        duration: matches
+            .value_of("commit_interval_ms")
+            .map(|value| match value.parse::<u64>() {
+                Ok(millis) if millis > 0 => Duration::from_millis(millis),
+                _ => panic!("Commit interval must be a positive number of milliseconds"),
+            }),
+    }
+}
+
+/// Reads how commits failing with a serialization failure or deadlock are retried
+#[cfg(not(tarpaulin_include))]
+fn retry_policy(matches: &ArgMatches) -> RetryPolicy {
+    RetryPolicy {
+        max_retries: matches
+            .value_of("db_retries")
+            .unwrap()
+            .parse::<u32>()
+            .expect("Database retries must be a whole number"),
+        initial_backoff: Duration::from_millis(
+            matches
+                .value_of("db_retry_backoff_ms")
+                .unwrap()
+                .parse::<u64>()
+                .expect("Database retry backoff must be a whole number of milliseconds"),
+        ),
+        reconnect_timeout: Some(
+            parse_duration(matches.value_of("db_reconnect_timeout").unwrap())
+                .expect("Invalid database reconnect timeout"),
+        )
+        .filter(|timeout| *timeout > Duration::from_secs(0)),
+    }
+}
+
+/// Connects DataManager to the reporting database, and connects it again if the
+/// connection is lost
+#[cfg(not(tarpaulin_include))]
+fn reconnecting_data_manager(dsn: String) -> ReconnectingStore {
+    ReconnectingStore::connect(Box::new(move || {
+        Ok(Box::new(DataManager::new(&dsn)?) as Box<dyn BlockStore>)
+    }))
+    .expect("Failed to connect to database")
+}
+
+/// Claims every reporting database written for the shard, exiting if another
+/// shard already writes one of them
+#[cfg(not(tarpaulin_include))]
+fn claim_shard(matches: &ArgMatches, sqlite: bool, prefix: &str, dsns: &[String], shard: Shard) {
+    let claimed = if sqlite {
+        shard_claim::claim_sqlite(
+            matches
+                .value_of("dbpath")
+                .expect("--dbpath is required for the sqlite backend"),
+            prefix,
+            shard,
+        )
+    } else {
+        dsns.iter().try_for_each(|dsn| {
+            shard_claim::claim_postgres(
+                &format!("postgres://{}", dsn),
+                matches.value_of("dbschema").unwrap(),
+                shard,
+            )
+        })
+    };
+    if let Err(err) = claimed {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Keeps only the current row of each entity in the store with --storage-mode
+/// latest, deleting closed rows through the given connection once they are more
+/// than `keep_blocks` blocks old. Exits if the database already holds older
+/// closed rows, unless --discard-history is given.
+#[cfg(not(tarpaulin_include))]
+fn with_storage_mode<F>(
+    store: Box<dyn BlockStore>,
+    mode: StorageMode,
+    keep_blocks: i64,
+    discard_history: bool,
+    closed_rows: F,
+) -> Box<dyn BlockStore>
+where
+    F: FnOnce() -> Box<dyn ClosedRows>,
+{
+    match mode {
+        StorageMode::Versioned => store,
+        StorageMode::Latest => {
+            info!("Keeping only the latest version of each entity, without history");
+            match LatestStore::new(store, closed_rows(), keep_blocks, discard_history) {
+                Ok(latest) => Box::new(latest),
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Applies the captured event lists to the reporting database, then exits
+#[cfg(not(tarpaulin_include))]
+fn apply_replay(source: &ReplaySource, mut event_handler: EventHandler) -> ! {
+    let applied = match source {
+        ReplaySource::File(path) => replay::apply_events(
+            &fs::read(path).expect("Error reading the replay file"),
+            &mut event_handler,
+            &mut io::stdout(),
+        ),
+        ReplaySource::Archive(files) => {
+            replay::apply_archive(files, &mut event_handler, &mut io::stdout())
+        }
+    };
+    match applied {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            error!("Error applying the replayed events: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Starts pruning rows closed more than the given number of blocks ago in the background
+#[cfg(not(tarpaulin_include))]
+fn start_pruning(matches: &ArgMatches, age: &str) {
+    let age = match age.parse::<i64>() {
+        Ok(age) if age > 0 => age,
+        _ => panic!("Prune age must be a positive number of blocks"),
+    };
+    let interval = parse_duration(matches.value_of("prune_interval").unwrap())
+        .expect("Invalid prune interval");
+    let dsn = dsn::build_dsn(
+        matches.value_of("dbuser").unwrap(),
+        matches.value_of("dbpass").unwrap(),
+        matches.value_of("dbhost").unwrap(),
+        matches.value_of("dbport").unwrap(),
+        matches.value_of("dbname").unwrap(),
+        &ConnectionOptions::default(),
+    )
+    .expect("Invalid database configuration");
+    info!(
+        "Pruning rows closed more than {} blocks ago every {:?}",
+        age, interval
+    );
+    prune::spawn_pruner(
+        format!("postgres://{}", dsn),
+        matches.value_of("dbschema").unwrap().to_string(),
+        age,
+        interval,
+    );
+}
+
+/// Returns the URL of the database the subscriber's read-only checks query: the
+/// read replica if one is given, or else the primary
+#[cfg(not(tarpaulin_include))]
+fn read_url(matches: &ArgMatches) -> String {
+    let dsn = dsn::build_dsn(
+        matches.value_of("dbuser").unwrap(),
+        matches.value_of("dbpass").unwrap(),
+        matches
+            .value_of("read_dbhost")
+            .unwrap_or_else(|| matches.value_of("dbhost").unwrap()),
+        matches
+            .value_of("read_dbport")
+            .unwrap_or_else(|| matches.value_of("dbport").unwrap()),
+        matches.value_of("dbname").unwrap(),
+        &ConnectionOptions::default(),
+    )
+    .expect("Invalid database configuration");
+    format!("postgres://{}", dsn)
+}
+
+/// Reads the prefix of the reporting table names, which defaults to none. The
+/// Postgres tables are written by DataManager, whose table names are fixed, so a
+/// prefix is refused there rather than applied to only some of the queries.
+#[cfg(not(tarpaulin_include))]
+fn table_prefix(matches: &ArgMatches) -> String {
+    let prefix = matches.value_of("table_prefix").unwrap_or_default();
+    if !prefix.is_empty() && matches.value_of("db_backend") != Some("sqlite") {
+        panic!(
+            "--table-prefix is only supported with --db-backend sqlite, use --dbschema to \
+             separate Postgres reporting tables"
+        );
+    }
+    sqlite_store::validate_table_prefix(prefix).expect("Invalid table prefix");
+    prefix.to_string()
+}
+
+/// Opens the append-only event table in the reporting database, creating it if it
+/// doesn't exist. With several Postgres hosts, it is only written on the primary.
+#[cfg(not(tarpaulin_include))]
+fn event_table(matches: &ArgMatches, sqlite: bool, dsn: &str, prefix: &str) -> Box<dyn EventTable> {
+    info!(
+        "Appending every entity written to the {} table",
+        event_log::EVENT_TABLE
+    );
+    if sqlite {
+        Box::new(
+            SqliteEventTable::open(matches.value_of("dbpath").unwrap(), prefix)
+                .expect("Failed to open the event table"),
+        )
+    } else {
+        Box::new(
+            PostgresEventTable::connect(
+                &format!("postgres://{}", dsn),
+                matches.value_of("dbschema").unwrap(),
+            )
+            .expect("Failed to open the event table"),
+        )
+    }
+}
+
+/// Creates the verifier comparing blocks with the reporting database, which
+/// reads from the read replica, if one is given
+#[cfg(not(tarpaulin_include))]
+fn verifier(matches: &ArgMatches, sqlite: bool) -> Verifier {
+    let reader: Box<dyn RowReader> = if sqlite {
+        Box::new(
+            SqliteStore::open_with_prefix(
+                matches.value_of("dbpath").unwrap(),
+                &table_prefix(matches),
+            )
+            .expect("Failed to open the SQLite database"),
+        )
+    } else {
+        Box::new(
+            PostgresRowReader::connect(&read_url(matches), matches.value_of("dbschema").unwrap())
+                .expect("Failed to connect to the database to verify"),
+        )
+    };
+    info!("Verifying blocks against the reporting database, without writing");
+    Verifier::new(reader)
+}
+
+/// Writes the current entities of the reporting database and the references
+/// between them to a DOT file, then exits. Postgres is read from the read
+/// replica, if one is given.
+#[cfg(not(tarpaulin_include))]
+fn export_graph(matches: &ArgMatches, sqlite: bool, path: &str) -> ! {
+    let graph = if sqlite {
+        SqliteStore::open_with_prefix(matches.value_of("dbpath").unwrap(), &table_prefix(matches))
+            .expect("Failed to open the SQLite database")
+            .fetch_graph()
+            .expect("Error reading the reporting tables")
+    } else {
+        graph::fetch_graph(&read_url(matches), matches.value_of("dbschema").unwrap())
+            .expect("Error reading the reporting tables")
+    };
+    fs::write(path, graph.to_string()).expect("Error writing the graph");
+    let (entities, references) = graph.size();
+    info!(
+        "Wrote {} entities and {} references to {}",
+        entities, references, path
+    );
+    process::exit(0);
+}
+
+/// Compares the reporting database schema with the columns the subscriber writes,
+/// printing any mismatches, and exits non-zero if there are any. The schema is
+/// read from the read replica, if one is given.
+#[cfg(not(tarpaulin_include))]
+fn check_schema(matches: &ArgMatches) -> ! {
+    let schema = matches.value_of("dbschema").unwrap();
+    let found = schema_check::fetch_schema(&read_url(matches), schema)
+        .expect("Error reading the database schema");
+    let mismatches = schema_check::compare_schema(&schema_check::EXPECTED_TABLES, &found);
+    if mismatches.is_empty() {
+        println!(
+            "The {} schema has every column the subscriber writes",
+            schema
+        );
+        process::exit(0);
+    }
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    process::exit(1);
 }
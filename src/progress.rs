@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+/// Weight given to the latest rate sample in the moving average
+const RATE_SMOOTHING: f64 = 0.3;
+
+/// Periodically reports how far the subscriber has caught up with the chain head.
+///
+/// The block rate is an exponential moving average of the rate observed
+/// between reports, so a single slow or fast interval does not swing the ETA.
+pub struct ProgressReporter {
+    interval: Duration,
+    last_report: Instant,
+    last_block_num: Option<i64>,
+    rate: Option<f64>,
+}
+
+impl ProgressReporter {
+    pub fn new(interval: Duration, now: Instant) -> ProgressReporter {
+        ProgressReporter {
+            interval,
+            last_report: now,
+            last_block_num: None,
+            rate: None,
+        }
+    }
+
+    /// Returns true if a report should be logged at the given time
+    pub fn is_due(&self, now: Instant) -> bool {
+        now.duration_since(self.last_report) >= self.interval
+    }
+
+    /// Records the latest processed block and returns a progress message.
+    /// The chain head is optional, as it may not be known if the validator
+    /// could not be queried for it.
+    pub fn report(&mut self, block_num: i64, head: Option<i64>, now: Instant) -> String {
+        let elapsed = now.duration_since(self.last_report);
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        if let Some(last_block_num) = self.last_block_num {
+            if seconds > 0.0 {
+                let sample = (block_num - last_block_num) as f64 / seconds;
+                self.rate = Some(match self.rate {
+                    Some(rate) => RATE_SMOOTHING * sample + (1.0 - RATE_SMOOTHING) * rate,
+                    None => sample,
+                });
+            }
+        }
+        self.last_report = now;
+        self.last_block_num = Some(block_num);
+
+        let mut message = match head {
+            Some(head) => format!("Processed block {}/{}", block_num, head),
+            None => format!("Processed block {}", block_num),
+        };
+        if let Some(rate) = self.rate {
+            message.push_str(&format!(", {:.0} blocks/sec", rate));
+            if let Some(head) = head {
+                if rate > 0.0 && head > block_num {
+                    let eta = ((head - block_num) as f64 / rate) as u64;
+                    message.push_str(&format!(", ETA {}", format_duration(eta)));
+                }
+            }
+        }
+        message
+    }
+}
+
+/// Formats a number of seconds as a short duration, such as 1h5m or 15m
+fn format_duration(seconds: u64) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    if hours > 0 {
+        format!("{}h{}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that a report is only due once the interval has elapsed
+    fn test_report_is_due() {
+        let start = Instant::now();
+        let reporter = ProgressReporter::new(Duration::from_secs(10), start);
+        assert!(!reporter.is_due(start + Duration::from_secs(5)));
+        assert!(reporter.is_due(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    /// Test that reports include the block rate and ETA once a rate is known
+    fn test_report_rate_and_eta() {
+        let start = Instant::now();
+        let mut reporter = ProgressReporter::new(Duration::from_secs(10), start);
+        assert_eq!(
+            reporter.report(1000, Some(450_000), start),
+            "Processed block 1000/450000"
+        );
+        assert_eq!(
+            reporter.report(4500, Some(450_000), start + Duration::from_secs(10)),
+            "Processed block 4500/450000, 350 blocks/sec, ETA 21m"
+        );
+        assert_eq!(
+            reporter.report(9500, None, start + Duration::from_secs(20)),
+            "Processed block 9500, 395 blocks/sec"
+        );
+    }
+
+    #[test]
+    /// Test that durations are formatted in the largest sensible unit
+    fn test_format_duration() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(900), "15m");
+        assert_eq!(format_duration(3900), "1h5m");
+    }
+}
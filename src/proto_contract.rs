@@ -0,0 +1,385 @@
+use protobuf::reflect::{ReflectFieldRef, ReflectValueRef};
+use protobuf::Message;
+
+/// The fields of the state messages deliberately not stored in the reporting
+/// database, by their path from the top level message, e.g. `Agent.name`
+pub const UNMAPPED_FIELDS: &[&str] = &[];
+
+/// Returns the path of every field of the message, and of the messages nested in
+/// it, holding its default value, such as an empty string or list. A fixture
+/// setting every field returns none, so a field added to the proto definitions
+/// shows up here until the fixture, and the transformer, handle it.
+pub fn unset_fields(message: &dyn Message) -> Vec<String> {
+    let mut unset = vec![];
+    collect_unset_fields(message, message.descriptor().name(), &mut unset);
+    unset
+}
+
+fn collect_unset_fields(message: &dyn Message, path: &str, unset: &mut Vec<String>) {
+    for field in message.descriptor().fields() {
+        let field_path = format!("{}.{}", path, field.name());
+        match field.get_reflect(message) {
+            ReflectFieldRef::Repeated(repeated) => {
+                if repeated.len() == 0 {
+                    unset.push(field_path);
+                }
+                for index in 0..repeated.len() {
+                    if let ReflectValueRef::Message(item) = repeated.get(index).as_ref() {
+                        collect_unset_fields(item, &field_path, unset);
+                    }
+                }
+            }
+            ReflectFieldRef::Map(map) => {
+                if map.len() == 0 {
+                    unset.push(field_path);
+                }
+            }
+            ReflectFieldRef::Optional(Some(ReflectValueRef::Message(item))) => {
+                collect_unset_fields(item, &field_path, unset)
+            }
+            ReflectFieldRef::Optional(Some(_)) => (),
+            ReflectFieldRef::Optional(None) => unset.push(field_path),
+        }
+    }
+}
+
+/// The contract between the state messages and the reporting rows. Each test
+/// builds a message with every field set to a distinct value, checks that no
+/// field was missed, and that each value lands in its own column. A field added
+/// to a message fails `unset_fields`, and a field the transformer stops reading
+/// or reads into the wrong column fails the comparison with the expected rows.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformer::FromStateAtBlock;
+
+    use common::proto::{agent, assertion, certificate, organization, request, standard};
+    use database::custom_types::{
+        AssertionTypeEnum, OrganizationTypeEnum, RequestStatusEnum, RoleEnum,
+    };
+    use database::data_manager::MAX_BLOCK_NUM;
+    use database::models::{
+        NewAccreditation, NewAddress, NewAgent, NewAssertion, NewAuthorization, NewCertificate,
+        NewContact, NewOrganization, NewRequest, NewStandard, NewStandardVersion,
+    };
+    use protobuf::RepeatedField;
+
+    const BLOCK_NUM: i64 = 7;
+
+    /// Asserts that every field of the message is set, except those deliberately
+    /// not stored
+    fn assert_all_fields_set(message: &dyn Message) {
+        let missed: Vec<String> = unset_fields(message)
+            .into_iter()
+            .filter(|path| !UNMAPPED_FIELDS.contains(&path.as_str()))
+            .collect();
+        assert!(
+            missed.is_empty(),
+            "{:?} are not set by the fixture. Map each to a reporting column in its \
+             transformer in event_handler.rs and set it here, or add it to UNMAPPED_FIELDS",
+            missed
+        );
+    }
+
+    #[test]
+    /// Test that a field left at its default is reported by its path, including
+    /// fields of nested messages and empty lists
+    fn test_unset_fields() {
+        let mut agent = agent::Agent::new();
+        agent.set_public_key("agent_public_key".to_string());
+        agent.set_timestamp(1);
+        let unset = unset_fields(&agent);
+        assert!(unset.contains(&"Agent.name".to_string()));
+        assert!(unset.contains(&"Agent.organization_id".to_string()));
+        assert!(!unset.contains(&"Agent.public_key".to_string()));
+
+        let mut standard = standard::Standard::new();
+        standard.set_versions(RepeatedField::from_vec(vec![
+            standard::Standard_StandardVersion::new(),
+        ]));
+        let unset = unset_fields(&standard);
+        assert!(unset.contains(&"Standard.versions.link".to_string()));
+        assert!(!unset.contains(&"Standard.versions".to_string()));
+        assert!(unset_fields(&organization::Organization::new())
+            .contains(&"Organization.contacts".to_string()));
+    }
+
+    #[test]
+    /// Test that every field of an agent maps to a column
+    fn test_agent_contract() {
+        let mut agent = agent::Agent::new();
+        agent.set_public_key("agent_public_key".to_string());
+        agent.set_organization_id("agent_organization_id".to_string());
+        agent.set_name("agent_name".to_string());
+        agent.set_timestamp(11);
+        assert_all_fields_set(&agent);
+
+        let row: NewAgent = FromStateAtBlock::at_block(BLOCK_NUM, &agent);
+        assert_eq!(
+            row,
+            NewAgent {
+                public_key: "agent_public_key".to_string(),
+                organization_id: Some("agent_organization_id".to_string()),
+                name: "agent_name".to_string(),
+                timestamp: 11,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }
+        );
+    }
+
+    #[test]
+    /// Test that every field of an organization maps to a column, reading the
+    /// certifying body details of a certifying body and the factory details of a
+    /// factory
+    fn test_organization_contract() {
+        let mut contact = organization::Organization_Contact::new();
+        contact.set_name("contact_name".to_string());
+        contact.set_phone_number("contact_phone_number".to_string());
+        contact.set_language_code("contact_language_code".to_string());
+        let mut authorization = organization::Organization_Authorization::new();
+        authorization.set_public_key("authorization_public_key".to_string());
+        authorization.set_role(organization::Organization_Authorization_Role::TRANSACTOR);
+        let mut accreditation = organization::CertifyingBody_Accreditation::new();
+        accreditation.set_standard_id("accreditation_standard_id".to_string());
+        accreditation.set_standard_version("accreditation_standard_version".to_string());
+        accreditation.set_accreditor_id("accreditation_accreditor_id".to_string());
+        accreditation.set_valid_from(12);
+        accreditation.set_valid_to(13);
+        let mut certifying_body = organization::CertifyingBody::new();
+        certifying_body.set_accreditations(RepeatedField::from_vec(vec![accreditation]));
+        let mut address = organization::Factory_Address::new();
+        address.set_street_line_1("address_street_line_1".to_string());
+        address.set_street_line_2("address_street_line_2".to_string());
+        address.set_city("address_city".to_string());
+        address.set_state_province("address_state_province".to_string());
+        address.set_country("address_country".to_string());
+        address.set_postal_code("address_postal_code".to_string());
+        let mut factory = organization::Factory::new();
+        factory.set_address(address);
+
+        let mut org = organization::Organization::new();
+        org.set_id("organization_id".to_string());
+        org.set_name("organization_name".to_string());
+        org.set_organization_type(organization::Organization_Type::CERTIFYING_BODY);
+        org.set_contacts(RepeatedField::from_vec(vec![contact]));
+        org.set_authorizations(RepeatedField::from_vec(vec![authorization]));
+        org.set_certifying_body_details(certifying_body);
+        org.set_factory_details(factory);
+        assert_all_fields_set(&org);
+
+        let (new_org, accreditations, address, authorizations, contacts): (
+            NewOrganization,
+            Option<Vec<NewAccreditation>>,
+            Option<NewAddress>,
+            Vec<NewAuthorization>,
+            Vec<NewContact>,
+        ) = FromStateAtBlock::at_block(BLOCK_NUM, &org);
+        assert_eq!(
+            new_org,
+            NewOrganization {
+                organization_id: "organization_id".to_string(),
+                name: "organization_name".to_string(),
+                organization_type: OrganizationTypeEnum::CertifyingBody,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }
+        );
+        assert_eq!(
+            accreditations,
+            Some(vec![NewAccreditation {
+                organization_id: "organization_id".to_string(),
+                standard_id: "accreditation_standard_id".to_string(),
+                standard_version: "accreditation_standard_version".to_string(),
+                accreditor_id: "accreditation_accreditor_id".to_string(),
+                valid_from: 12,
+                valid_to: 13,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }])
+        );
+        assert_eq!(address, None);
+        assert_eq!(
+            authorizations,
+            vec![NewAuthorization {
+                organization_id: "organization_id".to_string(),
+                public_key: "authorization_public_key".to_string(),
+                role: RoleEnum::Transactor,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }]
+        );
+        assert_eq!(
+            contacts,
+            vec![NewContact {
+                organization_id: "organization_id".to_string(),
+                name: "contact_name".to_string(),
+                phone_number: "contact_phone_number".to_string(),
+                language_code: "contact_language_code".to_string(),
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }]
+        );
+
+        org.set_organization_type(organization::Organization_Type::FACTORY);
+        let (new_org, accreditations, address, _, _): (
+            NewOrganization,
+            Option<Vec<NewAccreditation>>,
+            Option<NewAddress>,
+            Vec<NewAuthorization>,
+            Vec<NewContact>,
+        ) = FromStateAtBlock::at_block(BLOCK_NUM, &org);
+        assert_eq!(new_org.organization_type, OrganizationTypeEnum::Factory);
+        assert_eq!(accreditations, None);
+        assert_eq!(
+            address,
+            Some(NewAddress {
+                organization_id: "organization_id".to_string(),
+                street_line_1: "address_street_line_1".to_string(),
+                street_line_2: Some("address_street_line_2".to_string()),
+                city: "address_city".to_string(),
+                state_province: Some("address_state_province".to_string()),
+                country: "address_country".to_string(),
+                postal_code: Some("address_postal_code".to_string()),
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            })
+        );
+    }
+
+    #[test]
+    /// Test that every field of a certificate maps to a column. The certificate
+    /// data entries are set so the message is complete, but have no columns yet.
+    fn test_certificate_contract() {
+        let data = |field: &str, data: &str| {
+            let mut entry = certificate::Certificate_CertificateData::new();
+            entry.set_field(field.to_string());
+            entry.set_data(data.to_string());
+            entry
+        };
+        let mut new_certificate = certificate::Certificate::new();
+        new_certificate.set_id("certificate_id".to_string());
+        new_certificate.set_certifying_body_id("certificate_certifying_body_id".to_string());
+        new_certificate.set_factory_id("certificate_factory_id".to_string());
+        new_certificate.set_standard_id("certificate_standard_id".to_string());
+        new_certificate.set_standard_version("certificate_standard_version".to_string());
+        new_certificate.set_valid_from(14);
+        new_certificate.set_valid_to(15);
+        new_certificate.set_certificate_data(RepeatedField::from_vec(vec![
+            data("source", "certificate_source"),
+            data("issuer_notes", "certificate_issuer_notes"),
+            data("cert_data_url", "https://example.com/certificate.pdf"),
+        ]));
+        assert_all_fields_set(&new_certificate);
+
+        let row: NewCertificate = FromStateAtBlock::at_block(BLOCK_NUM, &new_certificate);
+        assert_eq!(
+            row,
+            NewCertificate {
+                certificate_id: "certificate_id".to_string(),
+                certifying_body_id: "certificate_certifying_body_id".to_string(),
+                factory_id: "certificate_factory_id".to_string(),
+                standard_id: "certificate_standard_id".to_string(),
+                standard_version: "certificate_standard_version".to_string(),
+                valid_from: 14,
+                valid_to: 15,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }
+        );
+    }
+
+    #[test]
+    /// Test that every field of a request maps to a column
+    fn test_request_contract() {
+        let mut new_request = request::Request::new();
+        new_request.set_id("request_id".to_string());
+        new_request.set_status(request::Request_Status::IN_PROGRESS);
+        new_request.set_standard_id("request_standard_id".to_string());
+        new_request.set_factory_id("request_factory_id".to_string());
+        new_request.set_request_date(16);
+        assert_all_fields_set(&new_request);
+
+        let row: NewRequest = FromStateAtBlock::at_block(BLOCK_NUM, &new_request);
+        assert_eq!(
+            row,
+            NewRequest {
+                request_id: "request_id".to_string(),
+                factory_id: "request_factory_id".to_string(),
+                standard_id: "request_standard_id".to_string(),
+                status: RequestStatusEnum::InProgress,
+                request_date: 16,
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }
+        );
+    }
+
+    #[test]
+    /// Test that every field of a standard and its versions maps to a column
+    fn test_standard_contract() {
+        let mut version = standard::Standard_StandardVersion::new();
+        version.set_version("version_version".to_string());
+        version.set_description("version_description".to_string());
+        version.set_link("version_link".to_string());
+        version.set_approval_date(17);
+        let mut new_standard = standard::Standard::new();
+        new_standard.set_id("standard_id".to_string());
+        new_standard.set_organization_id("standard_organization_id".to_string());
+        new_standard.set_name("standard_name".to_string());
+        new_standard.set_versions(RepeatedField::from_vec(vec![version]));
+        assert_all_fields_set(&new_standard);
+
+        let rows: (NewStandard, Vec<NewStandardVersion>) =
+            FromStateAtBlock::at_block(BLOCK_NUM, &new_standard);
+        assert_eq!(
+            rows,
+            (
+                NewStandard {
+                    standard_id: "standard_id".to_string(),
+                    organization_id: "standard_organization_id".to_string(),
+                    name: "standard_name".to_string(),
+                    start_block_num: BLOCK_NUM,
+                    end_block_num: MAX_BLOCK_NUM,
+                },
+                vec![NewStandardVersion {
+                    standard_id: "standard_id".to_string(),
+                    version: "version_version".to_string(),
+                    link: "version_link".to_string(),
+                    description: "version_description".to_string(),
+                    approval_date: 17,
+                    start_block_num: BLOCK_NUM,
+                    end_block_num: MAX_BLOCK_NUM,
+                }]
+            )
+        );
+    }
+
+    #[test]
+    /// Test that every field of an assertion maps to a column
+    fn test_assertion_contract() {
+        let mut new_assertion = assertion::Assertion::new();
+        new_assertion.set_id("assertion_id".to_string());
+        new_assertion.set_address("assertion_address".to_string());
+        new_assertion.set_assertor_pub_key("assertion_assertor_pub_key".to_string());
+        new_assertion.set_assertion_type(assertion::Assertion_Type::CERTIFICATE);
+        new_assertion.set_object_id("assertion_object_id".to_string());
+        new_assertion.set_data_id("assertion_data_id".to_string());
+        assert_all_fields_set(&new_assertion);
+
+        let row: NewAssertion = FromStateAtBlock::at_block(BLOCK_NUM, &new_assertion);
+        assert_eq!(
+            row,
+            NewAssertion {
+                assertion_id: "assertion_id".to_string(),
+                address: "assertion_address".to_string(),
+                assertor_pub_key: "assertion_assertor_pub_key".to_string(),
+                assertion_type: AssertionTypeEnum::Certificate,
+                object_id: "assertion_object_id".to_string(),
+                data_id: Some("assertion_data_id".to_string()),
+                start_block_num: BLOCK_NUM,
+                end_block_num: MAX_BLOCK_NUM,
+            }
+        );
+    }
+}
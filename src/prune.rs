@@ -0,0 +1,115 @@
+use crate::errors::SubscriberError;
+use crate::schema_check::EXPECTED_TABLES;
+
+use database::{data_manager::MAX_BLOCK_NUM, errors::DatabaseError};
+use postgres::{Connection, TlsMode};
+use std::thread;
+use std::time::Duration;
+
+/// Returns the block number before which closed rows may be pruned, keeping the
+/// rows closed in the last `age` blocks before the head. Returns None if the
+/// chain is not yet longer than `age`.
+pub fn prune_threshold(head_block_num: i64, age: i64) -> Option<i64> {
+    match head_block_num - age {
+        threshold if threshold > 0 => Some(threshold),
+        _ => None,
+    }
+}
+
+/// Returns one DELETE statement for each versioned table in the given schema.
+/// Each deletes the rows closed before the block number bound to `$1`. Open rows
+/// hold MAX_BLOCK_NUM, bound to `$2`, and are excluded explicitly as well as by
+/// the threshold.
+pub fn prune_statements(schema: &str) -> Vec<(&'static str, String)> {
+    EXPECTED_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "blocks")
+        .map(|(table, _)| {
+            (
+                *table,
+                format!(
+                    "DELETE FROM \"{}\".\"{}\" WHERE end_block_num < $1 AND end_block_num <> $2",
+                    schema, table
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Deletes the closed rows of every versioned table that were closed more than
+/// `age` blocks before the last block in the reporting database, returning the
+/// number of rows deleted. Rows that are still open are never deleted.
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried
+/// ```
+pub fn prune_closed_rows(url: &str, schema: &str, age: i64) -> Result<u64, SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let head_block_num: Option<i64> = conn
+        .query(
+            &format!("SELECT MAX(block_num) FROM \"{}\".\"blocks\"", schema),
+            &[],
+        )
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?
+        .get(0)
+        .get(0);
+    let threshold = match head_block_num.and_then(|head| prune_threshold(head, age)) {
+        Some(threshold) => threshold,
+        None => return Ok(0),
+    };
+    let mut pruned = 0;
+    for (table, statement) in prune_statements(schema) {
+        let rows = conn
+            .execute(&statement, &[&threshold, &MAX_BLOCK_NUM])
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        if rows > 0 {
+            debug!("Pruned {} closed rows from {}", rows, table);
+        }
+        pruned += rows;
+    }
+    Ok(pruned)
+}
+
+/// Starts a background thread pruning closed rows every `interval`, logging the
+/// rows pruned. Errors are logged and the next run is tried as scheduled.
+#[cfg(not(tarpaulin_include))]
+pub fn spawn_pruner(url: String, schema: String, age: i64, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match prune_closed_rows(&url, &schema, age) {
+            Ok(pruned) => info!("Pruned {} rows closed more than {} blocks ago", pruned, age),
+            Err(err) => error!("Error pruning closed rows: {}", err),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the threshold keeps the last `age` blocks of closed rows
+    fn test_prune_threshold() {
+        assert_eq!(prune_threshold(1000, 100), Some(900));
+        assert_eq!(prune_threshold(100, 100), None);
+        assert_eq!(prune_threshold(10, 100), None);
+    }
+
+    #[test]
+    /// Test that every versioned table is pruned and the blocks table is not
+    fn test_prune_statements() {
+        let statements = prune_statements("reporting");
+        assert_eq!(statements.len(), EXPECTED_TABLES.len() - 1);
+        assert!(statements.iter().all(|(table, _)| *table != "blocks"));
+        assert_eq!(
+            statements[0],
+            (
+                "organizations",
+                "DELETE FROM \"reporting\".\"organizations\" \
+                 WHERE end_block_num < $1 AND end_block_num <> $2"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A message taken from the queue, with when it arrived from the source
+#[derive(Debug, PartialEq)]
+pub struct Received<T> {
+    pub message: T,
+    pub at: Instant,
+}
+
+/// A bounded queue between receiving messages and handling them.
+///
+/// A background thread moves messages from the source into the queue as soon as
+/// they arrive, so receiving is never held up by handling. When the queue is
+/// full, the thread waits for the handler to take a message, so at most
+/// `capacity` messages, plus the one waiting, are held in the queue. Each
+/// message is stamped with when it arrived, so time spent queued can be measured.
+pub struct ReceiveQueue<T> {
+    receiver: Receiver<Received<T>>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> ReceiveQueue<T> {
+    /// Starts moving messages from the source into a queue holding up to
+    /// `capacity` messages. The queue is closed once the source is.
+    pub fn spawn(source: Receiver<T>, capacity: usize) -> ReceiveQueue<T> {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        let depth = Arc::new(AtomicUsize::new(0));
+        let queued = depth.clone();
+        thread::spawn(move || {
+            for message in source {
+                let message = Received {
+                    message,
+                    at: Instant::now(),
+                };
+                queued.fetch_add(1, Ordering::SeqCst);
+                let message = match sender.try_send(message) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(message)) => message,
+                    Err(TrySendError::Disconnected(_)) => return,
+                };
+                warn!("The receive queue is full, waiting for the event handler to catch up");
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        ReceiveQueue { receiver, depth }
+    }
+}
+
+impl<T> ReceiveQueue<T> {
+    /// Waits up to the timeout for the next message
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Received<T>, RecvTimeoutError> {
+        let message = self.receiver.recv_timeout(timeout)?;
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        Ok(message)
+    }
+
+    /// Returns the next message if one is already queued
+    pub fn try_recv(&self) -> Result<Received<T>, TryRecvError> {
+        let message = self.receiver.try_recv()?;
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        Ok(message)
+    }
+
+    /// Discards the messages waiting to be handled, and those still arriving from
+    /// the source until none arrives within `quiet`. Returns the number discarded.
+    pub fn discard(&self, quiet: Duration) -> usize {
+        let mut discarded = 0;
+        while self.recv_timeout(quiet).is_ok() {
+            discarded += 1;
+        }
+        discarded
+    }
+
+    /// Returns the number of messages received and waiting to be handled
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    #[test]
+    /// Test that a full queue holds back further messages until they are taken,
+    /// and that every message is delivered in order
+    fn test_bounded_queue() {
+        let (source, receiver) = channel();
+        for message in 0..5 {
+            source.send(message).unwrap();
+        }
+        let queue = ReceiveQueue::spawn(receiver, 2);
+
+        let started = Instant::now();
+        while queue.depth() < 3 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.depth(), 3);
+
+        drop(source);
+        let received: Vec<i32> = (0..5)
+            .map(|_| queue.recv_timeout(Duration::from_secs(5)).unwrap().message)
+            .collect();
+        assert_eq!(received, vec![0, 1, 2, 3, 4]);
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(
+            queue.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    /// Test that discarding empties the queue, and later messages are delivered
+    fn test_discard() {
+        let (source, receiver) = channel();
+        for message in 0..3 {
+            source.send(message).unwrap();
+        }
+        let queue = ReceiveQueue::spawn(receiver, 2);
+        assert_eq!(queue.discard(Duration::from_millis(200)), 3);
+        assert_eq!(queue.depth(), 0);
+
+        source.send(3).unwrap();
+        assert_eq!(
+            queue.recv_timeout(Duration::from_secs(5)).unwrap().message,
+            3
+        );
+    }
+}
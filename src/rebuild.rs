@@ -0,0 +1,44 @@
+use crate::errors::SubscriberError;
+use crate::schema_check::EXPECTED_TABLES;
+
+use database::errors::DatabaseError;
+use postgres::{Connection, TlsMode};
+
+/// Returns a single statement emptying every reporting table in the given schema,
+/// including the blocks table, so the tables are emptied together or not at all
+pub fn truncate_statement(schema: &str) -> String {
+    let tables: Vec<String> = EXPECTED_TABLES
+        .iter()
+        .map(|(table, _)| format!("\"{}\".\"{}\"", schema, table))
+        .collect();
+    format!("TRUNCATE TABLE {}", tables.join(", "))
+}
+
+/// Empties every reporting table in the given schema, so a full rebuild can
+/// subscribe from the genesis block
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to, or a table is missing
+/// ```
+pub fn truncate_reporting_tables(url: &str, schema: &str) -> Result<(), SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    conn.execute(&truncate_statement(schema), &[])
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that every reporting table is truncated by one statement
+    fn test_truncate_statement() {
+        let statement = truncate_statement("reporting");
+        assert!(statement.starts_with("TRUNCATE TABLE \"reporting\".\"blocks\", "));
+        for (table, _) in EXPECTED_TABLES.iter() {
+            assert!(statement.contains(&format!("\"reporting\".\"{}\"", table)));
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use crate::address_types::ADDRESS_TYPES;
+use crate::errors::SubscriberError;
+use crate::sqlite_store::{entity_writes, EntityWrite};
+use crate::store::BlockStore;
+use crate::verify::ENUM_COLUMNS;
+
+use database::{
+    data_manager::{OperationType, MAX_BLOCK_NUM},
+    errors::DatabaseError,
+    models::Block,
+};
+use postgres::types::ToSql;
+use postgres::{Connection, TlsMode};
+use rusqlite::types::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Returns the reporting tables the transformer of the named address type writes
+pub fn type_tables(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "organization" => Some(&[
+            "organizations",
+            "accreditations",
+            "addresses",
+            "authorizations",
+            "contacts",
+        ]),
+        "agent" => Some(&["agents"]),
+        "certificate" => Some(&["certificates"]),
+        "request" => Some(&["requests"]),
+        "standard" => Some(&["standards", "standard_versions"]),
+        "assertion" => Some(&["assertions"]),
+        _ => None,
+    }
+}
+
+/// The rows of an address type's tables to replace before the first block is
+/// re-ingested
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reset {
+    pub tables: &'static [&'static str],
+    /// The first block re-ingested. Rows opened at or after it are removed, and
+    /// rows closed at or after it are reopened.
+    pub from_block: i64,
+}
+
+impl Reset {
+    /// Returns the reset of the named address type's tables from the given block
+    /// ```
+    /// # Errors
+    /// Returns an error if the name is not one of ADDRESS_TYPES
+    /// ```
+    pub fn new(name: &str, from_block: i64) -> Result<Reset, String> {
+        let tables = type_tables(name).ok_or_else(|| {
+            format!(
+                "Unknown address type {:?}, expected one of {}",
+                name,
+                ADDRESS_TYPES.join(", ")
+            )
+        })?;
+        Ok(Reset { tables, from_block })
+    }
+
+    /// Returns the statements resetting the tables, each name given by the
+    /// function, with the block number as their only parameter, written `?` or `$1`
+    pub fn statements(&self, table_name: impl Fn(&str) -> String, param: &str) -> Vec<String> {
+        self.tables
+            .iter()
+            .flat_map(|table| {
+                let table = table_name(table);
+                vec![
+                    format!("DELETE FROM {} WHERE start_block_num >= {}", table, param),
+                    format!(
+                        "UPDATE {} SET end_block_num = {} WHERE end_block_num >= {} \
+                         AND end_block_num <> {}",
+                        table, MAX_BLOCK_NUM, param, MAX_BLOCK_NUM
+                    ),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// A reporting database an address type is re-ingested into
+pub trait ReingestTarget {
+    /// Returns the id of the block with the given number in the blocks table, or
+    /// None if it isn't there
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError>;
+
+    /// Applies the writes of a block in one transaction, without recording the
+    /// block, first resetting the tables if a reset is given
+    fn apply_writes(
+        &self,
+        block_num: i64,
+        writes: &[EntityWrite],
+        reset: Option<&Reset>,
+    ) -> Result<(), DatabaseError>;
+}
+
+/// Re-derives the rows of one address type from the blocks already in the
+/// reporting database, leaving the other tables and the blocks table untouched.
+///
+/// Applying a known block through the usual store would resolve it as a fork and
+/// rewrite every table from it, so this store writes the type's rows itself.
+/// The first block re-ingested first resets the type's tables from the block on,
+/// in the same transaction. Each block must be in the blocks table with the same
+/// id, or the chain has changed since it was ingested and re-ingesting fails.
+/// Once the last block known when re-ingesting started is applied, the
+/// subscriber is stopped, and later blocks are left to the usual subscriber.
+pub struct ReingestStore {
+    target: Box<dyn ReingestTarget>,
+    reset: Reset,
+    last_block: Block,
+    reset_done: Cell<bool>,
+    applied: RefCell<Option<Block>>,
+    active: Arc<AtomicBool>,
+}
+
+impl ReingestStore {
+    /// Creates a store re-ingesting up to the given last block, which clears the
+    /// active flag once it is applied
+    pub fn new(
+        target: Box<dyn ReingestTarget>,
+        reset: Reset,
+        last_block: Block,
+        active: Arc<AtomicBool>,
+    ) -> ReingestStore {
+        ReingestStore {
+            target,
+            reset,
+            last_block,
+            reset_done: Cell::new(false),
+            applied: RefCell::new(None),
+            active,
+        }
+    }
+}
+
+impl BlockStore for ReingestStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        if block.block_num < self.reset.from_block || block.block_num > self.last_block.block_num {
+            debug!(
+                "Skipping block {} outside the re-ingested blocks",
+                block.block_num
+            );
+            return Ok(());
+        }
+        match self.target.block_id(block.block_num)? {
+            Some(ref block_id) if *block_id == block.block_id => (),
+            found => {
+                return Err(DatabaseError::ConnectionError(
+                    format!(
+                        "Cannot re-ingest block {} {}, the reporting database has {}. \
+                         Run the subscriber to catch up with the chain first",
+                        block.block_num,
+                        block.block_id,
+                        found.map_or("no block at that number".to_string(), |id| format!(
+                            "block {} instead",
+                            id
+                        ))
+                    )
+                    .into(),
+                ))
+            }
+        }
+        let writes: Vec<EntityWrite> = operations.iter().flat_map(entity_writes).collect();
+        let reset = if self.reset_done.get() {
+            None
+        } else {
+            Some(&self.reset)
+        };
+        self.target.apply_writes(block.block_num, &writes, reset)?;
+        self.reset_done.set(true);
+        *self.applied.borrow_mut() = Some(Block {
+            block_num: block.block_num,
+            block_id: block.block_id.clone(),
+        });
+        if block.block_num == self.last_block.block_num {
+            info!(
+                "Re-ingested up to the last known block {} ({})",
+                block.block_num, block.block_id
+            );
+            self.active.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Returns the last block re-ingested, or the block before the first one to
+    /// re-ingest if none was yet, so a resubscription resumes where re-ingesting is
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        if let Some(ref block) = *self.applied.borrow() {
+            return Ok(vec![Block {
+                block_num: block.block_num,
+                block_id: block.block_id.clone(),
+            }]);
+        }
+        let before = self.reset.from_block - 1;
+        if before < 0 {
+            return Ok(vec![]);
+        }
+        Ok(self
+            .target
+            .block_id(before)?
+            .map(|block_id| Block {
+                block_num: before,
+                block_id,
+            })
+            .into_iter()
+            .collect())
+    }
+}
+
+/// Re-ingests into the tables of a Postgres schema. Values are cast from text to
+/// the type of their column, and enum values are matched to the column's labels
+/// ignoring case and underscores, as when verifying.
+pub struct PostgresReingestTarget {
+    connection: Connection,
+    schema: String,
+    /// The type of each column, by table and column
+    column_types: HashMap<(String, String), String>,
+    /// The labels of each enum type
+    enum_labels: HashMap<String, Vec<String>>,
+}
+
+impl PostgresReingestTarget {
+    /// Connects to the database and reads the types of the columns in the schema
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be connected to or queried
+    /// ```
+    pub fn connect(url: &str, schema: &str) -> Result<PostgresReingestTarget, SubscriberError> {
+        let connection = Connection::connect(url, TlsMode::None).map_err(to_database_error)?;
+        let column_types = connection
+            .query(
+                "SELECT c.relname, a.attname, format_type(a.atttypid, a.atttypmod) \
+                 FROM pg_attribute a \
+                 JOIN pg_class c ON c.oid = a.attrelid \
+                 JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE n.nspname = $1 AND a.attnum > 0 AND NOT a.attisdropped",
+                &[&schema],
+            )
+            .map_err(to_database_error)?
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect();
+        let mut enum_labels: HashMap<String, Vec<String>> = HashMap::new();
+        for row in connection
+            .query(
+                "SELECT format_type(e.enumtypid, NULL), e.enumlabel FROM pg_enum e \
+                 ORDER BY e.enumsortorder",
+                &[],
+            )
+            .map_err(to_database_error)?
+            .iter()
+        {
+            enum_labels.entry(row.get(0)).or_default().push(row.get(1));
+        }
+        Ok(PostgresReingestTarget {
+            connection,
+            schema: schema.to_string(),
+            column_types,
+            enum_labels,
+        })
+    }
+
+    /// Returns the value of a column as text to cast to its type, matching an
+    /// enum value to its label
+    fn column_text(&self, table: &str, column: &str, value: &Value) -> Option<String> {
+        match value {
+            Value::Null => None,
+            Value::Integer(value) => Some(value.to_string()),
+            Value::Real(value) => Some(value.to_string()),
+            Value::Text(value) if ENUM_COLUMNS.contains(&column) => {
+                let normalized = normalize_label(value);
+                Some(
+                    self.enum_labels
+                        .get(&self.column_type(table, column))
+                        .and_then(|labels| {
+                            labels
+                                .iter()
+                                .find(|label| normalize_label(label) == normalized)
+                        })
+                        .unwrap_or(value)
+                        .clone(),
+                )
+            }
+            Value::Text(value) => Some(value.clone()),
+            Value::Blob(value) => Some(String::from_utf8_lossy(value).into_owned()),
+        }
+    }
+
+    fn column_type(&self, table: &str, column: &str) -> String {
+        self.column_types
+            .get(&(table.to_string(), column.to_string()))
+            .cloned()
+            .unwrap_or_else(|| "text".to_string())
+    }
+
+    fn table_name(&self, table: &str) -> String {
+        format!("\"{}\".\"{}\"", self.schema, table)
+    }
+}
+
+impl ReingestTarget for PostgresReingestTarget {
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError> {
+        let rows = self
+            .connection
+            .query(
+                &format!(
+                    "SELECT block_id FROM {} WHERE block_num = $1",
+                    self.table_name("blocks")
+                ),
+                &[&block_num],
+            )
+            .map_err(to_database_error)?;
+        Ok(rows.iter().next().map(|row| row.get(0)))
+    }
+
+    fn apply_writes(
+        &self,
+        block_num: i64,
+        writes: &[EntityWrite],
+        reset: Option<&Reset>,
+    ) -> Result<(), DatabaseError> {
+        let transaction = self.connection.transaction().map_err(to_database_error)?;
+        if let Some(reset) = reset {
+            for statement in reset.statements(|table| self.table_name(table), "$1") {
+                transaction
+                    .execute(&statement, &[&reset.from_block])
+                    .map_err(to_database_error)?;
+            }
+        }
+        for write in writes {
+            for (table, key_column, key) in &write.closes {
+                transaction
+                    .execute(
+                        &format!(
+                            "UPDATE {} SET end_block_num = $1 WHERE \"{}\" = $2 \
+                             AND end_block_num = $3",
+                            self.table_name(table),
+                            key_column
+                        ),
+                        &[&block_num, key, &MAX_BLOCK_NUM],
+                    )
+                    .map_err(to_database_error)?;
+            }
+            for (table, columns) in &write.inserts {
+                let names: Vec<String> = columns
+                    .iter()
+                    .map(|(name, _)| format!("\"{}\"", name))
+                    .collect();
+                let params: Vec<Option<String>> = columns
+                    .iter()
+                    .map(|(name, value)| self.column_text(table, name, value))
+                    .collect();
+                let placeholders: Vec<String> = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| {
+                        format!(
+                            "CAST(${}::text AS {})",
+                            index + 1,
+                            self.column_type(table, name)
+                        )
+                    })
+                    .collect();
+                let params: Vec<&dyn ToSql> =
+                    params.iter().map(|param| param as &dyn ToSql).collect();
+                transaction
+                    .execute(
+                        &format!(
+                            "INSERT INTO {} ({}) VALUES ({})",
+                            self.table_name(table),
+                            names.join(", "),
+                            placeholders.join(", ")
+                        ),
+                        &params,
+                    )
+                    .map_err(to_database_error)?;
+            }
+        }
+        transaction.commit().map_err(to_database_error)
+    }
+}
+
+/// Lowercases an enum label or variant name and removes its underscores
+fn normalize_label(label: &str) -> String {
+    label.replace('_', "").to_lowercase()
+}
+
+fn to_database_error(err: postgres::Error) -> DatabaseError {
+    DatabaseError::ConnectionError(Box::new(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema_check::EXPECTED_TABLES;
+    use crate::sqlite_store::SqliteStore;
+    use crate::store::test_support::{make_agent_operation_with_key, make_block};
+
+    #[test]
+    /// Test that every reporting table but blocks belongs to exactly one address
+    /// type, and the reset statements remove later rows and reopen rows closed later
+    fn test_type_tables() {
+        let mut tables: Vec<&str> = ADDRESS_TYPES
+            .iter()
+            .flat_map(|name| type_tables(name).unwrap().iter().cloned())
+            .collect();
+        tables.sort();
+        let mut expected: Vec<&str> = EXPECTED_TABLES
+            .iter()
+            .map(|(table, _)| *table)
+            .filter(|table| *table != "blocks")
+            .collect();
+        expected.sort();
+        assert_eq!(tables, expected);
+        assert!(Reset::new("block", 0).is_err());
+
+        assert_eq!(
+            Reset::new("agent", 3)
+                .unwrap()
+                .statements(String::from, "?"),
+            vec![
+                "DELETE FROM agents WHERE start_block_num >= ?".to_string(),
+                format!(
+                    "UPDATE agents SET end_block_num = {} WHERE end_block_num >= ? \
+                     AND end_block_num <> {}",
+                    MAX_BLOCK_NUM, MAX_BLOCK_NUM
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that re-ingesting a type from a block replaces its rows from the block
+    /// on, leaves the blocks untouched, and stops after the last
+    /// known block
+    fn test_reingest_store() {
+        let path = std::env::temp_dir().join(format!("sds-reingest-{}.db", uuid::Uuid::new_v4()));
+        let store = SqliteStore::open(&path).unwrap();
+        for block_num in 1..=3 {
+            store
+                .execute_operations_in_block(
+                    vec![make_agent_operation_with_key("key_1", "buggy", block_num)],
+                    &make_block(block_num),
+                )
+                .unwrap();
+        }
+
+        let active = Arc::new(AtomicBool::new(true));
+        let reingest = ReingestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Reset::new("agent", 2).unwrap(),
+            make_block(3),
+            active.clone(),
+        );
+        let known = |reingest: &ReingestStore| {
+            reingest
+                .fetch_known_blocks()
+                .unwrap()
+                .into_iter()
+                .map(|block| block.block_num)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(known(&reingest), vec![1]);
+        reingest
+            .execute_operations_in_block(
+                vec![make_agent_operation_with_key("key_1", "fixed", 2)],
+                &make_block(2),
+            )
+            .unwrap();
+        assert!(active.load(Ordering::SeqCst));
+        assert_eq!(known(&reingest), vec![2]);
+        reingest
+            .execute_operations_in_block(vec![], &make_block(3))
+            .unwrap();
+        assert!(!active.load(Ordering::SeqCst));
+        reingest
+            .execute_operations_in_block(
+                vec![make_agent_operation_with_key("key_1", "later", 4)],
+                &make_block(4),
+            )
+            .unwrap();
+
+        assert_eq!(
+            agent_rows(&path),
+            vec![
+                ("buggy".to_string(), 1, 2),
+                ("fixed".to_string(), 2, MAX_BLOCK_NUM),
+            ]
+        );
+        assert_eq!(count_rows(&path, "blocks"), 3);
+
+        let err = ReingestStore::new(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            Reset::new("agent", 2).unwrap(),
+            make_block(3),
+            active,
+        )
+        .execute_operations_in_block(
+            vec![],
+            &Block {
+                block_num: 2,
+                block_id: "fork_block_2".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("block test_block_2 instead"));
+        drop(reingest);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn agent_rows(path: &std::path::Path) -> Vec<(String, i64, i64)> {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT name, start_block_num, end_block_num FROM agents ORDER BY start_block_num",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    fn count_rows(path: &std::path::Path, table: &str) -> i64 {
+        rusqlite::Connection::open(path)
+            .unwrap()
+            .query_row(
+                &format!("SELECT COUNT(*) FROM {}", table),
+                rusqlite::NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+}
@@ -0,0 +1,305 @@
+use crate::audit::{self, Redaction};
+use crate::errors::SubscriberError;
+use crate::event_handler::EventHandler;
+use crate::event_types::EventTypes;
+use crate::limits::EventLimits;
+use crate::strict::Strictness;
+
+use regex::Regex;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Splits a capture file into the serialized EventList messages it holds.
+///
+/// A file holding several messages writes each one length-delimited, i.e.
+/// prefixed with its length as a protobuf varint, as written by
+/// `write_length_delimited_to`. If the file can't be split that way exactly, it
+/// is read as a single message.
+pub fn split_messages(data: &[u8]) -> Vec<&[u8]> {
+    let mut messages = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let (length, prefix) = match read_varint(&data[position..]) {
+            Some(varint) => varint,
+            None => return vec![data],
+        };
+        let start = position + prefix;
+        match start.checked_add(length as usize) {
+            Some(end) if end <= data.len() => {
+                messages.push(&data[start..end]);
+                position = end;
+            }
+            _ => return vec![data],
+        }
+    }
+    if messages.is_empty() {
+        vec![data]
+    } else {
+        messages
+    }
+}
+
+/// Reads a protobuf varint from the start of the data, returning its value and
+/// the number of bytes it used
+fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (index, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+    None
+}
+
+/// Parses each message of a capture file and writes the block it belongs to and
+/// its operations, one JSON record per line, in the audit log format. Every
+/// message is parsed even if an earlier one fails. Returns the number of
+/// messages that failed to parse.
+/// ```
+/// # Errors
+/// Returns an error if the output cannot be written
+/// ```
+pub fn print_events<W: Write>(
+    data: &[u8],
+    namespace_regex: &Regex,
+    event_types: &EventTypes,
+    strict: &Strictness,
+    limits: &EventLimits,
+    redaction: &Redaction,
+    output: &mut W,
+) -> Result<usize, SubscriberError> {
+    let mut failures = 0;
+    for (index, message) in split_messages(data).into_iter().enumerate() {
+        match EventHandler::parse_events(message, namespace_regex, event_types, strict, limits) {
+            Ok((ref block, _)) if block.block_id.is_empty() => {
+                writeln!(output, "message {}: heartbeat", index)?
+            }
+            Ok((block, operations)) => {
+                writeln!(
+                    output,
+                    "message {}: block {} ({}), {} operations",
+                    index,
+                    block.block_num,
+                    block.block_id,
+                    operations.len()
+                )?;
+                audit::write_records(
+                    output,
+                    &audit::redacted_records(&block, &operations, redaction),
+                )?;
+            }
+            Err(err) => {
+                failures += 1;
+                writeln!(output, "message {}: {}", index, err)?;
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// Returns the capture files of an event archive directory in the order they are
+/// replayed: every file whose name doesn't start with a dot, sorted by name.
+/// Subdirectories are not read.
+/// ```
+/// # Errors
+/// Returns an error if the directory cannot be read
+/// ```
+pub fn archive_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && !entry.file_name().to_string_lossy().starts_with('.') {
+            files.push(entry.path());
+        }
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(files)
+}
+
+/// Parses the messages of each capture file of an archive in order, writing the
+/// name of each file before its messages as print_events does. Returns the number
+/// of messages that failed to parse, across all files.
+/// ```
+/// # Errors
+/// Returns an error if a file cannot be read, or the output written
+/// ```
+pub fn print_archive<W: Write>(
+    files: &[PathBuf],
+    namespace_regex: &Regex,
+    event_types: &EventTypes,
+    strict: &Strictness,
+    limits: &EventLimits,
+    redaction: &Redaction,
+    output: &mut W,
+) -> Result<usize, SubscriberError> {
+    let mut failures = 0;
+    for path in files {
+        writeln!(output, "file {}", path.display())?;
+        failures += print_events(
+            &fs::read(path)?,
+            namespace_regex,
+            event_types,
+            strict,
+            limits,
+            redaction,
+            output,
+        )?;
+    }
+    Ok(failures)
+}
+
+/// Applies each message of a capture file with the event handler, in order,
+/// writing what was committed. Stops at the first message that fails.
+/// ```
+/// # Errors
+/// Returns an error if a message cannot be applied, or the output written
+/// ```
+pub fn apply_events<W: Write>(
+    data: &[u8],
+    event_handler: &mut EventHandler,
+    output: &mut W,
+) -> Result<(), SubscriberError> {
+    apply_messages(data, event_handler, output)?;
+    event_handler.flush()?;
+    Ok(())
+}
+
+/// Applies the messages of each capture file of an archive in order, as if they
+/// had been streamed from the validator, writing the name of each file before what
+/// was committed. Stops at the first message that fails, and commits any pending
+/// blocks once every file is applied.
+/// ```
+/// # Errors
+/// Returns an error if a file cannot be read, a message cannot be applied, or the
+/// output written
+/// ```
+pub fn apply_archive<W: Write>(
+    files: &[PathBuf],
+    event_handler: &mut EventHandler,
+    output: &mut W,
+) -> Result<(), SubscriberError> {
+    for path in files {
+        writeln!(output, "file {}", path.display())?;
+        apply_messages(&fs::read(path)?, event_handler, output)?;
+    }
+    event_handler.flush()?;
+    Ok(())
+}
+
+/// Applies each message of a capture file, without committing blocks pending in
+/// a batch
+fn apply_messages<W: Write>(
+    data: &[u8],
+    event_handler: &mut EventHandler,
+    output: &mut W,
+) -> Result<(), SubscriberError> {
+    for (index, message) in split_messages(data).into_iter().enumerate() {
+        let report = event_handler.handle_events(message)?;
+        match report.block {
+            Some(block) => writeln!(
+                output,
+                "message {}: committed block {} ({}), {} operations",
+                index, block.block_num, block.block_id, report.operations_applied
+            )?,
+            None if report.skipped => {
+                writeln!(output, "message {}: skipped as a poison block", index)?
+            }
+            None => writeln!(output, "message {}: heartbeat", index)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+    use sawtooth_sdk::messages::events::{Event, EventList};
+
+    #[test]
+    /// Test that length-delimited messages are split, and anything else is one message
+    fn test_split_messages() {
+        let mut event = Event::new();
+        event.set_event_type("test".to_string());
+        let mut event_list = EventList::new();
+        event_list.set_events(protobuf::RepeatedField::from_vec(vec![event]));
+        let single = event_list.write_to_bytes().unwrap();
+
+        let mut delimited = Vec::new();
+        event_list
+            .write_length_delimited_to_vec(&mut delimited)
+            .unwrap();
+        event_list
+            .write_length_delimited_to_vec(&mut delimited)
+            .unwrap();
+        assert_eq!(split_messages(&delimited), vec![&single[..], &single[..]]);
+        assert_eq!(split_messages(&single), vec![&single[..]]);
+        assert_eq!(split_messages(&[0xff]), vec![&[0xff][..]]);
+    }
+
+    #[test]
+    /// Test that each message is printed, and failures are counted without stopping
+    fn test_print_events() {
+        let heartbeat = EventList::new();
+        let mut data = Vec::new();
+        heartbeat.write_length_delimited_to_vec(&mut data).unwrap();
+        data.extend(&[0x02, 0xff, 0xff]);
+
+        let mut output = Vec::new();
+        let failures = print_events(
+            &data,
+            &EventHandler::namespace_regex(None),
+            &EventTypes::default(),
+            &Strictness::default(),
+            &EventLimits::default(),
+            &Redaction::default(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(failures, 1);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("message 0: heartbeat\nmessage 1: "));
+    }
+
+    #[test]
+    /// Test that the files of an archive are read in name order, skipping hidden
+    /// files and subdirectories, and printed with the name of each file
+    fn test_print_archive() {
+        let dir = std::env::temp_dir().join(format!("sds-archive-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let heartbeat = EventList::new().write_to_bytes().unwrap();
+        fs::write(dir.join("000000010.bin"), &heartbeat).unwrap();
+        fs::write(dir.join("000000002.bin"), &heartbeat).unwrap();
+        fs::write(dir.join(".000000001.bin.tmp"), &[0xff]).unwrap();
+        fs::create_dir(dir.join("000000003")).unwrap();
+
+        let files = archive_files(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec![dir.join("000000002.bin"), dir.join("000000010.bin")]
+        );
+        let mut output = Vec::new();
+        let failures = print_archive(
+            &files,
+            &EventHandler::namespace_regex(None),
+            &EventTypes::default(),
+            &Strictness::default(),
+            &EventLimits::default(),
+            &Redaction::default(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(failures, 0);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "file {}\nmessage 0: heartbeat\nfile {}\nmessage 0: heartbeat\n",
+                files[0].display(),
+                files[1].display()
+            )
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use database::models::Block;
+use std::str::FromStr;
+
+/// What to do when the validator knows none of the known blocks, e.g. because the
+/// subscriber was down for longer than the validator keeps blocks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnknownBlocksPolicy {
+    /// Warn and subscribe from the genesis block, reapplying every block over the
+    /// rows already in the reporting database
+    Genesis,
+    /// Fail, leaving the reporting database as it is for an operator to decide
+    Error,
+}
+
+impl Default for UnknownBlocksPolicy {
+    fn default() -> Self {
+        UnknownBlocksPolicy::Genesis
+    }
+}
+
+impl FromStr for UnknownBlocksPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "genesis" => Ok(UnknownBlocksPolicy::Genesis),
+            "error" => Ok(UnknownBlocksPolicy::Error),
+            _ => Err(format!(
+                "Invalid unknown blocks policy {:?}, expected genesis or error",
+                value
+            )),
+        }
+    }
+}
+
+/// Returns the known blocks to resume the subscription from so that it continues
+/// after the block with the given id: that block and the known blocks before it,
+/// newest first. Blocks after it are delivered again by the validator.
+/// ```
+/// # Errors
+/// Returns an error if no known block has the given id
+/// ```
+pub fn resume_from_id(known_blocks: Vec<Block>, block_id: &str) -> Result<Vec<Block>, String> {
+    let block_num = known_blocks
+        .iter()
+        .find(|block| block.block_id == block_id)
+        .map(|block| block.block_num)
+        .ok_or_else(|| {
+            format!(
+                "Block {} is not in the reporting database, so the subscription can't \
+                 resume from it",
+                block_id
+            )
+        })?;
+    let mut blocks: Vec<Block> = known_blocks
+        .into_iter()
+        .filter(|block| block.block_num <= block_num)
+        .collect();
+    blocks.sort_by(|a, b| b.block_num.cmp(&a.block_num));
+    Ok(blocks)
+}
+
+/// Returns the known blocks to resume the subscription from, given the block
+/// recorded in the resume file. The reporting database records each block in the
+/// transaction that applies it, so its newest block is the committed position. The
+/// file is written after that commit and may lag it by a block, so it is only
+/// used when it is ahead of the database, e.g. after the database was wiped. Then
+/// it comes first, followed by the older known blocks as fallbacks in case the
+/// validator doesn't know it.
+pub fn resume_position(known_blocks: Vec<Block>, recorded: Option<Block>) -> Vec<Block> {
+    let newest_known = known_blocks.iter().map(|block| block.block_num).max();
+    match recorded {
+        Some(block) if newest_known.map_or(true, |newest| block.block_num > newest) => {
+            let mut blocks: Vec<Block> = known_blocks
+                .into_iter()
+                .filter(|known| known.block_num < block.block_num)
+                .collect();
+            blocks.insert(0, block);
+            blocks
+        }
+        _ => known_blocks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_blocks(block_nums: &[i64]) -> Vec<Block> {
+        block_nums
+            .iter()
+            .map(|block_num| Block {
+                block_num: *block_num,
+                block_id: format!("block_{}", block_num),
+            })
+            .collect()
+    }
+
+    #[test]
+    /// Test that the unknown blocks policies are parsed from their names
+    fn test_parse_unknown_blocks_policy() {
+        assert_eq!(
+            "genesis".parse::<UnknownBlocksPolicy>(),
+            Ok(UnknownBlocksPolicy::Genesis)
+        );
+        assert_eq!(
+            "error".parse::<UnknownBlocksPolicy>(),
+            Ok(UnknownBlocksPolicy::Error)
+        );
+        assert!("rebuild".parse::<UnknownBlocksPolicy>().is_err());
+    }
+
+    #[test]
+    /// Test that the subscription resumes from the given block and those before it,
+    /// and that an unknown block id is an error
+    fn test_resume_from_id() {
+        let blocks = resume_from_id(make_blocks(&[4, 3, 1, 2]), "block_3").unwrap();
+        let block_nums: Vec<i64> = blocks.iter().map(|block| block.block_num).collect();
+        assert_eq!(block_nums, vec![3, 2, 1]);
+
+        let err = resume_from_id(make_blocks(&[2, 1]), "block_3").unwrap_err();
+        assert!(err.contains("block_3"));
+    }
+
+    #[test]
+    /// Test that a crash after a block is committed but before the resume file is
+    /// written resumes after the committed block, so it isn't applied twice, and that
+    /// the file is used when it is ahead of the reporting database
+    fn test_resume_position() {
+        let block_nums = |blocks: Vec<Block>| -> Vec<i64> {
+            blocks.iter().map(|block| block.block_num).collect()
+        };
+        let recorded = || make_blocks(&[2]).pop();
+        assert_eq!(
+            block_nums(resume_position(make_blocks(&[3, 2, 1]), recorded())),
+            vec![3, 2, 1]
+        );
+        assert_eq!(
+            block_nums(resume_position(make_blocks(&[2, 1]), recorded())),
+            vec![2, 1]
+        );
+        assert_eq!(
+            block_nums(resume_position(make_blocks(&[1]), recorded())),
+            vec![2, 1]
+        );
+        assert_eq!(block_nums(resume_position(vec![], recorded())), vec![2]);
+        assert_eq!(
+            block_nums(resume_position(make_blocks(&[3, 2, 1]), None)),
+            vec![3, 2, 1]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use database::models::Block;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A local file holding the last committed block, so the subscription can resume
+/// from it even if the reporting database has been wiped. It holds one JSON object:
+/// ```text
+/// {"block_num":1,"block_id":"..."}
+/// ```
+#[derive(Debug, Clone)]
+pub struct ResumeFile {
+    path: PathBuf,
+}
+
+impl ResumeFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> ResumeFile {
+        ResumeFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the block recorded in the file. A missing, unreadable or corrupt
+    /// file is logged and read as no block, so the caller falls back to the
+    /// blocks in the reporting database.
+    pub fn read(&self) -> Option<Block> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                info!("No resume file at {}", self.path.display());
+                return None;
+            }
+            Err(err) => {
+                warn!(
+                    "Ignoring the resume file {}, which can't be read: {}",
+                    self.path.display(),
+                    err
+                );
+                return None;
+            }
+        };
+        match parse_block(&contents) {
+            Some(block) => Some(block),
+            None => {
+                warn!(
+                    "Ignoring the resume file {}, which doesn't hold a block: {:?}",
+                    self.path.display(),
+                    contents
+                );
+                None
+            }
+        }
+    }
+
+    /// Records the block in the file. The block is written to a temporary file
+    /// next to it, synced, then renamed over it, so a crash leaves either the old
+    /// or the new block, never a torn write.
+    /// ```
+    /// # Errors
+    /// Returns an error if the temporary file cannot be written or renamed
+    /// ```
+    pub fn write(&self, block: &Block) -> io::Result<()> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        {
+            let mut file = File::create(&temp_path)?;
+            let record = json!({
+                "block_num": block.block_num,
+                "block_id": block.block_id,
+            });
+            writeln!(file, "{}", record)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+fn parse_block(contents: &str) -> Option<Block> {
+    let record: Value = serde_json::from_str(contents).ok()?;
+    let block_num = record["block_num"].as_i64().filter(|num| *num >= 0)?;
+    let block_id = record["block_id"].as_str().filter(|id| !id.is_empty())?;
+    Some(Block {
+        block_num,
+        block_id: block_id.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that a written block is read back, and a missing or corrupt file reads
+    /// as no block
+    fn test_resume_file() {
+        let path = std::env::temp_dir().join(format!("sds-resume-{}.json", uuid::Uuid::new_v4()));
+        let resume_file = ResumeFile::new(&path);
+        assert!(resume_file.read().is_none());
+
+        for block_num in 1..3 {
+            resume_file
+                .write(&Block {
+                    block_num,
+                    block_id: format!("block_{}", block_num),
+                })
+                .unwrap();
+        }
+        let block = resume_file.read().unwrap();
+        assert_eq!(block.block_num, 2);
+        assert_eq!(block.block_id, "block_2");
+
+        fs::write(&path, "{\"block_num\":").unwrap();
+        assert!(resume_file.read().is_none());
+        fs::write(&path, "{\"block_num\":-1,\"block_id\":\"block_1\"}").unwrap();
+        assert!(resume_file.read().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
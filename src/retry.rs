@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// The default number of times a block's commit is retried after a retryable error
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+/// The default delay before the first retry
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// The longest delay between attempts to reconnect to the database
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a commit that fails with a retryable database error, such as a
+/// serialization failure or deadlock, is retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times the commit is retried before the error fails the block
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled for each retry after it
+    pub initial_backoff: Duration,
+    /// How long to keep reconnecting after the database connection is lost before
+    /// the error fails the block, if the block is retried at all
+    pub reconnect_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            reconnect_timeout: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, starting from 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff * 2u32.saturating_pow(retry.saturating_sub(1).min(16))
+    }
+
+    /// Returns the delay before the given attempt to reconnect, starting from 1,
+    /// which backs off like retries up to MAX_RECONNECT_BACKOFF
+    pub fn reconnect_backoff(&self, attempt: u32) -> Duration {
+        self.backoff(attempt).min(MAX_RECONNECT_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the backoff doubles with each retry
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.reconnect_backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.reconnect_backoff(30), MAX_RECONNECT_BACKOFF);
+    }
+}
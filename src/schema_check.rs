@@ -0,0 +1,325 @@
+use crate::errors::SubscriberError;
+
+use database::errors::DatabaseError;
+use postgres::{Connection, TlsMode};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The block number columns every versioned table must have. They hold
+/// MAX_BLOCK_NUM for open rows, so they must be 64 bit.
+const BLOCK_NUM_COLUMNS: [&str; 2] = ["start_block_num", "end_block_num"];
+const BLOCK_NUM_TYPE: &str = "bigint";
+
+/// The version of the reporting schema the subscriber writes, printed by
+/// `--schema-info`. Bump it whenever a table or column is added to or removed from
+/// EXPECTED_TABLES.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The database model written to each table of EXPECTED_TABLES, in the same order
+pub const TABLE_MODELS: [(&str, &str); 12] = [
+    ("blocks", "Block"),
+    ("organizations", "NewOrganization"),
+    ("accreditations", "NewAccreditation"),
+    ("addresses", "NewAddress"),
+    ("authorizations", "NewAuthorization"),
+    ("contacts", "NewContact"),
+    ("agents", "NewAgent"),
+    ("certificates", "NewCertificate"),
+    ("requests", "NewRequest"),
+    ("standards", "NewStandard"),
+    ("standard_versions", "NewStandardVersion"),
+    ("assertions", "NewAssertion"),
+];
+
+/// The tables and columns the transformers write, one entry per model
+pub const EXPECTED_TABLES: [(&str, &[&str]); 12] = [
+    ("blocks", &["block_num", "block_id"]),
+    (
+        "organizations",
+        &["organization_id", "name", "organization_type"],
+    ),
+    (
+        "accreditations",
+        &[
+            "organization_id",
+            "standard_id",
+            "standard_version",
+            "accreditor_id",
+            "valid_from",
+            "valid_to",
+        ],
+    ),
+    (
+        "addresses",
+        &[
+            "organization_id",
+            "street_line_1",
+            "street_line_2",
+            "city",
+            "state_province",
+            "country",
+            "postal_code",
+        ],
+    ),
+    ("authorizations", &["organization_id", "public_key", "role"]),
+    (
+        "contacts",
+        &["organization_id", "name", "phone_number", "language_code"],
+    ),
+    (
+        "agents",
+        &["public_key", "organization_id", "name", "timestamp"],
+    ),
+    (
+        "certificates",
+        &[
+            "certificate_id",
+            "certifying_body_id",
+            "factory_id",
+            "standard_id",
+            "standard_version",
+            "valid_from",
+            "valid_to",
+        ],
+    ),
+    (
+        "requests",
+        &[
+            "request_id",
+            "factory_id",
+            "standard_id",
+            "status",
+            "request_date",
+        ],
+    ),
+    ("standards", &["standard_id", "organization_id", "name"]),
+    (
+        "standard_versions",
+        &[
+            "standard_id",
+            "version",
+            "link",
+            "description",
+            "approval_date",
+        ],
+    ),
+    (
+        "assertions",
+        &[
+            "assertion_id",
+            "address",
+            "assertor_pub_key",
+            "assertion_type",
+            "object_id",
+            "data_id",
+        ],
+    ),
+];
+
+/// A difference between the columns the transformers write and the reporting database
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaMismatch {
+    MissingTable(String),
+    MissingColumn(String, String),
+    IncompatibleColumn {
+        table: String,
+        column: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaMismatch::MissingTable(table) => write!(f, "missing table {}", table),
+            SchemaMismatch::MissingColumn(table, column) => {
+                write!(f, "missing column {}.{}", table, column)
+            }
+            SchemaMismatch::IncompatibleColumn {
+                table,
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "column {}.{} has type {}, expected {}",
+                table, column, found, expected
+            ),
+        }
+    }
+}
+
+/// Compares the expected tables with the columns found in the database, given
+/// as a map of table name to a map of column name to data type
+pub fn compare_schema(
+    expected: &[(&str, &[&str])],
+    found: &HashMap<String, HashMap<String, String>>,
+) -> Vec<SchemaMismatch> {
+    let mut mismatches = Vec::new();
+    for (table, columns) in expected {
+        let found_columns = match found.get(*table) {
+            Some(found_columns) => found_columns,
+            None => {
+                mismatches.push(SchemaMismatch::MissingTable(table.to_string()));
+                continue;
+            }
+        };
+        let block_num_columns = if *table == "blocks" {
+            &[][..]
+        } else {
+            &BLOCK_NUM_COLUMNS[..]
+        };
+        for column in columns.iter().chain(block_num_columns) {
+            if !found_columns.contains_key(*column) {
+                mismatches.push(SchemaMismatch::MissingColumn(
+                    table.to_string(),
+                    column.to_string(),
+                ));
+            }
+        }
+        for column in block_num_columns.iter().chain(&["block_num"]) {
+            match found_columns.get(*column) {
+                Some(data_type) if data_type != BLOCK_NUM_TYPE => {
+                    mismatches.push(SchemaMismatch::IncompatibleColumn {
+                        table: table.to_string(),
+                        column: column.to_string(),
+                        expected: BLOCK_NUM_TYPE.to_string(),
+                        found: data_type.to_string(),
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
+    mismatches
+}
+
+/// Returns the schema version, then each model with its table and the columns it
+/// writes, block number columns last, for `--schema-info`
+pub fn schema_info_lines() -> Vec<String> {
+    let mut lines = vec![format!("schema version {}", SCHEMA_VERSION)];
+    lines.extend(TABLE_MODELS.iter().zip(EXPECTED_TABLES.iter()).map(
+        |((_, model), (table, columns))| {
+            let block_num_columns = if *table == "blocks" {
+                &[][..]
+            } else {
+                &BLOCK_NUM_COLUMNS[..]
+            };
+            let columns: Vec<&str> = columns.iter().chain(block_num_columns).cloned().collect();
+            format!("{:<18} {:<17} {}", model, table, columns.join(", "))
+        },
+    ));
+    lines
+}
+
+/// Reads the columns of every table in the given schema of the reporting database
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried
+/// ```
+pub fn fetch_schema(
+    url: &str,
+    schema: &str,
+) -> Result<HashMap<String, HashMap<String, String>>, SubscriberError> {
+    let conn = Connection::connect(url, TlsMode::None)
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let rows = conn
+        .query(
+            "SELECT table_name, column_name, data_type FROM information_schema.columns \
+             WHERE table_schema = $1",
+            &[&schema],
+        )
+        .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+    let mut tables: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for row in rows.iter() {
+        let table: String = row.get(0);
+        let column: String = row.get(1);
+        let data_type: String = row.get(2);
+        tables.entry(table).or_default().insert(column, data_type);
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that a database with every expected column has no mismatches
+    fn test_compare_matching_schema() {
+        assert!(compare_schema(&EXPECTED_TABLES, &make_schema()).is_empty());
+    }
+
+    #[test]
+    /// Test that missing tables, missing columns and narrow block numbers are reported
+    fn test_compare_mismatched_schema() {
+        let mut found = make_schema();
+        found.remove("contacts");
+        found.get_mut("agents").unwrap().remove("timestamp");
+        found
+            .get_mut("certificates")
+            .unwrap()
+            .insert("end_block_num".to_string(), "integer".to_string());
+
+        let mismatches = compare_schema(&EXPECTED_TABLES, &found);
+        assert_eq!(
+            mismatches,
+            vec![
+                SchemaMismatch::MissingTable("contacts".to_string()),
+                SchemaMismatch::MissingColumn("agents".to_string(), "timestamp".to_string()),
+                SchemaMismatch::IncompatibleColumn {
+                    table: "certificates".to_string(),
+                    column: "end_block_num".to_string(),
+                    expected: "bigint".to_string(),
+                    found: "integer".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that every table has its model, and that the column count matches the
+    /// schema version, so a change to the expected columns bumps the version
+    fn test_schema_info() {
+        for ((table, _), (expected, _)) in TABLE_MODELS.iter().zip(EXPECTED_TABLES.iter()) {
+            assert_eq!(table, expected);
+        }
+        let columns: usize = EXPECTED_TABLES
+            .iter()
+            .map(|(_, columns)| columns.len())
+            .sum();
+        assert_eq!(
+            (SCHEMA_VERSION, columns),
+            (1, 55),
+            "EXPECTED_TABLES changed, bump SCHEMA_VERSION and update this test"
+        );
+
+        let lines = schema_info_lines();
+        assert_eq!(lines.len(), EXPECTED_TABLES.len() + 1);
+        assert_eq!(lines[0], "schema version 1");
+        assert_eq!(
+            lines[7],
+            "NewAgent           agents            public_key, organization_id, name, timestamp, \
+             start_block_num, end_block_num"
+        );
+    }
+
+    fn make_schema() -> HashMap<String, HashMap<String, String>> {
+        EXPECTED_TABLES
+            .iter()
+            .map(|(table, columns)| {
+                let mut found_columns: HashMap<String, String> = columns
+                    .iter()
+                    .map(|column| (column.to_string(), "text".to_string()))
+                    .collect();
+                for column in BLOCK_NUM_COLUMNS.iter().chain(&["block_num"]) {
+                    if found_columns.contains_key(*column) || *table != "blocks" {
+                        found_columns.insert(column.to_string(), BLOCK_NUM_TYPE.to_string());
+                    }
+                }
+                (table.to_string(), found_columns)
+            })
+            .collect()
+    }
+}
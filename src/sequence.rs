@@ -0,0 +1,132 @@
+use std::fmt;
+
+/// How a committed block's number follows the block committed before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockSequence {
+    /// No block was committed before it since the subscriber started
+    First,
+    /// The block follows the previous block directly
+    Next,
+    /// Blocks between the previous block and this one were never received, which
+    /// means their events were dropped
+    Gap { previous: i64, missing: i64 },
+    /// The block is at or below the previous block, which means the chain forked
+    /// or events were replayed
+    Reorg { previous: i64 },
+}
+
+impl Default for BlockSequence {
+    fn default() -> Self {
+        BlockSequence::First
+    }
+}
+
+impl fmt::Display for BlockSequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockSequence::First => write!(f, "the first block"),
+            BlockSequence::Next => write!(f, "the next block"),
+            BlockSequence::Gap { previous, missing } => write!(
+                f,
+                "{} blocks after block {}, the blocks between were never received",
+                missing + 1,
+                previous
+            ),
+            BlockSequence::Reorg { previous } => write!(
+                f,
+                "not after the previous block {}, the chain forked or events were replayed",
+                previous
+            ),
+        }
+    }
+}
+
+/// Checks that committed block numbers increase one at a time
+#[derive(Debug, Default)]
+pub struct BlockSequenceCheck {
+    last_block_num: Option<i64>,
+}
+
+impl BlockSequenceCheck {
+    /// Returns how the given block number follows the last recorded block
+    pub fn check(&self, block_num: i64) -> BlockSequence {
+        match self.last_block_num {
+            None => BlockSequence::First,
+            Some(previous) if block_num == previous + 1 => BlockSequence::Next,
+            Some(previous) if block_num > previous => BlockSequence::Gap {
+                previous,
+                missing: block_num - previous - 1,
+            },
+            Some(previous) => BlockSequence::Reorg { previous },
+        }
+    }
+
+    /// Records that the block was committed
+    pub fn record(&mut self, block_num: i64) {
+        self.last_block_num = Some(block_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_all(block_nums: &[i64]) -> Vec<BlockSequence> {
+        let mut check = BlockSequenceCheck::default();
+        block_nums
+            .iter()
+            .map(|block_num| {
+                let sequence = check.check(*block_num);
+                check.record(*block_num);
+                sequence
+            })
+            .collect()
+    }
+
+    #[test]
+    /// Test that consecutive blocks follow each other
+    fn test_consecutive_blocks() {
+        assert_eq!(
+            check_all(&[4, 5, 6]),
+            vec![
+                BlockSequence::First,
+                BlockSequence::Next,
+                BlockSequence::Next
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that skipped block numbers are reported as a gap with the number missing
+    fn test_gapped_blocks() {
+        assert_eq!(
+            check_all(&[1, 2, 5, 6]),
+            vec![
+                BlockSequence::First,
+                BlockSequence::Next,
+                BlockSequence::Gap {
+                    previous: 2,
+                    missing: 2
+                },
+                BlockSequence::Next
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that a block at or below the previous one is reported as a reorg, and the
+    /// sequence continues from it
+    fn test_out_of_order_blocks() {
+        assert_eq!(
+            check_all(&[1, 2, 3, 2, 3, 3]),
+            vec![
+                BlockSequence::First,
+                BlockSequence::Next,
+                BlockSequence::Next,
+                BlockSequence::Reorg { previous: 3 },
+                BlockSequence::Next,
+                BlockSequence::Reorg { previous: 3 }
+            ]
+        );
+    }
+}
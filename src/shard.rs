@@ -0,0 +1,125 @@
+use std::fmt;
+
+/// The number of distinct values of the address byte used for sharding
+const SHARD_SPACE: usize = 256;
+
+/// A slice of the Certificate Registry namespace handled by one subscriber.
+///
+/// State addresses are split by their final byte, which is taken from a hash
+/// and so is evenly distributed across all address types. Shard `index` of
+/// `total` handles the addresses whose final byte falls in
+/// `[index * 256 / total, (index + 1) * 256 / total)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shard {
+    index: usize,
+    total: usize,
+}
+
+/// The whole namespace, handled by a subscriber running without `--shard`
+impl Default for Shard {
+    fn default() -> Self {
+        Shard { index: 0, total: 1 }
+    }
+}
+
+impl Shard {
+    /// Parses a shard in the form `<n>/<total>`, where n is zero based
+    /// ```
+    /// # Errors
+    /// Returns an error if
+    /// - The value is not of the form `<n>/<total>`
+    /// - total is zero or greater than 256
+    /// - n is not less than total
+    /// ```
+    pub fn parse(value: &str) -> Result<Shard, String> {
+        let parts: Vec<&str> = value.split('/').collect();
+        if parts.len() != 2 {
+            return Err(format!(
+                "Shard must be of the form <n>/<total>, got {}",
+                value
+            ));
+        }
+        let index = parts[0]
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("Invalid shard index {}: {}", parts[0], err))?;
+        let total = parts[1]
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("Invalid shard total {}: {}", parts[1], err))?;
+        if total == 0 || total > SHARD_SPACE {
+            return Err(format!(
+                "Shard total must be between 1 and {}, got {}",
+                SHARD_SPACE, total
+            ));
+        }
+        if index >= total {
+            return Err(format!(
+                "Shard index must be less than the total {}, got {}",
+                total, index
+            ));
+        }
+        Ok(Shard { index, total })
+    }
+
+    /// Returns a regex matching the addresses in the given namespace that
+    /// belong to this shard
+    pub fn address_regex(&self, namespace: &str) -> String {
+        if self.total == 1 {
+            return format!(r"^{}", namespace);
+        }
+        let start = self.index * SHARD_SPACE / self.total;
+        let end = (self.index + 1) * SHARD_SPACE / self.total;
+        let suffixes: Vec<String> = (start..end).map(|byte| format!("{:02x}", byte)).collect();
+        format!(r"^{}[0-9a-f]*(?:{})$", namespace, suffixes.join("|"))
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    const NAMESPACE: &str = "cad11d";
+
+    #[test]
+    /// Test that Shard::parse accepts valid shards and rejects invalid ones
+    fn test_parse_shard() {
+        assert_eq!(Shard::parse("0/4"), Ok(Shard { index: 0, total: 4 }));
+        assert_eq!(Shard::parse("3/4"), Ok(Shard { index: 3, total: 4 }));
+        assert!(Shard::parse("4/4").is_err());
+        assert!(Shard::parse("0/0").is_err());
+        assert!(Shard::parse("0/257").is_err());
+        assert!(Shard::parse("a/4").is_err());
+        assert!(Shard::parse("1").is_err());
+        assert!(Shard::parse("1/2/3").is_err());
+    }
+
+    #[test]
+    /// Test that every address in the namespace is matched by exactly one shard
+    fn test_shards_partition_namespace() {
+        let total = 3;
+        let regexes: Vec<Regex> = (0..total)
+            .map(|index| Regex::new(&Shard { index, total }.address_regex(NAMESPACE)).unwrap())
+            .collect();
+        for byte in 0..SHARD_SPACE {
+            let address = format!("{}0000{:02x}", NAMESPACE, byte);
+            let matches = regexes.iter().filter(|re| re.is_match(&address)).count();
+            assert_eq!(matches, 1, "address {} matched {} shards", address, matches);
+        }
+        assert!(!regexes[0].is_match("ffffff000000"));
+    }
+
+    #[test]
+    /// Test that a single shard matches the whole namespace
+    fn test_single_shard_matches_namespace() {
+        let shard = Shard::parse("0/1").unwrap();
+        assert_eq!(shard.address_regex(NAMESPACE), format!("^{}", NAMESPACE));
+    }
+}
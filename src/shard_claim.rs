@@ -0,0 +1,146 @@
+use crate::errors::SubscriberError;
+use crate::shard::Shard;
+
+use database::errors::DatabaseError;
+use rusqlite::NO_PARAMS;
+use std::path::Path;
+
+/// The name of the table recording which shard writes the reporting database,
+/// which may start with a prefix with SQLite
+pub const SHARD_TABLE: &str = "subscriber_shard";
+
+/// Checks the shard recorded in the reporting database against the shard this
+/// subscriber handles. Every shard records every block in the shared `blocks`
+/// table, and a block at a known height is applied as a fork, removing the rows
+/// written from that block on in every table, so two shards can't share one
+/// reporting database.
+/// ```
+/// # Errors
+/// Returns an error if the database is claimed by another shard
+/// ```
+pub fn check_claim(claimed: &str, shard: Shard) -> Result<(), SubscriberError> {
+    if claimed == shard.to_string() {
+        return Ok(());
+    }
+    Err(SubscriberError::DBError(DatabaseError::ConnectionError(
+        format!(
+            "The reporting database is written by shard {}, not {}. Each shard needs a \
+             reporting database or schema of its own.",
+            claimed, shard
+        )
+        .into(),
+    )))
+}
+
+/// Claims the reporting tables in a Postgres schema for the shard, creating the
+/// claim table if it doesn't exist. The first subscriber to write the schema
+/// claims it, so two shards starting at once can't both claim it.
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be connected to or queried, or it is
+/// claimed by another shard
+/// ```
+pub fn claim_postgres(url: &str, schema: &str, shard: Shard) -> Result<(), SubscriberError> {
+    let to_error = |err: postgres::Error| {
+        SubscriberError::DBError(DatabaseError::ConnectionError(Box::new(err)))
+    };
+    let connection =
+        postgres::Connection::connect(url, postgres::TlsMode::None).map_err(to_error)?;
+    let table = format!("\"{}\".\"{}\"", schema, SHARD_TABLE);
+    connection
+        .execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                 id INTEGER PRIMARY KEY CHECK (id = 1), \
+                 shard TEXT NOT NULL)",
+                table
+            ),
+            &[],
+        )
+        .map_err(to_error)?;
+    connection
+        .execute(
+            &format!(
+                "INSERT INTO {} (id, shard) VALUES (1, $1) ON CONFLICT (id) DO NOTHING",
+                table
+            ),
+            &[&shard.to_string()],
+        )
+        .map_err(to_error)?;
+    let claimed: String = connection
+        .query(&format!("SELECT shard FROM {} WHERE id = 1", table), &[])
+        .map_err(to_error)?
+        .get(0)
+        .get(0);
+    check_claim(&claimed, shard)
+}
+
+/// Claims the reporting tables in a SQLite file, each name starting with the
+/// prefix, for the shard, as claim_postgres does
+/// ```
+/// # Errors
+/// Returns an error if the database cannot be opened or queried, or it is
+/// claimed by another shard
+/// ```
+pub fn claim_sqlite<P: AsRef<Path>>(
+    path: P,
+    prefix: &str,
+    shard: Shard,
+) -> Result<(), SubscriberError> {
+    let to_error = |err: rusqlite::Error| {
+        SubscriberError::DBError(DatabaseError::ConnectionError(Box::new(err)))
+    };
+    let connection = rusqlite::Connection::open(path).map_err(to_error)?;
+    let table = format!("{}{}", prefix, SHARD_TABLE);
+    connection
+        .execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                 id INTEGER PRIMARY KEY CHECK (id = 1), \
+                 shard TEXT NOT NULL)",
+                table
+            ),
+            NO_PARAMS,
+        )
+        .map_err(to_error)?;
+    connection
+        .execute(
+            &format!("INSERT OR IGNORE INTO {} (id, shard) VALUES (1, ?)", table),
+            &[shard.to_string()],
+        )
+        .map_err(to_error)?;
+    let claimed: String = connection
+        .query_row(
+            &format!("SELECT shard FROM {} WHERE id = 1", table),
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .map_err(to_error)?;
+    check_claim(&claimed, shard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that two shards can't apply blocks to one store: the first shard to
+    /// claim it keeps it, and the other is refused before applying any block
+    fn test_two_shards_one_store() {
+        let path = std::env::temp_dir().join(format!("sds-shard-{}.db", uuid::Uuid::new_v4()));
+        let first = Shard::parse("0/2").unwrap();
+        let second = Shard::parse("1/2").unwrap();
+
+        claim_sqlite(&path, "", first).unwrap();
+        let err = claim_sqlite(&path, "", second).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("The reporting database is written by shard 0/2, not 1/2"));
+        claim_sqlite(&path, "", first).unwrap();
+        assert!(claim_sqlite(&path, "", Shard::default()).is_err());
+
+        // Another prefix is another reporting database in the same file
+        claim_sqlite(&path, "shard_1_", second).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
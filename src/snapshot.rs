@@ -0,0 +1,592 @@
+use crate::errors::SubscriberError;
+use crate::store::BlockStore;
+
+use database::{
+    data_manager::{OperationType, MAX_BLOCK_NUM},
+    errors::DatabaseError,
+    models::Block,
+};
+use rusqlite::types::Value;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// The tables of the rows an organization update replaces wholesale, each keyed
+/// by organization_id
+pub const REPLACED_TABLES: [&str; 3] = ["authorizations", "contacts", "accreditations"];
+
+/// Returns one UPDATE statement for each table of REPLACED_TABLES, named by
+/// `table_name`, closing the open rows left from before the organization's
+/// current row.
+///
+/// Organization state is sent as a full snapshot on every update, so each update
+/// opens a row for every authorization, contact and accreditation the
+/// organization still has, starting at the same block as its organization row.
+/// An open row that started before the open organization row was not in the
+/// latest snapshot, so it was removed at the block the organization row started,
+/// and is closed there. MAX_BLOCK_NUM is bound to the first parameter, and an
+/// organization id to the second when `by_organization` is set, otherwise every
+/// organization is checked.
+pub fn close_replaced_statements<F>(
+    table_name: F,
+    placeholders: (&str, &str),
+    by_organization: bool,
+) -> Vec<String>
+where
+    F: Fn(&str) -> String,
+{
+    let organizations = table_name("organizations");
+    REPLACED_TABLES
+        .iter()
+        .map(|table| {
+            let table = table_name(table);
+            let organization_start = format!(
+                "(SELECT o.start_block_num FROM {} o \
+                 WHERE o.organization_id = {}.organization_id AND o.end_block_num = {})",
+                organizations, table, placeholders.0
+            );
+            let mut statement = format!(
+                "UPDATE {} SET end_block_num = {} \
+                 WHERE end_block_num = {} AND start_block_num < {}",
+                table, organization_start, placeholders.0, organization_start
+            );
+            if by_organization {
+                statement.push_str(&format!(" AND organization_id = {}", placeholders.1));
+            }
+            statement
+        })
+        .collect()
+}
+
+/// Returns the ids of the organizations updated by the operations of a block
+pub fn updated_organization_ids(operations: &[OperationType]) -> BTreeSet<String> {
+    operations
+        .iter()
+        .filter_map(|operation| match operation {
+            OperationType::CreateOrganization(orgs) => Some(orgs),
+            _ => None,
+        })
+        .flatten()
+        .map(|(org, ..)| org.organization_id.clone())
+        .collect()
+}
+
+/// The rows of the reporting database that organization updates replace
+pub trait ReplacedRows {
+    /// Closes the open rows of the given organizations, or of every organization
+    /// if None, that their latest update left out, in one transaction. Returns the
+    /// number of rows closed.
+    fn close_replaced(
+        &self,
+        organization_ids: Option<&BTreeSet<String>>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Replaces the connection to the database after it was lost
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+/// The replaced rows of the reporting tables in a Postgres schema
+pub struct PostgresReplacedRows {
+    url: String,
+    connection: RefCell<postgres::Connection>,
+    schema: String,
+}
+
+impl PostgresReplacedRows {
+    /// Connects to the database holding the reporting tables in the schema
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be connected to
+    /// ```
+    pub fn connect(url: &str, schema: &str) -> Result<PostgresReplacedRows, SubscriberError> {
+        let connection = postgres::Connection::connect(url, postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(PostgresReplacedRows {
+            url: url.to_string(),
+            connection: RefCell::new(connection),
+            schema: schema.to_string(),
+        })
+    }
+
+    fn table_name(&self, table: &str) -> String {
+        format!("\"{}\".\"{}\"", self.schema, table)
+    }
+}
+
+impl ReplacedRows for PostgresReplacedRows {
+    fn close_replaced(
+        &self,
+        organization_ids: Option<&BTreeSet<String>>,
+    ) -> Result<u64, DatabaseError> {
+        let to_database_error =
+            |err: postgres::Error| DatabaseError::ConnectionError(Box::new(err));
+        let connection = self.connection.borrow();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        let mut closed = 0;
+        match organization_ids {
+            Some(ids) => {
+                let statements =
+                    close_replaced_statements(|table| self.table_name(table), ("$1", "$2"), true);
+                for statement in &statements {
+                    for id in ids {
+                        closed += transaction
+                            .execute(statement, &[&MAX_BLOCK_NUM, id])
+                            .map_err(to_database_error)?;
+                    }
+                }
+            }
+            None => {
+                let statements =
+                    close_replaced_statements(|table| self.table_name(table), ("$1", ""), false);
+                for statement in &statements {
+                    closed += transaction
+                        .execute(statement, &[&MAX_BLOCK_NUM])
+                        .map_err(to_database_error)?;
+                }
+            }
+        }
+        transaction.commit().map_err(to_database_error)?;
+        Ok(closed)
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        let connection = postgres::Connection::connect(self.url.as_str(), postgres::TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        *self.connection.borrow_mut() = connection;
+        Ok(())
+    }
+}
+
+/// The replaced rows of the reporting tables in a SQLite file, each name
+/// starting with the prefix
+pub struct SqliteReplacedRows {
+    connection: RefCell<rusqlite::Connection>,
+    prefix: String,
+}
+
+impl SqliteReplacedRows {
+    /// Opens the SQLite database at the given path, which must already hold the
+    /// reporting tables
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be opened
+    /// ```
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        prefix: &str,
+    ) -> Result<SqliteReplacedRows, DatabaseError> {
+        let connection = rusqlite::Connection::open(path).map_err(to_database_error)?;
+        Ok(SqliteReplacedRows {
+            connection: RefCell::new(connection),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+impl ReplacedRows for SqliteReplacedRows {
+    fn close_replaced(
+        &self,
+        organization_ids: Option<&BTreeSet<String>>,
+    ) -> Result<u64, DatabaseError> {
+        let table_name = |table: &str| format!("{}{}", self.prefix, table);
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        let mut closed = 0;
+        match organization_ids {
+            Some(ids) => {
+                for statement in &close_replaced_statements(table_name, ("?1", "?2"), true) {
+                    for id in ids {
+                        closed += transaction
+                            .execute(
+                                statement,
+                                &[Value::Integer(MAX_BLOCK_NUM), Value::Text(id.clone())],
+                            )
+                            .map_err(to_database_error)? as u64;
+                    }
+                }
+            }
+            None => {
+                for statement in &close_replaced_statements(table_name, ("?1", ""), false) {
+                    closed += transaction
+                        .execute(statement, &[Value::Integer(MAX_BLOCK_NUM)])
+                        .map_err(to_database_error)? as u64;
+                }
+            }
+        }
+        transaction.commit().map_err(to_database_error)?;
+        Ok(closed)
+    }
+}
+
+fn to_database_error(err: rusqlite::Error) -> DatabaseError {
+    DatabaseError::ConnectionError(Box::new(err))
+}
+
+/// Closes the rows an organization update left out: applies each block to the
+/// reporting database as the wrapped store does, then closes the open rows of
+/// each organization the block updated that started before its new row.
+///
+/// It wraps `DataManager`, which closes only the organization row on an update.
+/// `SqliteStore` closes every row of the organization in the block's transaction,
+/// so over it there is nothing left to close.
+///
+/// Whether a row was left out is read from the reporting database rather than
+/// remembered, so removals are found after a restart as well. The rows are closed
+/// in a transaction of their own once the block commits. If the subscriber stops
+/// in between, they stay open, so the rows left out by every organization's
+/// latest update are closed when the store is created. Closing them is safe to
+/// repeat, and closes nothing once they are closed.
+pub struct ReplacedRowsStore {
+    store: Box<dyn BlockStore>,
+    replaced_rows: Box<dyn ReplacedRows>,
+}
+
+impl ReplacedRowsStore {
+    /// Wraps the store, first closing the rows every organization's latest update
+    /// left out
+    /// ```
+    /// # Errors
+    /// Returns an error if the rows cannot be closed
+    /// ```
+    pub fn new(
+        store: Box<dyn BlockStore>,
+        replaced_rows: Box<dyn ReplacedRows>,
+    ) -> Result<ReplacedRowsStore, DatabaseError> {
+        let closed = replaced_rows.close_replaced(None)?;
+        if closed > 0 {
+            info!(
+                "Closed {} rows left out of the latest organization updates",
+                closed
+            );
+        }
+        Ok(ReplacedRowsStore {
+            store,
+            replaced_rows,
+        })
+    }
+}
+
+impl BlockStore for ReplacedRowsStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        let organization_ids = updated_organization_ids(&operations);
+        self.store.execute_operations_in_block(operations, block)?;
+        if organization_ids.is_empty() {
+            return Ok(());
+        }
+        let closed = self.replaced_rows.close_replaced(Some(&organization_ids))?;
+        if closed > 0 {
+            info!(
+                "Closed {} rows removed from organizations at block {}",
+                closed, block.block_num
+            );
+        }
+        Ok(())
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        self.store.fetch_known_blocks()
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        self.replaced_rows.reconnect()?;
+        self.store.reconnect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_store::{entity_writes, SqliteStore};
+    use crate::store::memory::MemoryStore;
+    use crate::store::test_support::make_block;
+    use database::{
+        custom_types::*,
+        models::{NewAccreditation, NewAuthorization, NewContact, NewOrganization},
+    };
+    use rusqlite::NO_PARAMS;
+
+    const ORG_ID: &str = "test_org";
+
+    #[test]
+    /// Test that an organization update dropping one of two authorizations closes
+    /// its row at the update's block, and keeps the other open
+    fn test_update_drops_authorization() {
+        let path = std::env::temp_dir().join(format!("sds-replaced-{}.db", uuid::Uuid::new_v4()));
+        drop(SqliteStore::open(&path).unwrap());
+        let replaced_rows = SqliteReplacedRows::open(&path, "").unwrap();
+
+        write_without_closing(&path, &make_org_operation(1, &["key_1", "key_2"], &[], &[]));
+        let ids = updated_organization_ids(&[make_org_operation(2, &["key_1"], &[], &[])]);
+        assert_eq!(replaced_rows.close_replaced(Some(&ids)).unwrap(), 0);
+
+        write_without_closing(&path, &make_org_operation(2, &["key_1"], &[], &[]));
+        assert_eq!(replaced_rows.close_replaced(Some(&ids)).unwrap(), 2);
+        assert_eq!(
+            authorization_rows(&path),
+            vec![
+                ("key_1".to_string(), 1, 2),
+                ("key_1".to_string(), 2, MAX_BLOCK_NUM),
+                ("key_2".to_string(), 1, 2),
+            ]
+        );
+        assert_eq!(replaced_rows.close_replaced(Some(&ids)).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that the rows left open by a store that stopped before closing them are
+    /// closed when the store is created, and after each block updating the
+    /// organization
+    fn test_replaced_rows_store() {
+        let path = std::env::temp_dir().join(format!("sds-replaced-{}.db", uuid::Uuid::new_v4()));
+        drop(SqliteStore::open(&path).unwrap());
+        write_without_closing(&path, &make_org_operation(1, &["key_1", "key_2"], &[], &[]));
+        write_without_closing(&path, &make_org_operation(2, &["key_1"], &[], &[]));
+
+        let store = MemoryStore::default();
+        let replaced = ReplacedRowsStore::new(
+            Box::new(store.clone()),
+            Box::new(SqliteReplacedRows::open(&path, "").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(open_authorizations(&path), vec![("key_1".to_string(), 2)]);
+
+        write_without_closing(&path, &make_org_operation(3, &["key_2"], &[], &[]));
+        replaced
+            .execute_operations_in_block(
+                vec![make_org_operation(3, &["key_2"], &[], &[])],
+                &make_block(3),
+            )
+            .unwrap();
+        assert_eq!(open_authorizations(&path), vec![("key_2".to_string(), 3)]);
+        assert_eq!(store.block_ids().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that over a SqliteStore, which closes the rows an update leaves out
+    /// itself, an authorization dropped by an update is closed once, at the
+    /// update's block
+    fn test_replaced_rows_store_over_sqlite() {
+        let path = std::env::temp_dir().join(format!("sds-replaced-{}.db", uuid::Uuid::new_v4()));
+        let store = SqliteStore::open(&path).unwrap();
+        let replaced = ReplacedRowsStore::new(
+            Box::new(store),
+            Box::new(SqliteReplacedRows::open(&path, "").unwrap()),
+        )
+        .unwrap();
+        replaced
+            .execute_operations_in_block(
+                vec![make_org_operation(1, &["key_1", "key_2"], &[], &[])],
+                &make_block(1),
+            )
+            .unwrap();
+        replaced
+            .execute_operations_in_block(
+                vec![make_org_operation(2, &["key_1"], &[], &[])],
+                &make_block(2),
+            )
+            .unwrap();
+        assert_eq!(
+            authorization_rows(&path),
+            vec![
+                ("key_1".to_string(), 1, 2),
+                ("key_1".to_string(), 2, MAX_BLOCK_NUM),
+                ("key_2".to_string(), 1, 2),
+            ]
+        );
+        assert_eq!(
+            SqliteReplacedRows::open(&path, "")
+                .unwrap()
+                .close_replaced(None)
+                .unwrap(),
+            0
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that a contact and an accreditation added by one organization update and
+    /// dropped by the next are closed at the block of the second
+    fn test_update_drops_contact_and_accreditation() {
+        let path = std::env::temp_dir().join(format!("sds-replaced-{}.db", uuid::Uuid::new_v4()));
+        drop(SqliteStore::open(&path).unwrap());
+        let replaced_rows = SqliteReplacedRows::open(&path, "").unwrap();
+
+        write_without_closing(
+            &path,
+            &make_org_operation(1, &["key_1"], &["first", "second"], &["standard_1"]),
+        );
+        let update = make_org_operation(2, &["key_1"], &["first"], &[]);
+        write_without_closing(&path, &update);
+        let ids = updated_organization_ids(&[update]);
+        assert_eq!(replaced_rows.close_replaced(Some(&ids)).unwrap(), 4);
+        assert_eq!(
+            rows(&path, "contacts", "name"),
+            vec![
+                ("first".to_string(), 1, 2),
+                ("first".to_string(), 2, MAX_BLOCK_NUM),
+                ("second".to_string(), 1, 2),
+            ]
+        );
+        assert_eq!(
+            rows(&path, "accreditations", "standard_id"),
+            vec![("standard_1".to_string(), 1, 2)]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that the statements close rows by organization, or for every
+    /// organization
+    fn test_close_replaced_statements() {
+        let statements =
+            close_replaced_statements(|table| format!("s.{}", table), ("$1", "$2"), true);
+        assert_eq!(statements.len(), REPLACED_TABLES.len());
+        assert_eq!(
+            statements[0],
+            "UPDATE s.authorizations SET end_block_num = (SELECT o.start_block_num \
+             FROM s.organizations o WHERE o.organization_id = s.authorizations.organization_id \
+             AND o.end_block_num = $1) WHERE end_block_num = $1 AND start_block_num < \
+             (SELECT o.start_block_num FROM s.organizations o \
+             WHERE o.organization_id = s.authorizations.organization_id \
+             AND o.end_block_num = $1) AND organization_id = $2"
+        );
+        assert!(
+            !close_replaced_statements(|table| table.to_string(), ("?1", ""), false)[0]
+                .contains("AND organization_id")
+        );
+    }
+
+    /// Writes the rows of an operation as a store that closes the organization row
+    /// but not the rows it replaces would
+    fn write_without_closing(path: &Path, operation: &OperationType) {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        for write in entity_writes(operation) {
+            for (table, key_column, key) in write.closes {
+                if table != "organizations" {
+                    continue;
+                }
+                let start = organization_start(operation);
+                connection
+                    .execute(
+                        &format!(
+                            "UPDATE {} SET end_block_num = ? WHERE {} = ? AND end_block_num = ?",
+                            table, key_column
+                        ),
+                        &[
+                            Value::Integer(start),
+                            Value::Text(key),
+                            Value::Integer(MAX_BLOCK_NUM),
+                        ],
+                    )
+                    .unwrap();
+            }
+            for (table, columns) in write.inserts {
+                let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+                let values: Vec<Value> = columns.into_iter().map(|(_, value)| value).collect();
+                connection
+                    .execute(
+                        &format!(
+                            "INSERT INTO {} ({}) VALUES ({})",
+                            table,
+                            names.join(", "),
+                            vec!["?"; names.len()].join(", ")
+                        ),
+                        &values,
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    fn organization_start(operation: &OperationType) -> i64 {
+        match operation {
+            OperationType::CreateOrganization(orgs) => orgs[0].0.start_block_num,
+            _ => panic!("Expected an organization operation"),
+        }
+    }
+
+    fn authorization_rows(path: &Path) -> Vec<(String, i64, i64)> {
+        rows(path, "authorizations", "public_key")
+    }
+
+    fn rows(path: &Path, table: &str, key_column: &str) -> Vec<(String, i64, i64)> {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {}, start_block_num, end_block_num FROM {} \
+                 ORDER BY {}, start_block_num",
+                key_column, table, key_column
+            ))
+            .unwrap();
+        let rows = statement
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    fn open_authorizations(path: &Path) -> Vec<(String, i64)> {
+        authorization_rows(path)
+            .into_iter()
+            .filter(|(_, _, end)| *end == MAX_BLOCK_NUM)
+            .map(|(key, start, _)| (key, start))
+            .collect()
+    }
+
+    fn make_org_operation(
+        block_num: i64,
+        public_keys: &[&str],
+        contact_names: &[&str],
+        standard_ids: &[&str],
+    ) -> OperationType {
+        let org = NewOrganization {
+            organization_id: ORG_ID.to_string(),
+            name: "test".to_string(),
+            organization_type: OrganizationTypeEnum::CertifyingBody,
+            start_block_num: block_num,
+            end_block_num: MAX_BLOCK_NUM,
+        };
+        let auths = public_keys
+            .iter()
+            .map(|public_key| NewAuthorization {
+                organization_id: ORG_ID.to_string(),
+                public_key: public_key.to_string(),
+                role: RoleEnum::Admin,
+                start_block_num: block_num,
+                end_block_num: MAX_BLOCK_NUM,
+            })
+            .collect();
+        let contacts = contact_names
+            .iter()
+            .map(|name| NewContact {
+                organization_id: ORG_ID.to_string(),
+                name: name.to_string(),
+                phone_number: "test".to_string(),
+                language_code: "test".to_string(),
+                start_block_num: block_num,
+                end_block_num: MAX_BLOCK_NUM,
+            })
+            .collect();
+        let accreditations = standard_ids
+            .iter()
+            .map(|standard_id| NewAccreditation {
+                organization_id: ORG_ID.to_string(),
+                standard_id: standard_id.to_string(),
+                standard_version: "1.0".to_string(),
+                accreditor_id: "test".to_string(),
+                valid_from: 1,
+                valid_to: 2,
+                start_block_num: block_num,
+                end_block_num: MAX_BLOCK_NUM,
+            })
+            .collect();
+        OperationType::CreateOrganization(vec![(org, Some(accreditations), None, auths, contacts)])
+    }
+}
@@ -0,0 +1,953 @@
+use crate::graph::{Graph, GRAPH_TABLES};
+use crate::reingest::{ReingestTarget, Reset};
+use crate::schema_check::EXPECTED_TABLES;
+use crate::store::BlockStore;
+use crate::verify::RowReader;
+
+use database::{
+    data_manager::{OperationType, MAX_BLOCK_NUM},
+    errors::DatabaseError,
+    models::Block,
+};
+use rusqlite::types::Value;
+use rusqlite::{Connection, Transaction, NO_PARAMS};
+use std::cell::RefCell;
+use std::path::Path;
+
+/// The rows written for one entity: the open rows to close, as a table, key
+/// column and key, and the new rows to open, as a table and its columns
+#[derive(Debug, Default, PartialEq)]
+pub struct EntityWrite {
+    pub closes: Vec<(&'static str, &'static str, String)>,
+    pub inserts: Vec<(&'static str, Vec<(&'static str, Value)>)>,
+}
+
+/// Returns the rows written for each entity in an operation. Enum values are
+/// stored as the names of their variants.
+pub fn entity_writes(operation: &OperationType) -> Vec<EntityWrite> {
+    let text = |value: &str| Value::Text(value.to_string());
+    let optional = |value: &Option<String>| match value {
+        Some(value) => Value::Text(value.clone()),
+        None => Value::Null,
+    };
+    let versioned = |mut columns: Vec<(&'static str, Value)>, start: i64, end: i64| {
+        columns.push(("start_block_num", Value::Integer(start)));
+        columns.push(("end_block_num", Value::Integer(end)));
+        columns
+    };
+    match operation {
+        OperationType::CreateOrganization(orgs) => orgs
+            .iter()
+            .map(|(org, accreditations, address, auths, contacts)| {
+                let id = &org.organization_id;
+                let mut inserts = vec![(
+                    "organizations",
+                    versioned(
+                        vec![
+                            ("organization_id", text(id)),
+                            ("name", text(&org.name)),
+                            (
+                                "organization_type",
+                                Value::Text(format!("{:?}", org.organization_type)),
+                            ),
+                        ],
+                        org.start_block_num,
+                        org.end_block_num,
+                    ),
+                )];
+                inserts.extend(accreditations.iter().flatten().map(|accreditation| {
+                    (
+                        "accreditations",
+                        versioned(
+                            vec![
+                                ("organization_id", text(id)),
+                                ("standard_id", text(&accreditation.standard_id)),
+                                ("standard_version", text(&accreditation.standard_version)),
+                                ("accreditor_id", text(&accreditation.accreditor_id)),
+                                ("valid_from", Value::Integer(accreditation.valid_from)),
+                                ("valid_to", Value::Integer(accreditation.valid_to)),
+                            ],
+                            accreditation.start_block_num,
+                            accreditation.end_block_num,
+                        ),
+                    )
+                }));
+                inserts.extend(address.iter().map(|address| {
+                    (
+                        "addresses",
+                        versioned(
+                            vec![
+                                ("organization_id", text(id)),
+                                ("street_line_1", text(&address.street_line_1)),
+                                ("street_line_2", optional(&address.street_line_2)),
+                                ("city", text(&address.city)),
+                                ("state_province", optional(&address.state_province)),
+                                ("country", text(&address.country)),
+                                ("postal_code", optional(&address.postal_code)),
+                            ],
+                            address.start_block_num,
+                            address.end_block_num,
+                        ),
+                    )
+                }));
+                inserts.extend(auths.iter().map(|auth| {
+                    (
+                        "authorizations",
+                        versioned(
+                            vec![
+                                ("organization_id", text(id)),
+                                ("public_key", text(&auth.public_key)),
+                                ("role", Value::Text(format!("{:?}", auth.role))),
+                            ],
+                            auth.start_block_num,
+                            auth.end_block_num,
+                        ),
+                    )
+                }));
+                inserts.extend(contacts.iter().map(|contact| {
+                    (
+                        "contacts",
+                        versioned(
+                            vec![
+                                ("organization_id", text(id)),
+                                ("name", text(&contact.name)),
+                                ("phone_number", text(&contact.phone_number)),
+                                ("language_code", text(&contact.language_code)),
+                            ],
+                            contact.start_block_num,
+                            contact.end_block_num,
+                        ),
+                    )
+                }));
+                EntityWrite {
+                    closes: [
+                        "organizations",
+                        "accreditations",
+                        "addresses",
+                        "authorizations",
+                        "contacts",
+                    ]
+                    .iter()
+                    .map(|table| (*table, "organization_id", id.clone()))
+                    .collect(),
+                    inserts,
+                }
+            })
+            .collect(),
+        OperationType::CreateAgent(agents) => agents
+            .iter()
+            .map(|agent| EntityWrite {
+                closes: vec![("agents", "public_key", agent.public_key.clone())],
+                inserts: vec![(
+                    "agents",
+                    versioned(
+                        vec![
+                            ("public_key", text(&agent.public_key)),
+                            ("organization_id", optional(&agent.organization_id)),
+                            ("name", text(&agent.name)),
+                            ("timestamp", Value::Integer(agent.timestamp)),
+                        ],
+                        agent.start_block_num,
+                        agent.end_block_num,
+                    ),
+                )],
+            })
+            .collect(),
+        OperationType::CreateCertificate(certificates) => certificates
+            .iter()
+            .map(|certificate| EntityWrite {
+                closes: vec![(
+                    "certificates",
+                    "certificate_id",
+                    certificate.certificate_id.clone(),
+                )],
+                inserts: vec![(
+                    "certificates",
+                    versioned(
+                        vec![
+                            ("certificate_id", text(&certificate.certificate_id)),
+                            ("certifying_body_id", text(&certificate.certifying_body_id)),
+                            ("factory_id", text(&certificate.factory_id)),
+                            ("standard_id", text(&certificate.standard_id)),
+                            ("standard_version", text(&certificate.standard_version)),
+                            ("valid_from", Value::Integer(certificate.valid_from)),
+                            ("valid_to", Value::Integer(certificate.valid_to)),
+                        ],
+                        certificate.start_block_num,
+                        certificate.end_block_num,
+                    ),
+                )],
+            })
+            .collect(),
+        OperationType::CreateRequest(requests) => requests
+            .iter()
+            .map(|request| EntityWrite {
+                closes: vec![("requests", "request_id", request.request_id.clone())],
+                inserts: vec![(
+                    "requests",
+                    versioned(
+                        vec![
+                            ("request_id", text(&request.request_id)),
+                            ("factory_id", text(&request.factory_id)),
+                            ("standard_id", text(&request.standard_id)),
+                            ("status", Value::Text(format!("{:?}", request.status))),
+                            ("request_date", Value::Integer(request.request_date)),
+                        ],
+                        request.start_block_num,
+                        request.end_block_num,
+                    ),
+                )],
+            })
+            .collect(),
+        OperationType::CreateStandard(standards) => standards
+            .iter()
+            .map(|(standard, versions)| {
+                let id = &standard.standard_id;
+                let mut inserts = vec![(
+                    "standards",
+                    versioned(
+                        vec![
+                            ("standard_id", text(id)),
+                            ("organization_id", text(&standard.organization_id)),
+                            ("name", text(&standard.name)),
+                        ],
+                        standard.start_block_num,
+                        standard.end_block_num,
+                    ),
+                )];
+                inserts.extend(versions.iter().map(|version| {
+                    (
+                        "standard_versions",
+                        versioned(
+                            vec![
+                                ("standard_id", text(id)),
+                                ("version", text(&version.version)),
+                                ("link", text(&version.link)),
+                                ("description", text(&version.description)),
+                                ("approval_date", Value::Integer(version.approval_date)),
+                            ],
+                            version.start_block_num,
+                            version.end_block_num,
+                        ),
+                    )
+                }));
+                EntityWrite {
+                    closes: vec![
+                        ("standards", "standard_id", id.clone()),
+                        ("standard_versions", "standard_id", id.clone()),
+                    ],
+                    inserts,
+                }
+            })
+            .collect(),
+        OperationType::CreateAssertion(assertions) => assertions
+            .iter()
+            .map(|assertion| EntityWrite {
+                closes: vec![("assertions", "assertion_id", assertion.assertion_id.clone())],
+                inserts: vec![(
+                    "assertions",
+                    versioned(
+                        vec![
+                            ("assertion_id", text(&assertion.assertion_id)),
+                            ("address", text(&assertion.address)),
+                            ("assertor_pub_key", text(&assertion.assertor_pub_key)),
+                            (
+                                "assertion_type",
+                                Value::Text(format!("{:?}", assertion.assertion_type)),
+                            ),
+                            ("object_id", text(&assertion.object_id)),
+                            ("data_id", optional(&assertion.data_id)),
+                        ],
+                        assertion.start_block_num,
+                        assertion.end_block_num,
+                    ),
+                )],
+            })
+            .collect(),
+        OperationType::DeleteAssertion(address, _) => vec![EntityWrite {
+            closes: vec![("assertions", "address", address.clone())],
+            inserts: vec![],
+        }],
+    }
+}
+
+/// Checks that a table name prefix is lowercase letters, digits and underscores,
+/// starting with a letter or underscore, so prefixed names need no quoting
+/// ```
+/// # Errors
+/// Returns an error describing the allowed characters otherwise
+/// ```
+pub fn validate_table_prefix(prefix: &str) -> Result<(), String> {
+    let valid = prefix
+        .chars()
+        .next()
+        .map_or(true, |first| !first.is_ascii_digit())
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid table prefix {:?}, use lowercase letters, digits and underscores, \
+             not starting with a digit",
+            prefix
+        ))
+    }
+}
+
+/// The key columns the open rows of each versioned table are closed by, as in
+/// entity_writes
+pub const KEY_COLUMNS: [(&str, &str); 12] = [
+    ("organizations", "organization_id"),
+    ("accreditations", "organization_id"),
+    ("addresses", "organization_id"),
+    ("authorizations", "organization_id"),
+    ("contacts", "organization_id"),
+    ("agents", "public_key"),
+    ("certificates", "certificate_id"),
+    ("requests", "request_id"),
+    ("standards", "standard_id"),
+    ("standard_versions", "standard_id"),
+    ("assertions", "assertion_id"),
+    ("assertions", "address"),
+];
+
+/// Returns the statements creating the reporting tables, each name starting with
+/// the prefix, and their indexes, if they don't exist yet. Each versioned table
+/// is indexed on its block numbers, which forks delete and reopen rows by, and
+/// on each key column with the end block, which closing an entity's open rows
+/// looks up.
+pub fn create_table_statements(prefix: &str) -> Vec<String> {
+    let tables = EXPECTED_TABLES.iter().map(|(table, columns)| {
+        let columns = if *table == "blocks" {
+            "block_num INTEGER PRIMARY KEY, block_id TEXT NOT NULL".to_string()
+        } else {
+            let mut columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+            columns.push("start_block_num INTEGER NOT NULL".to_string());
+            columns.push("end_block_num INTEGER NOT NULL".to_string());
+            columns.join(", ")
+        };
+        format!(
+            "CREATE TABLE IF NOT EXISTS {}{} ({})",
+            prefix, table, columns
+        )
+    });
+    let block_indexes = EXPECTED_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "blocks")
+        .flat_map(|(table, _)| {
+            ["start_block_num", "end_block_num"]
+                .iter()
+                .map(move |column| {
+                    format!(
+                        "CREATE INDEX IF NOT EXISTS {}{}_{} ON {}{} ({})",
+                        prefix, table, column, prefix, table, column
+                    )
+                })
+        });
+    let key_indexes = KEY_COLUMNS.iter().map(|(table, column)| {
+        format!(
+            "CREATE INDEX IF NOT EXISTS {}{}_{}_end ON {}{} ({}, end_block_num)",
+            prefix, table, column, prefix, table, column
+        )
+    });
+    tables.chain(block_indexes).chain(key_indexes).collect()
+}
+
+/// A reporting database in a single SQLite file, for local development and tests.
+///
+/// Rows are versioned the same way as in Postgres: a change closes the open rows
+/// of an entity by setting their end block, and opens new rows ending at
+/// MAX_BLOCK_NUM. A block at or below the last known block is a fork, so the
+/// rows written from that block on are removed and the rows closed from that
+/// block on are reopened before it is applied.
+///
+/// Every table name may start with a prefix, so several reporting databases can
+/// share one file.
+pub struct SqliteStore {
+    connection: RefCell<Connection>,
+    prefix: String,
+}
+
+impl SqliteStore {
+    /// Opens the SQLite database at the given path, creating it and the reporting
+    /// tables if they don't exist
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be opened or the tables created
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, DatabaseError> {
+        Self::open_with_prefix(path, "")
+    }
+
+    /// Opens the SQLite database at the given path, creating it and the reporting
+    /// tables, each name starting with the prefix, if they don't exist
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be opened or the tables created
+    /// ```
+    pub fn open_with_prefix<P: AsRef<Path>>(
+        path: P,
+        prefix: &str,
+    ) -> Result<SqliteStore, DatabaseError> {
+        let connection = Connection::open(path).map_err(to_database_error)?;
+        for statement in create_table_statements(prefix) {
+            connection
+                .execute(&statement, NO_PARAMS)
+                .map_err(to_database_error)?;
+        }
+        Ok(SqliteStore {
+            connection: RefCell::new(connection),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Returns the standard id and version of every current standard_versions row
+    /// ```
+    /// # Errors
+    /// Returns an error if the table cannot be queried
+    /// ```
+    pub fn fetch_standard_versions(&self) -> Result<Vec<(String, String)>, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT standard_id, version FROM {}standard_versions WHERE end_block_num = ?",
+                self.prefix
+            ))
+            .map_err(to_database_error)?;
+        let versions = statement
+            .query_map(&[Value::Integer(MAX_BLOCK_NUM)], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(to_database_error)?
+            .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()
+            .map_err(to_database_error)?;
+        Ok(versions)
+    }
+
+    /// Returns the id of every current organizations row
+    /// ```
+    /// # Errors
+    /// Returns an error if the table cannot be queried
+    /// ```
+    pub fn fetch_organization_ids(&self) -> Result<Vec<String>, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT organization_id FROM {}organizations WHERE end_block_num = ?",
+                self.prefix
+            ))
+            .map_err(to_database_error)?;
+        let ids = statement
+            .query_map(&[Value::Integer(MAX_BLOCK_NUM)], |row| row.get(0))
+            .map_err(to_database_error)?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()
+            .map_err(to_database_error)?;
+        Ok(ids)
+    }
+
+    /// Returns the current rows of the graph's tables as a relationship graph
+    /// ```
+    /// # Errors
+    /// Returns an error if a table cannot be queried
+    /// ```
+    pub fn fetch_graph(&self) -> Result<Graph, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut graph = Graph::default();
+        for table in GRAPH_TABLES.iter() {
+            let mut statement = connection
+                .prepare(&table.select_statement(&format!("{}{}", self.prefix, table.table)))
+                .map_err(to_database_error)?;
+            let columns = statement.column_count();
+            let rows = statement
+                .query_map(NO_PARAMS, |row| {
+                    (0..columns)
+                        .map(|index| row.get(index))
+                        .collect::<rusqlite::Result<Vec<Option<String>>>>()
+                })
+                .map_err(to_database_error)?
+                .collect::<Result<Vec<Vec<Option<String>>>, rusqlite::Error>>()
+                .map_err(to_database_error)?;
+            for values in rows {
+                graph.add_row(table, values);
+            }
+        }
+        Ok(graph)
+    }
+}
+
+impl BlockStore for SqliteStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        apply_block(&transaction, &self.prefix, &operations, block).map_err(to_database_error)?;
+        transaction.commit().map_err(to_database_error)
+    }
+
+    fn execute_operations_in_blocks(
+        &self,
+        blocks: Vec<(Vec<OperationType>, Block)>,
+    ) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        for (operations, block) in &blocks {
+            apply_block(&transaction, &self.prefix, operations, block)
+                .map_err(to_database_error)?;
+        }
+        transaction.commit().map_err(to_database_error)
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT block_num, block_id FROM {}blocks ORDER BY block_num DESC",
+                self.prefix
+            ))
+            .map_err(to_database_error)?;
+        let blocks = statement
+            .query_map(NO_PARAMS, |row| {
+                Ok(Block {
+                    block_num: row.get(0)?,
+                    block_id: row.get(1)?,
+                })
+            })
+            .map_err(to_database_error)?
+            .collect::<Result<Vec<Block>, rusqlite::Error>>()
+            .map_err(to_database_error)?;
+        Ok(blocks)
+    }
+}
+
+impl RowReader for SqliteStore {
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError> {
+        let connection = self.connection.borrow();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT block_id FROM {}blocks WHERE block_num = ?",
+                self.prefix
+            ))
+            .map_err(to_database_error)?;
+        let mut ids = statement
+            .query_map(&[Value::Integer(block_num)], |row| row.get(0))
+            .map_err(to_database_error)?;
+        ids.next().transpose().map_err(to_database_error)
+    }
+
+    fn has_row(&self, table: &str, columns: &[(&str, Value)]) -> Result<bool, DatabaseError> {
+        let conditions: Vec<String> = columns
+            .iter()
+            .map(|(column, value)| match value {
+                Value::Null => format!("{} IS NULL", column),
+                _ => format!("{} = ?", column),
+            })
+            .collect();
+        let params: Vec<&Value> = columns
+            .iter()
+            .map(|(_, value)| value)
+            .filter(|value| **value != Value::Null)
+            .collect();
+        let connection = self.connection.borrow();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT 1 FROM {}{} WHERE {} LIMIT 1",
+                self.prefix,
+                table,
+                conditions.join(" AND ")
+            ))
+            .map_err(to_database_error)?;
+        statement.exists(&params).map_err(to_database_error)
+    }
+}
+
+impl ReingestTarget for SqliteStore {
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError> {
+        RowReader::block_id(self, block_num)
+    }
+
+    fn apply_writes(
+        &self,
+        block_num: i64,
+        writes: &[EntityWrite],
+        reset: Option<&Reset>,
+    ) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.borrow_mut();
+        let transaction = connection.transaction().map_err(to_database_error)?;
+        if let Some(reset) = reset {
+            for statement in reset.statements(|table| format!("{}{}", self.prefix, table), "?") {
+                transaction
+                    .execute(&statement, &[Value::Integer(reset.from_block)])
+                    .map_err(to_database_error)?;
+            }
+        }
+        for write in writes {
+            apply_write(&transaction, &self.prefix, write, block_num).map_err(to_database_error)?;
+        }
+        transaction.commit().map_err(to_database_error)
+    }
+}
+
+/// Records the block and applies its operations within the given transaction,
+/// first undoing the fork if the block is at or below the last known block
+fn apply_block(
+    transaction: &Transaction,
+    prefix: &str,
+    operations: &[OperationType],
+    block: &Block,
+) -> rusqlite::Result<()> {
+    let last_block_num: Option<i64> = transaction.query_row(
+        &format!("SELECT MAX(block_num) FROM {}blocks", prefix),
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    if last_block_num.map_or(false, |last| block.block_num <= last) {
+        resolve_fork(transaction, prefix, block.block_num)?;
+    }
+    transaction.execute(
+        &format!(
+            "INSERT INTO {}blocks (block_num, block_id) VALUES (?, ?)",
+            prefix
+        ),
+        &[
+            Value::Integer(block.block_num),
+            Value::Text(block.block_id.clone()),
+        ],
+    )?;
+    for write in operations.iter().flat_map(entity_writes) {
+        apply_write(transaction, prefix, &write, block.block_num)?;
+    }
+    Ok(())
+}
+
+/// Removes the rows written at or after the given block and reopens the rows
+/// closed at or after it, so the block can be applied again on a new fork
+fn resolve_fork(transaction: &Transaction, prefix: &str, block_num: i64) -> rusqlite::Result<()> {
+    let block_num = Value::Integer(block_num);
+    for (table, _) in EXPECTED_TABLES
+        .iter()
+        .filter(|(table, _)| *table != "blocks")
+    {
+        transaction.execute(
+            &format!("DELETE FROM {}{} WHERE start_block_num >= ?", prefix, table),
+            &[block_num.clone()],
+        )?;
+        transaction.execute(
+            &format!(
+                "UPDATE {}{} SET end_block_num = ? WHERE end_block_num >= ? AND end_block_num <> ?",
+                prefix, table
+            ),
+            &[
+                Value::Integer(MAX_BLOCK_NUM),
+                block_num.clone(),
+                Value::Integer(MAX_BLOCK_NUM),
+            ],
+        )?;
+    }
+    transaction.execute(
+        &format!("DELETE FROM {}blocks WHERE block_num >= ?", prefix),
+        &[block_num],
+    )?;
+    Ok(())
+}
+
+/// Closes the open rows of an entity at the given block, then opens its new rows
+fn apply_write(
+    transaction: &Transaction,
+    prefix: &str,
+    write: &EntityWrite,
+    block_num: i64,
+) -> rusqlite::Result<()> {
+    for (table, key_column, key) in &write.closes {
+        transaction.execute(
+            &format!(
+                "UPDATE {}{} SET end_block_num = ? WHERE {} = ? AND end_block_num = ?",
+                prefix, table, key_column
+            ),
+            &[
+                Value::Integer(block_num),
+                Value::Text(key.clone()),
+                Value::Integer(MAX_BLOCK_NUM),
+            ],
+        )?;
+    }
+    for (table, columns) in &write.inserts {
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        let values: Vec<&Value> = columns.iter().map(|(_, value)| value).collect();
+        transaction.execute(
+            &format!(
+                "INSERT INTO {}{} ({}) VALUES ({})",
+                prefix,
+                table,
+                names.join(", "),
+                vec!["?"; names.len()].join(", ")
+            ),
+            &values,
+        )?;
+    }
+    Ok(())
+}
+
+fn to_database_error(err: rusqlite::Error) -> DatabaseError {
+    DatabaseError::ConnectionError(Box::new(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_support::{make_agent_operation, make_block, PUBLIC_KEY};
+
+    #[test]
+    /// Test that a change closes the open row of an entity and opens a new one
+    fn test_versioned_rows() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+
+        assert_eq!(
+            agent_rows(&store),
+            vec![
+                ("first".to_string(), 1, 2),
+                ("second".to_string(), 2, MAX_BLOCK_NUM),
+            ]
+        );
+        let known: Vec<i64> = store
+            .fetch_known_blocks()
+            .unwrap()
+            .iter()
+            .map(|block| block.block_num)
+            .collect();
+        assert_eq!(known, vec![2, 1]);
+    }
+
+    #[test]
+    /// Test that a block at a known height replaces the fork it was applied on
+    fn test_fork_resolution() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("fork", 2)], &make_block(2))
+            .unwrap();
+
+        assert_eq!(
+            agent_rows(&store),
+            vec![
+                ("first".to_string(), 1, 2),
+                ("fork".to_string(), 2, MAX_BLOCK_NUM),
+            ]
+        );
+        assert_eq!(store.fetch_known_blocks().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// Test that reprocessing committed blocks, as after a crash before the position
+    /// was read back, leaves the same rows without duplicates
+    fn test_reapply_blocks() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let blocks = || {
+            vec![
+                (vec![make_agent_operation("first", 1)], make_block(1)),
+                (vec![make_agent_operation("second", 2)], make_block(2)),
+            ]
+        };
+        store.execute_operations_in_blocks(blocks()).unwrap();
+        let rows = agent_rows(&store);
+
+        store.execute_operations_in_blocks(blocks()).unwrap();
+        assert_eq!(agent_rows(&store), rows);
+        store
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+        assert_eq!(agent_rows(&store), rows);
+        assert_eq!(store.fetch_known_blocks().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// Test that blocks and rows are read back for verification, null columns included
+    fn test_row_reader() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        assert_eq!(store.block_id(1).unwrap(), Some(make_block(1).block_id));
+        assert_eq!(store.block_id(2).unwrap(), None);
+
+        let row = |name: &str| {
+            vec![
+                ("public_key", Value::Text(PUBLIC_KEY.to_string())),
+                ("organization_id", Value::Null),
+                ("name", Value::Text(name.to_string())),
+                ("start_block_num", Value::Integer(1)),
+            ]
+        };
+        assert!(store.has_row("agents", &row("first")).unwrap());
+        assert!(!store.has_row("agents", &row("second")).unwrap());
+    }
+
+    #[test]
+    /// Test that coalesced blocks are applied in order in one transaction
+    fn test_execute_operations_in_blocks() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_blocks(vec![
+                (vec![make_agent_operation("first", 1)], make_block(1)),
+                (vec![make_agent_operation("second", 2)], make_block(2)),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            agent_rows(&store),
+            vec![
+                ("first".to_string(), 1, 2),
+                ("second".to_string(), 2, MAX_BLOCK_NUM),
+            ]
+        );
+        assert_eq!(store.fetch_known_blocks().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// Test that a block failing partway, as if the subscriber crashed, leaves neither
+    /// its rows nor its position, so on restart it is applied once from the last
+    /// committed block
+    fn test_block_recorded_with_operations() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        store
+            .connection
+            .borrow()
+            .execute_batch(
+                "CREATE TEMP TRIGGER crash BEFORE INSERT ON agents WHEN NEW.name = 'crash' \
+                 BEGIN SELECT RAISE(ABORT, 'simulated crash'); END;",
+            )
+            .unwrap();
+        assert!(store
+            .execute_operations_in_block(
+                vec![
+                    make_agent_operation("second", 2),
+                    make_agent_operation("crash", 2),
+                ],
+                &make_block(2),
+            )
+            .is_err());
+        assert_eq!(
+            agent_rows(&store),
+            vec![("first".to_string(), 1, MAX_BLOCK_NUM)]
+        );
+        let known: Vec<i64> = store
+            .fetch_known_blocks()
+            .unwrap()
+            .iter()
+            .map(|block| block.block_num)
+            .collect();
+        assert_eq!(known, vec![1]);
+
+        store
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+        assert_eq!(
+            agent_rows(&store),
+            vec![
+                ("first".to_string(), 1, 2),
+                ("second".to_string(), 2, MAX_BLOCK_NUM),
+            ]
+        );
+        assert_eq!(store.fetch_known_blocks().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// Test that stores with different table prefixes share a file without seeing
+    /// each other's blocks or rows, and that prefixes are validated
+    fn test_table_prefix() {
+        let path = std::env::temp_dir().join(format!("sds-prefix-{}.db", uuid::Uuid::new_v4()));
+        let first = SqliteStore::open_with_prefix(&path, "first_").unwrap();
+        let second = SqliteStore::open_with_prefix(&path, "second_").unwrap();
+        first
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+
+        assert_eq!(first.fetch_known_blocks().unwrap().len(), 1);
+        assert!(second.fetch_known_blocks().unwrap().is_empty());
+        let row = vec![("name", Value::Text("first".to_string()))];
+        assert!(first.has_row("agents", &row).unwrap());
+        assert!(!second.has_row("agents", &row).unwrap());
+        drop((first, second));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(validate_table_prefix("").is_ok());
+        assert!(validate_table_prefix("sds_2_").is_ok());
+        assert!(validate_table_prefix("2sds_").is_err());
+        assert!(validate_table_prefix("sds-").is_err());
+        assert!(validate_table_prefix("Sds_").is_err());
+    }
+
+    #[test]
+    /// Test that each versioned table is indexed on its block numbers and key
+    /// columns, for every prefix, and opening the file again keeps the indexes
+    fn test_indexes() {
+        let path = std::env::temp_dir().join(format!("sds-indexes-{}.db", uuid::Uuid::new_v4()));
+        drop(SqliteStore::open(&path).unwrap());
+        drop(SqliteStore::open(&path).unwrap());
+        drop(SqliteStore::open_with_prefix(&path, "first_").unwrap());
+
+        let connection = Connection::open(&path).unwrap();
+        let count_indexes = |pattern: &str| -> i64 {
+            connection
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name LIKE ?",
+                    &[pattern],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let expected = ((EXPECTED_TABLES.len() - 1) * 2 + KEY_COLUMNS.len()) as i64;
+        assert_eq!(count_indexes("first_%"), expected);
+        assert_eq!(count_indexes("agents_public_key_end"), 1);
+        assert_eq!(count_indexes("first_assertions_address_end"), 1);
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Test that the graph holds the current rows only, with their references
+    fn test_fetch_graph() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("first", 1)], &make_block(1))
+            .unwrap();
+        store
+            .execute_operations_in_block(vec![make_agent_operation("second", 2)], &make_block(2))
+            .unwrap();
+
+        let dot = store.fetch_graph().unwrap().to_string();
+        assert!(dot.contains(&format!("[label=\"second\\nagent {}\"", PUBLIC_KEY)));
+        assert!(!dot.contains("first"));
+    }
+
+    fn agent_rows(store: &SqliteStore) -> Vec<(String, i64, i64)> {
+        let connection = store.connection.borrow();
+        let mut statement = connection
+            .prepare(
+                "SELECT name, start_block_num, end_block_num FROM agents ORDER BY start_block_num",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+}
@@ -0,0 +1,162 @@
+use crate::histogram::{LatencyHistogram, SizeHistogram};
+use crate::sequence::BlockSequence;
+use crate::throughput::Throughput;
+use crate::verify::VerifyStats;
+
+use std::fmt;
+use std::time::Instant;
+
+/// Tracks the events the subscriber has processed since it started
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberStatus {
+    /// The number of the last block committed to the reporting database
+    pub last_block_num: Option<i64>,
+    /// The id of the last block committed to the reporting database
+    pub last_block_id: Option<String>,
+    /// The number of event messages received from the validator, including heartbeats
+    pub events_received: u64,
+    /// The number of empty event messages received, as sent for settings heartbeats
+    pub heartbeats: u64,
+    /// The number of operations applied for the last committed block. Zero means
+    /// the block had no Certificate Registry state changes.
+    pub last_block_operations: usize,
+    /// The number of blocks committed to the reporting database
+    pub blocks_committed: u64,
+    /// The number of committed blocks that had no Certificate Registry state changes
+    pub empty_blocks: u64,
+    /// The number of committed blocks that skipped block numbers, meaning events
+    /// were dropped
+    pub block_gaps: u64,
+    /// The number of committed blocks at or below the previous block, meaning the
+    /// chain forked or events were replayed
+    pub block_reorgs: u64,
+    /// The number of commits retried after a serialization failure or deadlock
+    pub db_retries: u64,
+    /// The number of errors receiving messages from the validator
+    pub receive_errors: u64,
+    /// The number of times the validator knew none of the known blocks, so the
+    /// subscription fell back to the genesis block
+    pub genesis_fallbacks: u64,
+    /// The number of times a block skipped more block numbers than allowed, so the
+    /// subscription was resumed after the last block before the gap
+    pub gap_resyncs: u64,
+    /// The number of blocks that failed to apply and were skipped, after being
+    /// written to the poison block dead-letter log
+    pub poison_blocks: u64,
+    /// The sizes of the event messages received from the validator, in bytes
+    pub message_sizes: SizeHistogram,
+    /// The time from receiving each committed block's events to committing it
+    pub block_latency: LatencyHistogram,
+    /// The moving averages of the blocks committed and event messages received
+    /// per second
+    pub throughput: Throughput,
+    /// The counts of the blocks verified against the reporting database, when
+    /// verifying instead of writing
+    pub verification: Option<VerifyStats>,
+}
+
+impl SubscriberStatus {
+    /// Records that an event message of the given size in bytes was received from
+    /// the validator
+    pub fn record_events(&mut self, size: usize) {
+        self.events_received += 1;
+        self.message_sizes.record(size);
+        self.throughput.record_events(Instant::now());
+    }
+
+    /// Records that a block was committed to the reporting database with the
+    /// given number of operations
+    pub fn record_block(&mut self, block_num: i64, block_id: &str, operations: usize) {
+        self.last_block_num = Some(block_num);
+        self.last_block_id = Some(block_id.to_string());
+        self.last_block_operations = operations;
+        self.blocks_committed += 1;
+        self.throughput.record_block(Instant::now());
+        if operations == 0 {
+            self.empty_blocks += 1;
+        }
+    }
+
+    /// Records how a committed block followed the previous one
+    pub fn record_sequence(&mut self, sequence: BlockSequence) {
+        match sequence {
+            BlockSequence::Gap { .. } => self.block_gaps += 1,
+            BlockSequence::Reorg { .. } => self.block_reorgs += 1,
+            BlockSequence::First | BlockSequence::Next => (),
+        }
+    }
+
+    /// Returns true if the last committed block changed Certificate Registry state
+    pub fn last_block_had_changes(&self) -> bool {
+        self.last_block_num.is_some() && self.last_block_operations > 0
+    }
+}
+
+impl fmt::Display for SubscriberStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.last_block_num, &self.last_block_id) {
+            (Some(block_num), Some(block_id)) => write!(
+                f,
+                "stopped at block {} ({}) after {} events, {} blocks committed ({} empty), \
+                 {} gaps, {} reorgs, {} commit retries, {} receive errors",
+                block_num,
+                block_id,
+                self.events_received,
+                self.blocks_committed,
+                self.empty_blocks,
+                self.block_gaps,
+                self.block_reorgs,
+                self.db_retries,
+                self.receive_errors
+            ),
+            _ => write!(
+                f,
+                "stopped before committing any blocks after {} events",
+                self.events_received
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the status tracks the last committed block and the event counts
+    fn test_record_status() {
+        let mut status = SubscriberStatus::default();
+        assert_eq!(
+            status.to_string(),
+            "stopped before committing any blocks after 0 events"
+        );
+
+        status.record_events(10);
+        status.record_events(10);
+        status.record_block(1, "block_1", 3);
+        assert!(status.last_block_had_changes());
+        status.record_events(10);
+        status.record_block(2, "block_2", 0);
+        assert!(!status.last_block_had_changes());
+        status.record_sequence(BlockSequence::Gap {
+            previous: 0,
+            missing: 1,
+        });
+        status.record_sequence(BlockSequence::Next);
+
+        assert_eq!(status.last_block_num, Some(2));
+        assert_eq!(status.last_block_id, Some("block_2".to_string()));
+        assert_eq!(status.events_received, 3);
+        assert_eq!(status.blocks_committed, 2);
+        assert_eq!(status.empty_blocks, 1);
+        assert_eq!(status.block_gaps, 1);
+        assert_eq!(status.block_reorgs, 0);
+        assert_eq!(status.message_sizes.total(), 30);
+        assert!(status.throughput.blocks_per_sec(Instant::now()) > 0.0);
+        assert_eq!(
+            status.to_string(),
+            "stopped at block 2 (block_2) after 3 events, 2 blocks committed (1 empty), \
+             1 gaps, 0 reorgs, 0 commit retries, 0 receive errors"
+        );
+    }
+}
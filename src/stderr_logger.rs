@@ -0,0 +1,32 @@
+use log::{LogLevel, LogMetadata, LogRecord, SetLoggerError};
+
+/// A logger writing to stderr, used when stdout carries data for another
+/// program. Lines have the same format as simple_logger's.
+struct StderrLogger {
+    level: LogLevel,
+}
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{:<5} [{}] {}",
+                record.level().to_string(),
+                record.location().module_path(),
+                record.args()
+            );
+        }
+    }
+}
+
+/// Sets a logger writing messages at or above the given level to stderr
+pub fn init_with_level(level: LogLevel) -> Result<(), SetLoggerError> {
+    log::set_logger(|max_level| {
+        max_level.set(level.to_log_level_filter());
+        Box::new(StderrLogger { level })
+    })
+}
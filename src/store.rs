@@ -0,0 +1,215 @@
+use database::{
+    data_manager::{DataManager, OperationType},
+    errors::DatabaseError,
+    models::Block,
+};
+use std::cell::RefCell;
+
+/// The reporting database operations the subscriber uses, so the event handler
+/// can be run against something other than Postgres. The event handler only
+/// depends on this trait. `DataManager` implements it for Postgres, delegating to
+/// its own methods unchanged, and `SqliteStore`, `FanOutStore` and the in-memory
+/// test store implement it for the other backends. A new backend implements the
+/// block methods so that each block's operations and its block row are committed
+/// in one transaction, which resuming relies on.
+pub trait BlockStore {
+    /// Applies the operations of a block in one transaction, recording the block
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError>;
+
+    /// Applies the operations of several consecutive blocks, recording each block.
+    /// Stores that can apply them in one transaction override this; by default each
+    /// block is applied in its own transaction.
+    fn execute_operations_in_blocks(
+        &self,
+        blocks: Vec<(Vec<OperationType>, Block)>,
+    ) -> Result<(), DatabaseError> {
+        for (operations, block) in blocks {
+            self.execute_operations_in_block(operations, &block)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the blocks already applied to the reporting database
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError>;
+
+    /// Replaces the connection to the reporting database after it was lost.
+    /// Stores that connect again by themselves on their next transaction, such
+    /// as those drawing connections from a pool, keep this default.
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+impl BlockStore for DataManager {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        DataManager::execute_operations_in_block(self, operations, block)
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        DataManager::fetch_known_blocks(self)
+    }
+}
+
+/// Connects a store
+pub type Connect = Box<dyn Fn() -> Result<Box<dyn BlockStore>, DatabaseError>>;
+
+/// A store holding a single connection, such as `DataManager`, which reconnects
+/// by connecting a new store in its place
+pub struct ReconnectingStore {
+    connect: Connect,
+    store: RefCell<Box<dyn BlockStore>>,
+}
+
+impl ReconnectingStore {
+    /// Connects the store with the given function, which is called again to
+    /// reconnect
+    /// ```
+    /// # Errors
+    /// Returns an error if the store cannot be connected
+    /// ```
+    pub fn connect(connect: Connect) -> Result<ReconnectingStore, DatabaseError> {
+        let store = connect()?;
+        Ok(ReconnectingStore {
+            connect,
+            store: RefCell::new(store),
+        })
+    }
+}
+
+impl BlockStore for ReconnectingStore {
+    fn execute_operations_in_block(
+        &self,
+        operations: Vec<OperationType>,
+        block: &Block,
+    ) -> Result<(), DatabaseError> {
+        self.store
+            .borrow()
+            .execute_operations_in_block(operations, block)
+    }
+
+    fn execute_operations_in_blocks(
+        &self,
+        blocks: Vec<(Vec<OperationType>, Block)>,
+    ) -> Result<(), DatabaseError> {
+        self.store.borrow().execute_operations_in_blocks(blocks)
+    }
+
+    fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+        self.store.borrow().fetch_known_blocks()
+    }
+
+    fn reconnect(&self) -> Result<(), DatabaseError> {
+        let store = (self.connect)()?;
+        *self.store.borrow_mut() = store;
+        Ok(())
+    }
+}
+
+/// An in-memory store for tests, recording the operations of every block applied
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Clones share the same blocks, so a test can keep a handle on a store
+    /// after handing it to an event handler
+    #[derive(Clone, Default)]
+    pub struct MemoryStore {
+        blocks: Rc<RefCell<Vec<(Block, Vec<OperationType>)>>>,
+    }
+
+    impl MemoryStore {
+        /// Returns the number and id of each block applied, in order
+        pub fn block_ids(&self) -> Vec<(i64, String)> {
+            self.blocks
+                .borrow()
+                .iter()
+                .map(|(block, _)| (block.block_num, block.block_id.clone()))
+                .collect()
+        }
+
+        /// Calls the given function with the operations applied in each block, in order
+        pub fn with_operations<F, T>(&self, f: F) -> T
+        where
+            F: FnOnce(&[(Block, Vec<OperationType>)]) -> T,
+        {
+            f(&self.blocks.borrow())
+        }
+    }
+
+    impl BlockStore for MemoryStore {
+        fn execute_operations_in_block(
+            &self,
+            operations: Vec<OperationType>,
+            block: &Block,
+        ) -> Result<(), DatabaseError> {
+            let block = Block {
+                block_num: block.block_num,
+                block_id: block.block_id.clone(),
+            };
+            self.blocks.borrow_mut().push((block, operations));
+            Ok(())
+        }
+
+        fn fetch_known_blocks(&self) -> Result<Vec<Block>, DatabaseError> {
+            Ok(self
+                .block_ids()
+                .into_iter()
+                .map(|(block_num, block_id)| Block {
+                    block_num,
+                    block_id,
+                })
+                .collect())
+        }
+    }
+}
+
+/// Blocks and operations shared by the tests of the stores
+#[cfg(test)]
+pub mod test_support {
+    use database::{
+        data_manager::{OperationType, MAX_BLOCK_NUM},
+        models::{Block, NewAgent},
+    };
+
+    /// The public key of the agent written by make_agent_operation
+    pub const PUBLIC_KEY: &str = "test_public_key";
+
+    /// Returns the block with the given number, and an id made from it
+    pub fn make_block(block_num: i64) -> Block {
+        Block {
+            block_num,
+            block_id: format!("test_block_{}", block_num),
+        }
+    }
+
+    /// Returns an operation writing the agent with PUBLIC_KEY under the given name
+    pub fn make_agent_operation(name: &str, block_num: i64) -> OperationType {
+        make_agent_operation_with_key(PUBLIC_KEY, name, block_num)
+    }
+
+    /// Returns an operation writing the agent with the given key and name
+    pub fn make_agent_operation_with_key(
+        public_key: &str,
+        name: &str,
+        block_num: i64,
+    ) -> OperationType {
+        OperationType::CreateAgent(vec![NewAgent {
+            public_key: public_key.to_string(),
+            organization_id: None,
+            name: name.to_string(),
+            timestamp: 1,
+            start_block_num: block_num,
+            end_block_num: MAX_BLOCK_NUM,
+        }])
+    }
+}
@@ -0,0 +1,29 @@
+/// Which state changes that would otherwise be skipped or partly dropped with a
+/// warning are instead an error that fails the block while parsing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Strictness {
+    /// Fail on enum fields holding values unknown to this build, instead of
+    /// reading them as unset
+    pub enums: bool,
+    /// Fail on deletions the reporting database cannot apply, instead of
+    /// skipping them
+    pub deletes: bool,
+    /// Fail on organizations with the UNSET_TYPE organization type, instead of
+    /// applying them without accreditations or an address
+    pub unset_organization_types: bool,
+    /// Fail on entities with an empty required string field, such as an agent
+    /// without a name, instead of applying them as they are
+    pub required_fields: bool,
+}
+
+impl Strictness {
+    /// Fails the block on every parse-time skip
+    pub fn all() -> Strictness {
+        Strictness {
+            enums: true,
+            deletes: true,
+            unset_organization_types: true,
+            required_fields: true,
+        }
+    }
+}
@@ -1,52 +1,204 @@
+use crate::address_types::AddressTypes;
 use crate::errors::SubscriberError;
-use crate::event_handler::EventHandler;
+use crate::event_handler::{BlockAppliedHook, EventHandler};
+use crate::event_types::EventTypes;
+use crate::liveness::{Liveness, LivenessTracker};
+use crate::progress::ProgressReporter;
+use crate::queue::{ReceiveQueue, Received};
+use crate::resume::UnknownBlocksPolicy;
+use crate::shard::Shard;
+use crate::status::SubscriberStatus;
+use crate::subscription_state::{SharedSubscriptionState, SubscriptionState};
+use crate::throughput::Throughput;
 
-use common::addressing::get_family_namespace_prefix;
+use database::models::Block;
 use protobuf::Message;
+use sawtooth_sdk::messages::block::BlockHeader;
+use sawtooth_sdk::messages::client_block::{
+    ClientBlockListRequest, ClientBlockListResponse, ClientBlockListResponse_Status,
+};
 use sawtooth_sdk::messages::client_event::{
     ClientEventsSubscribeRequest, ClientEventsSubscribeResponse,
     ClientEventsSubscribeResponse_Status, ClientEventsUnsubscribeRequest,
     ClientEventsUnsubscribeResponse, ClientEventsUnsubscribeResponse_Status,
 };
+use sawtooth_sdk::messages::client_list_control::ClientPagingControls;
+use sawtooth_sdk::messages::client_state::{
+    ClientStateGetRequest, ClientStateGetResponse, ClientStateGetResponse_Status,
+    ClientStateListRequest, ClientStateListResponse, ClientStateListResponse_Status,
+};
 use sawtooth_sdk::messages::events::{EventFilter, EventFilter_FilterType, EventSubscription};
-use sawtooth_sdk::messages::validator::Message_MessageType;
-use sawtooth_sdk::messaging::stream::{MessageConnection, MessageReceiver, MessageSender};
+use sawtooth_sdk::messages::validator::{Message as ValidatorMessage, Message_MessageType};
+use sawtooth_sdk::messaging::stream::{
+    MessageConnection, MessageFuture, MessageSender, ReceiveError,
+};
 use sawtooth_sdk::messaging::zmq_stream::{ZmqMessageConnection, ZmqMessageSender};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 const NULL_BLOCK_ID: &str = "0000000000000000";
 const KNOWN_COUNT: usize = 10;
+const VALID_SCHEMES: [&str; 2] = ["tcp", "tcps"];
+/// How often a wait for a response checks whether the subscriber was stopped
+const RESPONSE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long to wait for a message before checking the runtime, liveness and commits
+const RECEIVE_INTERVAL: Duration = Duration::from_millis(1000);
+/// How many receive errors in a row make the subscriber resubscribe
+const RECEIVE_ERROR_LIMIT: u32 = 3;
+/// How long to wait for messages of the old subscription still on their way to
+/// the receive queue when resubscribing
+const DISCARD_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a readiness probe waits for the validator to respond
+const READY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait between readiness probes
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many state entries to request per page when listing state
+const STATE_PAGE_SIZE: i32 = 1000;
+
+/// Options controlling how the subscriber processes events
+#[derive(Default)]
+pub struct SubscriberOptions {
+    /// Unsubscribe after the first block is committed
+    pub once: bool,
+    /// Only subscribe to state deltas at addresses in this shard of the namespace
+    pub shard: Option<Shard>,
+    /// Only subscribe to state deltas at addresses of these types
+    pub address_types: AddressTypes,
+    /// How often to log progress towards the chain head, if at all
+    pub progress_interval: Option<Duration>,
+    /// The types of the events to subscribe to
+    pub event_types: EventTypes,
+    /// How long to process events before unsubscribing, if limited
+    pub max_runtime: Option<Duration>,
+    /// How long to wait without any message from the validator, including
+    /// heartbeats, before probing it for liveness
+    pub liveness_timeout: Option<Duration>,
+    /// How many received messages may wait to be handled before reception waits
+    /// for the handler. At least one message is always queued.
+    pub receive_queue_size: usize,
+    /// How long to wait for the validator to respond to a request, if limited
+    pub response_timeout: Option<Duration>,
+    /// What to do when the validator knows none of the known blocks
+    pub unknown_blocks: UnknownBlocksPolicy,
+    /// How long to wait for the validator endpoint to accept a connection when the
+    /// subscriber is created, if checked
+    pub connect_timeout: Option<Duration>,
+    /// The window of the throughput moving averages, or the default window if none
+    pub throughput_window: Option<Duration>,
+}
+
+/// How a subscription ended
+enum Subscription {
+    /// The subscriber stopped, with the final status
+    Stopped(SubscriberStatus),
+    /// The subscriber has to subscribe again, from these known block ids starting
+    /// at this index
+    Resubscribe(Vec<String>, usize),
+}
 
 /// Subscribes to the validator for block-commit and state-delta events
 /// Listens to events and calls the event handler to parse event and submit the data to the reporting database
 pub struct Subscriber {
     sender: ZmqMessageSender,
-    receiver: MessageReceiver,
+    queue: ReceiveQueue<Result<ValidatorMessage, ReceiveError>>,
     event_handler: EventHandler,
+    options: SubscriberOptions,
+    status: SubscriberStatus,
+    started: Option<Instant>,
+    receive_errors_in_row: u32,
+    liveness: LivenessTracker,
+    state: SharedSubscriptionState,
     pub active: Arc<AtomicBool>,
 }
 
 impl Subscriber {
-    pub fn new(validator_address: &str, event_handler: EventHandler) -> Subscriber {
+    /// Creates a subscriber for the validator at the given address.
+    /// ```
+    /// # Errors
+    /// Returns an error if
+    /// - The validator address is not a valid tcp endpoint
+    /// - The validator endpoint doesn't accept a connection within the connect timeout
+    /// ```
+    pub fn new(
+        validator_address: &str,
+        event_handler: EventHandler,
+        options: SubscriberOptions,
+    ) -> Result<Subscriber, SubscriberError> {
+        validate_endpoint(validator_address)?;
+        if let Some(timeout) = options.connect_timeout {
+            check_reachable(validator_address, timeout)?;
+        }
         let zmq = ZmqMessageConnection::new(validator_address);
         let (sender, receiver) = zmq.create();
-        Subscriber {
+        let status = SubscriberStatus {
+            throughput: options
+                .throughput_window
+                .map(Throughput::new)
+                .unwrap_or_default(),
+            ..SubscriberStatus::default()
+        };
+        Ok(Subscriber {
             sender,
-            receiver,
+            queue: ReceiveQueue::spawn(receiver, options.receive_queue_size),
             event_handler,
-            active: Arc::new(AtomicBool::new(false)),
-        }
+            options,
+            status,
+            started: None,
+            receive_errors_in_row: 0,
+            liveness: LivenessTracker::new(Instant::now()),
+            state: SharedSubscriptionState::default(),
+            active: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// Returns the status of the events processed so far, including the moving
+    /// averages of the blocks and events handled per second
+    pub fn status(&self) -> &SubscriberStatus {
+        &self.status
+    }
+
+    /// Returns where the subscriber is in its subscription to the validator
+    pub fn subscription_state(&self) -> SubscriptionState {
+        self.state.get()
+    }
+
+    /// Returns the subscription state shared with the subscriber, for reading it
+    /// from another thread while the subscriber runs
+    pub fn subscription_state_handle(&self) -> SharedSubscriptionState {
+        self.state.clone()
+    }
+
+    /// Returns true if the subscriber has neither stopped nor failed, and hasn't
+    /// been asked to stop. The `active` flag alone stays set after a failure.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst) && self.subscription_state().is_running()
+    }
+
+    /// Registers a function to call after each block is committed to the reporting
+    /// database, see EventHandler::on_block_applied
+    pub fn on_block_applied(&mut self, hook: BlockAppliedHook) {
+        self.event_handler.on_block_applied(hook);
     }
 
     /// Sends a subscription request to the validator, with a list of known block ids
     /// If the request is successful, it start listening for block-commit and state-delta events
+    /// until the subscriber is deactivated, then unsubscribes and returns the final status.
+    /// If the subscriber is deactivated while waiting for the validator to respond, it
+    /// closes the connection and returns the status without subscribing.
+    ///
+    /// The subscription state is connecting until the validator accepts the
+    /// subscription, then subscribed, and reconnecting while resubscribing. It ends
+    /// stopped once the subscriber stops, or failed if this returns an error.
     /// ```
     /// # Errors
     /// It returns an error if
     /// - It fails to connect to the validator
+    /// - The validator doesn't respond within the response timeout
     /// - The validator responds with an error
     /// - The event handler returns an error
     ///
@@ -59,7 +211,35 @@ impl Subscriber {
         &mut self,
         known_block_ids: &[String],
         start_index: usize,
-    ) -> Result<(), SubscriberError> {
+    ) -> Result<SubscriberStatus, SubscriberError> {
+        self.state.set(SubscriptionState::Connecting);
+        // Each resubscription returns here rather than calling subscribe again, so
+        // a long running subscriber doesn't grow the stack with every resync
+        let mut known_block_ids = known_block_ids.to_vec();
+        let mut start_index = start_index;
+        let result = loop {
+            match self.subscribe(&known_block_ids, start_index) {
+                Ok(Subscription::Stopped(status)) => break Ok(status),
+                Ok(Subscription::Resubscribe(block_ids, index)) => {
+                    known_block_ids = block_ids;
+                    start_index = index;
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        if result.is_err() {
+            self.state.set(SubscriptionState::Failed);
+        }
+        result
+    }
+
+    /// Subscribes from the known blocks and handles events until stopped, or until
+    /// it has to subscribe again, see start
+    fn subscribe(
+        &mut self,
+        known_block_ids: &[String],
+        start_index: usize,
+    ) -> Result<Subscription, SubscriberError> {
         let last_known_block_ids = self.get_last_known_block_ids(known_block_ids, start_index);
         let event_subscription_request = self.build_subscription_request(&last_known_block_ids);
         let content = protobuf::Message::write_to_bytes(&event_subscription_request)
@@ -72,40 +252,251 @@ impl Subscriber {
                 &correlation_id,
                 &content,
             )
-            .map_err(|err| SubscriberError::ConnError(err.to_string()))?;
-        let future_result = response_future
-            .get()
-            .map_err(|err| SubscriberError::ConnError(err.to_string()))?;
+            .map_err(|err| SubscriberError::SendError(err.to_string()))?;
+        let timeout = self.options.response_timeout;
+        let future_result = match self.wait_for_response(&mut response_future, timeout, true)? {
+            Some(future_result) => future_result,
+            None => {
+                info!("Stopped while waiting for the validator to accept the subscription");
+                self.sender.close();
+                self.state.set(SubscriptionState::Stopped);
+                return Ok(Subscription::Stopped(self.status.clone()));
+            }
+        };
         let response: ClientEventsSubscribeResponse =
             Message::parse_from_bytes(&future_result.get_content())
-                .expect("Error parsing protobuf data.");
+                .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
         match response.get_status() {
             ClientEventsSubscribeResponse_Status::OK => {
                 info!("Successfully subscribed to receive events from validator");
-                self.active.swap(true, Ordering::SeqCst);
+                self.state.set(SubscriptionState::Subscribed);
+                // Resubscribing restarts this loop, but not the runtime limit
+                let started = *self.started.get_or_insert_with(Instant::now);
+                self.liveness.reset(Instant::now());
+                let mut progress = self
+                    .options
+                    .progress_interval
+                    .map(|interval| ProgressReporter::new(interval, started));
 
                 while self.active.load(Ordering::SeqCst) {
-                    let messaged_received = self.receiver.recv_timeout(Duration::from_millis(1000));
-                    if let Ok(messaged_received) = messaged_received {
-                        let received = messaged_received.expect("Unexpected error");
-                        self.event_handler.handle_events(received.get_content())?;
+                    // Blocks are committed synchronously within this loop, so stopping
+                    // here never interrupts a commit in progress
+                    if let Some(max_runtime) = self.options.max_runtime {
+                        if started.elapsed() >= max_runtime {
+                            info!("Reached the maximum runtime, shutting down");
+                            self.active.store(false, Ordering::SeqCst);
+                            break;
+                        }
+                    }
+                    if let Some(liveness_timeout) = self.options.liveness_timeout {
+                        let liveness = self.liveness.check(Instant::now(), liveness_timeout);
+                        if liveness == Liveness::Quiet {
+                            debug!(
+                                "No blocks in {:?}, but heartbeats are arriving, so the \
+                                 validator is alive",
+                                liveness_timeout
+                            );
+                            self.liveness.reset(Instant::now());
+                        } else if liveness == Liveness::Silent {
+                            if self.is_behind_chain_head()? {
+                                warn!(
+                                    "No events received from the validator in {:?}, resubscribing",
+                                    liveness_timeout
+                                );
+                                return self.resubscribe(known_block_ids);
+                            }
+                            self.liveness.reset(Instant::now());
+                        }
                     }
+                    match self.queue.recv_timeout(RECEIVE_INTERVAL) {
+                        Ok(received) => {
+                            match self.handle_message(received, &mut progress) {
+                                Err(SubscriberError::BlockGap(message)) => {
+                                    warn!("Missed blocks {}, resubscribing", message);
+                                    self.status.gap_resyncs += 1;
+                                    return self.resubscribe(known_block_ids);
+                                }
+                                result => result?,
+                            }
+                            if self.receive_errors_in_row >= RECEIVE_ERROR_LIMIT {
+                                warn!(
+                                    "{} errors in a row receiving from the validator, resubscribing",
+                                    self.receive_errors_in_row
+                                );
+                                self.receive_errors_in_row = 0;
+                                return self.resubscribe(known_block_ids);
+                            }
+                        }
+                        // No message within the interval, which is normal on a quiet chain
+                        Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => {
+                            self.status.receive_errors += 1;
+                            return Err(SubscriberError::ConnError(
+                                "The connection to the validator was closed".to_string(),
+                            ));
+                        }
+                    }
+                    self.event_handler.flush_if_due()?;
                 }
-                self.stop()?;
-                Ok(())
+                if !self.options.once {
+                    self.drain_queue(&mut progress)?;
+                }
+                self.event_handler.flush()?;
+                self.status.db_retries = self.event_handler.db_retries();
+                self.status.block_latency = self.event_handler.block_latency().clone();
+                self.status.verification = self.event_handler.verify_stats();
+                self.stop().map(Subscription::Stopped)
             }
             ClientEventsSubscribeResponse_Status::UNKNOWN_BLOCK => {
-                debug!("Validator returned UNKNOWN_BLOCK response. Trying again with new set of blocks");
-                self.start(known_block_ids, start_index + KNOWN_COUNT)
+                let next_index = start_index + KNOWN_COUNT;
+                if !falls_back_to_genesis(known_block_ids.len(), start_index, next_index) {
+                    debug!("Validator returned UNKNOWN_BLOCK response. Trying again with new set of blocks");
+                    return Ok(Subscription::Resubscribe(
+                        known_block_ids.to_vec(),
+                        next_index,
+                    ));
+                }
+                self.status.genesis_fallbacks += 1;
+                let message = format!(
+                    "The validator knows none of the {} known blocks, the newest being {}, \
+                     so the subscriber fell behind the blocks it keeps",
+                    known_block_ids.len(),
+                    known_block_ids[0]
+                );
+                match self.options.unknown_blocks {
+                    UnknownBlocksPolicy::Genesis => {
+                        warn!(
+                            "{}. Subscribing from the genesis block and reapplying every block \
+                             over the reporting database",
+                            message
+                        );
+                        Ok(Subscription::Resubscribe(
+                            known_block_ids.to_vec(),
+                            next_index,
+                        ))
+                    }
+                    UnknownBlocksPolicy::Error => Err(SubscriberError::SubscribeRejected(
+                        ClientEventsSubscribeResponse_Status::UNKNOWN_BLOCK,
+                        format!(
+                            "{}. Rebuild the reporting database, or pass --on-unknown-blocks \
+                             genesis to reapply every block over it",
+                            message
+                        ),
+                    )),
+                }
             }
-            _ => Err(SubscriberError::ConnError(format!(
-                "The valiator returned an invalid response {:?}",
-                response.get_status()
-            ))),
+            _ => Err(subscribe_error(&response)),
+        }
+    }
+
+    /// Unsubscribes and commits any pending blocks, to subscribe again after the
+    /// blocks the reporting database knows now, see resume_block_ids
+    fn resubscribe(&mut self, known_block_ids: &[String]) -> Result<Subscription, SubscriberError> {
+        self.state.set(SubscriptionState::Reconnecting);
+        self.unsubscribe()?;
+        // The messages still queued are from the old subscription. After a gap,
+        // each of its blocks would be a gap again and resubscribe once more, so
+        // they are dropped, and the new subscription sends the blocks again.
+        let discarded = self.queue.discard(DISCARD_INTERVAL);
+        if discarded > 0 {
+            debug!(
+                "Discarded {} messages queued from the old subscription",
+                discarded
+            );
+        }
+        self.event_handler.flush()?;
+        let resume_ids = resume_block_ids(
+            &self.event_handler,
+            self.status.last_block_id.as_ref(),
+            known_block_ids,
+        );
+        Ok(Subscription::Resubscribe(resume_ids, 0))
+    }
+
+    /// Handles one message from the receive queue, recording the block it committed
+    /// and reporting progress if due. Stops the subscriber after the first block
+    /// if it runs once. A receive error is logged and counted, along with the
+    /// errors in a row, which a message received without error resets.
+    fn handle_message(
+        &mut self,
+        received: Received<Result<ValidatorMessage, ReceiveError>>,
+        progress: &mut Option<ProgressReporter>,
+    ) -> Result<(), SubscriberError> {
+        let received_at = received.at;
+        let received = match received.message {
+            Ok(received) => {
+                self.receive_errors_in_row = 0;
+                received
+            }
+            Err(err) => {
+                self.status.receive_errors += 1;
+                self.receive_errors_in_row += 1;
+                warn!(
+                    "Error receiving events from the validator ({} in a row): {:?}",
+                    self.receive_errors_in_row, err
+                );
+                return Ok(());
+            }
+        };
+        let size = received.get_content().len();
+        debug!("Received an event message of {} bytes", size);
+        self.status.record_events(size);
+        let report = self
+            .event_handler
+            .handle_received_events(received.get_content(), received_at);
+        self.status.db_retries = self.event_handler.db_retries();
+        self.status.block_latency = self.event_handler.block_latency().clone();
+        self.status.verification = self.event_handler.verify_stats();
+        let report = report?;
+        if report.skipped {
+            self.status.poison_blocks += 1;
         }
+        if report.is_heartbeat() {
+            self.status.heartbeats += 1;
+            self.liveness.record_heartbeat(Instant::now());
+        }
+        if let Some(ref block) = report.block {
+            self.liveness.record_block(Instant::now());
+            self.status
+                .record_block(block.block_num, &block.block_id, report.operations_applied);
+            self.status.record_sequence(report.sequence);
+            if let Some(ref mut progress) = progress {
+                let now = Instant::now();
+                if progress.is_due(now) {
+                    let head = self
+                        .get_chain_head()
+                        .map_err(|err| warn!("Unable to fetch chain head: {}", err))
+                        .ok();
+                    info!("{}", progress.report(block.block_num, head, now));
+                    info!("Throughput: {}", self.status.throughput.at(now));
+                }
+            }
+            if self.options.once {
+                info!("Processed a single block, shutting down");
+                self.active.store(false, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the messages already in the receive queue when the subscriber stops,
+    /// so events that were received are not dropped on shutdown
+    fn drain_queue(
+        &mut self,
+        progress: &mut Option<ProgressReporter>,
+    ) -> Result<(), SubscriberError> {
+        let queued = self.queue.depth();
+        if queued > 0 {
+            info!("Handling {} queued messages before shutting down", queued);
+        }
+        while let Ok(received) = self.queue.try_recv() {
+            self.handle_message(received, progress)?;
+        }
+        Ok(())
     }
 
-    /// Sends a unsubscribe request to the validator,
+    /// Sends a unsubscribe request to the validator, closes the connection
+    /// and returns the status of the events processed while subscribed
     /// ```
     /// # Errors
     /// It returns an error if
@@ -118,7 +509,15 @@ impl Subscriber {
     /// - If it fails to serialize the event subscription request to bytes.
     /// - It fails to deserialize the validator response to a protobuf message
     /// ```
-    pub fn stop(&mut self) -> Result<(), SubscriberError> {
+    pub fn stop(&mut self) -> Result<SubscriberStatus, SubscriberError> {
+        self.unsubscribe()?;
+        self.sender.close();
+        self.state.set(SubscriptionState::Stopped);
+        Ok(self.status.clone())
+    }
+
+    /// Sends a unsubscribe request to the validator, leaving the connection open
+    fn unsubscribe(&mut self) -> Result<(), SubscriberError> {
         let unsusbscribe_request = ClientEventsUnsubscribeRequest::new();
         let content = protobuf::Message::write_to_bytes(&unsusbscribe_request)
             .expect("Error writting protobuf data.");
@@ -130,26 +529,382 @@ impl Subscriber {
                 &correlation_id,
                 &content,
             )
-            .map_err(|err| SubscriberError::ConnError(err.to_string()))?;
-        let future_result = response_future
-            .get()
-            .map_err(|err| SubscriberError::ConnError(err.to_string()))?;
+            .map_err(|err| SubscriberError::SendError(err.to_string()))?;
+        let timeout = self.options.response_timeout;
+        let future_result = self
+            .wait_for_response(&mut response_future, timeout, false)?
+            .unwrap();
         let response: ClientEventsUnsubscribeResponse =
             Message::parse_from_bytes(&future_result.get_content())
-                .expect("Error parsing protobuf data.");
+                .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
         match response.get_status() {
             ClientEventsUnsubscribeResponse_Status::OK => {
                 info!("Successfully unsubscribed from receiving events from validator");
-                self.sender.close();
                 Ok(())
             }
-            _ => Err(SubscriberError::ConnError(format!(
+            _ => Err(SubscriberError::ResponseError(format!(
                 "The valiator returned an invalid response {:?}",
                 response.get_status()
             ))),
         }
     }
 
+    /// Probes a validator that has sent no events for a while. Returns true if the
+    /// chain head is past the last committed block, meaning events were missed,
+    /// or false if the chain is just quiet.
+    /// ```
+    /// # Errors
+    /// It returns an error if the validator does not respond to the probe
+    /// ```
+    fn is_behind_chain_head(&mut self) -> Result<bool, SubscriberError> {
+        let head = self.get_chain_head().map_err(|err| {
+            SubscriberError::ResponseError(format!(
+                "The validator stopped sending events and did not respond to a probe: {}",
+                err
+            ))
+        })?;
+        Ok(match self.status.last_block_num {
+            Some(block_num) => head > block_num,
+            None => false,
+        })
+    }
+
+    /// Waits until the validator is ready to serve requests, probing it for its
+    /// chain head until it responds with at least the genesis block. Returns false
+    /// without waiting further if the subscriber is deactivated.
+    /// ```
+    /// # Errors
+    /// It returns an error if the validator isn't ready within the timeout
+    /// ```
+    pub fn wait_for_validator(&mut self, timeout: Duration) -> Result<bool, SubscriberError> {
+        info!("Waiting up to {:?} for the validator to be ready", timeout);
+        let started = Instant::now();
+        let mut attempts = 0;
+        loop {
+            if !self.active.load(Ordering::SeqCst) {
+                info!("Stopped while waiting for the validator to be ready");
+                return Ok(false);
+            }
+            let remaining = match timeout.checked_sub(started.elapsed()) {
+                Some(remaining) if remaining > Duration::from_secs(0) => remaining,
+                _ => {
+                    return Err(SubscriberError::ResponseError(format!(
+                        "The validator was not ready within {:?} after {} probes",
+                        timeout, attempts
+                    )))
+                }
+            };
+            attempts += 1;
+            match self.request_chain_head(Some(remaining.min(READY_PROBE_TIMEOUT)), true) {
+                Ok(Some(head)) => {
+                    info!(
+                        "The validator is ready at block {} after {:?}",
+                        head,
+                        started.elapsed()
+                    );
+                    return Ok(true);
+                }
+                Ok(None) => {
+                    info!("Stopped while waiting for the validator to be ready");
+                    return Ok(false);
+                }
+                Err(err) if !err.is_retryable_request() => return Err(err),
+                Err(err) => {
+                    info!(
+                        "The validator is not ready yet (probe {}): {}",
+                        attempts, err
+                    );
+                    thread::sleep(READY_POLL_INTERVAL.min(remaining));
+                }
+            }
+        }
+    }
+
+    /// Requests the most recent block from the validator and returns its block number
+    /// ```
+    /// # Errors
+    /// It returns an error if
+    /// - It fails to connect to the validator
+    /// - The validator responds with an error or no blocks
+    /// - The response cannot be deserialized
+    /// ```
+    fn get_chain_head(&mut self) -> Result<i64, SubscriberError> {
+        let timeout = self.options.response_timeout;
+        self.request_chain_head(timeout, false)
+            .map(|head| head.unwrap())
+    }
+
+    /// Requests the most recent block from the validator, waiting for at most the
+    /// given timeout, and returns its block number. If interruptible, it returns
+    /// None once the subscriber is deactivated.
+    fn request_chain_head(
+        &mut self,
+        timeout: Option<Duration>,
+        interruptible: bool,
+    ) -> Result<Option<i64>, SubscriberError> {
+        Ok(self
+            .request_head_block(timeout, interruptible)?
+            .map(|(_, header)| header.get_block_num() as i64))
+    }
+
+    /// Reads an entity's state from the validator at the chain head and returns the
+    /// head block with the state, or None if there is no state at the address
+    /// ```
+    /// # Errors
+    /// It returns an error if
+    /// - It fails to connect to the validator
+    /// - The validator responds with an error or no blocks
+    /// - The response cannot be deserialized
+    /// ```
+    pub fn get_state(
+        &mut self,
+        address: &str,
+    ) -> Result<Option<(Block, Vec<u8>)>, SubscriberError> {
+        let timeout = self.options.response_timeout;
+        let (block_id, header) = self.request_head_block(timeout, false)?.unwrap();
+        let mut state_request = ClientStateGetRequest::new();
+        state_request.set_state_root(header.get_state_root_hash().to_string());
+        state_request.set_address(address.to_string());
+        let content =
+            protobuf::Message::write_to_bytes(&state_request).expect("Error writing to bytes");
+        let correlation_id = Uuid::new_v4().to_string();
+        let mut response_future = self
+            .sender
+            .send(
+                Message_MessageType::CLIENT_STATE_GET_REQUEST,
+                &correlation_id,
+                &content,
+            )
+            .map_err(|err| SubscriberError::SendError(err.to_string()))?;
+        let future_result = self
+            .wait_for_response(&mut response_future, timeout, false)?
+            .unwrap();
+        let response: ClientStateGetResponse =
+            Message::parse_from_bytes(&future_result.get_content())
+                .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
+        let block = Block {
+            block_num: header.get_block_num() as i64,
+            block_id,
+        };
+        match response.get_status() {
+            ClientStateGetResponse_Status::OK => Ok(Some((block, response.get_value().to_vec()))),
+            ClientStateGetResponse_Status::NO_RESOURCE => Ok(None),
+            status => Err(SubscriberError::ResponseError(format!(
+                "The validator returned an invalid state response {:?}",
+                status
+            ))),
+        }
+    }
+
+    /// Reads the state of every entity of the subscribed address types from the
+    /// validator at the chain head and applies it to the reporting database as the
+    /// head block, so the subscription can resume after it instead of replaying the
+    /// chain from the genesis block. Every page is read at the head's state root, so
+    /// the entities are consistent with each other even if the chain advances.
+    /// Returns the head block.
+    /// ```
+    /// # Errors
+    /// It returns an error if
+    /// - It fails to connect to the validator
+    /// - The validator responds with an error or no blocks
+    /// - A response or state value cannot be deserialized
+    /// - The state cannot be committed to the reporting database
+    /// ```
+    pub fn bootstrap_from_state(&mut self) -> Result<Block, SubscriberError> {
+        let prefixes = self.options.address_types.prefixes();
+        let (block, entries) = self.read_state(&prefixes)?;
+        info!(
+            "Bootstrapping the reporting database from the state at block {} ({})",
+            block.block_num, block.block_id
+        );
+        let listed = entries.len();
+        let operations = self.event_handler.apply_snapshot(
+            Block {
+                block_num: block.block_num,
+                block_id: block.block_id.clone(),
+            },
+            entries,
+        )?;
+        info!(
+            "Bootstrapped {} entities of {} state entries at block {}",
+            operations, listed, block.block_num
+        );
+        self.status
+            .record_block(block.block_num, &block.block_id, operations);
+        self.status.db_retries = self.event_handler.db_retries();
+        Ok(block)
+    }
+
+    /// Lists every state entry under the address prefixes at the chain head, and
+    /// returns the head block with the entries
+    /// ```
+    /// # Errors
+    /// It returns an error if
+    /// - It fails to connect to the validator
+    /// - The validator responds with an error or no blocks
+    /// - A response cannot be deserialized
+    /// ```
+    pub fn read_state(
+        &mut self,
+        prefixes: &[String],
+    ) -> Result<(Block, Vec<(String, Vec<u8>)>), SubscriberError> {
+        let timeout = self.options.response_timeout;
+        let (block_id, header) = self.request_head_block(timeout, false)?.unwrap();
+        let mut entries = Vec::new();
+        for prefix in prefixes {
+            entries.extend(self.list_state(header.get_state_root_hash(), prefix)?);
+        }
+        let block = Block {
+            block_num: header.get_block_num() as i64,
+            block_id,
+        };
+        Ok((block, entries))
+    }
+
+    /// Lists every state entry under the address prefix at the given state root,
+    /// requesting it a page at a time
+    fn list_state(
+        &mut self,
+        state_root: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, SubscriberError> {
+        let timeout = self.options.response_timeout;
+        let mut entries = Vec::new();
+        let mut start = String::new();
+        loop {
+            let mut paging = ClientPagingControls::new();
+            paging.set_limit(STATE_PAGE_SIZE);
+            paging.set_start(start);
+            let mut list_request = ClientStateListRequest::new();
+            list_request.set_state_root(state_root.to_string());
+            list_request.set_address(prefix.to_string());
+            list_request.set_paging(paging);
+            let content =
+                protobuf::Message::write_to_bytes(&list_request).expect("Error writing to bytes");
+            let correlation_id = Uuid::new_v4().to_string();
+            let mut response_future = self
+                .sender
+                .send(
+                    Message_MessageType::CLIENT_STATE_LIST_REQUEST,
+                    &correlation_id,
+                    &content,
+                )
+                .map_err(|err| SubscriberError::SendError(err.to_string()))?;
+            let future_result = self
+                .wait_for_response(&mut response_future, timeout, false)?
+                .unwrap();
+            let mut response: ClientStateListResponse =
+                Message::parse_from_bytes(&future_result.get_content())
+                    .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
+            match response.get_status() {
+                ClientStateListResponse_Status::OK => (),
+                // An empty prefix has no resource, which is an empty page
+                ClientStateListResponse_Status::NO_RESOURCE => return Ok(entries),
+                status => {
+                    return Err(SubscriberError::ResponseError(format!(
+                        "The validator returned an invalid state list response {:?}",
+                        status
+                    )))
+                }
+            }
+            entries.extend(
+                response
+                    .take_entries()
+                    .into_iter()
+                    .map(|mut entry| (entry.take_address(), entry.take_data())),
+            );
+            debug!("Listed {} state entries under {}", entries.len(), prefix);
+            start = response.get_paging().get_next().to_string();
+            if start.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    /// Requests the most recent block from the validator and returns its id and
+    /// header, or None if interrupted while waiting
+    fn request_head_block(
+        &mut self,
+        timeout: Option<Duration>,
+        interruptible: bool,
+    ) -> Result<Option<(String, BlockHeader)>, SubscriberError> {
+        let mut paging = ClientPagingControls::new();
+        paging.set_limit(1);
+        let mut block_list_request = ClientBlockListRequest::new();
+        block_list_request.set_paging(paging);
+        let content =
+            protobuf::Message::write_to_bytes(&block_list_request).expect("Error writing to bytes");
+        let correlation_id = Uuid::new_v4().to_string();
+        let mut response_future = self
+            .sender
+            .send(
+                Message_MessageType::CLIENT_BLOCK_LIST_REQUEST,
+                &correlation_id,
+                &content,
+            )
+            .map_err(|err| SubscriberError::SendError(err.to_string()))?;
+        let future_result =
+            match self.wait_for_response(&mut response_future, timeout, interruptible)? {
+                Some(future_result) => future_result,
+                None => return Ok(None),
+            };
+        let response: ClientBlockListResponse =
+            Message::parse_from_bytes(&future_result.get_content())
+                .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
+        if response.get_status() != ClientBlockListResponse_Status::OK {
+            return Err(SubscriberError::ResponseError(format!(
+                "The validator returned an invalid block list response {:?}",
+                response.get_status()
+            )));
+        }
+        let head = response.get_blocks().first().ok_or_else(|| {
+            SubscriberError::ResponseError("The validator returned no blocks".to_string())
+        })?;
+        let header: BlockHeader = Message::parse_from_bytes(head.get_header())
+            .map_err(|err| SubscriberError::DecodeError(err.to_string()))?;
+        Ok(Some((head.get_header_signature().to_string(), header)))
+    }
+
+    /// Waits for the validator's response to a request, for at most the given
+    /// timeout. If interruptible, it stops waiting once the subscriber is deactivated
+    /// and returns None; otherwise it only returns a response or an error.
+    /// ```
+    /// # Errors
+    /// It returns an error if the validator doesn't respond within the response
+    /// timeout, or the connection fails
+    /// ```
+    fn wait_for_response(
+        &self,
+        response_future: &mut MessageFuture,
+        timeout: Option<Duration>,
+        interruptible: bool,
+    ) -> Result<Option<ValidatorMessage>, SubscriberError> {
+        let started = Instant::now();
+        loop {
+            if interruptible && !self.active.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            let wait = match timeout {
+                Some(timeout) => {
+                    let elapsed = started.elapsed();
+                    if elapsed >= timeout {
+                        return Err(SubscriberError::ResponseError(format!(
+                            "The validator did not respond within {:?}",
+                            timeout
+                        )));
+                    }
+                    RESPONSE_POLL_INTERVAL.min(timeout - elapsed)
+                }
+                None => RESPONSE_POLL_INTERVAL,
+            };
+            match response_future.get_timeout(wait) {
+                Ok(response) => return Ok(Some(response)),
+                Err(ReceiveError::TimeoutError) => continue,
+                Err(err) => return Err(SubscriberError::ResponseError(err.to_string())),
+            }
+        }
+    }
+
     /// Given a list of known block ids, returns a list of at most 10 last know block ids starting
     /// from start_index.
     /// If start_index is greaten than the input list of known block ids, it returns
@@ -181,14 +936,12 @@ impl Subscriber {
         &self,
         last_known_block_ids: &[String],
     ) -> ClientEventsSubscribeRequest {
-        let block_subscription = self.get_block_commit_subscription();
-        let state_delta_subscription = self.get_state_delta_subscription();
+        let mut subscriptions = vec![self.get_block_commit_subscription()];
+        subscriptions.extend(self.get_state_delta_subscriptions());
 
         let mut event_subscription_request = ClientEventsSubscribeRequest::new();
-        event_subscription_request.set_subscriptions(protobuf::RepeatedField::from_vec(vec![
-            block_subscription,
-            state_delta_subscription,
-        ]));
+        event_subscription_request
+            .set_subscriptions(protobuf::RepeatedField::from_vec(subscriptions));
         event_subscription_request.set_last_known_block_ids(protobuf::RepeatedField::from_vec(
             last_known_block_ids.to_vec(),
         ));
@@ -198,27 +951,445 @@ impl Subscriber {
 
     fn get_block_commit_subscription(&self) -> EventSubscription {
         let mut block_commit_subscription = EventSubscription::new();
-        block_commit_subscription.set_event_type(String::from("sawtooth/block-commit"));
+        block_commit_subscription.set_event_type(self.options.event_types.block_commit.clone());
         block_commit_subscription
     }
 
-    fn get_state_delta_subscription(&self) -> EventSubscription {
-        let mut state_delta_subscription = EventSubscription::new();
-        state_delta_subscription.set_event_type(String::from("sawtooth/state-delta"));
+    /// Returns a state-delta subscription for each enabled address type. The filters
+    /// of one subscription must all match, so each type gets its own subscription,
+    /// and the validator sends the events matching any of them.
+    fn get_state_delta_subscriptions(&self) -> Vec<EventSubscription> {
+        self.options
+            .address_types
+            .address_regexes(self.options.shard)
+            .into_iter()
+            .map(|match_string| {
+                let mut state_delta_subscription = EventSubscription::new();
+                state_delta_subscription
+                    .set_event_type(self.options.event_types.state_delta.clone());
+
+                let mut event_filter = EventFilter::new();
+                event_filter.set_key(String::from("address"));
+                event_filter.set_match_string(match_string);
+
+                let event_filter_type = EventFilter_FilterType::REGEX_ANY;
+                event_filter.set_filter_type(event_filter_type);
+
+                let event_filter_vec = vec![event_filter];
+                let repeated_field = protobuf::RepeatedField::from_vec(event_filter_vec);
+
+                state_delta_subscription.set_filters(repeated_field);
+                state_delta_subscription
+            })
+            .collect()
+    }
+}
+
+/// Returns true if, after the validator knew none of the known blocks from
+/// start_index, the next subscription has none left to offer and starts from the
+/// genesis block. A subscription that started from the genesis block doesn't fall
+/// back to it.
+fn falls_back_to_genesis(known_count: usize, start_index: usize, next_index: usize) -> bool {
+    start_index < known_count && next_index >= known_count
+}
+
+/// Returns the ids of the blocks to resume a subscription from after reconnecting.
+///
+/// The known blocks are read from the reporting database again, newest first,
+/// rather than reusing those read at startup. After a long outage, another
+/// subscriber may have committed later blocks, and the blocks known at startup
+/// may be past the validator's pruning horizon. If the database has no blocks, or
+/// can't be read, or the handler verifies blocks, it resumes after the last
+/// committed block, or from the startup blocks if none was committed.
+fn resume_block_ids(
+    event_handler: &EventHandler,
+    last_block_id: Option<&String>,
+    known_block_ids: &[String],
+) -> Vec<String> {
+    match event_handler.known_blocks() {
+        Ok(Some(ref blocks)) if !blocks.is_empty() => {
+            if Some(&blocks[0].block_id) != last_block_id {
+                info!(
+                    "Resuming after block {} ({}), the newest in the reporting database",
+                    blocks[0].block_num, blocks[0].block_id
+                );
+            }
+            blocks.iter().map(|block| block.block_id.clone()).collect()
+        }
+        Ok(_) => fallback_block_ids(last_block_id, known_block_ids),
+        Err(err) => {
+            warn!(
+                "Unable to read the known blocks, resuming after the last committed block: {}",
+                err
+            );
+            fallback_block_ids(last_block_id, known_block_ids)
+        }
+    }
+}
+
+/// Returns the last committed block, or the startup blocks if none was committed
+fn fallback_block_ids(last_block_id: Option<&String>, known_block_ids: &[String]) -> Vec<String> {
+    match last_block_id {
+        Some(block_id) => vec![block_id.clone()],
+        None => known_block_ids.to_vec(),
+    }
+}
+
+/// Describes a subscribe response that is neither OK nor UNKNOWN_BLOCK, with the
+/// message the validator gave, if any, and guidance for the statuses we know
+fn subscribe_error(response: &ClientEventsSubscribeResponse) -> SubscriberError {
+    let guidance = match response.get_status() {
+        ClientEventsSubscribeResponse_Status::INVALID_FILTER => {
+            ", check the namespace filter regexes, which are narrowed by --shard and --address-types"
+        }
+        _ => "",
+    };
+    let message = match response.get_response_message() {
+        "" => String::new(),
+        message => format!(": {}", message),
+    };
+    SubscriberError::SubscribeRejected(
+        response.get_status(),
+        format!(
+            "The validator refused the subscription with status {:?}{}{} (response {:?})",
+            response.get_status(),
+            message,
+            guidance,
+            response
+        ),
+    )
+}
+
+/// Checks that the validator address is of the form `<scheme>://<host>:<port>`,
+/// where the scheme is tcp or tcps and the port is a non-zero number
+fn validate_endpoint(endpoint: &str) -> Result<(), SubscriberError> {
+    let invalid = |reason: &str| {
+        Err(SubscriberError::ConnError(format!(
+            "Invalid validator endpoint {}: {}. Expected tcp://<host>:<port>",
+            endpoint, reason
+        )))
+    };
+    let (scheme, address) = match endpoint.find("://") {
+        Some(index) => (&endpoint[..index], &endpoint[index + 3..]),
+        None => return invalid("missing scheme"),
+    };
+    if !VALID_SCHEMES.contains(&scheme) {
+        return invalid(&format!("unsupported scheme {}", scheme));
+    }
+    let (host, port) = match address.rfind(':') {
+        Some(index) => (&address[..index], &address[index + 1..]),
+        None => return invalid("missing port"),
+    };
+    if host.is_empty() || host == "[]" {
+        return invalid("missing host");
+    }
+    match port.parse::<u16>() {
+        Ok(0) | Err(_) => invalid(&format!("invalid port {}", port)),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Opens and closes a TCP connection to the validator endpoint, waiting at most
+/// the timeout in all. The zmq connection connects in the background and retries
+/// forever, so a bad endpoint would otherwise only show as requests timing out.
+/// ```
+/// # Errors
+/// Returns an error naming the endpoint if its host can't be resolved, or none of
+/// its addresses accepts a connection within the timeout
+/// ```
+fn check_reachable(endpoint: &str, timeout: Duration) -> Result<(), SubscriberError> {
+    let unreachable = |reason: String| {
+        SubscriberError::ConnError(format!(
+            "Could not connect to the validator at {} within {:?}: {}",
+            endpoint, timeout, reason
+        ))
+    };
+    let address = match endpoint.find("://") {
+        Some(index) => &endpoint[index + 3..],
+        None => endpoint,
+    };
+    let started = Instant::now();
+    let mut reason = "the host has no addresses".to_string();
+    for socket_address in address
+        .to_socket_addrs()
+        .map_err(|err| unreachable(err.to_string()))?
+    {
+        let remaining = match timeout.checked_sub(started.elapsed()) {
+            Some(remaining) if remaining > Duration::from_secs(0) => remaining,
+            _ => break,
+        };
+        match TcpStream::connect_timeout(&socket_address, remaining) {
+            Ok(_) => return Ok(()),
+            Err(err) => reason = format!("{}: {}", socket_address, err),
+        }
+    }
+    Err(unreachable(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handler::test_support::make_events;
+    use crate::event_handler::EventHandlerOptions;
+    use crate::store::memory::MemoryStore;
+
+    #[test]
+    /// Test that a refused subscription reports the validator's message and guidance
+    fn test_subscribe_error() {
+        let mut response = ClientEventsSubscribeResponse::new();
+        response.set_status(ClientEventsSubscribeResponse_Status::INVALID_FILTER);
+        response.set_response_message("Invalid regex".to_string());
+        let message = subscribe_error(&response).to_string();
+        assert!(message.contains("INVALID_FILTER: Invalid regex"));
+        assert!(message.contains("check the namespace filter regex"));
+    }
+
+    #[test]
+    /// Test that only running out of known blocks counts as falling back to the
+    /// genesis block
+    fn test_falls_back_to_genesis() {
+        assert!(!falls_back_to_genesis(25, 0, 10));
+        assert!(!falls_back_to_genesis(25, 10, 20));
+        assert!(falls_back_to_genesis(25, 20, 30));
+        assert!(falls_back_to_genesis(10, 0, 10));
+        assert!(!falls_back_to_genesis(0, 0, 10));
+    }
+
+    #[test]
+    /// Test that well formed tcp endpoints are accepted
+    fn test_validate_endpoint() {
+        assert!(validate_endpoint("tcp://localhost:4004").is_ok());
+        assert!(validate_endpoint("tcps://validator.example.com:4004").is_ok());
+        assert!(validate_endpoint("tcp://127.0.0.1:4004").is_ok());
+        assert!(validate_endpoint("tcp://[::1]:4004").is_ok());
+    }
+
+    #[test]
+    /// Test that an endpoint accepting connections is reachable, and one refusing
+    /// them is an error naming the endpoint
+    fn test_check_reachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("tcp://{}", listener.local_addr().unwrap());
+        assert!(check_reachable(&endpoint, Duration::from_secs(5)).is_ok());
+
+        drop(listener);
+        match check_reachable(&endpoint, Duration::from_secs(5)) {
+            Err(SubscriberError::ConnError(msg)) => assert!(msg.starts_with(&format!(
+                "Could not connect to the validator at {} within 5s: ",
+                endpoint
+            ))),
+            _ => panic!("Expected the closed endpoint to be unreachable"),
+        }
+    }
+
+    #[test]
+    /// Test that the subscription state is connecting until started, stopped if
+    /// the subscriber is stopped before the validator responds, and failed if the
+    /// validator never responds
+    fn test_subscription_state_transitions() {
+        let new_subscriber = |response_timeout| {
+            // Nothing answers on the port once the listener is dropped
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let endpoint = format!("tcp://{}", listener.local_addr().unwrap());
+            drop(listener);
+            Subscriber::new(
+                &endpoint,
+                EventHandler::new(
+                    Box::new(MemoryStore::default()),
+                    EventHandlerOptions::default(),
+                ),
+                SubscriberOptions {
+                    response_timeout: Some(response_timeout),
+                    ..SubscriberOptions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let mut subscriber = new_subscriber(Duration::from_secs(30));
+        let state = subscriber.subscription_state_handle();
+        assert_eq!(state.get(), SubscriptionState::Connecting);
+        assert!(subscriber.is_active());
+        subscriber.active.store(false, Ordering::SeqCst);
+        assert!(!subscriber.is_active());
+        subscriber.start(&[], 0).unwrap();
+        assert_eq!(state.get(), SubscriptionState::Stopped);
+
+        let mut subscriber = new_subscriber(Duration::from_millis(100));
+        assert!(subscriber.start(&[], 0).is_err());
+        assert_eq!(subscriber.subscription_state(), SubscriptionState::Failed);
+        assert!(subscriber.active.load(Ordering::SeqCst));
+        assert!(!subscriber.is_active());
+    }
+
+    #[test]
+    /// Test that the blocks queued behind a gap are discarded when resubscribing,
+    /// so the gap costs one resync rather than one for every stale block
+    fn test_gap_resyncs_once() {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::ROUTER).unwrap();
+        socket.set_rcvtimeo(1000).unwrap();
+        socket.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+
+        let store = MemoryStore::default();
+        let mut subscriber = Subscriber::new(
+            &endpoint,
+            EventHandler::new(
+                Box::new(store.clone()),
+                EventHandlerOptions {
+                    max_block_gap: Some(1),
+                    ..EventHandlerOptions::default()
+                },
+            ),
+            SubscriberOptions {
+                response_timeout: Some(Duration::from_secs(5)),
+                ..SubscriberOptions::default()
+            },
+        )
+        .unwrap();
+        let active = subscriber.active.clone();
 
-        let mut event_filter = EventFilter::new();
-        event_filter.set_key(String::from("address"));
+        // A validator sending block 1, then blocks 5 to 8 past a gap, to the first
+        // subscription, and block 2 to each later one. It stops the subscriber once
+        // no request arrived for a second after a resubscription.
+        let validator = thread::spawn(move || {
+            let _context = context;
+            let send = |identity: &[u8], message_type, correlation_id: &str, content| {
+                let mut message = ValidatorMessage::new();
+                message.set_message_type(message_type);
+                message.set_correlation_id(correlation_id.to_string());
+                message.set_content(content);
+                socket
+                    .send_multipart(
+                        vec![identity.to_vec(), message.write_to_bytes().unwrap()],
+                        0,
+                    )
+                    .unwrap();
+            };
+            let mut subscriptions = 0;
+            let mut timeouts = 0;
+            loop {
+                let frames = match socket.recv_multipart(0) {
+                    Ok(frames) => frames,
+                    Err(_) if subscriptions > 1 && active.load(Ordering::SeqCst) => {
+                        active.store(false, Ordering::SeqCst);
+                        continue;
+                    }
+                    Err(_) if timeouts < 10 => {
+                        timeouts += 1;
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                let request: ValidatorMessage = Message::parse_from_bytes(&frames[1]).unwrap();
+                let correlation_id = request.get_correlation_id();
+                match request.get_message_type() {
+                    Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_REQUEST => {
+                        subscriptions += 1;
+                        let mut response = ClientEventsSubscribeResponse::new();
+                        response.set_status(ClientEventsSubscribeResponse_Status::OK);
+                        send(
+                            &frames[0],
+                            Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_RESPONSE,
+                            correlation_id,
+                            response.write_to_bytes().unwrap(),
+                        );
+                        let block_nums = if subscriptions == 1 {
+                            vec![1, 5, 6, 7, 8]
+                        } else {
+                            vec![2]
+                        };
+                        for block_num in block_nums {
+                            send(
+                                &frames[0],
+                                Message_MessageType::CLIENT_EVENTS,
+                                "",
+                                make_events(block_num, &format!("block_{}", block_num), vec![]),
+                            );
+                        }
+                    }
+                    Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_REQUEST => {
+                        let mut response = ClientEventsUnsubscribeResponse::new();
+                        response.set_status(ClientEventsUnsubscribeResponse_Status::OK);
+                        send(
+                            &frames[0],
+                            Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_RESPONSE,
+                            correlation_id,
+                            response.write_to_bytes().unwrap(),
+                        );
+                        if !active.load(Ordering::SeqCst) {
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            subscriptions
+        });
 
-        let namespace = get_family_namespace_prefix();
-        event_filter.set_match_string(format!(r"^{}", namespace));
+        let status = subscriber.start(&[], 0).unwrap();
+        assert_eq!(validator.join().unwrap(), 2);
+        assert_eq!(status.gap_resyncs, 1);
+        assert_eq!(
+            store.block_ids(),
+            vec![(1, "block_1".to_string()), (2, "block_2".to_string())]
+        );
+    }
 
-        let event_filter_type = EventFilter_FilterType::REGEX_ANY;
-        event_filter.set_filter_type(event_filter_type);
+    #[test]
+    /// Test that reconnecting resumes after the newest block in the reporting
+    /// database, including blocks another process committed during the outage,
+    /// and from the startup blocks while the database has none
+    fn test_resume_block_ids() {
+        let store = MemoryStore::default();
+        let commit = |block_num: i64| {
+            store
+                .execute_operations_in_block(
+                    vec![],
+                    &Block {
+                        block_num,
+                        block_id: format!("block_{}", block_num),
+                    },
+                )
+                .unwrap()
+        };
+        let startup_ids = vec!["block_0".to_string()];
+        let handler = EventHandler::new(Box::new(store.clone()), EventHandlerOptions::default());
+        assert_eq!(resume_block_ids(&handler, None, &startup_ids), startup_ids);
 
-        let event_filter_vec = vec![event_filter];
-        let repeated_field = protobuf::RepeatedField::from_vec(event_filter_vec);
+        commit(1);
+        commit(2);
+        let last_block_id = "block_2".to_string();
+        // Another subscriber commits while this one is reconnecting
+        commit(3);
+        commit(4);
+        assert_eq!(
+            resume_block_ids(&handler, Some(&last_block_id), &startup_ids),
+            vec!["block_4", "block_3", "block_2", "block_1"]
+        );
+    }
 
-        state_delta_subscription.set_filters(repeated_field);
-        state_delta_subscription
+    #[test]
+    /// Test that malformed endpoints are rejected with a ConnError
+    fn test_validate_malformed_endpoint() {
+        let malformed = [
+            "",
+            "localhost:4004",
+            "http://localhost:4004",
+            "tcp:/localhost:4004",
+            "tcp://localhost",
+            "tcp://:4004",
+            "tcp://localhost:",
+            "tcp://localhost:port",
+            "tcp://localhost:0",
+            "tcp://localhost:70000",
+        ];
+        for endpoint in malformed.iter() {
+            match validate_endpoint(endpoint) {
+                Err(SubscriberError::ConnError(msg)) => assert!(msg.contains(endpoint)),
+                _ => panic!("Expected endpoint {:?} to be rejected", endpoint),
+            }
+        }
     }
 }
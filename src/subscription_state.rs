@@ -0,0 +1,95 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Where the subscriber is in its subscription to the validator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionState {
+    /// Created, or asking the validator for the subscription, which it hasn't
+    /// accepted yet
+    Connecting,
+    /// The validator accepted the subscription, and events are being handled
+    Subscribed,
+    /// Subscribing again after events were missed or the validator went silent
+    Reconnecting,
+    /// Unsubscribed after being stopped, or stopped before the validator accepted
+    /// the subscription
+    Stopped,
+    /// Subscribing or handling events failed with an error
+    Failed,
+}
+
+impl Default for SubscriptionState {
+    fn default() -> Self {
+        SubscriptionState::Connecting
+    }
+}
+
+impl SubscriptionState {
+    /// Returns true until the subscriber has stopped or failed
+    pub fn is_running(self) -> bool {
+        match self {
+            SubscriptionState::Connecting
+            | SubscriptionState::Subscribed
+            | SubscriptionState::Reconnecting => true,
+            SubscriptionState::Stopped | SubscriptionState::Failed => false,
+        }
+    }
+}
+
+impl fmt::Display for SubscriptionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SubscriptionState::Connecting => "connecting",
+            SubscriptionState::Subscribed => "subscribed",
+            SubscriptionState::Reconnecting => "reconnecting",
+            SubscriptionState::Stopped => "stopped",
+            SubscriptionState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The subscription state of a subscriber, shared with the threads reading it
+/// while the subscriber runs. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct SharedSubscriptionState(Arc<Mutex<SubscriptionState>>);
+
+impl SharedSubscriptionState {
+    /// Returns the current state
+    pub fn get(&self) -> SubscriptionState {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Moves to the given state, logging the transition at debug level
+    pub(crate) fn set(&self, state: SubscriptionState) {
+        let mut current = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if *current != state {
+            debug!("Subscription state {} -> {}", *current, state);
+            *current = state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that the state starts connecting, is shared between clones, and only
+    /// runs until stopped or failed
+    fn test_shared_subscription_state() {
+        let state = SharedSubscriptionState::default();
+        let watcher = state.clone();
+        assert_eq!(watcher.get(), SubscriptionState::Connecting);
+        assert!(watcher.get().is_running());
+
+        state.set(SubscriptionState::Subscribed);
+        assert_eq!(watcher.get(), SubscriptionState::Subscribed);
+        state.set(SubscriptionState::Reconnecting);
+        assert!(watcher.get().is_running());
+        state.set(SubscriptionState::Failed);
+        assert!(!watcher.get().is_running());
+        assert!(!SubscriptionState::Stopped.is_running());
+        assert_eq!(watcher.get().to_string(), "failed");
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The default window of the throughput moving averages
+pub const DEFAULT_THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+
+/// A rate whose counts decay exponentially over time, so it follows a steady
+/// stream of counts and falls towards zero once they stop
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DecayingRate {
+    rate: f64,
+    updated: Option<Instant>,
+}
+
+impl DecayingRate {
+    /// Returns the rate per second at the given time
+    fn at(&self, now: Instant, window: Duration) -> f64 {
+        match self.updated {
+            Some(updated) => {
+                let idle = now.saturating_duration_since(updated).as_secs_f64();
+                self.rate * (-idle / window.as_secs_f64()).exp()
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Adds a count at the given time
+    fn record(&mut self, count: u64, now: Instant, window: Duration) {
+        self.rate = self.at(now, window) + count as f64 / window.as_secs_f64();
+        self.updated = Some(now);
+    }
+}
+
+/// Moving averages of the blocks committed and event messages received per second.
+///
+/// Each count decays exponentially with the window as its time constant, so the
+/// averages follow the throughput of roughly the last window, and fall towards
+/// zero while nothing arrives, which makes a stalled subscriber visible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throughput {
+    window: Duration,
+    blocks: DecayingRate,
+    events: DecayingRate,
+}
+
+impl Default for Throughput {
+    fn default() -> Self {
+        Throughput::new(DEFAULT_THROUGHPUT_WINDOW)
+    }
+}
+
+impl Throughput {
+    /// Creates averages over the given window, or the default window if it is zero
+    pub fn new(window: Duration) -> Throughput {
+        Throughput {
+            window: if window > Duration::from_secs(0) {
+                window
+            } else {
+                DEFAULT_THROUGHPUT_WINDOW
+            },
+            blocks: DecayingRate::default(),
+            events: DecayingRate::default(),
+        }
+    }
+
+    /// Records that a block was committed at the given time
+    pub fn record_block(&mut self, now: Instant) {
+        self.blocks.record(1, now, self.window);
+    }
+
+    /// Records that an event message was received at the given time
+    pub fn record_events(&mut self, now: Instant) {
+        self.events.record(1, now, self.window);
+    }
+
+    /// Returns the average blocks committed per second at the given time
+    pub fn blocks_per_sec(&self, now: Instant) -> f64 {
+        self.blocks.at(now, self.window)
+    }
+
+    /// Returns the average event messages received per second at the given time
+    pub fn events_per_sec(&self, now: Instant) -> f64 {
+        self.events.at(now, self.window)
+    }
+
+    /// Returns the averages at the given time, for display
+    pub fn at(&self, now: Instant) -> ThroughputAt {
+        ThroughputAt {
+            window: self.window,
+            blocks_per_sec: self.blocks_per_sec(now),
+            events_per_sec: self.events_per_sec(now),
+        }
+    }
+}
+
+/// The throughput averages at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputAt {
+    pub window: Duration,
+    pub blocks_per_sec: f64,
+    pub events_per_sec: f64,
+}
+
+impl fmt::Display for ThroughputAt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} blocks/sec, {:.2} events/sec over {:?}",
+            self.blocks_per_sec, self.events_per_sec, self.window
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that a steady stream of blocks averages to its rate, and the average
+    /// decays towards zero once the blocks stop
+    fn test_throughput() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(Duration::from_secs(10));
+        assert_eq!(throughput.blocks_per_sec(start), 0.0);
+
+        // Five blocks and ten event messages a second for two minutes
+        for tick in 1..=1200 {
+            let now = start + Duration::from_millis(tick * 100);
+            if tick % 2 == 0 {
+                throughput.record_block(now);
+            }
+            throughput.record_events(now);
+        }
+        let end = start + Duration::from_secs(120);
+        assert!((throughput.blocks_per_sec(end) - 5.0).abs() < 0.2);
+        assert!((throughput.events_per_sec(end) - 10.0).abs() < 0.4);
+
+        let idle = end + Duration::from_secs(10);
+        let decayed = throughput.blocks_per_sec(idle);
+        assert!(decayed < throughput.blocks_per_sec(end) / 2.0);
+        assert!(throughput.blocks_per_sec(end + Duration::from_secs(120)) < 0.001);
+        assert_eq!(
+            throughput.at(end).to_string(),
+            format!(
+                "{:.2} blocks/sec, {:.2} events/sec over 10s",
+                throughput.blocks_per_sec(end),
+                throughput.events_per_sec(end)
+            )
+        );
+        assert_eq!(
+            Throughput::new(Duration::from_secs(0)),
+            Throughput::default()
+        );
+    }
+}
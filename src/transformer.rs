@@ -14,6 +14,9 @@ where
 
     /// Transforms this Container of Protobuf messages to a type that implements
     /// FromStateAtBlock for that message.
+    ///
+    /// The models are in the same order as the values in the container, which is
+    /// the order they are stored in state. Callers may rely on this order.
     #[cfg(not(tarpaulin_include))]
     fn to_models<D>(&self, at_block_num: i64) -> Vec<D>
     where
@@ -24,15 +27,46 @@ where
             .map(|state_value| FromStateAtBlock::at_block(at_block_num, state_value))
             .collect()
     }
+
+    /// Transforms this Container of Protobuf messages like `to_models`, sorting the
+    /// models by the given key. The sort is stable, so models with equal keys keep
+    /// their order in the container, and the output is deterministic for a given
+    /// state value.
+    fn to_models_sorted_by<D, K, F>(&self, at_block_num: i64, key: F) -> Vec<D>
+    where
+        D: FromStateAtBlock<S>,
+        F: FnMut(&D) -> K,
+        K: Ord,
+    {
+        let mut models = self.to_models(at_block_num);
+        models.sort_by_key(key);
+        models
+    }
 }
 
+/// Implements Container for a protobuf container message, reading its values from
+/// the `entries` field by default, from the given field, or from the given getter
+/// method for containers that only expose their values through an accessor:
+///
+/// ```ignore
+/// containerize!(agent::Agent, agent::AgentContainer);
+/// containerize!(agent::Agent, AgentList, agents);
+/// containerize!(agent::Agent, AgentList, method get_agents);
+/// ```
 #[macro_export]
 macro_rules! containerize {
     ($val_type:path, $container_type:path) => {
         containerize!($val_type, $container_type, entries);
     };
+    ($val_type:path, $container_type:path, method $getter:ident) => {
+        impl $crate::transformer::Container<$val_type> for $container_type {
+            fn values(&self) -> &[$val_type] {
+                self.$getter()
+            }
+        }
+    };
     ($val_type:path, $container_type:path, $container_field:ident) => {
-        impl crate::transformer::Container<$val_type> for $container_type {
+        impl $crate::transformer::Container<$val_type> for $container_type {
             fn values(&self) -> &[$val_type] {
                 &self.$container_field
             }
@@ -50,3 +84,82 @@ where
     /// with the values described in the given state value.
     fn at_block(block_num: i64, state_value: &S) -> Self;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::proto::agent;
+    use database::models::NewAgent;
+
+    fn make_container(public_keys: &[&str]) -> agent::AgentContainer {
+        let mut container = agent::AgentContainer::new();
+        for public_key in public_keys {
+            let mut agent = agent::Agent::new();
+            agent.set_public_key(public_key.to_string());
+            container.entries.push(agent);
+        }
+        container
+    }
+
+    /// A container exposing its values through a field
+    struct FieldContainer {
+        agents: Vec<agent::Agent>,
+    }
+
+    containerize!(agent::Agent, FieldContainer, agents);
+
+    /// A container exposing its values only through a getter
+    struct GetterContainer {
+        agents: Vec<agent::Agent>,
+    }
+
+    impl GetterContainer {
+        fn get_agents(&self) -> &[agent::Agent] {
+            &self.agents
+        }
+    }
+
+    containerize!(agent::Agent, GetterContainer, method get_agents);
+
+    fn public_keys(agents: &[NewAgent]) -> Vec<&str> {
+        agents
+            .iter()
+            .map(|agent| agent.public_key.as_str())
+            .collect()
+    }
+
+    #[test]
+    /// Test that models keep the order of the values in the container
+    fn test_to_models_preserves_order() {
+        let container = make_container(&["c", "a", "b"]);
+        let agents: Vec<NewAgent> = container.to_models(1);
+        assert_eq!(public_keys(&agents), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    /// Test that models are sorted by the key, keeping the container order of equal keys
+    fn test_to_models_sorted_by() {
+        let container = make_container(&["bb", "c", "aa", "a"]);
+        let agents: Vec<NewAgent> =
+            container.to_models_sorted_by(1, |agent: &NewAgent| agent.public_key.clone());
+        assert_eq!(public_keys(&agents), vec!["a", "aa", "bb", "c"]);
+
+        let agents: Vec<NewAgent> =
+            container.to_models_sorted_by(1, |agent: &NewAgent| agent.public_key.len());
+        assert_eq!(public_keys(&agents), vec!["c", "a", "bb", "aa"]);
+    }
+
+    #[test]
+    /// Test that containers can expose their values through a field or a getter
+    fn test_containerize_forms() {
+        let agents = make_container(&["a", "b"]).entries.into_vec();
+        let by_field = FieldContainer {
+            agents: agents.clone(),
+        };
+        let by_getter = GetterContainer { agents };
+        assert_eq!(by_field.values().len(), 2);
+        assert_eq!(by_getter.values(), by_field.values());
+        let models: Vec<NewAgent> = by_getter.to_models(1);
+        assert_eq!(public_keys(&models), vec!["a", "b"]);
+    }
+}
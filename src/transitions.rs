@@ -0,0 +1,149 @@
+use database::{custom_types::RequestStatusEnum, data_manager::OperationType};
+use std::collections::HashMap;
+
+/// The position of a request status in the request lifecycle. A request may
+/// only move to a later position, and closed or certified requests are final.
+fn stage(status: &RequestStatusEnum) -> u8 {
+    match status {
+        RequestStatusEnum::UnsetStatus => 0,
+        RequestStatusEnum::Open => 1,
+        RequestStatusEnum::InProgress => 2,
+        RequestStatusEnum::PreCertified => 3,
+        RequestStatusEnum::Closed | RequestStatusEnum::Certified => 4,
+    }
+}
+
+/// Returns true if a request may move from one status to another.
+/// Re-sending the same status is always legal.
+pub fn is_legal_transition(from: &RequestStatusEnum, to: &RequestStatusEnum) -> bool {
+    from == to || (stage(from) < stage(to) && stage(from) < 4)
+}
+
+/// A request status change that moved backwards, or out of a final status
+#[derive(Debug, Clone, PartialEq)]
+pub struct IllegalTransition {
+    pub request_id: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// The result of checking a block's request updates. The new statuses are
+/// only recorded once the block is committed.
+#[derive(Debug, Default)]
+pub struct TransitionUpdate {
+    pub illegal: Vec<IllegalTransition>,
+    statuses: HashMap<String, RequestStatusEnum>,
+}
+
+/// Tracks the last status of each request seen by the subscriber, so status
+/// changes can be checked against the request lifecycle
+#[derive(Debug, Default)]
+pub struct RequestTransitions {
+    statuses: HashMap<String, RequestStatusEnum>,
+}
+
+impl RequestTransitions {
+    /// Checks the request updates in a block's operations against the last
+    /// status of each request. Requests seen for the first time are not checked.
+    pub fn check(&self, operations: &[OperationType]) -> TransitionUpdate {
+        let mut update = TransitionUpdate::default();
+        for operation in operations {
+            if let OperationType::CreateRequest(requests) = operation {
+                for request in requests {
+                    let previous = update
+                        .statuses
+                        .get(&request.request_id)
+                        .or_else(|| self.statuses.get(&request.request_id));
+                    if let Some(previous) = previous {
+                        if !is_legal_transition(previous, &request.status) {
+                            update.illegal.push(IllegalTransition {
+                                request_id: request.request_id.clone(),
+                                from: format!("{:?}", previous),
+                                to: format!("{:?}", request.status),
+                            });
+                        }
+                    }
+                    update
+                        .statuses
+                        .insert(request.request_id.clone(), status_of(&request.status));
+                }
+            }
+        }
+        update
+    }
+
+    /// Records the request statuses from a committed block
+    pub fn record(&mut self, update: TransitionUpdate) {
+        self.statuses.extend(update.statuses);
+    }
+}
+
+/// Copies a status, as the database enum is not Clone
+fn status_of(status: &RequestStatusEnum) -> RequestStatusEnum {
+    match status {
+        RequestStatusEnum::UnsetStatus => RequestStatusEnum::UnsetStatus,
+        RequestStatusEnum::Open => RequestStatusEnum::Open,
+        RequestStatusEnum::InProgress => RequestStatusEnum::InProgress,
+        RequestStatusEnum::PreCertified => RequestStatusEnum::PreCertified,
+        RequestStatusEnum::Closed => RequestStatusEnum::Closed,
+        RequestStatusEnum::Certified => RequestStatusEnum::Certified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::{data_manager::MAX_BLOCK_NUM, models::NewRequest};
+
+    const REQUEST_ID: &str = "test_request";
+
+    #[test]
+    /// Test that requests may only advance through their lifecycle
+    fn test_is_legal_transition() {
+        use RequestStatusEnum::*;
+        assert!(is_legal_transition(&Open, &InProgress));
+        assert!(is_legal_transition(&InProgress, &Certified));
+        assert!(is_legal_transition(&InProgress, &Closed));
+        assert!(is_legal_transition(&Open, &Closed));
+        assert!(is_legal_transition(&Open, &Open));
+        assert!(is_legal_transition(&Certified, &Certified));
+        assert!(!is_legal_transition(&Certified, &Open));
+        assert!(!is_legal_transition(&InProgress, &Open));
+        assert!(!is_legal_transition(&Closed, &Certified));
+    }
+
+    #[test]
+    /// Test that an illegal transition is found against the last committed status
+    fn test_check_illegal_transition() {
+        let mut transitions = RequestTransitions::default();
+        let first = transitions.check(&[make_request_operation(RequestStatusEnum::Open)]);
+        assert!(first.illegal.is_empty());
+        transitions.record(first);
+
+        let second = transitions.check(&[make_request_operation(RequestStatusEnum::Certified)]);
+        assert!(second.illegal.is_empty());
+        transitions.record(second);
+
+        let third = transitions.check(&[make_request_operation(RequestStatusEnum::Open)]);
+        assert_eq!(
+            third.illegal,
+            vec![IllegalTransition {
+                request_id: REQUEST_ID.to_string(),
+                from: "Certified".to_string(),
+                to: "Open".to_string(),
+            }]
+        );
+    }
+
+    fn make_request_operation(status: RequestStatusEnum) -> OperationType {
+        OperationType::CreateRequest(vec![NewRequest {
+            request_id: REQUEST_ID.to_string(),
+            factory_id: "test_factory".to_string(),
+            standard_id: "test_standard".to_string(),
+            status,
+            request_date: 1,
+            start_block_num: 1,
+            end_block_num: MAX_BLOCK_NUM,
+        }])
+    }
+}
@@ -0,0 +1,408 @@
+use crate::errors::SubscriberError;
+use crate::sqlite_store::entity_writes;
+
+use database::{data_manager::OperationType, errors::DatabaseError, models::Block};
+use postgres::types::ToSql;
+use postgres::{Connection, TlsMode};
+use rusqlite::types::Value;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The columns holding enum values. The subscriber's records name them by their
+/// variants, e.g. `CertifyingBody`, while Postgres stores its own labels, so they
+/// are compared ignoring case and underscores.
+pub const ENUM_COLUMNS: [&str; 4] = ["organization_type", "role", "status", "assertion_type"];
+
+/// How many blocks may wait for the primary subscriber to commit them before the
+/// oldest is reported as not committed
+pub const MAX_WAITING_BLOCKS: usize = 1000;
+
+/// Reads the rows of the reporting database a block's operations are compared with
+pub trait RowReader {
+    /// Returns the id of the block with the given number in the reporting
+    /// database, or None if it isn't there yet
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError>;
+
+    /// Returns true if the table holds a row with every given column value, where
+    /// a null value matches a null column
+    fn has_row(&self, table: &str, columns: &[(&str, Value)]) -> Result<bool, DatabaseError>;
+}
+
+/// A difference between what a block's operations write and what the reporting
+/// database holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The reporting database holds a different block at the same number
+    Fork {
+        block_num: i64,
+        expected: String,
+        found: String,
+    },
+    /// The reporting database has no row with the values an operation writes
+    MissingRow {
+        block_num: i64,
+        table: &'static str,
+        key: String,
+    },
+    /// The primary subscriber did not commit the block while it waited
+    NotCommitted { block_num: i64, block_id: String },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::Fork {
+                block_num,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Block {} is {} in the reporting database, but {} on chain",
+                block_num, found, expected
+            ),
+            Divergence::MissingRow {
+                block_num,
+                table,
+                key,
+            } => write!(
+                f,
+                "The {} row for {} written at block {} is missing or differs in the reporting \
+                 database",
+                table, key, block_num
+            ),
+            Divergence::NotCommitted {
+                block_num,
+                block_id,
+            } => write!(
+                f,
+                "Block {} ({}) was not committed to the reporting database after {} later blocks",
+                block_num, block_id, MAX_WAITING_BLOCKS
+            ),
+        }
+    }
+}
+
+/// Counts of the blocks verified against the reporting database
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyStats {
+    /// The number of blocks compared with the reporting database
+    pub blocks_verified: u64,
+    /// The number of rows compared with the reporting database
+    pub rows_verified: u64,
+    /// The number of divergences found
+    pub divergences: u64,
+    /// The number of blocks still waiting for the primary subscriber to commit them
+    pub blocks_waiting: usize,
+}
+
+impl fmt::Display for VerifyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks and {} rows verified, {} divergences, {} blocks waiting to be committed",
+            self.blocks_verified, self.rows_verified, self.divergences, self.blocks_waiting
+        )
+    }
+}
+
+/// Compares the operations of each block with the rows the primary subscriber
+/// wrote for it, without writing anything. Blocks the primary hasn't committed
+/// yet wait until it has.
+pub struct Verifier {
+    reader: Box<dyn RowReader>,
+    waiting: VecDeque<(Block, Vec<OperationType>)>,
+    stats: VerifyStats,
+}
+
+impl Verifier {
+    pub fn new(reader: Box<dyn RowReader>) -> Verifier {
+        Verifier {
+            reader,
+            waiting: VecDeque::new(),
+            stats: VerifyStats::default(),
+        }
+    }
+
+    /// Queues a block and verifies every queued block the primary subscriber has
+    /// committed, logging each divergence as a warning. Returns the divergences.
+    /// ```
+    /// # Errors
+    /// Returns an error if the reporting database cannot be read
+    /// ```
+    pub fn verify(
+        &mut self,
+        block: Block,
+        operations: Vec<OperationType>,
+    ) -> Result<Vec<Divergence>, SubscriberError> {
+        self.waiting.push_back((block, operations));
+        self.verify_waiting()
+    }
+
+    /// Verifies every queued block the primary subscriber has committed, oldest
+    /// first, logging each divergence as a warning. Returns the divergences.
+    /// ```
+    /// # Errors
+    /// Returns an error if the reporting database cannot be read
+    /// ```
+    pub fn verify_waiting(&mut self) -> Result<Vec<Divergence>, SubscriberError> {
+        let mut divergences = vec![];
+        while let Some(block_num) = self.waiting.front().map(|(block, _)| block.block_num) {
+            let found = self.reader.block_id(block_num)?;
+            if found.is_none() && self.waiting.len() <= MAX_WAITING_BLOCKS {
+                break;
+            }
+            let (block, operations) = self.waiting.pop_front().unwrap();
+            match found {
+                None => divergences.push(Divergence::NotCommitted {
+                    block_num,
+                    block_id: block.block_id,
+                }),
+                Some(found) if found != block.block_id => divergences.push(Divergence::Fork {
+                    block_num,
+                    expected: block.block_id,
+                    found,
+                }),
+                Some(_) => {
+                    divergences.extend(self.compare_rows(&block, &operations)?);
+                    self.stats.blocks_verified += 1;
+                }
+            }
+        }
+        for divergence in &divergences {
+            warn!("{}", divergence);
+        }
+        self.stats.divergences += divergences.len() as u64;
+        self.stats.blocks_waiting = self.waiting.len();
+        Ok(divergences)
+    }
+
+    /// Returns the counts of the blocks verified so far
+    pub fn stats(&self) -> &VerifyStats {
+        &self.stats
+    }
+
+    /// Compares the rows the operations open with the reporting database. The end
+    /// block of each row is left out, as later blocks may have closed it since.
+    fn compare_rows(
+        &mut self,
+        block: &Block,
+        operations: &[OperationType],
+    ) -> Result<Vec<Divergence>, SubscriberError> {
+        let mut divergences = vec![];
+        for operation in operations {
+            for write in entity_writes(operation) {
+                for (table, columns) in write.inserts {
+                    let columns: Vec<(&str, Value)> = columns
+                        .into_iter()
+                        .filter(|(column, _)| *column != "end_block_num")
+                        .collect();
+                    self.stats.rows_verified += 1;
+                    if !self.reader.has_row(table, &columns)? {
+                        divergences.push(Divergence::MissingRow {
+                            block_num: block.block_num,
+                            table,
+                            key: row_key(&columns),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(divergences)
+    }
+}
+
+/// Names a row by the value of its first column, which is its entity's id
+fn row_key(columns: &[(&str, Value)]) -> String {
+    match columns.first() {
+        Some((column, Value::Text(value))) => format!("{} {}", column, value),
+        Some((column, value)) => format!("{} {:?}", column, value),
+        None => "an empty row".to_string(),
+    }
+}
+
+/// Reads the rows of the reporting database in Postgres
+pub struct PostgresRowReader {
+    connection: Connection,
+    schema: String,
+}
+
+impl PostgresRowReader {
+    /// Connects to the reporting database with the given URL, reading the tables
+    /// in the given schema
+    /// ```
+    /// # Errors
+    /// Returns an error if the database cannot be connected to
+    /// ```
+    pub fn connect(url: &str, schema: &str) -> Result<PostgresRowReader, SubscriberError> {
+        let connection = Connection::connect(url, TlsMode::None)
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(PostgresRowReader {
+            connection,
+            schema: schema.to_string(),
+        })
+    }
+}
+
+impl RowReader for PostgresRowReader {
+    fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError> {
+        let rows = self
+            .connection
+            .query(
+                &format!(
+                    "SELECT block_id FROM \"{}\".\"blocks\" WHERE block_num = $1",
+                    self.schema
+                ),
+                &[&block_num],
+            )
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(rows.iter().next().map(|row| row.get(0)))
+    }
+
+    fn has_row(&self, table: &str, columns: &[(&str, Value)]) -> Result<bool, DatabaseError> {
+        let mut conditions = vec![];
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+        for (column, value) in columns {
+            let param = format!("${}", params.len() + 1);
+            match value {
+                Value::Null => {
+                    conditions.push(format!("\"{}\" IS NULL", column));
+                    continue;
+                }
+                Value::Text(value) if ENUM_COLUMNS.contains(column) => {
+                    conditions.push(format!(
+                        "lower(replace(CAST(\"{}\" AS TEXT), '_', '')) = {}",
+                        column, param
+                    ));
+                    params.push(Box::new(value.replace('_', "").to_lowercase()));
+                }
+                Value::Text(value) => {
+                    conditions.push(format!("\"{}\" = {}", column, param));
+                    params.push(Box::new(value.clone()));
+                }
+                Value::Integer(value) => {
+                    conditions.push(format!("\"{}\" = {}", column, param));
+                    params.push(Box::new(*value));
+                }
+                Value::Real(value) => {
+                    conditions.push(format!("\"{}\" = {}", column, param));
+                    params.push(Box::new(*value));
+                }
+                Value::Blob(value) => {
+                    conditions.push(format!("\"{}\" = {}", column, param));
+                    params.push(Box::new(value.clone()));
+                }
+            }
+        }
+        let params: Vec<&dyn ToSql> = params.iter().map(|param| param.as_ref()).collect();
+        let rows = self
+            .connection
+            .query(
+                &format!(
+                    "SELECT 1 FROM \"{}\".\"{}\" WHERE {} LIMIT 1",
+                    self.schema,
+                    table,
+                    conditions.join(" AND ")
+                ),
+                &params,
+            )
+            .map_err(|err| DatabaseError::ConnectionError(Box::new(err)))?;
+        Ok(!rows.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_support::make_agent_operation;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// Rows by table, and the ids of the committed blocks by number
+    #[derive(Default)]
+    struct FakeReader {
+        blocks: Rc<RefCell<HashMap<i64, String>>>,
+        rows: Vec<(&'static str, Vec<(&'static str, Value)>)>,
+    }
+
+    impl RowReader for FakeReader {
+        fn block_id(&self, block_num: i64) -> Result<Option<String>, DatabaseError> {
+            Ok(self.blocks.borrow().get(&block_num).cloned())
+        }
+
+        fn has_row(&self, table: &str, columns: &[(&str, Value)]) -> Result<bool, DatabaseError> {
+            Ok(self.rows.iter().any(|(row_table, row)| {
+                *row_table == table && columns.iter().all(|column| row.contains(column))
+            }))
+        }
+    }
+
+    fn make_block(block_num: i64, block_id: &str) -> Block {
+        Block {
+            block_num,
+            block_id: block_id.to_string(),
+        }
+    }
+
+    #[test]
+    /// Test that blocks wait for the primary subscriber, that matching rows verify
+    /// even if closed since, and that differing rows and forks are divergences
+    fn test_verify_blocks() {
+        let blocks = Rc::new(RefCell::new(HashMap::new()));
+        let reader = FakeReader {
+            blocks: blocks.clone(),
+            rows: vec![(
+                "agents",
+                vec![
+                    ("public_key", Value::Text("test_public_key".to_string())),
+                    ("organization_id", Value::Null),
+                    ("name", Value::Text("test".to_string())),
+                    ("timestamp", Value::Integer(1)),
+                    ("start_block_num", Value::Integer(1)),
+                    ("end_block_num", Value::Integer(2)),
+                ],
+            )],
+        };
+        let mut verifier = Verifier::new(Box::new(reader));
+
+        let divergences = verifier
+            .verify(
+                make_block(1, "block_1"),
+                vec![make_agent_operation("test", 1)],
+            )
+            .unwrap();
+        assert!(divergences.is_empty());
+        assert_eq!(verifier.stats().blocks_waiting, 1);
+
+        blocks.borrow_mut().insert(1, "block_1".to_string());
+        blocks.borrow_mut().insert(2, "block_2".to_string());
+        let divergences = verifier
+            .verify(
+                make_block(2, "block_2"),
+                vec![make_agent_operation("renamed", 2)],
+            )
+            .unwrap();
+        assert_eq!(
+            divergences,
+            vec![Divergence::MissingRow {
+                block_num: 2,
+                table: "agents",
+                key: "public_key test_public_key".to_string(),
+            }]
+        );
+        assert_eq!(verifier.stats().blocks_verified, 2);
+        assert_eq!(verifier.stats().rows_verified, 2);
+        assert_eq!(verifier.stats().blocks_waiting, 0);
+
+        let divergences = verifier.verify(make_block(2, "other"), vec![]).unwrap();
+        assert_eq!(
+            divergences,
+            vec![Divergence::Fork {
+                block_num: 2,
+                expected: "other".to_string(),
+                found: "block_2".to_string(),
+            }]
+        );
+        assert_eq!(verifier.stats().divergences, 2);
+    }
+}
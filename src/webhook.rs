@@ -0,0 +1,196 @@
+use crate::audit::{self, AuditLog};
+
+use serde_json::{json, Value};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The delay before the first retry of a failed delivery, doubled for each retry after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Settings of the webhook sink
+pub struct WebhookOptions {
+    /// The URL each batch of records is POSTed to
+    pub url: String,
+    /// The operations that are sent, e.g. create_certificate
+    pub operations: Vec<String>,
+    /// How long records are collected before they are sent as one batch
+    pub batch_interval: Duration,
+    /// How many times a failed delivery is retried before it is given up
+    pub max_retries: u32,
+    /// The file batches that could not be delivered are appended to
+    pub dead_letter: Option<AuditLog>,
+}
+
+/// Sends the records of selected operations to a webhook from a background
+/// thread, so a slow or failing endpoint never holds up ingestion
+pub struct Webhook {
+    operations: Vec<String>,
+    sender: Sender<Value>,
+}
+
+impl Webhook {
+    /// Starts the background thread delivering batches to the webhook
+    #[cfg(not(tarpaulin_include))]
+    pub fn start(options: WebhookOptions) -> Webhook {
+        let (sender, receiver) = channel::<Value>();
+        let operations = options.operations.clone();
+        thread::spawn(move || {
+            let mut options = options;
+            let client = reqwest::Client::new();
+            let mut batch = Vec::new();
+            let mut deadline = Instant::now() + options.batch_interval;
+            loop {
+                let timeout = deadline
+                    .checked_duration_since(Instant::now())
+                    .unwrap_or_default();
+                match receiver.recv_timeout(timeout) {
+                    Ok(record) => {
+                        batch.push(record);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        deliver(&client, &mut options, &batch);
+                        return;
+                    }
+                }
+                if !batch.is_empty() {
+                    deliver(&client, &mut options, &batch);
+                    batch.clear();
+                }
+                deadline = Instant::now() + options.batch_interval;
+            }
+        });
+        Webhook { operations, sender }
+    }
+
+    /// Queues the records of the selected operations from a committed block.
+    /// This never blocks; the records are sent with the next batch.
+    pub fn send(&self, records: &[Value]) {
+        for record in select_records(records, &self.operations) {
+            if self.sender.send(record.clone()).is_err() {
+                error!("The webhook thread has stopped, dropping record");
+            }
+        }
+    }
+}
+
+/// Returns the records of the given operations
+pub fn select_records<'a>(records: &'a [Value], operations: &'a [String]) -> Vec<&'a Value> {
+    records
+        .iter()
+        .filter(|record| {
+            operations
+                .iter()
+                .any(|operation| record["operation"] == operation.as_str())
+        })
+        .collect()
+}
+
+/// Returns the delay before the given retry, starting from 1
+pub fn backoff(retry: u32) -> Duration {
+    INITIAL_BACKOFF * 2u32.saturating_pow(retry.saturating_sub(1).min(16))
+}
+
+/// POSTs a batch to the webhook, retrying with backoff, and appends the batch to
+/// the dead-letter file if every attempt fails
+#[cfg(not(tarpaulin_include))]
+fn deliver(client: &reqwest::Client, options: &mut WebhookOptions, batch: &[Value]) {
+    if batch.is_empty() {
+        return;
+    }
+    let payload = json!({ "records": batch });
+    for attempt in 0..=options.max_retries {
+        if attempt > 0 {
+            thread::sleep(backoff(attempt));
+        }
+        match client
+            .post(&options.url)
+            .json(&payload)
+            .send()
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => {
+                debug!("Delivered {} records to the webhook", batch.len());
+                return;
+            }
+            Err(err) => warn!(
+                "Error delivering {} records to the webhook (attempt {}): {}",
+                batch.len(),
+                attempt + 1,
+                err
+            ),
+        }
+    }
+    error!(
+        "Giving up delivering {} records to the webhook",
+        batch.len()
+    );
+    if let Some(ref mut dead_letter) = options.dead_letter {
+        if let Err(err) = dead_letter.append(batch) {
+            error!(
+                "Error writing records to the webhook dead-letter log: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Parses a comma separated list of operation names, e.g. create_certificate,create_request
+/// ```
+/// # Errors
+/// Returns an error naming the first unknown operation
+/// ```
+pub fn parse_operations(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|operation| !operation.is_empty())
+        .map(|operation| {
+            if audit::OPERATIONS.contains(&operation) {
+                Ok(operation.to_string())
+            } else {
+                Err(format!(
+                    "Unknown operation {:?}, expected one of {}",
+                    operation,
+                    audit::OPERATIONS.join(", ")
+                ))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that only the records of the selected operations are sent
+    fn test_select_records() {
+        let records = vec![
+            json!({"operation": "create_agent", "id": "agent"}),
+            json!({"operation": "create_certificate", "id": "certificate"}),
+        ];
+        let operations = vec!["create_certificate".to_string()];
+        assert_eq!(select_records(&records, &operations), vec![&records[1]]);
+    }
+
+    #[test]
+    /// Test that the delay doubles with each retry
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+    }
+
+    #[test]
+    /// Test that operation lists are parsed and unknown operations rejected
+    fn test_parse_operations() {
+        assert_eq!(
+            parse_operations("create_certificate, create_request").unwrap(),
+            vec!["create_certificate", "create_request"]
+        );
+        assert!(parse_operations("create_widget").is_err());
+    }
+}